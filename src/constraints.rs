@@ -7,7 +7,7 @@ use crate::{
     },
     error::SwapError,
 };
-use solana_program::program_error::ProgramError;
+use solana_program::{program_error::ProgramError, pubkey::Pubkey};
 
 #[cfg(feature = "production")]
 use std::env;
@@ -25,23 +25,73 @@ pub struct SwapConstraints<'a> {
     pub valid_curve_types: &'a [CurveType],
     /// Valid fees
     pub fees: &'a Fees,
+    /// Maximum fees, compared as fractions with the fees of new swaps
+    pub max_fees: &'a Fees,
+    /// Minimum amplification coefficient, for curves that have one
+    pub min_amp: u64,
+    /// Maximum amplification coefficient, for curves that have one
+    pub max_amp: u64,
+    /// Mints that swaps may pair, any mint is allowed if empty
+    pub allowed_mints: &'a [Pubkey],
+    /// Mints that swaps may not pair
+    pub denied_mints: &'a [Pubkey],
 }
 
 impl<'a> SwapConstraints<'a> {
     /// Checks that the provided curve is valid for the given constraints
     pub fn validate_curve(&self, swap_curve: &SwapCurve) -> Result<(), ProgramError> {
-        if self
+        if !self
             .valid_curve_types
             .iter()
             .any(|x| *x == swap_curve.curve_type)
         {
-            Ok(())
-        } else {
-            Err(SwapError::UnsupportedCurveType.into())
+            return Err(SwapError::UnsupportedCurveType.into());
+        }
+        match swap_curve.calculator.scaled_amp() {
+            Some((amp, factor)) => self.validate_scaled_amp(amp, factor),
+            None => Ok(()),
         }
     }
 
-    /// Checks that the provided curve is valid for the given constraints
+    /// Checks that the provided amplification coefficient, if any, is within
+    /// the given constraints.  Must also be used when the amplification
+    /// coefficient of an existing swap is changed.
+    pub fn validate_amp(&self, amp: Option<u64>) -> Result<(), ProgramError> {
+        match amp {
+            Some(amp) => self.validate_scaled_amp(amp, 1),
+            None => Ok(()),
+        }
+    }
+
+    /// Compares an amplification coefficient stored scaled by `factor` with
+    /// the scaled bounds, so that no rounding lets it past them
+    fn validate_scaled_amp(&self, amp: u64, factor: u64) -> Result<(), ProgramError> {
+        let amp = u128::from(amp);
+        if amp < u128::from(self.min_amp) * u128::from(factor)
+            || amp > u128::from(self.max_amp) * u128::from(factor)
+        {
+            return Err(SwapError::InvalidCurve.into());
+        }
+        Ok(())
+    }
+
+    /// Checks that the provided token mints may be paired in a swap
+    pub fn validate_mints(
+        &self,
+        token_a_mint: &Pubkey,
+        token_b_mint: &Pubkey,
+    ) -> Result<(), ProgramError> {
+        for mint in [*token_a_mint, *token_b_mint].iter() {
+            if (!self.allowed_mints.is_empty() && !self.allowed_mints.contains(mint))
+                || self.denied_mints.contains(mint)
+            {
+                return Err(SwapError::UnsupportedMint.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks that the provided fees are within the given constraints
     pub fn validate_fees(&self, fees: &Fees) -> Result<(), ProgramError> {
         if fees.trade_fee_numerator >= self.fees.trade_fee_numerator
             && fees.trade_fee_denominator == self.fees.trade_fee_denominator
@@ -51,6 +101,34 @@ impl<'a> SwapConstraints<'a> {
             && fees.owner_withdraw_fee_denominator == self.fees.owner_withdraw_fee_denominator
             && fees.host_fee_numerator == self.fees.host_fee_numerator
             && fees.host_fee_denominator == self.fees.host_fee_denominator
+            && fees.offpeg_fee_multiplier_numerator >= self.fees.offpeg_fee_multiplier_numerator
+            && fees.offpeg_fee_multiplier_denominator == self.fees.offpeg_fee_multiplier_denominator
+            && fee_at_most(
+                fees.trade_fee_numerator,
+                fees.trade_fee_denominator,
+                self.max_fees.trade_fee_numerator,
+                self.max_fees.trade_fee_denominator,
+            )
+            && fee_at_most(
+                fees.owner_trade_fee_numerator,
+                fees.owner_trade_fee_denominator,
+                self.max_fees.owner_trade_fee_numerator,
+                self.max_fees.owner_trade_fee_denominator,
+            )
+            && fee_at_most(
+                fees.owner_withdraw_fee_numerator,
+                fees.owner_withdraw_fee_denominator,
+                self.max_fees.owner_withdraw_fee_numerator,
+                self.max_fees.owner_withdraw_fee_denominator,
+            )
+            && (!fees.has_offpeg_fee_multiplier()
+                || self.max_fees.has_offpeg_fee_multiplier()
+                    && fee_at_most(
+                        fees.offpeg_fee_multiplier_numerator,
+                        fees.offpeg_fee_multiplier_denominator,
+                        self.max_fees.offpeg_fee_multiplier_numerator,
+                        self.max_fees.offpeg_fee_multiplier_denominator,
+                    ))
         {
             Ok(())
         } else {
@@ -59,6 +137,18 @@ impl<'a> SwapConstraints<'a> {
     }
 }
 
+/// Whether the fee fraction is at most the maximum one, compared by
+/// cross-multiplying since the denominators may differ.  A zero denominator
+/// means no fee.
+fn fee_at_most(numerator: u64, denominator: u64, max_numerator: u64, max_denominator: u64) -> bool {
+    if numerator == 0 || denominator == 0 {
+        return true;
+    }
+    max_denominator != 0
+        && u128::from(numerator) * u128::from(max_denominator)
+            <= u128::from(max_numerator) * u128::from(denominator)
+}
+
 #[cfg(feature = "production")]
 const OWNER_KEY: &str = env!("SWAP_PROGRAM_OWNER_FEE_ADDRESS");
 #[cfg(feature = "production")]
@@ -71,16 +161,39 @@ const FEES: &Fees = &Fees {
     owner_withdraw_fee_denominator: 0,
     host_fee_numerator: 20,
     host_fee_denominator: 100,
+    offpeg_fee_multiplier_numerator: 0,
+    offpeg_fee_multiplier_denominator: 0,
 };
 #[cfg(feature = "production")]
-const VALID_CURVE_TYPES: &[CurveType] = &[CurveType::Stable];
+const MAX_FEES: &Fees = &Fees {
+    trade_fee_numerator: 100,
+    trade_fee_denominator: 10000,
+    owner_trade_fee_numerator: 100,
+    owner_trade_fee_denominator: 10000,
+    owner_withdraw_fee_numerator: 0,
+    owner_withdraw_fee_denominator: 0,
+    host_fee_numerator: 20,
+    host_fee_denominator: 100,
+    offpeg_fee_multiplier_numerator: 0,
+    offpeg_fee_multiplier_denominator: 0,
+};
+#[cfg(feature = "production")]
+const MIN_AMP: u64 = 1;
+#[cfg(feature = "production")]
+const MAX_AMP: u64 = 10_000;
+#[cfg(feature = "production")]
+const VALID_CURVE_TYPES: &[CurveType] = &[CurveType::Stable, CurveType::ConstantPrice];
 
 /// Fee structure defined by program creator in order to enforce certain
 /// fees when others use the program.  Adds checks on pool creation and
 /// swapping to ensure the correct fees and account owners are passed.
 /// Fees provided during production build currently are considered min
-/// fees that creator of the pool can specify. Host fee is a fixed
-/// percentage that host receives as a portion of owner fees
+/// fees that creator of the pool can specify, up to the max fees. Host fee
+/// is a fixed percentage that host receives as a portion of owner fees.
+/// The amplification coefficient of new curves must be within the amp bounds.
+/// Once the program config account is set, its constraints are used instead.
+/// The built-in constraints pair any mints, mints are only restricted by the
+/// program config.
 pub const SWAP_CONSTRAINTS: Option<SwapConstraints> = {
     #[cfg(feature = "production")]
     {
@@ -88,6 +201,11 @@ pub const SWAP_CONSTRAINTS: Option<SwapConstraints> = {
             owner_key: OWNER_KEY,
             valid_curve_types: VALID_CURVE_TYPES,
             fees: FEES,
+            max_fees: MAX_FEES,
+            min_amp: MIN_AMP,
+            max_amp: MAX_AMP,
+            allowed_mints: &[],
+            denied_mints: &[],
         })
     }
     #[cfg(not(feature = "production"))]
@@ -99,7 +217,11 @@ pub const SWAP_CONSTRAINTS: Option<SwapConstraints> = {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::swap::{base::CurveType, stable::StableCurve};
+    use crate::swap::{
+        base::CurveType,
+        crypto::{CryptoCurve, A_MULTIPLIER},
+        stable::StableCurve,
+    };
     use std::sync::Arc;
 
     #[test]
@@ -124,16 +246,29 @@ mod tests {
             owner_withdraw_fee_denominator,
             host_fee_numerator,
             host_fee_denominator,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
         };
         let calculator = StableCurve { amp };
         let swap_curve = SwapCurve {
             curve_type,
             calculator: Arc::new(calculator.clone()),
         };
+        let max_fees = Fees {
+            trade_fee_numerator: trade_fee_numerator + 1,
+            owner_trade_fee_numerator: owner_trade_fee_numerator + 1,
+            owner_withdraw_fee_numerator: owner_withdraw_fee_numerator + 1,
+            ..valid_fees.clone()
+        };
         let constraints = SwapConstraints {
             owner_key,
             valid_curve_types: &[curve_type],
             fees: &valid_fees,
+            max_fees: &max_fees,
+            min_amp: 1,
+            max_amp: 100,
+            allowed_mints: &[],
+            denied_mints: &[],
         };
 
         constraints.validate_curve(&swap_curve).unwrap();
@@ -184,5 +319,191 @@ mod tests {
             constraints.validate_fees(&fees),
         );
         fees.owner_trade_fee_denominator = owner_trade_fee_denominator;
+
+        // passing fees up to the max is ok
+        fees.trade_fee_numerator = trade_fee_numerator + 1;
+        fees.owner_trade_fee_numerator = owner_trade_fee_numerator + 1;
+        fees.owner_withdraw_fee_numerator = owner_withdraw_fee_numerator + 1;
+        assert_eq!(constraints.validate_fees(&fees), Ok(()));
+        fees.trade_fee_numerator = trade_fee_numerator;
+        fees.owner_trade_fee_numerator = owner_trade_fee_numerator;
+        fees.owner_withdraw_fee_numerator = owner_withdraw_fee_numerator;
+
+        fees.trade_fee_numerator = trade_fee_numerator + 2;
+        assert_eq!(
+            Err(SwapError::InvalidFee.into()),
+            constraints.validate_fees(&fees),
+        );
+        fees.trade_fee_numerator = trade_fee_numerator;
+
+        fees.owner_trade_fee_numerator = owner_trade_fee_numerator + 2;
+        assert_eq!(
+            Err(SwapError::InvalidFee.into()),
+            constraints.validate_fees(&fees),
+        );
+        fees.owner_trade_fee_numerator = owner_trade_fee_numerator;
+
+        fees.owner_withdraw_fee_numerator = owner_withdraw_fee_numerator + 2;
+        assert_eq!(
+            Err(SwapError::InvalidFee.into()),
+            constraints.validate_fees(&fees),
+        );
+        fees.owner_withdraw_fee_numerator = owner_withdraw_fee_numerator;
+
+        // the maximum fees are fractions, not numerators
+        let min_fees = Fees {
+            trade_fee_numerator: 0,
+            trade_fee_denominator: 1,
+            ..valid_fees.clone()
+        };
+        let max_fees = Fees {
+            trade_fee_numerator: 100,
+            trade_fee_denominator: 10_000,
+            ..max_fees
+        };
+        let constraints = SwapConstraints {
+            fees: &min_fees,
+            max_fees: &max_fees,
+            ..constraints
+        };
+        constraints.validate_fees(&min_fees).unwrap();
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            ..min_fees.clone()
+        };
+        assert_eq!(
+            Err(SwapError::InvalidFee.into()),
+            constraints.validate_fees(&fees),
+        );
+    }
+
+    #[test]
+    fn validate_curve() {
+        let curve_type = CurveType::Stable;
+        let fees = Fees::default();
+        let min_amp = 10;
+        let max_amp = 100;
+        let constraints = SwapConstraints {
+            owner_key: "",
+            valid_curve_types: &[curve_type],
+            fees: &fees,
+            max_fees: &fees,
+            min_amp,
+            max_amp,
+            allowed_mints: &[],
+            denied_mints: &[],
+        };
+        let swap_curve = |amp| SwapCurve {
+            curve_type,
+            calculator: Arc::new(StableCurve { amp }),
+        };
+
+        constraints.validate_curve(&swap_curve(min_amp)).unwrap();
+        constraints.validate_curve(&swap_curve(max_amp)).unwrap();
+        assert_eq!(
+            Err(SwapError::InvalidCurve.into()),
+            constraints.validate_curve(&swap_curve(min_amp - 1)),
+        );
+        assert_eq!(
+            Err(SwapError::InvalidCurve.into()),
+            constraints.validate_curve(&swap_curve(max_amp + 1)),
+        );
+
+        // the crypto curve is bounded by its amplification coefficient
+        // without the factor of its stored value
+        let crypto_constraints = SwapConstraints {
+            valid_curve_types: &[CurveType::Crypto],
+            ..constraints
+        };
+        let crypto_curve = |a| SwapCurve {
+            curve_type: CurveType::Crypto,
+            calculator: Arc::new(CryptoCurve {
+                a,
+                ..CryptoCurve::default()
+            }),
+        };
+        let a_factor = 4 * A_MULTIPLIER;
+        crypto_constraints
+            .validate_curve(&crypto_curve(min_amp * a_factor))
+            .unwrap();
+        crypto_constraints
+            .validate_curve(&crypto_curve(max_amp * a_factor))
+            .unwrap();
+        assert_eq!(
+            Err(SwapError::InvalidCurve.into()),
+            crypto_constraints.validate_curve(&crypto_curve(min_amp * a_factor - 1)),
+        );
+        assert_eq!(
+            Err(SwapError::InvalidCurve.into()),
+            crypto_constraints.validate_curve(&crypto_curve(max_amp * a_factor + 1)),
+        );
+        // an amplification coefficient below one is not rounded down to zero
+        let zero_min_constraints = SwapConstraints {
+            min_amp: 0,
+            max_amp: 0,
+            ..crypto_constraints
+        };
+        assert_eq!(
+            Err(SwapError::InvalidCurve.into()),
+            zero_min_constraints.validate_curve(&crypto_curve(a_factor - 1)),
+        );
+
+        let constraints = SwapConstraints {
+            valid_curve_types: &[],
+            ..constraints
+        };
+        assert_eq!(
+            Err(SwapError::UnsupportedCurveType.into()),
+            constraints.validate_curve(&swap_curve(min_amp)),
+        );
+    }
+    #[test]
+    fn validate_mints() {
+        let fees = Fees::default();
+        let mint_a = Pubkey::new_unique();
+        let mint_b = Pubkey::new_unique();
+        let mint_c = Pubkey::new_unique();
+        let constraints = SwapConstraints {
+            owner_key: "",
+            valid_curve_types: &[],
+            fees: &fees,
+            max_fees: &fees,
+            min_amp: 1,
+            max_amp: 1,
+            allowed_mints: &[],
+            denied_mints: &[],
+        };
+        constraints.validate_mints(&mint_a, &mint_b).unwrap();
+
+        let allowed_mints = [mint_a, mint_b];
+        let constraints = SwapConstraints {
+            allowed_mints: &allowed_mints,
+            ..constraints
+        };
+        constraints.validate_mints(&mint_a, &mint_b).unwrap();
+        assert_eq!(
+            Err(SwapError::UnsupportedMint.into()),
+            constraints.validate_mints(&mint_a, &mint_c),
+        );
+        assert_eq!(
+            Err(SwapError::UnsupportedMint.into()),
+            constraints.validate_mints(&mint_c, &mint_b),
+        );
+
+        let denied_mints = [mint_c];
+        let constraints = SwapConstraints {
+            allowed_mints: &[],
+            denied_mints: &denied_mints,
+            ..constraints
+        };
+        constraints.validate_mints(&mint_a, &mint_b).unwrap();
+        assert_eq!(
+            Err(SwapError::UnsupportedMint.into()),
+            constraints.validate_mints(&mint_a, &mint_c),
+        );
+        assert_eq!(
+            Err(SwapError::UnsupportedMint.into()),
+            constraints.validate_mints(&mint_c, &mint_b),
+        );
     }
 }
@@ -102,6 +102,61 @@ pub enum SwapError {
     /// The operation cannot be performed on the given curve
     #[error("The operation cannot be performed on the given curve")]
     UnsupportedCurveOperation,
+    /// The pool token supply would fall below the locked minimum liquidity
+    #[error("The pool token supply would fall below the locked minimum liquidity")]
+    InsufficientLiquidity,
+    /// The provided admin account is not the swap admin or is not a signer
+    #[error("The provided admin account is not the swap admin or is not a signer")]
+    InvalidAdmin,
+
+    // 30.
+    /// The swap account uses a state version too old to hold the new settings
+    #[error("The swap account uses a state version too old to hold the new settings")]
+    OutdatedSwapVersion,
+    /// The fee recipients or their weights are invalid
+    #[error("The fee recipients or their weights are invalid")]
+    InvalidFeeRecipients,
+    /// The provided upgrade authority does not match the program data
+    #[error("The provided upgrade authority does not match the program data")]
+    InvalidUpgradeAuthority,
+    /// The token mint is not allowed by the program owner
+    #[error("The token mint is not allowed by the program owner")]
+    UnsupportedMint,
+    /// The user transfer authority is not on the deposit allowlist of the swap
+    #[error("The user transfer authority is not on the deposit allowlist of the swap")]
+    DepositNotAllowed,
+    /// The deposit would exceed the deposit caps of the swap
+    #[error("The deposit would exceed the deposit caps of the swap")]
+    DepositCapExceeded,
+
+    // 35.
+    /// The spot price moved beyond the circuit breaker band within the slot
+    #[error("The spot price moved beyond the circuit breaker band within the slot")]
+    PriceBandExceeded,
+    /// The oracle price account is not the one of the swap, or its price is
+    /// unusable
+    #[error("The oracle price account is invalid or its price is unusable")]
+    InvalidOracle,
+    /// The pool price after the swap deviates too far from the oracle price
+    #[error("The pool price after the swap deviates too far from the oracle price")]
+    OracleDeviationExceeded,
+    /// Pool tokens other than the locked minimum liquidity are in circulation
+    #[error("The pool still holds liquidity above the locked minimum")]
+    PoolNotEmpty,
+    /// The swaps do not trade the same token A and token B mints
+    #[error("The swaps do not trade the same token mints")]
+    MismatchedSwapMints,
+
+    // 40.
+    /// The base swap is not the one whose pool token the metapool trades
+    #[error("The base swap does not match the pool token traded by the metapool")]
+    IncorrectBaseSwap,
+    /// The admin fees held by the swap token accounts are not withdrawn yet
+    #[error("The admin fees have not been withdrawn")]
+    AdminFeesNotWithdrawn,
+    /// The destination token account does not hold the mint of the swap token account
+    #[error("The destination token account does not match the swap token mint")]
+    IncorrectDestinationMint,
 }
 impl From<SwapError> for ProgramError {
     fn from(e: SwapError) -> Self {
@@ -4,13 +4,16 @@
 
 use crate::swap::{base::SwapCurve, fees::Fees};
 use crate::error::SwapError;
+use crate::oracle::OracleType;
+use crate::state::{MAX_CONFIG_MINTS, MAX_FEE_RECIPIENTS};
 use solana_program::{
     instruction::{AccountMeta, Instruction},
     program_error::ProgramError,
     program_pack::Pack,
     pubkey::Pubkey,
+    system_program,
 };
-use std::convert::TryInto;
+use std::convert::{TryFrom, TryInto};
 use std::mem::size_of;
 
 #[cfg(feature = "fuzz")]
@@ -78,6 +81,161 @@ pub struct WithdrawSingleTokenTypeExactAmountOut {
     pub maximum_pool_token_amount: u64,
 }
 
+/// WithdrawSingleTokenTypeExactAmountIn instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct WithdrawSingleTokenTypeExactAmountIn {
+    /// Amount of pool tokens to burn, including the withdrawal fee. User
+    /// receives an output of token A or B for the pool tokens returned.
+    pub pool_token_amount: u64,
+    /// Minimum amount of token A or B to receive, prevents excessive slippage
+    pub minimum_token_amount: u64,
+}
+
+/// SetFeeRecipients instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetFeeRecipients {
+    /// Share of each fee recipient in basis points, in the order of the
+    /// recipient accounts.  Unused entries must be zero, and the used entries
+    /// must add up to 10,000.
+    pub weights: [u16; MAX_FEE_RECIPIENTS],
+}
+
+/// SetDepositGate instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetDepositGate {
+    /// Restrict deposits to the user transfer authorities on the allowlist
+    pub enabled: bool,
+}
+
+/// SetAllowlistEntry instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetAllowlistEntry {
+    /// Create the allowlist entry if true, close it otherwise
+    pub allowed: bool,
+}
+
+/// SetDepositCaps instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetDepositCaps {
+    /// Maximum token A reserve after a deposit, zero if uncapped
+    pub max_token_a_amount: u64,
+    /// Maximum token B reserve after a deposit, zero if uncapped
+    pub max_token_b_amount: u64,
+    /// Maximum pool token supply after a deposit, zero if uncapped
+    pub max_pool_supply: u64,
+}
+
+/// SetPriceBand instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetPriceBand {
+    /// Maximum move of the spot price within a slot in basis points, zero to
+    /// turn the circuit breaker off
+    pub price_band: u16,
+}
+
+/// SetOracle instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetOracle {
+    /// Layout of the oracle price account
+    pub oracle_type: OracleType,
+    /// Maximum deviation of the pool price after a swap from the oracle
+    /// price in basis points, zero to turn the oracle guard off
+    pub max_deviation: u16,
+}
+
+/// RescueTokens instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct RescueTokens {
+    /// Amount of tokens to transfer out of the stray token account
+    pub amount: u64,
+}
+
+/// MigrateLiquidity instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct MigrateLiquidity {
+    /// Amount of pool tokens of the source swap to burn
+    pub pool_token_amount: u64,
+    /// Minimum amount of pool tokens of the destination swap to receive,
+    /// prevents excessive slippage
+    pub minimum_pool_token_amount: u64,
+}
+
+/// SwapUnderlying instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SwapUnderlying {
+    /// Amount of token A of the metapool to transfer
+    pub amount_in: u64,
+    /// Minimum amount of the base swap token to output, prevents excessive
+    /// slippage
+    pub minimum_amount_out: u64,
+}
+
+/// DepositUnderlying instruction data
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct DepositUnderlying {
+    /// Amount of base swap pool tokens to mint from the base swap tokens
+    pub base_pool_token_amount: u64,
+    /// Amount of metapool pool tokens to mint
+    pub pool_token_amount: u64,
+    /// Maximum token A amount of the metapool to deposit, prevents excessive
+    /// slippage
+    pub maximum_token_a_amount: u64,
+    /// Maximum token A amount of the base swap to deposit, prevents excessive
+    /// slippage
+    pub maximum_base_token_a_amount: u64,
+    /// Maximum token B amount of the base swap to deposit, prevents excessive
+    /// slippage
+    pub maximum_base_token_b_amount: u64,
+}
+
+/// SetProgramConfig instruction data
+#[repr(C)]
+#[derive(Clone, Debug, PartialEq)]
+pub struct SetProgramConfig {
+    /// Owner of all the pools created by the program, if it differs from
+    /// `Pubkey::default()`
+    pub owner_key: Pubkey,
+    /// Bitmask of the valid curve types, bit `n` is set if `CurveType` `n`
+    /// is allowed
+    pub valid_curve_types: u32,
+    /// Minimum amplification coefficient of the new pools
+    pub min_amp: u64,
+    /// Maximum amplification coefficient of the new pools
+    pub max_amp: u64,
+    /// Minimum fees, and fixed host fee, of the new pools
+    pub fees: Fees,
+    /// Maximum fees of the new pools, compared as fractions
+    pub max_fees: Fees,
+    /// Mints that the new pools may pair, `Pubkey::default()` for unused
+    /// entries.  Any mint is allowed if there are none.
+    pub allowed_mints: [Pubkey; MAX_CONFIG_MINTS],
+    /// Mints that the new pools may not pair, `Pubkey::default()` for unused
+    /// entries
+    pub denied_mints: [Pubkey; MAX_CONFIG_MINTS],
+}
+
 /// Instructions supported by the token swap program.
 #[repr(C)]
 #[derive(Debug, PartialEq)]
@@ -93,12 +251,25 @@ pub enum SwapInstruction {
     ///   Must be empty, not owned by swap authority
     ///   6. `[writable]` Pool Token Account to deposit the initial pool token
     ///   supply.  Must be empty, not owned by swap authority.
-    ///   7. `[]` Token program id
+    ///   7. `[writable]` Pool Token Account to hold the permanently locked
+    ///      minimum liquidity.  Must be empty, owned by swap authority.
+    ///   8. `[]` Token program id
+    ///   9. `[]` Program config account derived from
+    ///      `find_program_address(&["config"])`.  Its constraints are used if
+    ///      it is initialized, otherwise the ones built into the program.
+    ///   10. `[optional]` token_a Account to receive admin fees.  If provided
+    ///       along with the token_b admin fee account, admin trading fees are kept
+    ///       in the swap token accounts instead of being minted as pool tokens.
+    ///   11. `[optional]` token_b Account to receive admin fees
+    ///   12. `[]` Base swap, only for metapools.  Its pool token must be
+    ///       token_b.
+    ///   13. `[]` Pool MINT account of the base swap, only for metapools
     Initialize(Initialize),
 
-    ///   Swap the tokens in the pool.
+    ///   Swap the tokens in the pool.  Metapools refresh the cached virtual
+    ///   price of the base swap pool token first, from the base swap accounts.
     ///
-    ///   0. `[]` Token-swap
+    ///   0. `[writable]` Token-swap
     ///   1. `[]` swap authority
     ///   2. `[]` user transfer authority
     ///   3. `[writable]` token_(A|B) SOURCE Account, amount is transferable by user transfer authority,
@@ -108,14 +279,21 @@ pub enum SwapInstruction {
     ///   7. `[writable]` Pool token mint, to generate trading fees
     ///   8. `[writable]` Fee account, to receive trading fees
     ///   9. `[]` Token program id
-    ///   10. `[optional, writable]` Host fee account to receive additional trading fees
+    ///   10. `[]` Base swap, only if the swap is a metapool
+    ///   11. `[]` Pool mint account of the base swap, only if the swap is a
+    ///       metapool
+    ///   12. `[optional, writable]` Host fee account to receive additional trading fees.
+    ///       Must be a pool token account, or a SOURCE token account if admin fees
+    ///       are held in tokens.
+    ///   13. `[]` Oracle price account of the swap, only if its oracle guard
+    ///       is on.  Always the last account.
     Swap(Swap),
 
     ///   Deposit both types of tokens into the pool.  The output is a "pool"
     ///   token representing ownership in the pool. Inputs are converted to
     ///   the current ratio.
     ///
-    ///   0. `[]` Token-swap
+    ///   0. `[writable]` Token-swap
     ///   1. `[]` swap authority
     ///   2. `[]` user transfer authority
     ///   3. `[writable]` token_a user transfer authority can transfer amount,
@@ -125,13 +303,15 @@ pub enum SwapInstruction {
     ///   7. `[writable]` Pool MINT account, swap authority is the owner.
     ///   8. `[writable]` Pool Account to deposit the generated tokens, user is the owner.
     ///   9. `[]` Token program id
+    ///   10. `[optional]` Allowlist entry of the user transfer authority,
+    ///       required if the swap has a deposit gate
     DepositAllTokenTypes(DepositAllTokenTypes),
 
     ///   Withdraw both types of tokens from the pool at the current ratio, given
     ///   pool tokens.  The pool tokens are burned in exchange for an equivalent
     ///   amount of token A and B.
     ///
-    ///   0. `[]` Token-swap
+    ///   0. `[writable]` Token-swap
     ///   1. `[]` swap authority
     ///   2. `[]` user transfer authority
     ///   3. `[writable]` Pool mint account, swap authority is the owner
@@ -144,9 +324,9 @@ pub enum SwapInstruction {
     ///   10. `[]` Token program id
     WithdrawAllTokenTypes(WithdrawAllTokenTypes),
     ///   Withdraw one token type from the pool at the current ratio given the
-    ///   exact amount out expected.
+    ///   exact amount out expected.  Not supported by metapools.
     ///
-    ///   0. `[]` Token-swap
+    ///   0. `[writable]` Token-swap
     ///   1. `[]` swap authority
     ///   2. `[]` user transfer authority
     ///   3. `[writable]` Pool mint account, swap authority is the owner
@@ -157,6 +337,255 @@ pub enum SwapInstruction {
     ///   8. `[writable]` Fee account, to receive withdrawal fees
     ///   9. `[]` Token program id
     WithdrawSingleTokenTypeExactAmountOut(WithdrawSingleTokenTypeExactAmountOut),
+
+    ///   Withdraw the admin fees held in the swap token accounts to the admin
+    ///   fee accounts given on initialization.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[signer]` swap admin
+    ///   3. `[writable]` token_a Swap Account to withdraw FROM.
+    ///   4. `[writable]` token_b Swap Account to withdraw FROM.
+    ///   5. `[writable]` token_a admin fee Account to credit.
+    ///   6. `[writable]` token_b admin fee Account to credit.
+    ///   7. `[]` Token program id
+    WithdrawAdminFees,
+
+    ///   Set the accounts sharing the pool fees, moving the pool fees to a pool
+    ///   token account owned by the swap authority so that they can be
+    ///   distributed by anyone with `DistributeFees`.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[signer]` swap admin
+    ///   3. `[]` new Pool Token fee Account.  Must be owned by swap authority.
+    ///   4. ..4+N `[]` Pool Token recipient Accounts, one for each non-zero weight
+    SetFeeRecipients(SetFeeRecipients),
+
+    ///   Distribute the pool tokens held by the pool fee account to the fee
+    ///   recipients, in proportion to their weights.  Any rounding dust is
+    ///   left for the next distribution.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[writable]` Pool Token fee Account, owned by swap authority
+    ///   3. `[]` Token program id
+    ///   4. ..4+N `[writable]` Pool Token recipient Accounts, in the order set
+    DistributeFees,
+
+    ///   Withdraw one token type from the pool given the exact amount of pool
+    ///   tokens to burn.  Not supported by metapools.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[]` user transfer authority
+    ///   3. `[writable]` Pool mint account, swap authority is the owner
+    ///   4. `[writable]` SOURCE Pool account, amount is transferable by user transfer authority.
+    ///   5. `[writable]` token_a Swap Account to potentially withdraw from.
+    ///   6. `[writable]` token_b Swap Account to potentially withdraw from.
+    ///   7. `[writable]` token_(A|B) User Account to credit
+    ///   8. `[writable]` Fee account, to receive withdrawal fees
+    ///   9. `[]` Token program id
+    WithdrawSingleTokenTypeExactAmountIn(WithdrawSingleTokenTypeExactAmountIn),
+
+    ///   Create or update the program config, holding the constraints that all
+    ///   new pools must satisfy.  Only the program upgrade authority can sign.
+    ///
+    ///   0. `[writable]` Program config account derived from
+    ///      `find_program_address(&["config"])`
+    ///   1. `[signer]` Program upgrade authority
+    ///   2. `[]` Program data account of the upgradeable program
+    ///   3. `[writable, signer]` Payer of the program config account rent
+    ///   4. `[]` System program id
+    SetProgramConfig(Box<SetProgramConfig>),
+
+    ///   Restrict deposits to the user transfer authorities with an allowlist
+    ///   entry, or open them to everyone again.  Swaps and withdrawals are
+    ///   never restricted.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[signer]` swap admin
+    SetDepositGate(SetDepositGate),
+
+    ///   Create or close the deposit allowlist entry of a user transfer
+    ///   authority.  The payer, chosen by the admin, must sign.  An entry
+    ///   closed in a transaction cannot be created again in the same one.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[signer]` swap admin
+    ///   2. `[writable]` Allowlist entry derived from
+    ///      `find_program_address(&["allowlist", Token-swap, user])`
+    ///   3. `[]` user transfer authority to allow
+    ///   4. `[writable, signer]` Payer of the entry rent, refunded on close
+    ///   5. `[]` System program id
+    SetAllowlistEntry(SetAllowlistEntry),
+
+    ///   Cap the token reserves and the pool token supply that deposits may
+    ///   reach.  Swaps and withdrawals are never restricted.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[signer]` swap admin
+    SetDepositCaps(SetDepositCaps),
+
+    ///   Set the circuit breaker band.  Swaps and single token withdrawals
+    ///   fail if they move the spot price further than the band from the
+    ///   spot price before the first of them in the same slot.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[signer]` swap admin
+    SetPriceBand(SetPriceBand),
+
+    ///   Set the oracle guard.  Swaps fail if the pool spot price after the
+    ///   swap deviates from the oracle price by more than the maximum
+    ///   deviation.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[signer]` swap admin
+    ///   2. `[]` Oracle price account, ignored if the guard is turned off
+    SetOracle(SetOracle),
+
+    ///   Send the tokens held by the swap token accounts above the accounted
+    ///   reserves and admin fees, such as tokens transferred directly to
+    ///   them, to the given token accounts.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[signer]` swap admin
+    ///   3. `[writable]` token_a Swap Account to skim FROM.
+    ///   4. `[writable]` token_b Swap Account to skim FROM.
+    ///   5. `[writable]` token_a Account to credit.
+    ///   6. `[writable]` token_b Account to credit.
+    ///   7. `[]` Token program id
+    Skim,
+
+    ///   Add the tokens held by the swap token accounts above the accounted
+    ///   reserves and admin fees to the reserves, donating them to the
+    ///   liquidity providers.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[signer]` swap admin
+    ///   2. `[]` token_a Swap Account
+    ///   3. `[]` token_b Swap Account
+    Sync,
+
+    ///   Transfer tokens out of a token account owned by the swap authority
+    ///   that is not one of the swap token accounts, the pool fee account or
+    ///   a pool token account, such as tokens sent to the swap authority by
+    ///   mistake.
+    ///
+    ///   0. `[]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[signer]` swap admin
+    ///   3. `[writable]` token Account owned by swap authority to transfer FROM.
+    ///   4. `[writable]` token Account to credit.
+    ///   5. `[]` Token program id
+    RescueTokens(RescueTokens),
+
+    ///   Close an empty pool, once the only pool tokens left are the locked
+    ///   minimum liquidity.  The locked pool tokens are burned, the tokens left
+    ///   in the swap token accounts are sent to the given token accounts, and
+    ///   the lamports of the locked pool token account, of the swap token
+    ///   accounts and of the swap to the recipient.  The admin fees must be
+    ///   withdrawn first.
+    ///
+    ///   0. `[writable]` Token-swap
+    ///   1. `[]` swap authority
+    ///   2. `[signer]` swap admin
+    ///   3. `[writable]` Pool MINT
+    ///   4. `[writable]` Pool Token Account holding the locked minimum
+    ///      liquidity, owned by swap authority.
+    ///   5. `[writable]` token_a Swap Account to close.
+    ///   6. `[writable]` token_b Swap Account to close.
+    ///   7. `[writable]` token_a Account to credit with the remaining tokens.
+    ///   8. `[writable]` token_b Account to credit with the remaining tokens.
+    ///   9. `[writable]` Recipient of the lamports
+    ///   10. `[]` Token program id
+    ClosePool,
+
+    ///   Move liquidity from a swap to another swap of the same token mints.
+    ///   The pool tokens of the source swap are burned, and the token A and B
+    ///   they are worth are deposited into the destination swap at its current
+    ///   ratio.  Tokens left over by the ratio are sent to the user token
+    ///   accounts.
+    ///
+    ///   0. `[writable]` Token-swap to withdraw from
+    ///   1. `[]` swap authority of the source swap
+    ///   2. `[]` user transfer authority
+    ///   3. `[writable]` Pool mint account of the source swap
+    ///   4. `[writable]` SOURCE Pool account, amount is transferable by user transfer authority.
+    ///   5. `[writable]` token_a Swap Account of the source swap to withdraw FROM.
+    ///   6. `[writable]` token_b Swap Account of the source swap to withdraw FROM.
+    ///   7. `[writable]` Fee account of the source swap, to receive withdrawal fees
+    ///   8. `[writable]` Token-swap to deposit into
+    ///   9. `[]` swap authority of the destination swap
+    ///   10. `[writable]` token_a Base Account of the destination swap to deposit into.
+    ///   11. `[writable]` token_b Base Account of the destination swap to deposit into.
+    ///   12. `[writable]` Pool MINT account of the destination swap.
+    ///   13. `[writable]` Pool Account to deposit the generated tokens, user is the owner.
+    ///   14. `[writable]` token_a user Account to credit with the left over tokens.
+    ///   15. `[writable]` token_b user Account to credit with the left over tokens.
+    ///   16. `[]` Token program id
+    ///   17. `[optional]` Allowlist entry of the user transfer authority,
+    ///       required if the destination swap has a deposit gate
+    MigrateLiquidity(MigrateLiquidity),
+
+    ///   Swap token A of a metapool for a token of its base swap.  Token A is
+    ///   swapped for base swap pool tokens, which are then withdrawn from the
+    ///   base swap as a single token type.  The cached virtual price of the
+    ///   base swap pool token is refreshed first.
+    ///
+    ///   0. `[writable]` Metapool
+    ///   1. `[]` swap authority of the metapool
+    ///   2. `[]` user transfer authority
+    ///   3. `[writable]` token_a SOURCE Account, amount is transferable by user transfer authority.
+    ///   4. `[writable]` token_a Swap Account of the metapool to swap INTO.
+    ///   5. `[writable]` token_b Swap Account of the metapool to swap FROM.
+    ///   6. `[writable]` base swap Pool Account to receive the swapped pool tokens,
+    ///      amount is transferable by user transfer authority.
+    ///   7. `[writable]` Pool token mint of the metapool, to generate trading fees
+    ///   8. `[writable]` Fee account of the metapool, to receive trading fees
+    ///   9. `[writable]` Base swap
+    ///   10. `[]` swap authority of the base swap
+    ///   11. `[writable]` Pool mint account of the base swap
+    ///   12. `[writable]` token_a Swap Account of the base swap to withdraw FROM.
+    ///   13. `[writable]` token_b Swap Account of the base swap to withdraw FROM.
+    ///   14. `[writable]` token (A or B) DESTINATION Account of the base swap.
+    ///   15. `[writable]` Fee account of the base swap, to receive withdrawal fees
+    ///   16. `[]` Token program id
+    ///   17. `[]` Token-swap program id
+    ///   18. `[optional]` Oracle account, required if the metapool has an oracle guard
+    SwapUnderlying(SwapUnderlying),
+
+    ///   Deposit the tokens of the base swap of a metapool and token A of the
+    ///   metapool.  The base swap tokens are deposited into the base swap, and
+    ///   the received base swap pool tokens and token A into the metapool.
+    ///   The cached virtual price of the base swap pool token is refreshed
+    ///   first.
+    ///
+    ///   0. `[writable]` Metapool
+    ///   1. `[]` swap authority of the metapool
+    ///   2. `[]` user transfer authority
+    ///   3. `[writable]` token_a user transfer authority can transfer amount,
+    ///   4. `[writable]` base swap Pool Account to receive the base swap pool tokens,
+    ///      amount is transferable by user transfer authority.
+    ///   5. `[writable]` token_a Base Account of the metapool to deposit into.
+    ///   6. `[writable]` token_b Base Account of the metapool to deposit into.
+    ///   7. `[writable]` Pool MINT account of the metapool.
+    ///   8. `[writable]` Pool Account to deposit the generated tokens, user is the owner.
+    ///   9. `[writable]` Base swap
+    ///   10. `[]` swap authority of the base swap
+    ///   11. `[writable]` token_a of the base swap, user transfer authority can transfer amount,
+    ///   12. `[writable]` token_b of the base swap, user transfer authority can transfer amount,
+    ///   13. `[writable]` token_a Base Account of the base swap to deposit into.
+    ///   14. `[writable]` token_b Base Account of the base swap to deposit into.
+    ///   15. `[writable]` Pool MINT account of the base swap.
+    ///   16. `[]` Token program id
+    ///   17. `[]` Token-swap program id
+    ///   18. `[optional]` Allowlist entry of the user transfer authority,
+    ///       required if the metapool has a deposit gate
+    ///   19. `[optional]` Allowlist entry of the user transfer authority,
+    ///       required if the base swap has a deposit gate
+    DepositUnderlying(DepositUnderlying),
 }
 
 impl SwapInstruction {
@@ -210,10 +639,188 @@ impl SwapInstruction {
                     maximum_pool_token_amount,
                 })
             }
+            5 => Self::WithdrawAdminFees,
+            6 => {
+                let mut weights = [0u16; MAX_FEE_RECIPIENTS];
+                let mut rest = rest;
+                for weight in weights.iter_mut() {
+                    let (value, remaining) = Self::unpack_u16(rest)?;
+                    *weight = value;
+                    rest = remaining;
+                }
+                Self::SetFeeRecipients(SetFeeRecipients { weights })
+            }
+            7 => Self::DistributeFees,
+            8 => {
+                let (pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (minimum_token_amount, _rest) = Self::unpack_u64(rest)?;
+                Self::WithdrawSingleTokenTypeExactAmountIn(WithdrawSingleTokenTypeExactAmountIn {
+                    pool_token_amount,
+                    minimum_token_amount,
+                })
+            }
+            9 => {
+                let (owner_key, rest) = Self::unpack_pubkey(rest)?;
+                let (valid_curve_types, rest) = Self::unpack_u32(rest)?;
+                let (min_amp, rest) = Self::unpack_u64(rest)?;
+                let (max_amp, rest) = Self::unpack_u64(rest)?;
+                let (fees, rest) = Self::unpack_fees(rest)?;
+                let (max_fees, rest) = Self::unpack_fees(rest)?;
+                let mut rest = rest;
+                let mut allowed_mints = [Pubkey::default(); MAX_CONFIG_MINTS];
+                for mint in allowed_mints.iter_mut() {
+                    let (key, remaining) = Self::unpack_pubkey(rest)?;
+                    *mint = key;
+                    rest = remaining;
+                }
+                let mut denied_mints = [Pubkey::default(); MAX_CONFIG_MINTS];
+                for mint in denied_mints.iter_mut() {
+                    let (key, remaining) = Self::unpack_pubkey(rest)?;
+                    *mint = key;
+                    rest = remaining;
+                }
+                Self::SetProgramConfig(Box::new(SetProgramConfig {
+                    owner_key,
+                    valid_curve_types,
+                    min_amp,
+                    max_amp,
+                    fees,
+                    max_fees,
+                    allowed_mints,
+                    denied_mints,
+                }))
+            }
+            10 => {
+                let (enabled, _rest) = Self::unpack_bool(rest)?;
+                Self::SetDepositGate(SetDepositGate { enabled })
+            }
+            11 => {
+                let (allowed, _rest) = Self::unpack_bool(rest)?;
+                Self::SetAllowlistEntry(SetAllowlistEntry { allowed })
+            }
+            12 => {
+                let (max_token_a_amount, rest) = Self::unpack_u64(rest)?;
+                let (max_token_b_amount, rest) = Self::unpack_u64(rest)?;
+                let (max_pool_supply, _rest) = Self::unpack_u64(rest)?;
+                Self::SetDepositCaps(SetDepositCaps {
+                    max_token_a_amount,
+                    max_token_b_amount,
+                    max_pool_supply,
+                })
+            }
+            13 => {
+                let (price_band, _rest) = Self::unpack_u16(rest)?;
+                Self::SetPriceBand(SetPriceBand { price_band })
+            }
+            14 => {
+                let (&oracle_type, rest) =
+                    rest.split_first().ok_or(SwapError::InvalidInstruction)?;
+                let oracle_type =
+                    OracleType::try_from(oracle_type).map_err(|_| SwapError::InvalidInstruction)?;
+                let (max_deviation, _rest) = Self::unpack_u16(rest)?;
+                Self::SetOracle(SetOracle {
+                    oracle_type,
+                    max_deviation,
+                })
+            }
+            15 => Self::Skim,
+            16 => Self::Sync,
+            17 => {
+                let (amount, _rest) = Self::unpack_u64(rest)?;
+                Self::RescueTokens(RescueTokens { amount })
+            }
+            18 => Self::ClosePool,
+            19 => {
+                let (pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (minimum_pool_token_amount, _rest) = Self::unpack_u64(rest)?;
+                Self::MigrateLiquidity(MigrateLiquidity {
+                    pool_token_amount,
+                    minimum_pool_token_amount,
+                })
+            }
+            20 => {
+                let (amount_in, rest) = Self::unpack_u64(rest)?;
+                let (minimum_amount_out, _rest) = Self::unpack_u64(rest)?;
+                Self::SwapUnderlying(SwapUnderlying {
+                    amount_in,
+                    minimum_amount_out,
+                })
+            }
+            21 => {
+                let (base_pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (pool_token_amount, rest) = Self::unpack_u64(rest)?;
+                let (maximum_token_a_amount, rest) = Self::unpack_u64(rest)?;
+                let (maximum_base_token_a_amount, rest) = Self::unpack_u64(rest)?;
+                let (maximum_base_token_b_amount, _rest) = Self::unpack_u64(rest)?;
+                Self::DepositUnderlying(DepositUnderlying {
+                    base_pool_token_amount,
+                    pool_token_amount,
+                    maximum_token_a_amount,
+                    maximum_base_token_a_amount,
+                    maximum_base_token_b_amount,
+                })
+            }
             _ => return Err(SwapError::InvalidInstruction.into()),
         })
     }
 
+    fn unpack_bool(input: &[u8]) -> Result<(bool, &[u8]), ProgramError> {
+        let (&value, rest) = input.split_first().ok_or(SwapError::InvalidInstruction)?;
+        match value {
+            0 => Ok((false, rest)),
+            1 => Ok((true, rest)),
+            _ => Err(SwapError::InvalidInstruction.into()),
+        }
+    }
+
+    fn unpack_u16(input: &[u8]) -> Result<(u16, &[u8]), ProgramError> {
+        if input.len() >= 2 {
+            let (amount, rest) = input.split_at(2);
+            let amount = amount
+                .get(..2)
+                .and_then(|slice| slice.try_into().ok())
+                .map(u16::from_le_bytes)
+                .ok_or(SwapError::InvalidInstruction)?;
+            Ok((amount, rest))
+        } else {
+            Err(SwapError::InvalidInstruction.into())
+        }
+    }
+
+    fn unpack_u32(input: &[u8]) -> Result<(u32, &[u8]), ProgramError> {
+        if input.len() >= 4 {
+            let (amount, rest) = input.split_at(4);
+            let amount = amount
+                .get(..4)
+                .and_then(|slice| slice.try_into().ok())
+                .map(u32::from_le_bytes)
+                .ok_or(SwapError::InvalidInstruction)?;
+            Ok((amount, rest))
+        } else {
+            Err(SwapError::InvalidInstruction.into())
+        }
+    }
+
+    fn unpack_pubkey(input: &[u8]) -> Result<(Pubkey, &[u8]), ProgramError> {
+        if input.len() >= 32 {
+            let (key, rest) = input.split_at(32);
+            let key = Pubkey::new(key);
+            Ok((key, rest))
+        } else {
+            Err(SwapError::InvalidInstruction.into())
+        }
+    }
+
+    fn unpack_fees(input: &[u8]) -> Result<(Fees, &[u8]), ProgramError> {
+        if input.len() >= Fees::LEN {
+            let (fees, rest) = input.split_at(Fees::LEN);
+            let fees = Fees::unpack_unchecked(fees)?;
+            Ok((fees, rest))
+        } else {
+            Err(SwapError::InvalidInstruction.into())
+        }
+    }
+
     fn unpack_u64(input: &[u8]) -> Result<(u64, &[u8]), ProgramError> {
         if input.len() >= 8 {
             let (amount, rest) = input.split_at(8);
@@ -279,6 +886,124 @@ impl SwapInstruction {
                 buf.extend_from_slice(&destination_token_amount.to_le_bytes());
                 buf.extend_from_slice(&maximum_pool_token_amount.to_le_bytes());
             }
+            Self::WithdrawAdminFees => {
+                buf.push(5);
+            }
+            Self::SetFeeRecipients(SetFeeRecipients { weights }) => {
+                buf.push(6);
+                for weight in weights.iter() {
+                    buf.extend_from_slice(&weight.to_le_bytes());
+                }
+            }
+            Self::DistributeFees => {
+                buf.push(7);
+            }
+            Self::WithdrawSingleTokenTypeExactAmountIn(WithdrawSingleTokenTypeExactAmountIn {
+                pool_token_amount,
+                minimum_token_amount,
+            }) => {
+                buf.push(8);
+                buf.extend_from_slice(&pool_token_amount.to_le_bytes());
+                buf.extend_from_slice(&minimum_token_amount.to_le_bytes());
+            }
+            Self::SetProgramConfig(instruction) => {
+                let SetProgramConfig {
+                    owner_key,
+                    valid_curve_types,
+                    min_amp,
+                    max_amp,
+                    fees,
+                    max_fees,
+                    allowed_mints,
+                    denied_mints,
+                } = &**instruction;
+                buf.push(9);
+                buf.extend_from_slice(owner_key.as_ref());
+                buf.extend_from_slice(&valid_curve_types.to_le_bytes());
+                buf.extend_from_slice(&min_amp.to_le_bytes());
+                buf.extend_from_slice(&max_amp.to_le_bytes());
+                let mut fees_slice = [0u8; Fees::LEN];
+                Pack::pack_into_slice(fees, &mut fees_slice[..]);
+                buf.extend_from_slice(&fees_slice);
+                Pack::pack_into_slice(max_fees, &mut fees_slice[..]);
+                buf.extend_from_slice(&fees_slice);
+                for mint in allowed_mints.iter().chain(denied_mints.iter()) {
+                    buf.extend_from_slice(mint.as_ref());
+                }
+            }
+            Self::SetDepositGate(SetDepositGate { enabled }) => {
+                buf.push(10);
+                buf.push(*enabled as u8);
+            }
+            Self::SetAllowlistEntry(SetAllowlistEntry { allowed }) => {
+                buf.push(11);
+                buf.push(*allowed as u8);
+            }
+            Self::SetDepositCaps(SetDepositCaps {
+                max_token_a_amount,
+                max_token_b_amount,
+                max_pool_supply,
+            }) => {
+                buf.push(12);
+                buf.extend_from_slice(&max_token_a_amount.to_le_bytes());
+                buf.extend_from_slice(&max_token_b_amount.to_le_bytes());
+                buf.extend_from_slice(&max_pool_supply.to_le_bytes());
+            }
+            Self::SetPriceBand(SetPriceBand { price_band }) => {
+                buf.push(13);
+                buf.extend_from_slice(&price_band.to_le_bytes());
+            }
+            Self::SetOracle(SetOracle {
+                oracle_type,
+                max_deviation,
+            }) => {
+                buf.push(14);
+                buf.push(*oracle_type as u8);
+                buf.extend_from_slice(&max_deviation.to_le_bytes());
+            }
+            Self::Skim => {
+                buf.push(15);
+            }
+            Self::Sync => {
+                buf.push(16);
+            }
+            Self::RescueTokens(RescueTokens { amount }) => {
+                buf.push(17);
+                buf.extend_from_slice(&amount.to_le_bytes());
+            }
+            Self::ClosePool => {
+                buf.push(18);
+            }
+            Self::MigrateLiquidity(MigrateLiquidity {
+                pool_token_amount,
+                minimum_pool_token_amount,
+            }) => {
+                buf.push(19);
+                buf.extend_from_slice(&pool_token_amount.to_le_bytes());
+                buf.extend_from_slice(&minimum_pool_token_amount.to_le_bytes());
+            }
+            Self::SwapUnderlying(SwapUnderlying {
+                amount_in,
+                minimum_amount_out,
+            }) => {
+                buf.push(20);
+                buf.extend_from_slice(&amount_in.to_le_bytes());
+                buf.extend_from_slice(&minimum_amount_out.to_le_bytes());
+            }
+            Self::DepositUnderlying(DepositUnderlying {
+                base_pool_token_amount,
+                pool_token_amount,
+                maximum_token_a_amount,
+                maximum_base_token_a_amount,
+                maximum_base_token_b_amount,
+            }) => {
+                buf.push(21);
+                buf.extend_from_slice(&base_pool_token_amount.to_le_bytes());
+                buf.extend_from_slice(&pool_token_amount.to_le_bytes());
+                buf.extend_from_slice(&maximum_token_a_amount.to_le_bytes());
+                buf.extend_from_slice(&maximum_base_token_a_amount.to_le_bytes());
+                buf.extend_from_slice(&maximum_base_token_b_amount.to_le_bytes());
+            }
         }
         buf
     }
@@ -295,13 +1020,17 @@ pub fn initialize(
     pool_pubkey: &Pubkey,
     fee_pubkey: &Pubkey,
     destination_pubkey: &Pubkey,
+    locked_pubkey: &Pubkey,
+    config_pubkey: &Pubkey,
+    admin_fee_pubkeys: Option<(&Pubkey, &Pubkey)>,
+    base_swap_pubkeys: Option<(&Pubkey, &Pubkey)>,
     fees: Fees,
     swap_curve: SwapCurve,
 ) -> Result<Instruction, ProgramError> {
     let init_data = SwapInstruction::Initialize(Initialize { fees, swap_curve });
     let data = init_data.pack();
 
-    let accounts = vec![
+    let mut accounts = vec![
         AccountMeta::new(*swap_pubkey, true),
         AccountMeta::new_readonly(*authority_pubkey, false),
         AccountMeta::new_readonly(*token_a_pubkey, false),
@@ -309,8 +1038,18 @@ pub fn initialize(
         AccountMeta::new(*pool_pubkey, false),
         AccountMeta::new_readonly(*fee_pubkey, false),
         AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*locked_pubkey, false),
         AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*config_pubkey, false),
     ];
+    if let Some((admin_fee_a_pubkey, admin_fee_b_pubkey)) = admin_fee_pubkeys {
+        accounts.push(AccountMeta::new_readonly(*admin_fee_a_pubkey, false));
+        accounts.push(AccountMeta::new_readonly(*admin_fee_b_pubkey, false));
+    }
+    if let Some((base_swap_pubkey, base_pool_mint_pubkey)) = base_swap_pubkeys {
+        accounts.push(AccountMeta::new_readonly(*base_swap_pubkey, false));
+        accounts.push(AccountMeta::new_readonly(*base_pool_mint_pubkey, false));
+    }
 
     Ok(Instruction {
         program_id: *program_id,
@@ -332,12 +1071,13 @@ pub fn deposit_all_token_types(
     swap_token_b_pubkey: &Pubkey,
     pool_mint_pubkey: &Pubkey,
     destination_pubkey: &Pubkey,
+    allowlist_entry_pubkey: Option<&Pubkey>,
     instruction: DepositAllTokenTypes,
 ) -> Result<Instruction, ProgramError> {
     let data = SwapInstruction::DepositAllTokenTypes(instruction).pack();
 
-    let accounts = vec![
-        AccountMeta::new_readonly(*swap_pubkey, false),
+    let mut accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
         AccountMeta::new_readonly(*authority_pubkey, false),
         AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
         AccountMeta::new(*deposit_token_a_pubkey, false),
@@ -348,6 +1088,9 @@ pub fn deposit_all_token_types(
         AccountMeta::new(*destination_pubkey, false),
         AccountMeta::new_readonly(*token_program_id, false),
     ];
+    if let Some(allowlist_entry_pubkey) = allowlist_entry_pubkey {
+        accounts.push(AccountMeta::new_readonly(*allowlist_entry_pubkey, false));
+    }
 
     Ok(Instruction {
         program_id: *program_id,
@@ -375,7 +1118,7 @@ pub fn withdraw_all_token_types(
     let data = SwapInstruction::WithdrawAllTokenTypes(instruction).pack();
 
     let accounts = vec![
-        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new(*swap_pubkey, false),
         AccountMeta::new_readonly(*authority_pubkey, false),
         AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
         AccountMeta::new(*pool_mint_pubkey, false),
@@ -413,7 +1156,7 @@ pub fn withdraw_single_token_type_exact_amount_out(
     let data = SwapInstruction::WithdrawSingleTokenTypeExactAmountOut(instruction).pack();
 
     let accounts = vec![
-        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new(*swap_pubkey, false),
         AccountMeta::new_readonly(*authority_pubkey, false),
         AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
         AccountMeta::new(*pool_mint_pubkey, false),
@@ -432,38 +1175,610 @@ pub fn withdraw_single_token_type_exact_amount_out(
     })
 }
 
-/// Creates a 'swap' instruction.
-pub fn swap(
+/// Creates a 'withdraw_single_token_type_exact_amount_in' instruction.
+pub fn withdraw_single_token_type_exact_amount_in(
     program_id: &Pubkey,
     token_program_id: &Pubkey,
     swap_pubkey: &Pubkey,
     authority_pubkey: &Pubkey,
     user_transfer_authority_pubkey: &Pubkey,
-    source_pubkey: &Pubkey,
-    swap_source_pubkey: &Pubkey,
-    swap_destination_pubkey: &Pubkey,
-    destination_pubkey: &Pubkey,
     pool_mint_pubkey: &Pubkey,
-    pool_fee_pubkey: &Pubkey,
-    host_fee_pubkey: Option<&Pubkey>,
+    fee_account_pubkey: &Pubkey,
+    pool_token_source_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    instruction: WithdrawSingleTokenTypeExactAmountIn,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::WithdrawSingleTokenTypeExactAmountIn(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*pool_token_source_pubkey, false),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*fee_account_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'swap' instruction.
+pub fn swap(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    swap_source_pubkey: &Pubkey,
+    swap_destination_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    pool_fee_pubkey: &Pubkey,
+    base_swap_pubkeys: Option<(&Pubkey, &Pubkey)>,
+    host_fee_pubkey: Option<&Pubkey>,
+    oracle_pubkey: Option<&Pubkey>,
     instruction: Swap,
 ) -> Result<Instruction, ProgramError> {
-    let data = SwapInstruction::Swap(instruction).pack();
+    let data = SwapInstruction::Swap(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*swap_source_pubkey, false),
+        AccountMeta::new(*swap_destination_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*pool_fee_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+    if let Some((base_swap_pubkey, base_pool_mint_pubkey)) = base_swap_pubkeys {
+        accounts.push(AccountMeta::new_readonly(*base_swap_pubkey, false));
+        accounts.push(AccountMeta::new_readonly(*base_pool_mint_pubkey, false));
+    }
+    if let Some(host_fee_pubkey) = host_fee_pubkey {
+        accounts.push(AccountMeta::new(*host_fee_pubkey, false));
+    }
+    if let Some(oracle_pubkey) = oracle_pubkey {
+        accounts.push(AccountMeta::new_readonly(*oracle_pubkey, false));
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'withdraw_admin_fees' instruction.
+pub fn withdraw_admin_fees(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    admin_fee_a_pubkey: &Pubkey,
+    admin_fee_b_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::WithdrawAdminFees.pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*admin_fee_a_pubkey, false),
+        AccountMeta::new(*admin_fee_b_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_fee_recipients' instruction.
+pub fn set_fee_recipients(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    pool_fee_pubkey: &Pubkey,
+    recipient_pubkeys: &[&Pubkey],
+    instruction: SetFeeRecipients,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::SetFeeRecipients(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new_readonly(*pool_fee_pubkey, false),
+    ];
+    for recipient_pubkey in recipient_pubkeys {
+        accounts.push(AccountMeta::new_readonly(**recipient_pubkey, false));
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'distribute_fees' instruction.
+pub fn distribute_fees(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    pool_fee_pubkey: &Pubkey,
+    recipient_pubkeys: &[&Pubkey],
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::DistributeFees.pack();
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new(*pool_fee_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+    for recipient_pubkey in recipient_pubkeys {
+        accounts.push(AccountMeta::new(**recipient_pubkey, false));
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_program_config' instruction.
+pub fn set_program_config(
+    program_id: &Pubkey,
+    config_pubkey: &Pubkey,
+    upgrade_authority_pubkey: &Pubkey,
+    program_data_pubkey: &Pubkey,
+    payer_pubkey: &Pubkey,
+    instruction: SetProgramConfig,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::SetProgramConfig(Box::new(instruction)).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*config_pubkey, false),
+        AccountMeta::new_readonly(*upgrade_authority_pubkey, true),
+        AccountMeta::new_readonly(*program_data_pubkey, false),
+        AccountMeta::new(*payer_pubkey, true),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_deposit_gate' instruction.
+pub fn set_deposit_gate(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    instruction: SetDepositGate,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::SetDepositGate(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_allowlist_entry' instruction.
+pub fn set_allowlist_entry(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    allowlist_entry_pubkey: &Pubkey,
+    user_pubkey: &Pubkey,
+    payer_pubkey: &Pubkey,
+    instruction: SetAllowlistEntry,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::SetAllowlistEntry(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new(*allowlist_entry_pubkey, false),
+        AccountMeta::new_readonly(*user_pubkey, false),
+        AccountMeta::new(*payer_pubkey, true),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_deposit_caps' instruction.
+pub fn set_deposit_caps(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    instruction: SetDepositCaps,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::SetDepositCaps(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_price_band' instruction.
+pub fn set_price_band(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    instruction: SetPriceBand,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::SetPriceBand(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'set_oracle' instruction.
+pub fn set_oracle(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    oracle_pubkey: &Pubkey,
+    instruction: SetOracle,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::SetOracle(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new_readonly(*oracle_pubkey, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'skim' instruction.
+pub fn skim(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    destination_a_pubkey: &Pubkey,
+    destination_b_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::Skim.pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*destination_a_pubkey, false),
+        AccountMeta::new(*destination_b_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'sync' instruction.
+pub fn sync(
+    program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::Sync.pack();
+
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new_readonly(*swap_token_a_pubkey, false),
+        AccountMeta::new_readonly(*swap_token_b_pubkey, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'rescue_tokens' instruction.
+pub fn rescue_tokens(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    instruction: RescueTokens,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::RescueTokens(instruction).pack();
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'close_pool' instruction.
+pub fn close_pool(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    admin_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    pool_locked_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    destination_a_pubkey: &Pubkey,
+    destination_b_pubkey: &Pubkey,
+    recipient_pubkey: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::ClosePool.pack();
 
-    let mut accounts = vec![
-        AccountMeta::new_readonly(*swap_pubkey, false),
+    let accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
         AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*admin_pubkey, true),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*pool_locked_pubkey, false),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*destination_a_pubkey, false),
+        AccountMeta::new(*destination_b_pubkey, false),
+        AccountMeta::new(*recipient_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'migrate_liquidity' instruction.
+pub fn migrate_liquidity(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    source_swap_pubkey: &Pubkey,
+    source_authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    source_pool_mint_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    source_token_a_pubkey: &Pubkey,
+    source_token_b_pubkey: &Pubkey,
+    source_pool_fee_pubkey: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    user_token_a_pubkey: &Pubkey,
+    user_token_b_pubkey: &Pubkey,
+    allowlist_entry_pubkey: Option<&Pubkey>,
+    instruction: MigrateLiquidity,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::MigrateLiquidity(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*source_swap_pubkey, false),
+        AccountMeta::new_readonly(*source_authority_pubkey, false),
         AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*source_pool_mint_pubkey, false),
         AccountMeta::new(*source_pubkey, false),
-        AccountMeta::new(*swap_source_pubkey, false),
-        AccountMeta::new(*swap_destination_pubkey, false),
+        AccountMeta::new(*source_token_a_pubkey, false),
+        AccountMeta::new(*source_token_b_pubkey, false),
+        AccountMeta::new(*source_pool_fee_pubkey, false),
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*pool_mint_pubkey, false),
         AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*user_token_a_pubkey, false),
+        AccountMeta::new(*user_token_b_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+    ];
+    if let Some(allowlist_entry_pubkey) = allowlist_entry_pubkey {
+        accounts.push(AccountMeta::new_readonly(*allowlist_entry_pubkey, false));
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'swap_underlying' instruction.
+pub fn swap_underlying(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    source_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    base_pool_token_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    pool_fee_pubkey: &Pubkey,
+    base_swap_pubkey: &Pubkey,
+    base_authority_pubkey: &Pubkey,
+    base_pool_mint_pubkey: &Pubkey,
+    base_swap_token_a_pubkey: &Pubkey,
+    base_swap_token_b_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    base_pool_fee_pubkey: &Pubkey,
+    oracle_pubkey: Option<&Pubkey>,
+    instruction: SwapUnderlying,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::SwapUnderlying(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*source_pubkey, false),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*base_pool_token_pubkey, false),
         AccountMeta::new(*pool_mint_pubkey, false),
         AccountMeta::new(*pool_fee_pubkey, false),
+        AccountMeta::new(*base_swap_pubkey, false),
+        AccountMeta::new_readonly(*base_authority_pubkey, false),
+        AccountMeta::new(*base_pool_mint_pubkey, false),
+        AccountMeta::new(*base_swap_token_a_pubkey, false),
+        AccountMeta::new(*base_swap_token_b_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*base_pool_fee_pubkey, false),
         AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*program_id, false),
     ];
-    if let Some(host_fee_pubkey) = host_fee_pubkey {
-        accounts.push(AccountMeta::new(*host_fee_pubkey, false));
+    if let Some(oracle_pubkey) = oracle_pubkey {
+        accounts.push(AccountMeta::new_readonly(*oracle_pubkey, false));
+    }
+
+    Ok(Instruction {
+        program_id: *program_id,
+        accounts,
+        data,
+    })
+}
+
+/// Creates a 'deposit_underlying' instruction.
+pub fn deposit_underlying(
+    program_id: &Pubkey,
+    token_program_id: &Pubkey,
+    swap_pubkey: &Pubkey,
+    authority_pubkey: &Pubkey,
+    user_transfer_authority_pubkey: &Pubkey,
+    deposit_token_a_pubkey: &Pubkey,
+    base_pool_token_pubkey: &Pubkey,
+    swap_token_a_pubkey: &Pubkey,
+    swap_token_b_pubkey: &Pubkey,
+    pool_mint_pubkey: &Pubkey,
+    destination_pubkey: &Pubkey,
+    base_swap_pubkey: &Pubkey,
+    base_authority_pubkey: &Pubkey,
+    deposit_base_token_a_pubkey: &Pubkey,
+    deposit_base_token_b_pubkey: &Pubkey,
+    base_swap_token_a_pubkey: &Pubkey,
+    base_swap_token_b_pubkey: &Pubkey,
+    base_pool_mint_pubkey: &Pubkey,
+    allowlist_entry_pubkey: Option<&Pubkey>,
+    base_allowlist_entry_pubkey: Option<&Pubkey>,
+    instruction: DepositUnderlying,
+) -> Result<Instruction, ProgramError> {
+    let data = SwapInstruction::DepositUnderlying(instruction).pack();
+
+    let mut accounts = vec![
+        AccountMeta::new(*swap_pubkey, false),
+        AccountMeta::new_readonly(*authority_pubkey, false),
+        AccountMeta::new_readonly(*user_transfer_authority_pubkey, true),
+        AccountMeta::new(*deposit_token_a_pubkey, false),
+        AccountMeta::new(*base_pool_token_pubkey, false),
+        AccountMeta::new(*swap_token_a_pubkey, false),
+        AccountMeta::new(*swap_token_b_pubkey, false),
+        AccountMeta::new(*pool_mint_pubkey, false),
+        AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new(*base_swap_pubkey, false),
+        AccountMeta::new_readonly(*base_authority_pubkey, false),
+        AccountMeta::new(*deposit_base_token_a_pubkey, false),
+        AccountMeta::new(*deposit_base_token_b_pubkey, false),
+        AccountMeta::new(*base_swap_token_a_pubkey, false),
+        AccountMeta::new(*base_swap_token_b_pubkey, false),
+        AccountMeta::new(*base_pool_mint_pubkey, false),
+        AccountMeta::new_readonly(*token_program_id, false),
+        AccountMeta::new_readonly(*program_id, false),
+    ];
+    if let Some(allowlist_entry_pubkey) = allowlist_entry_pubkey {
+        accounts.push(AccountMeta::new_readonly(*allowlist_entry_pubkey, false));
+    }
+    if let Some(base_allowlist_entry_pubkey) = base_allowlist_entry_pubkey {
+        accounts.push(AccountMeta::new_readonly(
+            *base_allowlist_entry_pubkey,
+            false,
+        ));
     }
 
     Ok(Instruction {
@@ -500,6 +1815,8 @@ mod tests {
         let owner_withdraw_fee_denominator: u64 = 3;
         let host_fee_numerator: u64 = 5;
         let host_fee_denominator: u64 = 20;
+        let offpeg_fee_multiplier_numerator: u64 = 2;
+        let offpeg_fee_multiplier_denominator: u64 = 1;
         let fees = Fees {
             trade_fee_numerator,
             trade_fee_denominator,
@@ -509,6 +1826,8 @@ mod tests {
             owner_withdraw_fee_denominator,
             host_fee_numerator,
             host_fee_denominator,
+            offpeg_fee_multiplier_numerator,
+            offpeg_fee_multiplier_denominator,
         };
         let amp: u64 = 1;
         let curve_type = CurveType::Stable;
@@ -528,6 +1847,8 @@ mod tests {
         expect.extend_from_slice(&owner_withdraw_fee_denominator.to_le_bytes());
         expect.extend_from_slice(&host_fee_numerator.to_le_bytes());
         expect.extend_from_slice(&host_fee_denominator.to_le_bytes());
+        expect.extend_from_slice(&offpeg_fee_multiplier_numerator.to_le_bytes());
+        expect.extend_from_slice(&offpeg_fee_multiplier_denominator.to_le_bytes());
         expect.push(curve_type as u8);
         expect.extend_from_slice(&amp.to_le_bytes());
         expect.extend_from_slice(&[0u8; 24]);
@@ -611,4 +1932,287 @@ mod tests {
         let unpacked = SwapInstruction::unpack(&expect).unwrap();
         assert_eq!(unpacked, check);
     }
+
+    #[test]
+    fn pack_withdraw_one_exact_in() {
+        let pool_token_amount: u64 = 1212438012089;
+        let minimum_token_amount: u64 = 102198761982612;
+        let check = SwapInstruction::WithdrawSingleTokenTypeExactAmountIn(
+            WithdrawSingleTokenTypeExactAmountIn {
+                pool_token_amount,
+                minimum_token_amount,
+            },
+        );
+        let packed = check.pack();
+        let mut expect = vec![8];
+        expect.extend_from_slice(&pool_token_amount.to_le_bytes());
+        expect.extend_from_slice(&minimum_token_amount.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_withdraw_admin_fees() {
+        let check = SwapInstruction::WithdrawAdminFees;
+        let packed = check.pack();
+        let expect = vec![5];
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_set_fee_recipients() {
+        let weights = [6_000u16, 3_000, 1_000, 0];
+        let check = SwapInstruction::SetFeeRecipients(SetFeeRecipients { weights });
+        let packed = check.pack();
+        let mut expect = vec![6];
+        for weight in weights.iter() {
+            expect.extend_from_slice(&weight.to_le_bytes());
+        }
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_distribute_fees() {
+        let check = SwapInstruction::DistributeFees;
+        let packed = check.pack();
+        let expect = vec![7];
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_set_program_config() {
+        let owner_key = Pubkey::new_from_array([2u8; 32]);
+        let valid_curve_types: u32 = 1;
+        let min_amp: u64 = 10;
+        let max_amp: u64 = 5_000;
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 10000,
+            owner_trade_fee_numerator: 2,
+            owner_trade_fee_denominator: 10000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+            host_fee_numerator: 20,
+            host_fee_denominator: 100,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
+        };
+        let max_fees = Fees {
+            trade_fee_numerator: 100,
+            owner_trade_fee_numerator: 200,
+            ..fees.clone()
+        };
+        let mut allowed_mints = [Pubkey::default(); MAX_CONFIG_MINTS];
+        allowed_mints[0] = Pubkey::new_from_array([3u8; 32]);
+        let mut denied_mints = [Pubkey::default(); MAX_CONFIG_MINTS];
+        denied_mints[0] = Pubkey::new_from_array([4u8; 32]);
+        let check = SwapInstruction::SetProgramConfig(Box::new(SetProgramConfig {
+            owner_key,
+            valid_curve_types,
+            min_amp,
+            max_amp,
+            fees: fees.clone(),
+            max_fees: max_fees.clone(),
+            allowed_mints,
+            denied_mints,
+        }));
+        let packed = check.pack();
+        let mut expect = vec![9];
+        expect.extend_from_slice(owner_key.as_ref());
+        expect.extend_from_slice(&valid_curve_types.to_le_bytes());
+        expect.extend_from_slice(&min_amp.to_le_bytes());
+        expect.extend_from_slice(&max_amp.to_le_bytes());
+        let mut fees_slice = [0u8; Fees::LEN];
+        fees.pack_into_slice(&mut fees_slice);
+        expect.extend_from_slice(&fees_slice);
+        max_fees.pack_into_slice(&mut fees_slice);
+        expect.extend_from_slice(&fees_slice);
+        for mint in allowed_mints.iter().chain(denied_mints.iter()) {
+            expect.extend_from_slice(mint.as_ref());
+        }
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_set_deposit_gate() {
+        let check = SwapInstruction::SetDepositGate(SetDepositGate { enabled: true });
+        let packed = check.pack();
+        let expect = vec![10, 1];
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+        assert_eq!(
+            SwapInstruction::unpack(&[10, 2]),
+            Err(SwapError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn pack_set_allowlist_entry() {
+        let check = SwapInstruction::SetAllowlistEntry(SetAllowlistEntry { allowed: false });
+        let packed = check.pack();
+        let expect = vec![11, 0];
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_set_price_band() {
+        let price_band: u16 = 500;
+        let check = SwapInstruction::SetPriceBand(SetPriceBand { price_band });
+        let packed = check.pack();
+        let mut expect = vec![13];
+        expect.extend_from_slice(&price_band.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_set_oracle() {
+        let max_deviation: u16 = 50;
+        let check = SwapInstruction::SetOracle(SetOracle {
+            oracle_type: OracleType::PriceConf,
+            max_deviation,
+        });
+        let packed = check.pack();
+        let mut expect = vec![14, OracleType::PriceConf as u8];
+        expect.extend_from_slice(&max_deviation.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+        assert_eq!(
+            SwapInstruction::unpack(&[14, 1, 50, 0]),
+            Err(SwapError::InvalidInstruction.into())
+        );
+    }
+
+    #[test]
+    fn pack_skim_and_sync() {
+        let check = SwapInstruction::Skim;
+        let packed = check.pack();
+        let expect = vec![15];
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+
+        let check = SwapInstruction::Sync;
+        let packed = check.pack();
+        let expect = vec![16];
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_rescue_tokens() {
+        let amount: u64 = 1_234;
+        let check = SwapInstruction::RescueTokens(RescueTokens { amount });
+        let packed = check.pack();
+        let mut expect = vec![17];
+        expect.extend_from_slice(&amount.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_close_pool() {
+        let check = SwapInstruction::ClosePool;
+        let packed = check.pack();
+        let expect = vec![18];
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_migrate_liquidity() {
+        let pool_token_amount: u64 = 1_000;
+        let minimum_pool_token_amount: u64 = 990;
+        let check = SwapInstruction::MigrateLiquidity(MigrateLiquidity {
+            pool_token_amount,
+            minimum_pool_token_amount,
+        });
+        let packed = check.pack();
+        let mut expect = vec![19];
+        expect.extend_from_slice(&pool_token_amount.to_le_bytes());
+        expect.extend_from_slice(&minimum_pool_token_amount.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_swap_underlying() {
+        let amount_in: u64 = 1_000;
+        let minimum_amount_out: u64 = 990;
+        let check = SwapInstruction::SwapUnderlying(SwapUnderlying {
+            amount_in,
+            minimum_amount_out,
+        });
+        let packed = check.pack();
+        let mut expect = vec![20];
+        expect.extend_from_slice(&amount_in.to_le_bytes());
+        expect.extend_from_slice(&minimum_amount_out.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_deposit_underlying() {
+        let base_pool_token_amount: u64 = 2_000;
+        let pool_token_amount: u64 = 1_000;
+        let maximum_token_a_amount: u64 = 500;
+        let maximum_base_token_a_amount: u64 = 1_100;
+        let maximum_base_token_b_amount: u64 = 1_200;
+        let check = SwapInstruction::DepositUnderlying(DepositUnderlying {
+            base_pool_token_amount,
+            pool_token_amount,
+            maximum_token_a_amount,
+            maximum_base_token_a_amount,
+            maximum_base_token_b_amount,
+        });
+        let packed = check.pack();
+        let mut expect = vec![21];
+        expect.extend_from_slice(&base_pool_token_amount.to_le_bytes());
+        expect.extend_from_slice(&pool_token_amount.to_le_bytes());
+        expect.extend_from_slice(&maximum_token_a_amount.to_le_bytes());
+        expect.extend_from_slice(&maximum_base_token_a_amount.to_le_bytes());
+        expect.extend_from_slice(&maximum_base_token_b_amount.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
+
+    #[test]
+    fn pack_set_deposit_caps() {
+        let max_token_a_amount: u64 = 1_000_000;
+        let max_token_b_amount: u64 = 2_000_000;
+        let max_pool_supply: u64 = 0;
+        let check = SwapInstruction::SetDepositCaps(SetDepositCaps {
+            max_token_a_amount,
+            max_token_b_amount,
+            max_pool_supply,
+        });
+        let packed = check.pack();
+        let mut expect = vec![12];
+        expect.extend_from_slice(&max_token_a_amount.to_le_bytes());
+        expect.extend_from_slice(&max_token_b_amount.to_le_bytes());
+        expect.extend_from_slice(&max_pool_supply.to_le_bytes());
+        assert_eq!(packed, expect);
+        let unpacked = SwapInstruction::unpack(&expect).unwrap();
+        assert_eq!(unpacked, check);
+    }
 }
@@ -4,6 +4,7 @@ pub mod constraints;
 pub mod swap;
 pub mod error;
 pub mod instruction;
+pub mod oracle;
 pub mod processor;
 pub mod state;
 
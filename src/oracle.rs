@@ -0,0 +1,216 @@
+//! Reference prices read from oracle price accounts, used to guard swaps
+//! against a depeg of the pool price
+
+use crate::{error::SwapError, swap::calculator::SPOT_PRICE_PRECISION};
+use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
+use solana_program::{
+    program_error::ProgramError,
+    program_pack::{IsInitialized, Pack, Sealed},
+};
+use std::convert::TryFrom;
+
+#[cfg(feature = "fuzz")]
+use arbitrary::Arbitrary;
+
+/// Layouts of the oracle price accounts supported by the token-swap program.
+#[cfg_attr(feature = "fuzz", derive(Arbitrary))]
+#[repr(C)]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum OracleType {
+    /// Pyth-like price, confidence and exponent at the start of the account
+    #[default]
+    PriceConf,
+}
+
+impl TryFrom<u8> for OracleType {
+    type Error = ProgramError;
+
+    fn try_from(oracle_type: u8) -> Result<Self, Self::Error> {
+        match oracle_type {
+            0 => Ok(OracleType::PriceConf),
+            _ => Err(ProgramError::InvalidAccountData),
+        }
+    }
+}
+
+/// Reference price of token A in token B, scaled by `SPOT_PRICE_PRECISION`
+/// like the spot prices of the curves
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OraclePrice {
+    /// Price of token A in token B
+    pub price: u128,
+    /// Confidence interval around the price
+    pub conf: u128,
+}
+
+impl OracleType {
+    /// Reads the reference price from the data of an oracle price account
+    pub fn load_price(&self, data: &[u8]) -> Result<OraclePrice, SwapError> {
+        match self {
+            OracleType::PriceConf => {
+                let price_conf =
+                    PriceConf::unpack_from_slice(data).map_err(|_| SwapError::InvalidOracle)?;
+                price_conf.oracle_price().ok_or(SwapError::InvalidOracle)
+            }
+        }
+    }
+}
+
+/// Pyth-like price account layout: the price of token A in token B and its
+/// confidence interval are `price * 10^expo` and `conf * 10^expo`.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PriceConf {
+    /// Price mantissa, must be positive
+    pub price: i64,
+    /// Confidence interval mantissa
+    pub conf: u64,
+    /// Exponent of the price and confidence interval
+    pub expo: i32,
+}
+
+impl PriceConf {
+    /// Converts to a reference price, None if the price is not positive or
+    /// does not fit once scaled
+    pub fn oracle_price(&self) -> Option<OraclePrice> {
+        if self.price <= 0 {
+            return None;
+        }
+        Some(OraclePrice {
+            price: scale_by_exponent(self.price as u128, self.expo)?,
+            conf: scale_by_exponent(self.conf as u128, self.expo)?,
+        })
+    }
+}
+
+/// Scales `value * 10^expo` by `SPOT_PRICE_PRECISION`
+fn scale_by_exponent(value: u128, expo: i32) -> Option<u128> {
+    let value = value.checked_mul(SPOT_PRICE_PRECISION)?;
+    let factor = 10u128.checked_pow(expo.unsigned_abs())?;
+    if expo >= 0 {
+        value.checked_mul(factor)
+    } else {
+        value.checked_div(factor)
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for PriceConf {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for PriceConf {}
+impl Pack for PriceConf {
+    const LEN: usize = 20;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 20];
+        let (price, conf, expo) = mut_array_refs![output, 8, 8, 4];
+        *price = self.price.to_le_bytes();
+        *conf = self.conf.to_le_bytes();
+        *expo = self.expo.to_le_bytes();
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        if input.len() < Self::LEN {
+            return Err(ProgramError::InvalidAccountData);
+        }
+        let input = array_ref![input, 0, 20];
+        let (price, conf, expo) = array_refs![input, 8, 8, 4];
+        Ok(Self {
+            price: i64::from_le_bytes(*price),
+            conf: u64::from_le_bytes(*conf),
+            expo: i32::from_le_bytes(*expo),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pack_price_conf() {
+        let price_conf = PriceConf {
+            price: 99_950_000,
+            conf: 25_000,
+            expo: -8,
+        };
+        let mut packed = [0u8; PriceConf::LEN];
+        price_conf.pack_into_slice(&mut packed);
+        let mut expect = vec![];
+        expect.extend_from_slice(&price_conf.price.to_le_bytes());
+        expect.extend_from_slice(&price_conf.conf.to_le_bytes());
+        expect.extend_from_slice(&price_conf.expo.to_le_bytes());
+        assert_eq!(packed.to_vec(), expect);
+        assert_eq!(PriceConf::unpack_from_slice(&expect).unwrap(), price_conf);
+
+        // price accounts may hold more data after the price
+        expect.extend_from_slice(&[7u8; 12]);
+        assert_eq!(PriceConf::unpack_from_slice(&expect).unwrap(), price_conf);
+        assert_eq!(
+            PriceConf::unpack_from_slice(&expect[..PriceConf::LEN - 1]),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn load_price() {
+        let oracle_type = OracleType::try_from(0).unwrap();
+        let mut data = [0u8; PriceConf::LEN];
+        PriceConf {
+            price: 99_950_000,
+            conf: 25_000,
+            expo: -8,
+        }
+        .pack_into_slice(&mut data);
+        assert_eq!(
+            oracle_type.load_price(&data),
+            Ok(OraclePrice {
+                price: 999_500_000_000,
+                conf: 250_000_000,
+            })
+        );
+
+        PriceConf {
+            price: 2,
+            conf: 0,
+            expo: 1,
+        }
+        .pack_into_slice(&mut data);
+        assert_eq!(
+            oracle_type.load_price(&data),
+            Ok(OraclePrice {
+                price: 20 * SPOT_PRICE_PRECISION,
+                conf: 0,
+            })
+        );
+
+        // negative price
+        PriceConf {
+            price: -1,
+            conf: 0,
+            expo: 0,
+        }
+        .pack_into_slice(&mut data);
+        assert_eq!(oracle_type.load_price(&data), Err(SwapError::InvalidOracle));
+
+        // exponent out of range
+        PriceConf {
+            price: 1,
+            conf: 0,
+            expo: 40,
+        }
+        .pack_into_slice(&mut data);
+        assert_eq!(oracle_type.load_price(&data), Err(SwapError::InvalidOracle));
+
+        assert_eq!(
+            oracle_type.load_price(&data[..4]),
+            Err(SwapError::InvalidOracle)
+        );
+        assert_eq!(
+            OracleType::try_from(1),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+}
@@ -3,28 +3,42 @@
 use crate::constraints::{SwapConstraints, SWAP_CONSTRAINTS};
 use crate::{
     swap::{
-        base::SwapCurve,
-        calculator::{RoundDirection, TradeDirection},
+        base::{CurveType, SwapCurve},
+        calculator::{RoundDirection, TradeDirection, MINIMUM_LIQUIDITY},
+        crypto::{CryptoCurve, PriceState},
         fees::Fees,
+        meta::{MetaCurve, VIRTUAL_PRICE_PRECISION},
     },
     error::SwapError,
     instruction::{
-        DepositAllTokenTypes, Initialize, Swap,
-        SwapInstruction, WithdrawAllTokenTypes, WithdrawSingleTokenTypeExactAmountOut,
+        deposit_all_token_types, withdraw_single_token_type_exact_amount_in, DepositAllTokenTypes,
+        DepositUnderlying, Initialize, MigrateLiquidity, RescueTokens, SetAllowlistEntry,
+        SetDepositCaps, SetDepositGate, SetFeeRecipients, SetOracle, SetPriceBand,
+        SetProgramConfig, Swap, SwapInstruction, SwapUnderlying, WithdrawAllTokenTypes,
+        WithdrawSingleTokenTypeExactAmountIn, WithdrawSingleTokenTypeExactAmountOut,
+    },
+    oracle::OracleType,
+    state::{
+        AllowlistEntry, ProgramConfig, SwapState, SwapV2, SwapVersion, ALLOWLIST_SEED,
+        FEE_WEIGHTS_DENOMINATOR, MAX_FEE_RECIPIENTS, ORACLE_DEVIATION_DENOMINATOR,
+        PRICE_BAND_DENOMINATOR, PROGRAM_CONFIG_SEED,
     },
-    state::{SwapState, SwapV1, SwapVersion},
 };
 use solana_program::{
     account_info::{next_account_info, AccountInfo},
+    bpf_loader_upgradeable::{self, UpgradeableLoaderState},
     entrypoint::ProgramResult,
     msg,
-    program::invoke_signed,
+    program::{invoke, invoke_signed},
     program_error::{ProgramError},
     program_option::COption,
     program_pack::Pack,
+    program_utils::limited_deserialize,
     pubkey::Pubkey,
+    system_instruction,
+    sysvar::{clock::Clock, rent::Rent, Sysvar},
 };
-use std::convert::TryInto;
+use std::{convert::TryInto, sync::Arc};
 
 /// Program state handler.
 pub struct Processor {}
@@ -55,6 +69,203 @@ impl Processor {
         }
     }
 
+    /// Unpacks the program config, returning `None` if it has not been set.
+    pub fn unpack_program_config(
+        program_id: &Pubkey,
+        config_info: &AccountInfo,
+    ) -> Result<Option<ProgramConfig>, ProgramError> {
+        if *config_info.key != ProgramConfig::find_address(program_id).0 {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if config_info.owner != program_id {
+            return Ok(None);
+        }
+        let config = ProgramConfig::unpack_unchecked(&config_info.data.borrow())?;
+        if config.is_initialized {
+            Ok(Some(config))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Checks that the signer is the upgrade authority stored in the program
+    /// data account of the program.
+    pub fn check_upgrade_authority(
+        program_id: &Pubkey,
+        upgrade_authority_info: &AccountInfo,
+        program_data_info: &AccountInfo,
+    ) -> ProgramResult {
+        let (program_data_key, _) =
+            Pubkey::find_program_address(&[program_id.as_ref()], &bpf_loader_upgradeable::id());
+        if *program_data_info.key != program_data_key
+            || *program_data_info.owner != bpf_loader_upgradeable::id()
+        {
+            return Err(SwapError::InvalidUpgradeAuthority.into());
+        }
+        let upgrade_authority_address = match limited_deserialize(
+            &program_data_info.data.borrow(),
+            UpgradeableLoaderState::size_of_programdata_metadata() as u64,
+        ) {
+            Ok(UpgradeableLoaderState::ProgramData {
+                upgrade_authority_address,
+                ..
+            }) => upgrade_authority_address,
+            _ => None,
+        };
+        if !upgrade_authority_info.is_signer
+            || upgrade_authority_address != Some(*upgrade_authority_info.key)
+        {
+            return Err(SwapError::InvalidUpgradeAuthority.into());
+        }
+        Ok(())
+    }
+
+    /// Creates an account owned by the program at a program address.  Anyone
+    /// can send lamports to the address beforehand, which would fail
+    /// `create_account`, so the payer only tops up the rent and the account is
+    /// allocated and assigned separately.
+    fn create_program_account<'a>(
+        program_id: &Pubkey,
+        account_info: &AccountInfo<'a>,
+        payer_info: &AccountInfo<'a>,
+        system_program_info: &AccountInfo<'a>,
+        space: usize,
+        signer_seeds: &[&[u8]],
+    ) -> ProgramResult {
+        let required_lamports = Rent::get()?
+            .minimum_balance(space)
+            .saturating_sub(account_info.lamports());
+        if required_lamports > 0 {
+            invoke(
+                &system_instruction::transfer(payer_info.key, account_info.key, required_lamports),
+                &[
+                    payer_info.clone(),
+                    account_info.clone(),
+                    system_program_info.clone(),
+                ],
+            )?;
+        }
+        invoke_signed(
+            &system_instruction::allocate(account_info.key, space as u64),
+            &[account_info.clone(), system_program_info.clone()],
+            &[signer_seeds],
+        )?;
+        invoke_signed(
+            &system_instruction::assign(account_info.key, program_id),
+            &[account_info.clone(), system_program_info.clone()],
+            &[signer_seeds],
+        )
+    }
+
+    /// Checks that the allowlist entry allows the user to deposit into the swap.
+    pub fn check_allowlist_entry(
+        program_id: &Pubkey,
+        swap_key: &Pubkey,
+        user_key: &Pubkey,
+        allowlist_entry_info: &AccountInfo,
+    ) -> ProgramResult {
+        if allowlist_entry_info.owner != program_id {
+            return Err(SwapError::DepositNotAllowed.into());
+        }
+        let allowlist_entry = AllowlistEntry::unpack(&allowlist_entry_info.data.borrow())
+            .map_err(|_| SwapError::DepositNotAllowed)?;
+        if allowlist_entry.swap != *swap_key || allowlist_entry.user != *user_key {
+            return Err(SwapError::DepositNotAllowed.into());
+        }
+        Ok(())
+    }
+
+    /// Checks that a deposit of `amount` on top of `current` stays within the
+    /// deposit cap, where a zero cap means uncapped.
+    pub fn check_deposit_cap(cap: u64, current: u64, amount: u64) -> ProgramResult {
+        if cap == 0 {
+            return Ok(());
+        }
+        let new_amount = current
+            .checked_add(amount)
+            .ok_or(SwapError::CalculationFailure)?;
+        if new_amount > cap {
+            return Err(SwapError::DepositCapExceeded.into());
+        }
+        Ok(())
+    }
+
+    /// Circuit breaker of the swap: records the spot price before the first
+    /// swap or withdrawal of each slot, and fails if the spot price after this
+    /// one is further than the price band from it.
+    fn check_price_band(
+        swap_info: &AccountInfo,
+        token_swap: &dyn SwapState,
+        token_a_amount: u128,
+        token_b_amount: u128,
+        new_token_a_amount: u128,
+        new_token_b_amount: u128,
+    ) -> ProgramResult {
+        if token_swap.price_band() == 0 {
+            return Ok(());
+        }
+        let calculator = &token_swap.swap_curve().calculator;
+        let mut swap_v2 = SwapVersion::unpack_latest(&swap_info.data.borrow())?;
+        let slot = Clock::get()?.slot;
+        if swap_v2.reference_slot != slot || swap_v2.reference_price == 0 {
+            swap_v2.reference_slot = slot;
+            swap_v2.reference_price = calculator
+                .spot_price(token_a_amount, token_b_amount)
+                .ok_or(SwapError::UnsupportedCurveOperation)?;
+        }
+        // a side drained to zero has no spot price, which is as far out of
+        // band as the price can get
+        let spot_price = calculator
+            .spot_price(new_token_a_amount, new_token_b_amount)
+            .ok_or(SwapError::PriceBandExceeded)?;
+        let max_price_move = swap_v2
+            .reference_price
+            .checked_mul(swap_v2.price_band.into())
+            .and_then(|x| x.checked_div(PRICE_BAND_DENOMINATOR.into()))
+            .ok_or(SwapError::CalculationFailure)?;
+        if spot_price.abs_diff(swap_v2.reference_price) > max_price_move {
+            return Err(SwapError::PriceBandExceeded.into());
+        }
+        SwapVersion::pack(
+            SwapVersion::SwapV2(swap_v2),
+            &mut swap_info.data.borrow_mut(),
+        )
+    }
+
+    /// Oracle guard of the swap: fails if the spot price of the pool deviates
+    /// from the oracle price by more than the maximum deviation.
+    fn check_oracle_price(
+        token_swap: &dyn SwapState,
+        oracle_info: &AccountInfo,
+        token_a_amount: u128,
+        token_b_amount: u128,
+    ) -> ProgramResult {
+        if *oracle_info.key != *token_swap.oracle() {
+            return Err(SwapError::InvalidOracle.into());
+        }
+        let oracle_price = token_swap
+            .oracle_type()
+            .load_price(&oracle_info.data.borrow())?;
+        let max_deviation = oracle_price
+            .price
+            .checked_mul(token_swap.max_oracle_deviation().into())
+            .and_then(|x| x.checked_div(ORACLE_DEVIATION_DENOMINATOR.into()))
+            .ok_or(SwapError::CalculationFailure)?;
+        // an oracle unsure of its own price cannot vouch for the pool price
+        if oracle_price.conf > max_deviation {
+            return Err(SwapError::InvalidOracle.into());
+        }
+        let spot_price = token_swap
+            .swap_curve()
+            .calculator
+            .spot_price(token_a_amount, token_b_amount)
+            .ok_or(SwapError::OracleDeviationExceeded)?;
+        if spot_price.abs_diff(oracle_price.price) > max_deviation {
+            return Err(SwapError::OracleDeviationExceeded.into());
+        }
+        Ok(())
+    }
+
     /// Calculates the authority id by generating a program address.
     pub fn authority_id(
         program_id: &Pubkey,
@@ -100,6 +311,34 @@ impl Processor {
         )
     }
 
+    /// Issue a spl_token `CloseAccount` instruction.
+    pub fn token_close_account<'a>(
+        swap: &Pubkey,
+        token_program: AccountInfo<'a>,
+        account: AccountInfo<'a>,
+        destination: AccountInfo<'a>,
+        authority: AccountInfo<'a>,
+        bump_seed: u8,
+    ) -> Result<(), ProgramError> {
+        let swap_bytes = swap.to_bytes();
+        let authority_signature_seeds = [&swap_bytes[..32], &[bump_seed]];
+        let signers = &[&authority_signature_seeds[..]];
+
+        let ix = spl_token::instruction::close_account(
+            token_program.key,
+            account.key,
+            destination.key,
+            authority.key,
+            &[],
+        )?;
+
+        invoke_signed(
+            &ix,
+            &[account, destination, authority, token_program],
+            signers,
+        )
+    }
+
     /// Issue a spl_token `MintTo` instruction.
     pub fn token_mint_to<'a>(
         swap: &Pubkey,
@@ -153,6 +392,136 @@ impl Processor {
         )
     }
 
+    /// Checks that burning `burn_amount` pool tokens leaves at least the locked
+    /// minimum liquidity in circulation.
+    fn check_minimum_liquidity(pool_mint_supply: u128, burn_amount: u128) -> ProgramResult {
+        let remaining_supply = pool_mint_supply
+            .checked_sub(burn_amount)
+            .ok_or(SwapError::CalculationFailure)?;
+        if remaining_supply < MINIMUM_LIQUIDITY {
+            return Err(SwapError::InsufficientLiquidity.into());
+        }
+        Ok(())
+    }
+
+    /// Amount held in a swap token account that backs the curve, excluding any
+    /// admin fees waiting to be withdrawn.
+    fn reserve_amount(token_amount: u64, admin_fee: u64) -> Result<u64, SwapError> {
+        token_amount
+            .checked_sub(admin_fee)
+            .ok_or(SwapError::CalculationFailure)
+    }
+
+    /// Amount held in a swap token account above its accounted reserve and
+    /// admin fees, such as tokens transferred directly to it.
+    fn surplus_amount(token_amount: u64, reserve: u64, admin_fee: u64) -> Result<u64, SwapError> {
+        Self::reserve_amount(token_amount, admin_fee)?
+            .checked_sub(reserve)
+            .ok_or(SwapError::CalculationFailure)
+    }
+
+    /// Amounts of token A and B backing the curve: the accounted reserves, or
+    /// for swap versions not tracking them, the swap token account balances.
+    fn reserves(
+        token_swap: &dyn SwapState,
+        token_a_info: &AccountInfo,
+        token_b_info: &AccountInfo,
+    ) -> Result<(u64, u64), ProgramError> {
+        if let (Some(reserve_a), Some(reserve_b)) = (token_swap.reserve_a(), token_swap.reserve_b())
+        {
+            return Ok((reserve_a, reserve_b));
+        }
+        let token_a = Self::unpack_token_account(token_a_info, token_swap.token_program_id())?;
+        let token_b = Self::unpack_token_account(token_b_info, token_swap.token_program_id())?;
+        Ok((
+            Self::reserve_amount(token_a.amount, token_swap.admin_fee_a())?,
+            Self::reserve_amount(token_b.amount, token_swap.admin_fee_b())?,
+        ))
+    }
+
+    /// Records the accounted reserves of the swap after tokens moved in or
+    /// out of the swap token accounts.  Swap versions not tracking reserves
+    /// are left untouched.
+    fn update_reserves(
+        swap_info: &AccountInfo,
+        token_swap: &dyn SwapState,
+        reserve_a: u128,
+        reserve_b: u128,
+    ) -> ProgramResult {
+        if token_swap.reserve_a().is_none() {
+            return Ok(());
+        }
+        let mut swap_v2 = SwapVersion::unpack_latest(&swap_info.data.borrow())?;
+        swap_v2.reserve_a = to_u64(reserve_a)?;
+        swap_v2.reserve_b = to_u64(reserve_b)?;
+        SwapVersion::pack(
+            SwapVersion::SwapV2(swap_v2),
+            &mut swap_info.data.borrow_mut(),
+        )
+    }
+
+    /// Crypto curve of a swap curve of the crypto type, unpacked from its
+    /// packed parameters
+    fn crypto_curve(swap_curve: &SwapCurve) -> Result<CryptoCurve, ProgramError> {
+        if swap_curve.curve_type != CurveType::Crypto {
+            return Err(SwapError::UnsupportedCurveOperation.into());
+        }
+        let mut packed = [0u8; CryptoCurve::LEN];
+        swap_curve.calculator.pack_into_slice(&mut packed);
+        CryptoCurve::unpack_from_slice(&packed)
+    }
+
+    /// Updates the price state of a crypto curve swap after a trade or a
+    /// withdrawal, from the recorded reserves and the current pool token
+    /// supply, repegging its price scale if the pool made enough profit.
+    /// Does nothing for other curves.
+    fn tweak_price(
+        swap_info: &AccountInfo,
+        token_swap: &dyn SwapState,
+        pool_mint_info: &AccountInfo,
+        trade_price: Option<u128>,
+    ) -> ProgramResult {
+        if token_swap.swap_curve().curve_type != CurveType::Crypto {
+            return Ok(());
+        }
+        let mut swap_v2 = SwapVersion::unpack_latest(&swap_info.data.borrow())?;
+        let curve = Self::crypto_curve(&swap_v2.swap_curve)?;
+        let pool_mint = Self::unpack_mint(pool_mint_info, &swap_v2.token_program_id)?;
+        let price_state = PriceState {
+            price_oracle: swap_v2.price_oracle,
+            last_price: swap_v2.last_price,
+            last_price_timestamp: swap_v2.last_price_timestamp,
+            virtual_price: swap_v2.virtual_price,
+            xcp_profit: swap_v2.xcp_profit,
+        };
+        let (price_scale, price_state) = curve
+            .tweak_price(
+                &price_state,
+                to_u128(swap_v2.reserve_a)?,
+                to_u128(swap_v2.reserve_b)?,
+                to_u128(pool_mint.supply)?,
+                trade_price,
+                Clock::get()?.unix_timestamp,
+            )
+            .ok_or(SwapError::CalculationFailure)?;
+        swap_v2.swap_curve = SwapCurve {
+            curve_type: CurveType::Crypto,
+            calculator: Arc::new(CryptoCurve {
+                price_scale,
+                ..curve
+            }),
+        };
+        swap_v2.price_oracle = price_state.price_oracle;
+        swap_v2.last_price = price_state.last_price;
+        swap_v2.last_price_timestamp = price_state.last_price_timestamp;
+        swap_v2.virtual_price = price_state.virtual_price;
+        swap_v2.xcp_profit = price_state.xcp_profit;
+        SwapVersion::pack(
+            SwapVersion::SwapV2(swap_v2),
+            &mut swap_info.data.borrow_mut(),
+        )
+    }
+
     #[allow(clippy::too_many_arguments)]
     fn check_accounts(
         token_swap: &dyn SwapState,
@@ -221,13 +590,61 @@ impl Processor {
         let pool_mint_info = next_account_info(account_info_iter)?;
         let fee_account_info = next_account_info(account_info_iter)?;
         let destination_info = next_account_info(account_info_iter)?;
+        let locked_info = next_account_info(account_info_iter)?;
         let token_program_info = next_account_info(account_info_iter)?;
+        let config_info = next_account_info(account_info_iter)?;
+        // the base swap of a metapool and its pool mint are the last accounts
+        let mut optional_accounts = account_info_iter.as_slice();
+        let base_infos = if swap_curve.curve_type == CurveType::Meta {
+            let (base_pool_mint_info, rest) = optional_accounts
+                .split_last()
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            let (base_swap_info, rest) = rest
+                .split_last()
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            optional_accounts = rest;
+            Some((base_swap_info, base_pool_mint_info))
+        } else {
+            None
+        };
+        let account_info_iter = &mut optional_accounts.iter();
 
         let token_program_id = *token_program_info.key;
         if SwapVersion::is_initialized(&swap_info.data.borrow()) {
             return Err(SwapError::AlreadyInUse.into());
         }
 
+        // the program config, once set, takes precedence over the constraints
+        // built into the program
+        let program_config = Self::unpack_program_config(program_id, config_info)?;
+        let config_owner_key;
+        let config_curve_types;
+        let config_allowed_mints;
+        let config_denied_mints;
+        let config_constraints = if let Some(program_config) = &program_config {
+            config_owner_key = program_config.owner_key.to_string();
+            config_curve_types = program_config.valid_curve_types();
+            config_allowed_mints = program_config.allowed_mints();
+            config_denied_mints = program_config.denied_mints();
+            Some(SwapConstraints {
+                owner_key: &config_owner_key,
+                valid_curve_types: &config_curve_types,
+                fees: &program_config.fees,
+                max_fees: &program_config.max_fees,
+                min_amp: program_config.min_amp,
+                max_amp: program_config.max_amp,
+                allowed_mints: &config_allowed_mints,
+                denied_mints: &config_denied_mints,
+            })
+        } else {
+            None
+        };
+        let swap_constraints = if config_constraints.is_some() {
+            &config_constraints
+        } else {
+            swap_constraints
+        };
+
         let (swap_authority, bump_seed) =
             Pubkey::find_program_address(&[&swap_info.key.to_bytes()], program_id);
         if *authority_info.key != swap_authority {
@@ -237,6 +654,7 @@ impl Processor {
         let token_b = Self::unpack_token_account(token_b_info, &token_program_id)?;
         let fee_account = Self::unpack_token_account(fee_account_info, &token_program_id)?;
         let destination = Self::unpack_token_account(destination_info, &token_program_id)?;
+        let locked = Self::unpack_token_account(locked_info, &token_program_id)?;
         let pool_mint = Self::unpack_mint(pool_mint_info, &token_program_id)?;
         if *authority_info.key != token_a.owner {
             return Err(SwapError::InvalidOwner.into());
@@ -250,6 +668,9 @@ impl Processor {
         if *authority_info.key == fee_account.owner {
             return Err(SwapError::InvalidOutputOwner.into());
         }
+        if *authority_info.key != locked.owner {
+            return Err(SwapError::InvalidOwner.into());
+        }
         if COption::Some(*authority_info.key) != pool_mint.mint_authority {
             return Err(SwapError::InvalidOwner.into());
         }
@@ -282,6 +703,33 @@ impl Processor {
         if *pool_mint_info.key != fee_account.mint {
             return Err(SwapError::IncorrectPoolMint.into());
         }
+        if *pool_mint_info.key != locked.mint {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        if locked.delegate.is_some() {
+            return Err(SwapError::InvalidDelegate.into());
+        }
+        if locked.close_authority.is_some() {
+            return Err(SwapError::InvalidCloseAuthority.into());
+        }
+
+        let (admin_fee_account_a, admin_fee_account_b) = if let Ok(admin_fee_a_info) =
+            next_account_info(account_info_iter)
+        {
+            let admin_fee_b_info = next_account_info(account_info_iter)?;
+            let admin_fee_a = Self::unpack_token_account(admin_fee_a_info, &token_program_id)?;
+            let admin_fee_b = Self::unpack_token_account(admin_fee_b_info, &token_program_id)?;
+            if admin_fee_a.mint != token_a.mint || admin_fee_b.mint != token_b.mint {
+                return Err(SwapError::IncorrectFeeAccount.into());
+            }
+            if *authority_info.key == admin_fee_a.owner || *authority_info.key == admin_fee_b.owner
+            {
+                return Err(SwapError::InvalidOutputOwner.into());
+            }
+            (*admin_fee_a_info.key, *admin_fee_b_info.key)
+        } else {
+            (Pubkey::default(), Pubkey::default())
+        };
 
         if let Some(swap_constraints) = swap_constraints {
             let owner_key = swap_constraints
@@ -297,12 +745,51 @@ impl Processor {
 
             swap_constraints.validate_curve(&swap_curve)?;
             swap_constraints.validate_fees(&fees)?;
+            swap_constraints.validate_mints(&token_a.mint, &token_b.mint)?;
         }
         fees.validate()?;
+        // the off-peg fee measures the imbalance of the raw token amounts,
+        // which only the 1:1 stable curve is balanced at
+        if fees.has_offpeg_fee_multiplier() && swap_curve.curve_type != CurveType::Stable {
+            return Err(SwapError::InvalidFee.into());
+        }
+        // a metapool starts at the current virtual price of its base swap
+        let swap_curve = match base_infos {
+            Some((base_swap_info, base_pool_mint_info)) => SwapCurve {
+                curve_type: CurveType::Meta,
+                calculator: Arc::new(MetaCurve {
+                    amp: swap_curve.calculator.amp().ok_or(SwapError::InvalidCurve)?,
+                    base_virtual_price: Self::base_virtual_price(
+                        program_id,
+                        swap_info,
+                        &token_b.mint,
+                        base_swap_info,
+                        base_pool_mint_info,
+                    )?,
+                }),
+            },
+            None => swap_curve,
+        };
         swap_curve.calculator.validate()?;
 
-        let initial_amount = swap_curve.calculator.new_pool_supply();
+        let initial_amount = swap_curve
+            .calculator
+            .new_pool_supply(to_u128(token_a.amount)?, to_u128(token_b.amount)?)
+            .ok_or(SwapError::CalculationFailure)?;
+        let destination_amount = initial_amount
+            .checked_sub(MINIMUM_LIQUIDITY)
+            .filter(|amount| *amount > 0)
+            .ok_or(SwapError::InsufficientLiquidity)?;
 
+        Self::token_mint_to(
+            swap_info.key,
+            token_program_info.clone(),
+            pool_mint_info.clone(),
+            locked_info.clone(),
+            authority_info.clone(),
+            bump_seed,
+            to_u64(MINIMUM_LIQUIDITY)?,
+        )?;
         Self::token_mint_to(
             swap_info.key,
             token_program_info.clone(),
@@ -310,10 +797,19 @@ impl Processor {
             destination_info.clone(),
             authority_info.clone(),
             bump_seed,
-            to_u64(initial_amount)?,
+            to_u64(destination_amount)?,
         )?;
 
-        let obj = SwapVersion::SwapV1(SwapV1 {
+        let price_state = if swap_curve.curve_type == CurveType::Crypto {
+            PriceState::new(
+                Self::crypto_curve(&swap_curve)?.price_scale,
+                Clock::get()?.unix_timestamp,
+            )
+        } else {
+            PriceState::default()
+        };
+
+        let obj = SwapVersion::SwapV2(SwapV2 {
             is_initialized: true,
             bump_seed,
             token_program_id,
@@ -325,17 +821,84 @@ impl Processor {
             pool_fee_account: *fee_account_info.key,
             fees,
             swap_curve,
+            admin: fee_account.owner,
+            admin_fee_account_a,
+            admin_fee_account_b,
+            admin_fee_a: 0,
+            admin_fee_b: 0,
+            fee_recipients: [Pubkey::default(); MAX_FEE_RECIPIENTS],
+            fee_recipient_weights: [0; MAX_FEE_RECIPIENTS],
+            deposit_gate: false,
+            max_token_a_amount: 0,
+            max_token_b_amount: 0,
+            max_pool_supply: 0,
+            price_band: 0,
+            reference_slot: 0,
+            reference_price: 0,
+            oracle: Pubkey::default(),
+            oracle_type: OracleType::PriceConf,
+            max_oracle_deviation: 0,
+            reserve_a: token_a.amount,
+            reserve_b: token_b.amount,
+            price_oracle: price_state.price_oracle,
+            last_price: price_state.last_price,
+            last_price_timestamp: price_state.last_price_timestamp,
+            virtual_price: price_state.virtual_price,
+            xcp_profit: price_state.xcp_profit,
         });
         SwapVersion::pack(obj, &mut swap_info.data.borrow_mut())?;
         Ok(())
     }
 
+    /// Metapools only price trades at a fresh virtual price of the base swap
+    /// pool token, so withdrawals of a single token type, which do not
+    /// refresh it, are not supported.
+    fn check_not_metapool(token_swap: &dyn SwapState) -> ProgramResult {
+        if token_swap.swap_curve().curve_type == CurveType::Meta {
+            return Err(SwapError::UnsupportedCurveOperation.into());
+        }
+        Ok(())
+    }
+
     /// Processes an [Swap](enum.Instruction.html).
     pub fn process_swap(
         program_id: &Pubkey,
         amount_in: u64,
         minimum_amount_out: u64,
         accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let swap_info = accounts.first().ok_or(ProgramError::NotEnoughAccountKeys)?;
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        if token_swap.swap_curve().curve_type != CurveType::Meta {
+            return Self::swap_tokens(program_id, amount_in, minimum_amount_out, accounts);
+        }
+
+        // the base swap accounts of a metapool follow the token program id
+        if accounts.len() < 12 {
+            return Err(ProgramError::NotEnoughAccountKeys);
+        }
+        let (swap_accounts, optional_accounts) = accounts.split_at(10);
+        let (base_accounts, optional_accounts) = optional_accounts.split_at(2);
+        Self::refresh_base_virtual_price(
+            program_id,
+            swap_info,
+            &base_accounts[0],
+            &base_accounts[1],
+        )?;
+        Self::swap_tokens(
+            program_id,
+            amount_in,
+            minimum_amount_out,
+            &[swap_accounts, optional_accounts].concat(),
+        )
+    }
+
+    /// Swaps the tokens of any swap, metapools included.
+    fn swap_tokens(
+        program_id: &Pubkey,
+        amount_in: u64,
+        minimum_amount_out: u64,
+        accounts: &[AccountInfo],
     ) -> ProgramResult {
         let account_info_iter = &mut accounts.iter();
         let swap_info = next_account_info(account_info_iter)?;
@@ -353,6 +916,18 @@ impl Processor {
             return Err(ProgramError::IncorrectProgramId);
         }
         let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        // the oracle account comes last, after the optional host fee account
+        let mut optional_accounts = account_info_iter.as_slice();
+        let oracle_info = if token_swap.max_oracle_deviation() > 0 {
+            let (oracle_info, host_fee_accounts) = optional_accounts
+                .split_last()
+                .ok_or(ProgramError::NotEnoughAccountKeys)?;
+            optional_accounts = host_fee_accounts;
+            Some(oracle_info)
+        } else {
+            None
+        };
+        let account_info_iter = &mut optional_accounts.iter();
 
         if *authority_info.key
             != Self::authority_id(program_id, swap_info.key, token_swap.bump_seed())?
@@ -390,8 +965,6 @@ impl Processor {
 
         let source_account =
             Self::unpack_token_account(swap_source_info, token_swap.token_program_id())?;
-        let dest_account =
-            Self::unpack_token_account(swap_destination_info, token_swap.token_program_id())?;
         let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
 
         let trade_direction = if *swap_source_info.key == *token_swap.token_a_account() {
@@ -399,12 +972,24 @@ impl Processor {
         } else {
             TradeDirection::BtoA
         };
+        let (swap_source_amount, swap_destination_amount) = match trade_direction {
+            TradeDirection::AtoB => {
+                Self::reserves(token_swap.as_ref(), swap_source_info, swap_destination_info)?
+            }
+            TradeDirection::BtoA => {
+                let (reserve_a, reserve_b) =
+                    Self::reserves(token_swap.as_ref(), swap_destination_info, swap_source_info)?;
+                (reserve_b, reserve_a)
+            }
+        };
+        let swap_source_amount = to_u128(swap_source_amount)?;
+        let swap_destination_amount = to_u128(swap_destination_amount)?;
         let result = token_swap
             .swap_curve()
             .swap(
                 to_u128(amount_in)?,
-                to_u128(source_account.amount)?,
-                to_u128(dest_account.amount)?,
+                swap_source_amount,
+                swap_destination_amount,
                 trade_direction,
                 token_swap.fees(),
             )
@@ -423,6 +1008,26 @@ impl Processor {
                 result.new_swap_source_amount,
             ),
         };
+        let (token_a_amount, token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (swap_source_amount, swap_destination_amount),
+            TradeDirection::BtoA => (swap_destination_amount, swap_source_amount),
+        };
+        Self::check_price_band(
+            swap_info,
+            token_swap.as_ref(),
+            token_a_amount,
+            token_b_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+        )?;
+        if let Some(oracle_info) = oracle_info {
+            Self::check_oracle_price(
+                token_swap.as_ref(),
+                oracle_info,
+                swap_token_a_amount,
+                swap_token_b_amount,
+            )?;
+        }
 
         Self::token_transfer(
             swap_info.key,
@@ -434,17 +1039,34 @@ impl Processor {
             to_u64(result.source_amount_swapped)?,
         )?;
 
-        let mut pool_token_amount = token_swap
-            .swap_curve()
-            .withdraw_single_token_type_exact_out(
+        let mut pool_token_amount = if token_swap.admin_fees_in_tokens() {
+            // the owner fee stays in the swap source account, tracked as an
+            // admin fee, so no pool tokens are minted
+            Self::accrue_admin_fee(
+                swap_info,
+                authority_info,
+                swap_source_info,
+                token_program_info,
+                token_swap.as_ref(),
+                source_account.mint,
                 result.owner_fee,
-                swap_token_a_amount,
-                swap_token_b_amount,
-                to_u128(pool_mint.supply)?,
                 trade_direction,
-                token_swap.fees(),
-            )
-            .ok_or(SwapError::FeeCalculationFailure)?;
+                account_info_iter,
+            )?;
+            0
+        } else {
+            token_swap
+                .swap_curve()
+                .withdraw_single_token_type_exact_out(
+                    result.owner_fee,
+                    swap_token_a_amount,
+                    swap_token_b_amount,
+                    to_u128(pool_mint.supply)?,
+                    trade_direction,
+                    token_swap.fees(),
+                )
+                .ok_or(SwapError::FeeCalculationFailure)?
+        };
 
         if pool_token_amount > 0 {
             // Allow error to fall through
@@ -496,7 +1118,111 @@ impl Processor {
             to_u64(result.destination_amount_swapped)?,
         )?;
 
-        Ok(())
+        // an owner fee kept as an admin fee does not back the curve
+        let (reserve_a, reserve_b) = if token_swap.admin_fees_in_tokens() {
+            match trade_direction {
+                TradeDirection::AtoB => (
+                    swap_token_a_amount
+                        .checked_sub(result.owner_fee)
+                        .ok_or(SwapError::FeeCalculationFailure)?,
+                    swap_token_b_amount,
+                ),
+                TradeDirection::BtoA => (
+                    swap_token_a_amount,
+                    swap_token_b_amount
+                        .checked_sub(result.owner_fee)
+                        .ok_or(SwapError::FeeCalculationFailure)?,
+                ),
+            }
+        } else {
+            (swap_token_a_amount, swap_token_b_amount)
+        };
+        Self::update_reserves(swap_info, token_swap.as_ref(), reserve_a, reserve_b)?;
+
+        let (token_a_amount_swapped, token_b_amount_swapped) = match trade_direction {
+            TradeDirection::AtoB => (
+                result.source_amount_swapped,
+                result.destination_amount_swapped,
+            ),
+            TradeDirection::BtoA => (
+                result.destination_amount_swapped,
+                result.source_amount_swapped,
+            ),
+        };
+        Self::tweak_price(
+            swap_info,
+            token_swap.as_ref(),
+            pool_mint_info,
+            CryptoCurve::trade_price(token_a_amount_swapped, token_b_amount_swapped),
+        )
+    }
+
+    /// Keeps the owner trading fee in the swap source account as an admin fee,
+    /// paying out the host fee share in source tokens if a host fee account
+    /// is provided.
+    #[allow(clippy::too_many_arguments)]
+    fn accrue_admin_fee<'a>(
+        swap_info: &AccountInfo<'a>,
+        authority_info: &AccountInfo<'a>,
+        swap_source_info: &AccountInfo<'a>,
+        token_program_info: &AccountInfo<'a>,
+        token_swap: &dyn SwapState,
+        source_mint: Pubkey,
+        owner_fee: u128,
+        trade_direction: TradeDirection,
+        account_info_iter: &mut std::slice::Iter<AccountInfo<'a>>,
+    ) -> ProgramResult {
+        if owner_fee == 0 {
+            return Ok(());
+        }
+        let mut admin_fee = owner_fee;
+        // Allow error to fall through
+        if let Ok(host_fee_account_info) = next_account_info(account_info_iter) {
+            let host_fee_account =
+                Self::unpack_token_account(host_fee_account_info, token_swap.token_program_id())?;
+            if source_mint != host_fee_account.mint {
+                return Err(SwapError::IncorrectFeeAccount.into());
+            }
+            let host_fee = token_swap
+                .fees()
+                .host_fee(owner_fee)
+                .ok_or(SwapError::FeeCalculationFailure)?;
+            if host_fee > 0 {
+                admin_fee = admin_fee
+                    .checked_sub(host_fee)
+                    .ok_or(SwapError::FeeCalculationFailure)?;
+                Self::token_transfer(
+                    swap_info.key,
+                    token_program_info.clone(),
+                    swap_source_info.clone(),
+                    host_fee_account_info.clone(),
+                    authority_info.clone(),
+                    token_swap.bump_seed(),
+                    to_u64(host_fee)?,
+                )?;
+            }
+        }
+
+        let admin_fee = to_u64(admin_fee)?;
+        let mut swap_v2 = SwapVersion::unpack_latest(&swap_info.data.borrow())?;
+        match trade_direction {
+            TradeDirection::AtoB => {
+                swap_v2.admin_fee_a = swap_v2
+                    .admin_fee_a
+                    .checked_add(admin_fee)
+                    .ok_or(SwapError::FeeCalculationFailure)?;
+            }
+            TradeDirection::BtoA => {
+                swap_v2.admin_fee_b = swap_v2
+                    .admin_fee_b
+                    .checked_add(admin_fee)
+                    .ok_or(SwapError::FeeCalculationFailure)?;
+            }
+        }
+        SwapVersion::pack(
+            SwapVersion::SwapV2(swap_v2),
+            &mut swap_info.data.borrow_mut(),
+        )
     }
 
     /// Processes an [DepositAllTokenTypes](enum.Instruction.html).
@@ -537,23 +1263,37 @@ impl Processor {
             Some(source_b_info),
             None,
         )?;
+        if token_swap.deposit_gate() {
+            let allowlist_entry_info = next_account_info(account_info_iter)?;
+            Self::check_allowlist_entry(
+                program_id,
+                swap_info.key,
+                user_transfer_authority_info.key,
+                allowlist_entry_info,
+            )?;
+        }
 
-        let token_a = Self::unpack_token_account(token_a_info, token_swap.token_program_id())?;
-        let token_b = Self::unpack_token_account(token_b_info, token_swap.token_program_id())?;
         let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
+        let (reserve_a, reserve_b) =
+            Self::reserves(token_swap.as_ref(), token_a_info, token_b_info)?;
+        let token_a_reserve = to_u128(reserve_a)?;
+        let token_b_reserve = to_u128(reserve_b)?;
         let current_pool_mint_supply = to_u128(pool_mint.supply)?;
         let (pool_token_amount, pool_mint_supply) = if current_pool_mint_supply > 0 {
             (to_u128(pool_token_amount)?, current_pool_mint_supply)
         } else {
-            (calculator.new_pool_supply(), calculator.new_pool_supply())
+            let new_pool_supply = calculator
+                .new_pool_supply(token_a_reserve, token_b_reserve)
+                .ok_or(SwapError::CalculationFailure)?;
+            (new_pool_supply, new_pool_supply)
         };
 
         let results = calculator
             .pool_tokens_to_trading_tokens(
                 pool_token_amount,
                 pool_mint_supply,
-                to_u128(token_a.amount)?,
-                to_u128(token_b.amount)?,
+                token_a_reserve,
+                token_b_reserve,
                 RoundDirection::Ceiling,
             )
             .ok_or(SwapError::ZeroTradingTokens)?;
@@ -573,6 +1313,15 @@ impl Processor {
         }
 
         let pool_token_amount = to_u64(pool_token_amount)?;
+        // the caps apply to the reserves, so that tokens sent straight to the
+        // swap token accounts cannot block deposits
+        Self::check_deposit_cap(token_swap.max_token_a_amount(), reserve_a, token_a_amount)?;
+        Self::check_deposit_cap(token_swap.max_token_b_amount(), reserve_b, token_b_amount)?;
+        Self::check_deposit_cap(
+            token_swap.max_pool_supply(),
+            pool_mint.supply,
+            pool_token_amount,
+        )?;
 
         Self::token_transfer(
             swap_info.key,
@@ -602,7 +1351,17 @@ impl Processor {
             pool_token_amount,
         )?;
 
-        Ok(())
+        Self::update_reserves(
+            swap_info,
+            token_swap.as_ref(),
+            token_a_reserve
+                .checked_add(results.token_a_amount)
+                .ok_or(SwapError::CalculationFailure)?,
+            token_b_reserve
+                .checked_add(results.token_b_amount)
+                .ok_or(SwapError::CalculationFailure)?,
+        )?;
+        Self::tweak_price(swap_info, token_swap.as_ref(), pool_mint_info, None)
     }
 
     /// Processes an [WithdrawAllTokenTypes](enum.Instruction.html).
@@ -641,9 +1400,9 @@ impl Processor {
             Some(pool_fee_account_info),
         )?;
 
-        let token_a = Self::unpack_token_account(token_a_info, token_swap.token_program_id())?;
-        let token_b = Self::unpack_token_account(token_b_info, token_swap.token_program_id())?;
         let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
+        let (token_a_reserve, token_b_reserve) =
+            Self::reserves(token_swap.as_ref(), token_a_info, token_b_info)?;
 
         let calculator = &token_swap.swap_curve().calculator;
 
@@ -659,30 +1418,31 @@ impl Processor {
         let pool_token_amount = to_u128(pool_token_amount)?
             .checked_sub(withdraw_fee)
             .ok_or(SwapError::CalculationFailure)?;
+        Self::check_minimum_liquidity(to_u128(pool_mint.supply)?, pool_token_amount)?;
 
         let results = calculator
             .pool_tokens_to_trading_tokens(
                 pool_token_amount,
                 to_u128(pool_mint.supply)?,
-                to_u128(token_a.amount)?,
-                to_u128(token_b.amount)?,
+                to_u128(token_a_reserve)?,
+                to_u128(token_b_reserve)?,
                 RoundDirection::Floor,
             )
             .ok_or(SwapError::ZeroTradingTokens)?;
         let token_a_amount = to_u64(results.token_a_amount)?;
-        let token_a_amount = std::cmp::min(token_a.amount, token_a_amount);
+        let token_a_amount = std::cmp::min(token_a_reserve, token_a_amount);
         if token_a_amount < minimum_token_a_amount {
             return Err(SwapError::ExceededSlippage.into());
         }
-        if token_a_amount == 0 && token_a.amount != 0 {
+        if token_a_amount == 0 && token_a_reserve != 0 {
             return Err(SwapError::ZeroTradingTokens.into());
         }
         let token_b_amount = to_u64(results.token_b_amount)?;
-        let token_b_amount = std::cmp::min(token_b.amount, token_b_amount);
+        let token_b_amount = std::cmp::min(token_b_reserve, token_b_amount);
         if token_b_amount < minimum_token_b_amount {
             return Err(SwapError::ExceededSlippage.into());
         }
-        if token_b_amount == 0 && token_b.amount != 0 {
+        if token_b_amount == 0 && token_b_reserve != 0 {
             return Err(SwapError::ZeroTradingTokens.into());
         }
 
@@ -729,7 +1489,567 @@ impl Processor {
                 token_b_amount,
             )?;
         }
-        Ok(())
+
+        Self::update_reserves(
+            swap_info,
+            token_swap.as_ref(),
+            to_u128(token_a_reserve - token_a_amount)?,
+            to_u128(token_b_reserve - token_b_amount)?,
+        )
+    }
+
+    /// Processes a [MigrateLiquidity](enum.Instruction.html).
+    pub fn process_migrate_liquidity(
+        program_id: &Pubkey,
+        pool_token_amount: u64,
+        minimum_pool_token_amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let source_swap_info = next_account_info(account_info_iter)?;
+        let source_authority_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let source_pool_mint_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let source_token_a_info = next_account_info(account_info_iter)?;
+        let source_token_b_info = next_account_info(account_info_iter)?;
+        let source_pool_fee_account_info = next_account_info(account_info_iter)?;
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let dest_info = next_account_info(account_info_iter)?;
+        let user_token_a_info = next_account_info(account_info_iter)?;
+        let user_token_b_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        if source_swap_info.key == swap_info.key {
+            return Err(SwapError::InvalidInput.into());
+        }
+        let source_swap = SwapVersion::unpack(&source_swap_info.data.borrow())?;
+        Self::check_accounts(
+            source_swap.as_ref(),
+            program_id,
+            source_swap_info,
+            source_authority_info,
+            source_token_a_info,
+            source_token_b_info,
+            source_pool_mint_info,
+            token_program_info,
+            Some(user_token_a_info),
+            Some(user_token_b_info),
+            Some(source_pool_fee_account_info),
+        )?;
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        let calculator = &token_swap.swap_curve().calculator;
+        if !calculator.allows_deposits() {
+            return Err(SwapError::UnsupportedCurveOperation.into());
+        }
+        Self::check_accounts(
+            token_swap.as_ref(),
+            program_id,
+            swap_info,
+            authority_info,
+            token_a_info,
+            token_b_info,
+            pool_mint_info,
+            token_program_info,
+            Some(user_token_a_info),
+            Some(user_token_b_info),
+            None,
+        )?;
+        if *source_swap.token_a_mint() != *token_swap.token_a_mint()
+            || *source_swap.token_b_mint() != *token_swap.token_b_mint()
+        {
+            return Err(SwapError::MismatchedSwapMints.into());
+        }
+        if token_swap.deposit_gate() {
+            let allowlist_entry_info = next_account_info(account_info_iter)?;
+            Self::check_allowlist_entry(
+                program_id,
+                swap_info.key,
+                user_transfer_authority_info.key,
+                allowlist_entry_info,
+            )?;
+        }
+
+        // withdraw from the source swap
+        let source_pool_mint =
+            Self::unpack_mint(source_pool_mint_info, source_swap.token_program_id())?;
+        let source_pool_mint_supply = to_u128(source_pool_mint.supply)?;
+        let withdraw_fee: u128 = if *source_pool_fee_account_info.key == *source_info.key {
+            // withdrawing from the fee account, don't assess withdraw fee
+            0
+        } else {
+            source_swap
+                .fees()
+                .owner_withdraw_fee(to_u128(pool_token_amount)?)
+                .ok_or(SwapError::FeeCalculationFailure)?
+        };
+        let burn_pool_token_amount = to_u128(pool_token_amount)?
+            .checked_sub(withdraw_fee)
+            .ok_or(SwapError::CalculationFailure)?;
+        Self::check_minimum_liquidity(source_pool_mint_supply, burn_pool_token_amount)?;
+
+        let (source_reserve_a, source_reserve_b) = Self::reserves(
+            source_swap.as_ref(),
+            source_token_a_info,
+            source_token_b_info,
+        )?;
+        let source_reserve_a = to_u128(source_reserve_a)?;
+        let source_reserve_b = to_u128(source_reserve_b)?;
+        let withdrawn = source_swap
+            .swap_curve()
+            .calculator
+            .pool_tokens_to_trading_tokens(
+                burn_pool_token_amount,
+                source_pool_mint_supply,
+                source_reserve_a,
+                source_reserve_b,
+                RoundDirection::Floor,
+            )
+            .ok_or(SwapError::ZeroTradingTokens)?;
+        let withdrawn_a = std::cmp::min(source_reserve_a, withdrawn.token_a_amount);
+        let withdrawn_b = std::cmp::min(source_reserve_b, withdrawn.token_b_amount);
+
+        // deposit into the destination swap at its current ratio
+        let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
+        let pool_mint_supply = to_u128(pool_mint.supply)?;
+        let (reserve_a, reserve_b) =
+            Self::reserves(token_swap.as_ref(), token_a_info, token_b_info)?;
+        let reserve_a = to_u128(reserve_a)?;
+        let reserve_b = to_u128(reserve_b)?;
+        let pool_tokens_for_a = withdrawn_a
+            .checked_mul(pool_mint_supply)
+            .and_then(|x| x.checked_div(reserve_a))
+            .ok_or(SwapError::ZeroTradingTokens)?;
+        let pool_tokens_for_b = withdrawn_b
+            .checked_mul(pool_mint_supply)
+            .and_then(|x| x.checked_div(reserve_b))
+            .ok_or(SwapError::ZeroTradingTokens)?;
+        let new_pool_token_amount = std::cmp::min(pool_tokens_for_a, pool_tokens_for_b);
+        if new_pool_token_amount < to_u128(minimum_pool_token_amount)? {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+        if new_pool_token_amount == 0 {
+            return Err(SwapError::ZeroTradingTokens.into());
+        }
+        let deposited = calculator
+            .pool_tokens_to_trading_tokens(
+                new_pool_token_amount,
+                pool_mint_supply,
+                reserve_a,
+                reserve_b,
+                RoundDirection::Ceiling,
+            )
+            .ok_or(SwapError::ZeroTradingTokens)?;
+        let deposit_a = std::cmp::min(withdrawn_a, deposited.token_a_amount);
+        let deposit_b = std::cmp::min(withdrawn_b, deposited.token_b_amount);
+
+        Self::check_deposit_cap(
+            token_swap.max_token_a_amount(),
+            to_u64(reserve_a)?,
+            to_u64(deposit_a)?,
+        )?;
+        Self::check_deposit_cap(
+            token_swap.max_token_b_amount(),
+            to_u64(reserve_b)?,
+            to_u64(deposit_b)?,
+        )?;
+        Self::check_deposit_cap(
+            token_swap.max_pool_supply(),
+            pool_mint.supply,
+            to_u64(new_pool_token_amount)?,
+        )?;
+
+        if withdraw_fee > 0 {
+            Self::token_transfer(
+                source_swap_info.key,
+                token_program_info.clone(),
+                source_info.clone(),
+                source_pool_fee_account_info.clone(),
+                user_transfer_authority_info.clone(),
+                source_swap.bump_seed(),
+                to_u64(withdraw_fee)?,
+            )?;
+        }
+        Self::token_burn(
+            source_swap_info.key,
+            token_program_info.clone(),
+            source_info.clone(),
+            source_pool_mint_info.clone(),
+            user_transfer_authority_info.clone(),
+            source_swap.bump_seed(),
+            to_u64(burn_pool_token_amount)?,
+        )?;
+
+        for (source_token_info, token_info, user_token_info, withdrawn, deposit) in [
+            (
+                source_token_a_info,
+                token_a_info,
+                user_token_a_info,
+                withdrawn_a,
+                deposit_a,
+            ),
+            (
+                source_token_b_info,
+                token_b_info,
+                user_token_b_info,
+                withdrawn_b,
+                deposit_b,
+            ),
+        ]
+        .iter()
+        {
+            if *deposit > 0 {
+                Self::token_transfer(
+                    source_swap_info.key,
+                    token_program_info.clone(),
+                    (*source_token_info).clone(),
+                    (*token_info).clone(),
+                    source_authority_info.clone(),
+                    source_swap.bump_seed(),
+                    to_u64(*deposit)?,
+                )?;
+            }
+            // tokens left over by the ratio of the destination swap
+            let left_over = withdrawn
+                .checked_sub(*deposit)
+                .ok_or(SwapError::CalculationFailure)?;
+            if left_over > 0 {
+                Self::token_transfer(
+                    source_swap_info.key,
+                    token_program_info.clone(),
+                    (*source_token_info).clone(),
+                    (*user_token_info).clone(),
+                    source_authority_info.clone(),
+                    source_swap.bump_seed(),
+                    to_u64(left_over)?,
+                )?;
+            }
+        }
+        Self::token_mint_to(
+            swap_info.key,
+            token_program_info.clone(),
+            pool_mint_info.clone(),
+            dest_info.clone(),
+            authority_info.clone(),
+            token_swap.bump_seed(),
+            to_u64(new_pool_token_amount)?,
+        )?;
+
+        Self::update_reserves(
+            source_swap_info,
+            source_swap.as_ref(),
+            source_reserve_a
+                .checked_sub(withdrawn_a)
+                .ok_or(SwapError::CalculationFailure)?,
+            source_reserve_b
+                .checked_sub(withdrawn_b)
+                .ok_or(SwapError::CalculationFailure)?,
+        )?;
+        Self::update_reserves(
+            swap_info,
+            token_swap.as_ref(),
+            reserve_a
+                .checked_add(deposit_a)
+                .ok_or(SwapError::CalculationFailure)?,
+            reserve_b
+                .checked_add(deposit_b)
+                .ok_or(SwapError::CalculationFailure)?,
+        )
+    }
+
+    /// Virtual price of the pool token of the base swap of a metapool, the
+    /// value of the liquidity of the base swap per pool token, scaled by
+    /// `VIRTUAL_PRICE_PRECISION`.  The base swap must be a stable swap, whose
+    /// invariant is the value of its liquidity.
+    fn base_virtual_price(
+        program_id: &Pubkey,
+        swap_info: &AccountInfo,
+        token_b_mint: &Pubkey,
+        base_swap_info: &AccountInfo,
+        base_pool_mint_info: &AccountInfo,
+    ) -> Result<u64, ProgramError> {
+        if base_swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        if base_swap_info.key == swap_info.key {
+            return Err(SwapError::IncorrectBaseSwap.into());
+        }
+        let base_swap = SwapVersion::unpack_latest(&base_swap_info.data.borrow())?;
+        if base_swap.pool_mint != *token_b_mint
+            || base_swap.swap_curve.curve_type != CurveType::Stable
+        {
+            return Err(SwapError::IncorrectBaseSwap.into());
+        }
+        if *base_pool_mint_info.key != base_swap.pool_mint {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        let base_pool_mint = Self::unpack_mint(base_pool_mint_info, &base_swap.token_program_id)?;
+        // the supply of a new stable swap is its invariant
+        let invariant = base_swap
+            .swap_curve
+            .calculator
+            .new_pool_supply(to_u128(base_swap.reserve_a)?, to_u128(base_swap.reserve_b)?)
+            .ok_or(SwapError::CalculationFailure)?;
+        let virtual_price = invariant
+            .checked_mul(VIRTUAL_PRICE_PRECISION.into())
+            .and_then(|x| x.checked_div(base_pool_mint.supply.into()))
+            .ok_or(SwapError::CalculationFailure)?;
+        Ok(to_u64(virtual_price)?)
+    }
+
+    /// Caches the current virtual price of the base swap pool token in the
+    /// curve of a metapool.  Swaps, deposits and withdrawals on the metapool
+    /// use the cached virtual price.
+    fn refresh_base_virtual_price(
+        program_id: &Pubkey,
+        swap_info: &AccountInfo,
+        base_swap_info: &AccountInfo,
+        base_pool_mint_info: &AccountInfo,
+    ) -> ProgramResult {
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut token_swap = SwapVersion::unpack_latest(&swap_info.data.borrow())?;
+        if token_swap.swap_curve.curve_type != CurveType::Meta {
+            return Err(SwapError::UnsupportedCurveOperation.into());
+        }
+        let amp = token_swap
+            .swap_curve
+            .calculator
+            .amp()
+            .ok_or(SwapError::InvalidCurve)?;
+        let base_virtual_price = Self::base_virtual_price(
+            program_id,
+            swap_info,
+            &token_swap.token_b_mint,
+            base_swap_info,
+            base_pool_mint_info,
+        )?;
+        token_swap.swap_curve = SwapCurve {
+            curve_type: CurveType::Meta,
+            calculator: Arc::new(MetaCurve {
+                amp,
+                base_virtual_price,
+            }),
+        };
+        SwapVersion::pack(
+            SwapVersion::SwapV2(token_swap),
+            &mut swap_info.data.borrow_mut(),
+        )
+    }
+
+    /// Processes a [SwapUnderlying](enum.Instruction.html).
+    pub fn process_swap_underlying(
+        program_id: &Pubkey,
+        amount_in: u64,
+        minimum_amount_out: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let swap_token_a_info = next_account_info(account_info_iter)?;
+        let swap_token_b_info = next_account_info(account_info_iter)?;
+        let base_pool_token_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let pool_fee_account_info = next_account_info(account_info_iter)?;
+        let base_swap_info = next_account_info(account_info_iter)?;
+        let base_authority_info = next_account_info(account_info_iter)?;
+        let base_pool_mint_info = next_account_info(account_info_iter)?;
+        let base_token_a_info = next_account_info(account_info_iter)?;
+        let base_token_b_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let base_pool_fee_account_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let swap_program_info = next_account_info(account_info_iter)?;
+
+        Self::refresh_base_virtual_price(
+            program_id,
+            swap_info,
+            base_swap_info,
+            base_pool_mint_info,
+        )?;
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        if *swap_token_a_info.key != *token_swap.token_a_account() {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        let base_pool_token =
+            Self::unpack_token_account(base_pool_token_info, token_swap.token_program_id())?;
+
+        // swap token A for base swap pool tokens, the slippage is checked on
+        // the amount withdrawn from the base swap
+        let mut swap_accounts = vec![
+            swap_info.clone(),
+            authority_info.clone(),
+            user_transfer_authority_info.clone(),
+            source_info.clone(),
+            swap_token_a_info.clone(),
+            swap_token_b_info.clone(),
+            base_pool_token_info.clone(),
+            pool_mint_info.clone(),
+            pool_fee_account_info.clone(),
+            token_program_info.clone(),
+        ];
+        swap_accounts.extend(account_info_iter.cloned());
+        Self::swap_tokens(program_id, amount_in, 0, &swap_accounts)?;
+        let pool_token_amount =
+            Self::unpack_token_account(base_pool_token_info, token_swap.token_program_id())?
+                .amount
+                .checked_sub(base_pool_token.amount)
+                .ok_or(SwapError::CalculationFailure)?;
+
+        let instruction = withdraw_single_token_type_exact_amount_in(
+            program_id,
+            token_program_info.key,
+            base_swap_info.key,
+            base_authority_info.key,
+            user_transfer_authority_info.key,
+            base_pool_mint_info.key,
+            base_pool_fee_account_info.key,
+            base_pool_token_info.key,
+            base_token_a_info.key,
+            base_token_b_info.key,
+            destination_info.key,
+            WithdrawSingleTokenTypeExactAmountIn {
+                pool_token_amount,
+                minimum_token_amount: minimum_amount_out,
+            },
+        )?;
+        invoke(
+            &instruction,
+            &[
+                base_swap_info.clone(),
+                base_authority_info.clone(),
+                user_transfer_authority_info.clone(),
+                base_pool_mint_info.clone(),
+                base_pool_token_info.clone(),
+                base_token_a_info.clone(),
+                base_token_b_info.clone(),
+                destination_info.clone(),
+                base_pool_fee_account_info.clone(),
+                token_program_info.clone(),
+                swap_program_info.clone(),
+            ],
+        )
+    }
+
+    /// Processes a [DepositUnderlying](enum.Instruction.html).
+    pub fn process_deposit_underlying(
+        program_id: &Pubkey,
+        base_pool_token_amount: u64,
+        pool_token_amount: u64,
+        maximum_token_a_amount: u64,
+        maximum_base_token_a_amount: u64,
+        maximum_base_token_b_amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let source_a_info = next_account_info(account_info_iter)?;
+        let base_pool_token_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let dest_info = next_account_info(account_info_iter)?;
+        let base_swap_info = next_account_info(account_info_iter)?;
+        let base_authority_info = next_account_info(account_info_iter)?;
+        let base_source_a_info = next_account_info(account_info_iter)?;
+        let base_source_b_info = next_account_info(account_info_iter)?;
+        let base_token_a_info = next_account_info(account_info_iter)?;
+        let base_token_b_info = next_account_info(account_info_iter)?;
+        let base_pool_mint_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+        let swap_program_info = next_account_info(account_info_iter)?;
+
+        Self::refresh_base_virtual_price(
+            program_id,
+            swap_info,
+            base_swap_info,
+            base_pool_mint_info,
+        )?;
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        let allowlist_entry_info = if token_swap.deposit_gate() {
+            Some(next_account_info(account_info_iter)?)
+        } else {
+            None
+        };
+        let base_swap = SwapVersion::unpack(&base_swap_info.data.borrow())?;
+        let base_allowlist_entry_info = if base_swap.deposit_gate() {
+            Some(next_account_info(account_info_iter)?)
+        } else {
+            None
+        };
+
+        // deposit the base swap tokens for base swap pool tokens
+        let instruction = deposit_all_token_types(
+            program_id,
+            token_program_info.key,
+            base_swap_info.key,
+            base_authority_info.key,
+            user_transfer_authority_info.key,
+            base_source_a_info.key,
+            base_source_b_info.key,
+            base_token_a_info.key,
+            base_token_b_info.key,
+            base_pool_mint_info.key,
+            base_pool_token_info.key,
+            base_allowlist_entry_info.map(|x| x.key),
+            DepositAllTokenTypes {
+                pool_token_amount: base_pool_token_amount,
+                maximum_token_a_amount: maximum_base_token_a_amount,
+                maximum_token_b_amount: maximum_base_token_b_amount,
+            },
+        )?;
+        let mut base_accounts = vec![
+            base_swap_info.clone(),
+            base_authority_info.clone(),
+            user_transfer_authority_info.clone(),
+            base_source_a_info.clone(),
+            base_source_b_info.clone(),
+            base_token_a_info.clone(),
+            base_token_b_info.clone(),
+            base_pool_mint_info.clone(),
+            base_pool_token_info.clone(),
+            token_program_info.clone(),
+            swap_program_info.clone(),
+        ];
+        if let Some(base_allowlist_entry_info) = base_allowlist_entry_info {
+            base_accounts.push(base_allowlist_entry_info.clone());
+        }
+        invoke(&instruction, &base_accounts)?;
+
+        // deposit token A and the base swap pool tokens into the metapool, at
+        // most the base swap pool tokens just received
+        let mut deposit_accounts = vec![
+            swap_info.clone(),
+            authority_info.clone(),
+            user_transfer_authority_info.clone(),
+            source_a_info.clone(),
+            base_pool_token_info.clone(),
+            token_a_info.clone(),
+            token_b_info.clone(),
+            pool_mint_info.clone(),
+            dest_info.clone(),
+            token_program_info.clone(),
+        ];
+        if let Some(allowlist_entry_info) = allowlist_entry_info {
+            deposit_accounts.push(allowlist_entry_info.clone());
+        }
+        Self::process_deposit_all_token_types(
+            program_id,
+            pool_token_amount,
+            maximum_token_a_amount,
+            base_pool_token_amount,
+            &deposit_accounts,
+        )
     }
 
     /// Processes a [WithdrawSingleTokenTypeExactAmountOut](enum.Instruction.html).
@@ -752,6 +2072,7 @@ impl Processor {
         let token_program_info = next_account_info(account_info_iter)?;
 
         let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        Self::check_not_metapool(token_swap.as_ref())?;
         let destination_account =
             Self::unpack_token_account(destination_info, token_swap.token_program_id())?;
         let swap_token_a =
@@ -787,8 +2108,10 @@ impl Processor {
 
         let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
         let pool_mint_supply = to_u128(pool_mint.supply)?;
-        let swap_token_a_amount = to_u128(swap_token_a.amount)?;
-        let swap_token_b_amount = to_u128(swap_token_b.amount)?;
+        let (swap_token_a_amount, swap_token_b_amount) =
+            Self::reserves(token_swap.as_ref(), swap_token_a_info, swap_token_b_info)?;
+        let swap_token_a_amount = to_u128(swap_token_a_amount)?;
+        let swap_token_b_amount = to_u128(swap_token_b_amount)?;
 
         let burn_pool_token_amount = token_swap
             .swap_curve()
@@ -821,11 +2144,34 @@ impl Processor {
         if pool_token_amount == 0 {
             return Err(SwapError::ZeroTradingTokens.into());
         }
-
-        if withdraw_fee > 0 {
-            Self::token_transfer(
-                swap_info.key,
-                token_program_info.clone(),
+        Self::check_minimum_liquidity(pool_mint_supply, burn_pool_token_amount)?;
+        let (new_swap_token_a_amount, new_swap_token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (
+                swap_token_a_amount
+                    .checked_sub(to_u128(destination_token_amount)?)
+                    .ok_or(SwapError::CalculationFailure)?,
+                swap_token_b_amount,
+            ),
+            TradeDirection::BtoA => (
+                swap_token_a_amount,
+                swap_token_b_amount
+                    .checked_sub(to_u128(destination_token_amount)?)
+                    .ok_or(SwapError::CalculationFailure)?,
+            ),
+        };
+        Self::check_price_band(
+            swap_info,
+            token_swap.as_ref(),
+            swap_token_a_amount,
+            swap_token_b_amount,
+            new_swap_token_a_amount,
+            new_swap_token_b_amount,
+        )?;
+
+        if withdraw_fee > 0 {
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
                 source_info.clone(),
                 pool_fee_account_info.clone(),
                 user_transfer_authority_info.clone(),
@@ -868,2194 +2214,7545 @@ impl Processor {
             }
         }
 
-        Ok(())
-    }
-
-    /// Processes an [Instruction](enum.Instruction.html).
-    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
-        Self::process_with_constraints(program_id, accounts, input, &SWAP_CONSTRAINTS)
+        Self::update_reserves(
+            swap_info,
+            token_swap.as_ref(),
+            new_swap_token_a_amount,
+            new_swap_token_b_amount,
+        )?;
+        Self::tweak_price(swap_info, token_swap.as_ref(), pool_mint_info, None)
     }
 
-    /// Processes an instruction given extra constraint
-    pub fn process_with_constraints(
+    /// Processes a [WithdrawSingleTokenTypeExactAmountIn](enum.Instruction.html).
+    pub fn process_withdraw_single_token_type_exact_amount_in(
         program_id: &Pubkey,
+        pool_token_amount: u64,
+        minimum_token_amount: u64,
         accounts: &[AccountInfo],
-        input: &[u8],
-        swap_constraints: &Option<SwapConstraints>,
     ) -> ProgramResult {
-        let instruction = SwapInstruction::unpack(input)?;
-        match instruction {
-            SwapInstruction::Initialize(Initialize { fees, swap_curve }) => {
-                msg!("Instruction: Init");
-                Self::process_initialize(program_id, fees, swap_curve, accounts, swap_constraints)
-            }
-            SwapInstruction::Swap(Swap {
-                amount_in,
-                minimum_amount_out,
-            }) => {
-                msg!("Instruction: Swap");
-                Self::process_swap(program_id, amount_in, minimum_amount_out, accounts)
-            }
-            SwapInstruction::DepositAllTokenTypes(DepositAllTokenTypes {
-                pool_token_amount,
-                maximum_token_a_amount,
-                maximum_token_b_amount,
-            }) => {
-                msg!("Instruction: DepositAllTokenTypes");
-                Self::process_deposit_all_token_types(
-                    program_id,
-                    pool_token_amount,
-                    maximum_token_a_amount,
-                    maximum_token_b_amount,
-                    accounts,
-                )
-            }
-            SwapInstruction::WithdrawAllTokenTypes(WithdrawAllTokenTypes {
-                pool_token_amount,
-                minimum_token_a_amount,
-                minimum_token_b_amount,
-            }) => {
-                msg!("Instruction: WithdrawAllTokenTypes");
-                Self::process_withdraw_all_token_types(
-                    program_id,
-                    pool_token_amount,
-                    minimum_token_a_amount,
-                    minimum_token_b_amount,
-                    accounts,
-                )
-            }
-            SwapInstruction::WithdrawSingleTokenTypeExactAmountOut(
-                WithdrawSingleTokenTypeExactAmountOut {
-                    destination_token_amount,
-                    maximum_pool_token_amount,
-                },
-            ) => {
-                msg!("Instruction: WithdrawSingleTokenTypeExactAmountOut");
-                Self::process_withdraw_single_token_type_exact_amount_out(
-                    program_id,
-                    destination_token_amount,
-                    maximum_pool_token_amount,
-                    accounts,
-                )
-            }
-        }
-    }
-}
-
-fn to_u128(val: u64) -> Result<u128, SwapError> {
-    val.try_into().map_err(|_| SwapError::ConversionFailure)
-}
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let user_transfer_authority_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let swap_token_a_info = next_account_info(account_info_iter)?;
+        let swap_token_b_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let pool_fee_account_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
 
-fn to_u64(val: u128) -> Result<u64, SwapError> {
-    val.try_into().map_err(|_| SwapError::ConversionFailure)
-}
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        Self::check_not_metapool(token_swap.as_ref())?;
+        let destination_account =
+            Self::unpack_token_account(destination_info, token_swap.token_program_id())?;
+        let swap_token_a =
+            Self::unpack_token_account(swap_token_a_info, token_swap.token_program_id())?;
+        let swap_token_b =
+            Self::unpack_token_account(swap_token_b_info, token_swap.token_program_id())?;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::{
-        swap::calculator::{CurveCalculator, INITIAL_SWAP_POOL_AMOUNT},
-        swap::{
-            base::CurveType,
-            stable::StableCurve,
-        },
-        instruction::{
-            deposit_all_token_types, initialize, swap,
-            withdraw_all_token_types, withdraw_single_token_type_exact_amount_out,
-        },
-    };
-    use solana_program::{instruction::Instruction, program_stubs, rent::Rent};
-    use solana_sdk::account::{create_account_for_test, create_is_signer_account_infos, Account};
-    use spl_token::{
-        error::TokenError,
-        instruction::{
-            approve, initialize_account, initialize_mint, mint_to, revoke, set_authority,
-            AuthorityType,
-        },
-    };
-    use std::sync::Arc;
+        let trade_direction = if destination_account.mint == swap_token_a.mint {
+            TradeDirection::AtoB
+        } else if destination_account.mint == swap_token_b.mint {
+            TradeDirection::BtoA
+        } else {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        };
 
-    // Test program id for the swap program.
-    const SWAP_PROGRAM_ID: Pubkey = Pubkey::new_from_array([2u8; 32]);
+        let (destination_a_info, destination_b_info) = match trade_direction {
+            TradeDirection::AtoB => (Some(destination_info), None),
+            TradeDirection::BtoA => (None, Some(destination_info)),
+        };
+        Self::check_accounts(
+            token_swap.as_ref(),
+            program_id,
+            swap_info,
+            authority_info,
+            swap_token_a_info,
+            swap_token_b_info,
+            pool_mint_info,
+            token_program_info,
+            destination_a_info,
+            destination_b_info,
+            Some(pool_fee_account_info),
+        )?;
 
-    struct TestSyscallStubs {}
-    impl program_stubs::SyscallStubs for TestSyscallStubs {
-        fn sol_invoke_signed(
-            &self,
-            instruction: &Instruction,
-            account_infos: &[AccountInfo],
-            signers_seeds: &[&[&[u8]]],
-        ) -> ProgramResult {
-            msg!("TestSyscallStubs::sol_invoke_signed()");
+        let pool_mint = Self::unpack_mint(pool_mint_info, token_swap.token_program_id())?;
+        let pool_mint_supply = to_u128(pool_mint.supply)?;
+        let (swap_token_a_amount, swap_token_b_amount) =
+            Self::reserves(token_swap.as_ref(), swap_token_a_info, swap_token_b_info)?;
+        let swap_token_a_amount = to_u128(swap_token_a_amount)?;
+        let swap_token_b_amount = to_u128(swap_token_b_amount)?;
 
-            let mut new_account_infos = vec![];
+        let withdraw_fee: u128 = if *pool_fee_account_info.key == *source_info.key {
+            // withdrawing from the fee account, don't assess withdraw fee
+            0
+        } else {
+            token_swap
+                .fees()
+                .owner_withdraw_fee(to_u128(pool_token_amount)?)
+                .ok_or(SwapError::FeeCalculationFailure)?
+        };
+        let burn_pool_token_amount = to_u128(pool_token_amount)?
+            .checked_sub(withdraw_fee)
+            .ok_or(SwapError::CalculationFailure)?;
+        Self::check_minimum_liquidity(pool_mint_supply, burn_pool_token_amount)?;
 
-            // mimic check for token program in accounts
-            if !account_infos.iter().any(|x| *x.key == spl_token::id()) {
-                return Err(ProgramError::InvalidAccountData);
-            }
+        let destination_token_amount = token_swap
+            .swap_curve()
+            .withdraw_single_token_type_exact_in(
+                burn_pool_token_amount,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_mint_supply,
+                trade_direction,
+                token_swap.fees(),
+            )
+            .ok_or(SwapError::ZeroTradingTokens)?;
+        let destination_token_amount = to_u64(destination_token_amount)?;
 
-            for meta in instruction.accounts.iter() {
-                for account_info in account_infos.iter() {
-                    if meta.pubkey == *account_info.key {
-                        let mut new_account_info = account_info.clone();
-                        for seeds in signers_seeds.iter() {
-                            let signer =
-                                Pubkey::create_program_address(seeds, &SWAP_PROGRAM_ID).unwrap();
-                            if *account_info.key == signer {
-                                new_account_info.is_signer = true;
-                            }
-                        }
-                        new_account_infos.push(new_account_info);
-                    }
-                }
-            }
+        if destination_token_amount < minimum_token_amount {
+            return Err(SwapError::ExceededSlippage.into());
+        }
+        if destination_token_amount == 0 {
+            return Err(SwapError::ZeroTradingTokens.into());
+        }
+        let (new_swap_token_a_amount, new_swap_token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (
+                swap_token_a_amount
+                    .checked_sub(to_u128(destination_token_amount)?)
+                    .ok_or(SwapError::CalculationFailure)?,
+                swap_token_b_amount,
+            ),
+            TradeDirection::BtoA => (
+                swap_token_a_amount,
+                swap_token_b_amount
+                    .checked_sub(to_u128(destination_token_amount)?)
+                    .ok_or(SwapError::CalculationFailure)?,
+            ),
+        };
+        Self::check_price_band(
+            swap_info,
+            token_swap.as_ref(),
+            swap_token_a_amount,
+            swap_token_b_amount,
+            new_swap_token_a_amount,
+            new_swap_token_b_amount,
+        )?;
 
-            spl_token::processor::Processor::process(
-                &instruction.program_id,
-                &new_account_infos,
-                &instruction.data,
-            )
+        if withdraw_fee > 0 {
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                source_info.clone(),
+                pool_fee_account_info.clone(),
+                user_transfer_authority_info.clone(),
+                token_swap.bump_seed(),
+                to_u64(withdraw_fee)?,
+            )?;
         }
-    }
+        Self::token_burn(
+            swap_info.key,
+            token_program_info.clone(),
+            source_info.clone(),
+            pool_mint_info.clone(),
+            user_transfer_authority_info.clone(),
+            token_swap.bump_seed(),
+            to_u64(burn_pool_token_amount)?,
+        )?;
 
-    fn test_syscall_stubs() {
-        use std::sync::Once;
-        static ONCE: Once = Once::new();
+        let swap_token_info = match trade_direction {
+            TradeDirection::AtoB => swap_token_a_info,
+            TradeDirection::BtoA => swap_token_b_info,
+        };
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            swap_token_info.clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            token_swap.bump_seed(),
+            destination_token_amount,
+        )?;
 
-        ONCE.call_once(|| {
-            program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs {}));
-        });
-    }
+        Self::update_reserves(
+            swap_info,
+            token_swap.as_ref(),
+            new_swap_token_a_amount,
+            new_swap_token_b_amount,
+        )?;
+        Self::tweak_price(swap_info, token_swap.as_ref(), pool_mint_info, None)
+    }
+
+    /// Processes a [WithdrawAdminFees](enum.Instruction.html).
+    pub fn process_withdraw_admin_fees(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let admin_fee_a_info = next_account_info(account_info_iter)?;
+        let admin_fee_b_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut token_swap = SwapVersion::unpack_latest(&swap_info.data.borrow())?;
+        if *authority_info.key
+            != Self::authority_id(program_id, swap_info.key, token_swap.bump_seed)?
+        {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if !admin_info.is_signer || *admin_info.key != token_swap.admin {
+            return Err(SwapError::InvalidAdmin.into());
+        }
+        if *token_a_info.key != token_swap.token_a {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        if *token_b_info.key != token_swap.token_b {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        if !token_swap.admin_fees_in_tokens()
+            || *admin_fee_a_info.key != token_swap.admin_fee_account_a
+            || *admin_fee_b_info.key != token_swap.admin_fee_account_b
+        {
+            return Err(SwapError::IncorrectFeeAccount.into());
+        }
+        if *token_program_info.key != token_swap.token_program_id {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+
+        if token_swap.admin_fee_a > 0 {
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                token_a_info.clone(),
+                admin_fee_a_info.clone(),
+                authority_info.clone(),
+                token_swap.bump_seed,
+                token_swap.admin_fee_a,
+            )?;
+        }
+        if token_swap.admin_fee_b > 0 {
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                token_b_info.clone(),
+                admin_fee_b_info.clone(),
+                authority_info.clone(),
+                token_swap.bump_seed,
+                token_swap.admin_fee_b,
+            )?;
+        }
+        token_swap.admin_fee_a = 0;
+        token_swap.admin_fee_b = 0;
+        SwapVersion::pack(
+            SwapVersion::SwapV2(token_swap),
+            &mut swap_info.data.borrow_mut(),
+        )
+    }
+
+    /// Processes a [Skim](enum.Instruction.html).
+    pub fn process_skim(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let destination_a_info = next_account_info(account_info_iter)?;
+        let destination_b_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let token_swap = SwapVersion::unpack_latest(&swap_info.data.borrow())?;
+        if *authority_info.key
+            != Self::authority_id(program_id, swap_info.key, token_swap.bump_seed)?
+        {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if !admin_info.is_signer || *admin_info.key != token_swap.admin {
+            return Err(SwapError::InvalidAdmin.into());
+        }
+        if *token_a_info.key != token_swap.token_a {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        if *token_b_info.key != token_swap.token_b {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        if *token_program_info.key != token_swap.token_program_id {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+
+        let token_a = Self::unpack_token_account(token_a_info, &token_swap.token_program_id)?;
+        let token_b = Self::unpack_token_account(token_b_info, &token_swap.token_program_id)?;
+        let surplus_a =
+            Self::surplus_amount(token_a.amount, token_swap.reserve_a, token_swap.admin_fee_a)?;
+        let surplus_b =
+            Self::surplus_amount(token_b.amount, token_swap.reserve_b, token_swap.admin_fee_b)?;
+        if surplus_a > 0 {
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                token_a_info.clone(),
+                destination_a_info.clone(),
+                authority_info.clone(),
+                token_swap.bump_seed,
+                surplus_a,
+            )?;
+        }
+        if surplus_b > 0 {
+            Self::token_transfer(
+                swap_info.key,
+                token_program_info.clone(),
+                token_b_info.clone(),
+                destination_b_info.clone(),
+                authority_info.clone(),
+                token_swap.bump_seed,
+                surplus_b,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Processes a [Sync](enum.Instruction.html).
+    pub fn process_sync(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut token_swap = SwapVersion::unpack_latest(&swap_info.data.borrow())?;
+        if !admin_info.is_signer || *admin_info.key != token_swap.admin {
+            return Err(SwapError::InvalidAdmin.into());
+        }
+        if *token_a_info.key != token_swap.token_a {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        if *token_b_info.key != token_swap.token_b {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+
+        let token_a = Self::unpack_token_account(token_a_info, &token_swap.token_program_id)?;
+        let token_b = Self::unpack_token_account(token_b_info, &token_swap.token_program_id)?;
+        token_swap.reserve_a = Self::reserve_amount(token_a.amount, token_swap.admin_fee_a)?;
+        token_swap.reserve_b = Self::reserve_amount(token_b.amount, token_swap.admin_fee_b)?;
+        SwapVersion::pack(
+            SwapVersion::SwapV2(token_swap),
+            &mut swap_info.data.borrow_mut(),
+        )
+    }
+
+    /// Processes a [RescueTokens](enum.Instruction.html).
+    pub fn process_rescue_tokens(
+        program_id: &Pubkey,
+        amount: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let source_info = next_account_info(account_info_iter)?;
+        let destination_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let token_swap = SwapVersion::unpack_latest(&swap_info.data.borrow())?;
+        if *authority_info.key
+            != Self::authority_id(program_id, swap_info.key, token_swap.bump_seed)?
+        {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if !admin_info.is_signer || *admin_info.key != token_swap.admin {
+            return Err(SwapError::InvalidAdmin.into());
+        }
+        if *source_info.key == token_swap.token_a || *source_info.key == token_swap.token_b {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        // the pool fee account holds the pool fees of the fee recipients
+        if *source_info.key == token_swap.pool_fee_account {
+            return Err(SwapError::IncorrectFeeAccount.into());
+        }
+        if *token_program_info.key != token_swap.token_program_id {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+        let source = Self::unpack_token_account(source_info, &token_swap.token_program_id)?;
+        if source.owner != *authority_info.key {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        // pool tokens held by the swap authority are the locked minimum
+        // liquidity, which must stay in circulation
+        if source.mint == token_swap.pool_mint {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+
+        Self::token_transfer(
+            swap_info.key,
+            token_program_info.clone(),
+            source_info.clone(),
+            destination_info.clone(),
+            authority_info.clone(),
+            token_swap.bump_seed,
+            amount,
+        )
+    }
+
+    /// Processes a [ClosePool](enum.Instruction.html).
+    pub fn process_close_pool(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let pool_mint_info = next_account_info(account_info_iter)?;
+        let locked_info = next_account_info(account_info_iter)?;
+        let token_a_info = next_account_info(account_info_iter)?;
+        let token_b_info = next_account_info(account_info_iter)?;
+        let destination_a_info = next_account_info(account_info_iter)?;
+        let destination_b_info = next_account_info(account_info_iter)?;
+        let recipient_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let token_swap = SwapVersion::unpack_latest(&swap_info.data.borrow())?;
+        if *authority_info.key
+            != Self::authority_id(program_id, swap_info.key, token_swap.bump_seed)?
+        {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if !admin_info.is_signer || *admin_info.key != token_swap.admin {
+            return Err(SwapError::InvalidAdmin.into());
+        }
+        if *pool_mint_info.key != token_swap.pool_mint {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        if *token_a_info.key != token_swap.token_a {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        if *token_b_info.key != token_swap.token_b {
+            return Err(SwapError::IncorrectSwapAccount.into());
+        }
+        if *token_program_info.key != token_swap.token_program_id {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+        // the admin fees belong to the admin fee accounts, not to whoever
+        // receives the tokens left in the swap
+        if token_swap.admin_fee_a > 0 || token_swap.admin_fee_b > 0 {
+            return Err(SwapError::AdminFeesNotWithdrawn.into());
+        }
+        for (token_info, destination_info) in [
+            (token_a_info, destination_a_info),
+            (token_b_info, destination_b_info),
+        ]
+        .iter()
+        {
+            let token = Self::unpack_token_account(token_info, &token_swap.token_program_id)?;
+            let destination =
+                Self::unpack_token_account(destination_info, &token_swap.token_program_id)?;
+            if destination.mint != token.mint {
+                return Err(SwapError::IncorrectDestinationMint.into());
+            }
+        }
+
+        let locked = Self::unpack_token_account(locked_info, &token_swap.token_program_id)?;
+        if locked.owner != *authority_info.key {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        if locked.mint != token_swap.pool_mint {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        // only the pool tokens locked by the swap authority may be left, any
+        // others are still a claim on the swap token accounts
+        let pool_mint = Self::unpack_mint(pool_mint_info, &token_swap.token_program_id)?;
+        if pool_mint.supply != locked.amount {
+            return Err(SwapError::PoolNotEmpty.into());
+        }
+        if locked.amount > 0 {
+            Self::token_burn(
+                swap_info.key,
+                token_program_info.clone(),
+                locked_info.clone(),
+                pool_mint_info.clone(),
+                authority_info.clone(),
+                token_swap.bump_seed,
+                locked.amount,
+            )?;
+        }
+        Self::token_close_account(
+            swap_info.key,
+            token_program_info.clone(),
+            locked_info.clone(),
+            recipient_info.clone(),
+            authority_info.clone(),
+            token_swap.bump_seed,
+        )?;
+
+        for (token_info, destination_info) in [
+            (token_a_info, destination_a_info),
+            (token_b_info, destination_b_info),
+        ]
+        .iter()
+        {
+            let token = Self::unpack_token_account(token_info, &token_swap.token_program_id)?;
+            if token.amount > 0 {
+                Self::token_transfer(
+                    swap_info.key,
+                    token_program_info.clone(),
+                    (*token_info).clone(),
+                    (*destination_info).clone(),
+                    authority_info.clone(),
+                    token_swap.bump_seed,
+                    token.amount,
+                )?;
+            }
+            Self::token_close_account(
+                swap_info.key,
+                token_program_info.clone(),
+                (*token_info).clone(),
+                recipient_info.clone(),
+                authority_info.clone(),
+                token_swap.bump_seed,
+            )?;
+        }
+
+        let recipient_lamports = recipient_info
+            .lamports()
+            .checked_add(swap_info.lamports())
+            .ok_or(SwapError::CalculationFailure)?;
+        **recipient_info.lamports.borrow_mut() = recipient_lamports;
+        **swap_info.lamports.borrow_mut() = 0;
+        for byte in swap_info.data.borrow_mut().iter_mut() {
+            *byte = 0;
+        }
+        Ok(())
+    }
+
+    /// Processes a [SetFeeRecipients](enum.Instruction.html).
+    pub fn process_set_fee_recipients(
+        program_id: &Pubkey,
+        weights: [u16; MAX_FEE_RECIPIENTS],
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let pool_fee_account_info = next_account_info(account_info_iter)?;
+
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut token_swap = SwapVersion::unpack_latest(&swap_info.data.borrow())?;
+        if *authority_info.key
+            != Self::authority_id(program_id, swap_info.key, token_swap.bump_seed)?
+        {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if !admin_info.is_signer || *admin_info.key != token_swap.admin {
+            return Err(SwapError::InvalidAdmin.into());
+        }
+
+        let pool_fee_account =
+            Self::unpack_token_account(pool_fee_account_info, &token_swap.token_program_id)?;
+        if pool_fee_account.mint != token_swap.pool_mint {
+            return Err(SwapError::IncorrectPoolMint.into());
+        }
+        if *authority_info.key != pool_fee_account.owner {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        if pool_fee_account.delegate.is_some() {
+            return Err(SwapError::InvalidDelegate.into());
+        }
+        if pool_fee_account.close_authority.is_some() {
+            return Err(SwapError::InvalidCloseAuthority.into());
+        }
+
+        let num_recipients = weights.iter().take_while(|weight| **weight > 0).count();
+        if num_recipients == 0 || weights[num_recipients..].iter().any(|weight| *weight > 0) {
+            return Err(SwapError::InvalidFeeRecipients.into());
+        }
+        let total_weight: u32 = weights.iter().map(|weight| u32::from(*weight)).sum();
+        if total_weight != u32::from(FEE_WEIGHTS_DENOMINATOR) {
+            return Err(SwapError::InvalidFeeRecipients.into());
+        }
+
+        let mut fee_recipients = [Pubkey::default(); MAX_FEE_RECIPIENTS];
+        for fee_recipient in fee_recipients.iter_mut().take(num_recipients) {
+            let recipient_info = next_account_info(account_info_iter)?;
+            let recipient =
+                Self::unpack_token_account(recipient_info, &token_swap.token_program_id)?;
+            if recipient.mint != token_swap.pool_mint {
+                return Err(SwapError::IncorrectPoolMint.into());
+            }
+            if *recipient_info.key == *pool_fee_account_info.key {
+                return Err(SwapError::InvalidFeeRecipients.into());
+            }
+            *fee_recipient = *recipient_info.key;
+        }
+
+        token_swap.pool_fee_account = *pool_fee_account_info.key;
+        token_swap.fee_recipients = fee_recipients;
+        token_swap.fee_recipient_weights = weights;
+        SwapVersion::pack(
+            SwapVersion::SwapV2(token_swap),
+            &mut swap_info.data.borrow_mut(),
+        )
+    }
+
+    /// Processes a [DistributeFees](enum.Instruction.html).
+    pub fn process_distribute_fees(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let authority_info = next_account_info(account_info_iter)?;
+        let pool_fee_account_info = next_account_info(account_info_iter)?;
+        let token_program_info = next_account_info(account_info_iter)?;
+
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        if *authority_info.key
+            != Self::authority_id(program_id, swap_info.key, token_swap.bump_seed())?
+        {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        if *pool_fee_account_info.key != *token_swap.pool_fee_account() {
+            return Err(SwapError::IncorrectFeeAccount.into());
+        }
+        if *token_program_info.key != *token_swap.token_program_id() {
+            return Err(SwapError::IncorrectTokenProgramId.into());
+        }
+        if token_swap.fee_recipient_weights()[0] == 0 {
+            return Err(SwapError::InvalidFeeRecipients.into());
+        }
+
+        let pool_fee_account =
+            Self::unpack_token_account(pool_fee_account_info, token_swap.token_program_id())?;
+        let fee_amount = to_u128(pool_fee_account.amount)?;
+        for (fee_recipient, weight) in token_swap
+            .fee_recipients()
+            .iter()
+            .zip(token_swap.fee_recipient_weights().iter())
+            .filter(|(_, weight)| **weight > 0)
+        {
+            let recipient_info = next_account_info(account_info_iter)?;
+            if *recipient_info.key != *fee_recipient {
+                return Err(SwapError::IncorrectFeeAccount.into());
+            }
+            let share = fee_amount
+                .checked_mul(u128::from(*weight))
+                .and_then(|amount| amount.checked_div(u128::from(FEE_WEIGHTS_DENOMINATOR)))
+                .ok_or(SwapError::FeeCalculationFailure)?;
+            if share > 0 {
+                Self::token_transfer(
+                    swap_info.key,
+                    token_program_info.clone(),
+                    pool_fee_account_info.clone(),
+                    recipient_info.clone(),
+                    authority_info.clone(),
+                    token_swap.bump_seed(),
+                    to_u64(share)?,
+                )?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Processes a [SetProgramConfig](enum.Instruction.html).
+    pub fn process_set_program_config(
+        program_id: &Pubkey,
+        instruction: SetProgramConfig,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let config_info = next_account_info(account_info_iter)?;
+        let upgrade_authority_info = next_account_info(account_info_iter)?;
+        let program_data_info = next_account_info(account_info_iter)?;
+        let payer_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+
+        let (config_key, bump_seed) = ProgramConfig::find_address(program_id);
+        if *config_info.key != config_key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+        Self::check_upgrade_authority(program_id, upgrade_authority_info, program_data_info)?;
+
+        if instruction.owner_key == Pubkey::default() {
+            return Err(SwapError::InvalidOwner.into());
+        }
+        if instruction.min_amp > instruction.max_amp {
+            return Err(SwapError::InvalidCurve.into());
+        }
+        instruction.fees.validate()?;
+        instruction.max_fees.validate()?;
+
+        if config_info.owner != program_id {
+            Self::create_program_account(
+                program_id,
+                config_info,
+                payer_info,
+                system_program_info,
+                ProgramConfig::LEN,
+                &[PROGRAM_CONFIG_SEED, &[bump_seed]],
+            )?;
+        }
+
+        let config = ProgramConfig {
+            is_initialized: true,
+            bump_seed,
+            owner_key: instruction.owner_key,
+            valid_curve_types: instruction.valid_curve_types,
+            min_amp: instruction.min_amp,
+            max_amp: instruction.max_amp,
+            fees: instruction.fees,
+            max_fees: instruction.max_fees,
+            allowed_mints: instruction.allowed_mints,
+            denied_mints: instruction.denied_mints,
+        };
+        ProgramConfig::pack(config, &mut config_info.data.borrow_mut())?;
+        Ok(())
+    }
+
+    /// Processes a [SetDepositGate](enum.Instruction.html).
+    pub fn process_set_deposit_gate(
+        program_id: &Pubkey,
+        enabled: bool,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut token_swap = SwapVersion::unpack_latest(&swap_info.data.borrow())?;
+        if !admin_info.is_signer || *admin_info.key != token_swap.admin {
+            return Err(SwapError::InvalidAdmin.into());
+        }
+
+        token_swap.deposit_gate = enabled;
+        SwapVersion::pack(
+            SwapVersion::SwapV2(token_swap),
+            &mut swap_info.data.borrow_mut(),
+        )
+    }
+
+    /// Processes a [SetDepositCaps](enum.Instruction.html).
+    pub fn process_set_deposit_caps(
+        program_id: &Pubkey,
+        max_token_a_amount: u64,
+        max_token_b_amount: u64,
+        max_pool_supply: u64,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut token_swap = SwapVersion::unpack_latest(&swap_info.data.borrow())?;
+        if !admin_info.is_signer || *admin_info.key != token_swap.admin {
+            return Err(SwapError::InvalidAdmin.into());
+        }
+
+        token_swap.max_token_a_amount = max_token_a_amount;
+        token_swap.max_token_b_amount = max_token_b_amount;
+        token_swap.max_pool_supply = max_pool_supply;
+        SwapVersion::pack(
+            SwapVersion::SwapV2(token_swap),
+            &mut swap_info.data.borrow_mut(),
+        )
+    }
+
+    /// Processes a [SetPriceBand](enum.Instruction.html).
+    pub fn process_set_price_band(
+        program_id: &Pubkey,
+        price_band: u16,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut token_swap = SwapVersion::unpack_latest(&swap_info.data.borrow())?;
+        if !admin_info.is_signer || *admin_info.key != token_swap.admin {
+            return Err(SwapError::InvalidAdmin.into());
+        }
+        if price_band > PRICE_BAND_DENOMINATOR {
+            return Err(SwapError::InvalidInput.into());
+        }
+        if price_band > 0 && token_swap.swap_curve.calculator.spot_price(1, 1).is_none() {
+            return Err(SwapError::UnsupportedCurveOperation.into());
+        }
+
+        token_swap.price_band = price_band;
+        // start over from the spot price of the next swap or withdrawal
+        token_swap.reference_price = 0;
+        SwapVersion::pack(
+            SwapVersion::SwapV2(token_swap),
+            &mut swap_info.data.borrow_mut(),
+        )
+    }
+
+    /// Processes a [SetOracle](enum.Instruction.html).
+    pub fn process_set_oracle(
+        program_id: &Pubkey,
+        oracle_type: OracleType,
+        max_deviation: u16,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let oracle_info = next_account_info(account_info_iter)?;
+
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let mut token_swap = SwapVersion::unpack_latest(&swap_info.data.borrow())?;
+        if !admin_info.is_signer || *admin_info.key != token_swap.admin {
+            return Err(SwapError::InvalidAdmin.into());
+        }
+        if max_deviation > ORACLE_DEVIATION_DENOMINATOR {
+            return Err(SwapError::InvalidInput.into());
+        }
+
+        if max_deviation > 0 {
+            if token_swap.swap_curve.calculator.spot_price(1, 1).is_none() {
+                return Err(SwapError::UnsupportedCurveOperation.into());
+            }
+            oracle_type.load_price(&oracle_info.data.borrow())?;
+            token_swap.oracle = *oracle_info.key;
+        } else {
+            token_swap.oracle = Pubkey::default();
+        }
+        token_swap.oracle_type = oracle_type;
+        token_swap.max_oracle_deviation = max_deviation;
+        SwapVersion::pack(
+            SwapVersion::SwapV2(token_swap),
+            &mut swap_info.data.borrow_mut(),
+        )
+    }
+
+    /// Processes a [SetAllowlistEntry](enum.Instruction.html).
+    pub fn process_set_allowlist_entry(
+        program_id: &Pubkey,
+        allowed: bool,
+        accounts: &[AccountInfo],
+    ) -> ProgramResult {
+        let account_info_iter = &mut accounts.iter();
+        let swap_info = next_account_info(account_info_iter)?;
+        let admin_info = next_account_info(account_info_iter)?;
+        let allowlist_entry_info = next_account_info(account_info_iter)?;
+        let user_info = next_account_info(account_info_iter)?;
+        let payer_info = next_account_info(account_info_iter)?;
+        let system_program_info = next_account_info(account_info_iter)?;
+
+        if swap_info.owner != program_id {
+            return Err(ProgramError::IncorrectProgramId);
+        }
+        let token_swap = SwapVersion::unpack(&swap_info.data.borrow())?;
+        if !admin_info.is_signer || *admin_info.key != *token_swap.admin() {
+            return Err(SwapError::InvalidAdmin.into());
+        }
+        // the payer chosen by the admin funds the entry and gets its rent back
+        if !payer_info.is_signer {
+            return Err(ProgramError::MissingRequiredSignature);
+        }
+        let (allowlist_entry_key, bump_seed) =
+            AllowlistEntry::find_address(program_id, swap_info.key, user_info.key);
+        if *allowlist_entry_info.key != allowlist_entry_key {
+            return Err(SwapError::InvalidProgramAddress.into());
+        }
+
+        if allowed {
+            if allowlist_entry_info.owner != program_id {
+                Self::create_program_account(
+                    program_id,
+                    allowlist_entry_info,
+                    payer_info,
+                    system_program_info,
+                    AllowlistEntry::LEN,
+                    &[
+                        ALLOWLIST_SEED,
+                        swap_info.key.as_ref(),
+                        user_info.key.as_ref(),
+                        &[bump_seed],
+                    ],
+                )?;
+            } else if allowlist_entry_info.lamports() == 0 {
+                // closed earlier in the same transaction, the runtime only
+                // deletes the account once the transaction is done
+                return Err(ProgramError::AccountNotRentExempt);
+            }
+            let allowlist_entry = AllowlistEntry {
+                is_initialized: true,
+                bump_seed,
+                swap: *swap_info.key,
+                user: *user_info.key,
+            };
+            AllowlistEntry::pack(allowlist_entry, &mut allowlist_entry_info.data.borrow_mut())?;
+        } else if allowlist_entry_info.owner == program_id {
+            // close the entry, refunding its rent to the payer
+            let payer_lamports = payer_info
+                .lamports()
+                .checked_add(allowlist_entry_info.lamports())
+                .ok_or(SwapError::CalculationFailure)?;
+            **payer_info.lamports.borrow_mut() = payer_lamports;
+            **allowlist_entry_info.lamports.borrow_mut() = 0;
+            for byte in allowlist_entry_info.data.borrow_mut().iter_mut() {
+                *byte = 0;
+            }
+        }
+        Ok(())
+    }
+
+    /// Processes an [Instruction](enum.Instruction.html).
+    pub fn process(program_id: &Pubkey, accounts: &[AccountInfo], input: &[u8]) -> ProgramResult {
+        Self::process_with_constraints(program_id, accounts, input, &SWAP_CONSTRAINTS)
+    }
+
+    /// Processes an instruction given extra constraint
+    pub fn process_with_constraints(
+        program_id: &Pubkey,
+        accounts: &[AccountInfo],
+        input: &[u8],
+        swap_constraints: &Option<SwapConstraints>,
+    ) -> ProgramResult {
+        let instruction = SwapInstruction::unpack(input)?;
+        match instruction {
+            SwapInstruction::Initialize(Initialize { fees, swap_curve }) => {
+                msg!("Instruction: Init");
+                Self::process_initialize(program_id, fees, swap_curve, accounts, swap_constraints)
+            }
+            SwapInstruction::Swap(Swap {
+                amount_in,
+                minimum_amount_out,
+            }) => {
+                msg!("Instruction: Swap");
+                Self::process_swap(program_id, amount_in, minimum_amount_out, accounts)
+            }
+            SwapInstruction::DepositAllTokenTypes(DepositAllTokenTypes {
+                pool_token_amount,
+                maximum_token_a_amount,
+                maximum_token_b_amount,
+            }) => {
+                msg!("Instruction: DepositAllTokenTypes");
+                Self::process_deposit_all_token_types(
+                    program_id,
+                    pool_token_amount,
+                    maximum_token_a_amount,
+                    maximum_token_b_amount,
+                    accounts,
+                )
+            }
+            SwapInstruction::WithdrawAllTokenTypes(WithdrawAllTokenTypes {
+                pool_token_amount,
+                minimum_token_a_amount,
+                minimum_token_b_amount,
+            }) => {
+                msg!("Instruction: WithdrawAllTokenTypes");
+                Self::process_withdraw_all_token_types(
+                    program_id,
+                    pool_token_amount,
+                    minimum_token_a_amount,
+                    minimum_token_b_amount,
+                    accounts,
+                )
+            }
+            SwapInstruction::WithdrawSingleTokenTypeExactAmountOut(
+                WithdrawSingleTokenTypeExactAmountOut {
+                    destination_token_amount,
+                    maximum_pool_token_amount,
+                },
+            ) => {
+                msg!("Instruction: WithdrawSingleTokenTypeExactAmountOut");
+                Self::process_withdraw_single_token_type_exact_amount_out(
+                    program_id,
+                    destination_token_amount,
+                    maximum_pool_token_amount,
+                    accounts,
+                )
+            }
+            SwapInstruction::WithdrawAdminFees => {
+                msg!("Instruction: WithdrawAdminFees");
+                Self::process_withdraw_admin_fees(program_id, accounts)
+            }
+            SwapInstruction::SetFeeRecipients(SetFeeRecipients { weights }) => {
+                msg!("Instruction: SetFeeRecipients");
+                Self::process_set_fee_recipients(program_id, weights, accounts)
+            }
+            SwapInstruction::DistributeFees => {
+                msg!("Instruction: DistributeFees");
+                Self::process_distribute_fees(program_id, accounts)
+            }
+            SwapInstruction::WithdrawSingleTokenTypeExactAmountIn(
+                WithdrawSingleTokenTypeExactAmountIn {
+                    pool_token_amount,
+                    minimum_token_amount,
+                },
+            ) => {
+                msg!("Instruction: WithdrawSingleTokenTypeExactAmountIn");
+                Self::process_withdraw_single_token_type_exact_amount_in(
+                    program_id,
+                    pool_token_amount,
+                    minimum_token_amount,
+                    accounts,
+                )
+            }
+            SwapInstruction::SetProgramConfig(instruction) => {
+                msg!("Instruction: SetProgramConfig");
+                Self::process_set_program_config(program_id, *instruction, accounts)
+            }
+            SwapInstruction::SetDepositGate(SetDepositGate { enabled }) => {
+                msg!("Instruction: SetDepositGate");
+                Self::process_set_deposit_gate(program_id, enabled, accounts)
+            }
+            SwapInstruction::SetAllowlistEntry(SetAllowlistEntry { allowed }) => {
+                msg!("Instruction: SetAllowlistEntry");
+                Self::process_set_allowlist_entry(program_id, allowed, accounts)
+            }
+            SwapInstruction::SetDepositCaps(SetDepositCaps {
+                max_token_a_amount,
+                max_token_b_amount,
+                max_pool_supply,
+            }) => {
+                msg!("Instruction: SetDepositCaps");
+                Self::process_set_deposit_caps(
+                    program_id,
+                    max_token_a_amount,
+                    max_token_b_amount,
+                    max_pool_supply,
+                    accounts,
+                )
+            }
+            SwapInstruction::SetPriceBand(SetPriceBand { price_band }) => {
+                msg!("Instruction: SetPriceBand");
+                Self::process_set_price_band(program_id, price_band, accounts)
+            }
+            SwapInstruction::SetOracle(SetOracle {
+                oracle_type,
+                max_deviation,
+            }) => {
+                msg!("Instruction: SetOracle");
+                Self::process_set_oracle(program_id, oracle_type, max_deviation, accounts)
+            }
+            SwapInstruction::Skim => {
+                msg!("Instruction: Skim");
+                Self::process_skim(program_id, accounts)
+            }
+            SwapInstruction::Sync => {
+                msg!("Instruction: Sync");
+                Self::process_sync(program_id, accounts)
+            }
+            SwapInstruction::RescueTokens(RescueTokens { amount }) => {
+                msg!("Instruction: RescueTokens");
+                Self::process_rescue_tokens(program_id, amount, accounts)
+            }
+            SwapInstruction::ClosePool => {
+                msg!("Instruction: ClosePool");
+                Self::process_close_pool(program_id, accounts)
+            }
+            SwapInstruction::MigrateLiquidity(MigrateLiquidity {
+                pool_token_amount,
+                minimum_pool_token_amount,
+            }) => {
+                msg!("Instruction: MigrateLiquidity");
+                Self::process_migrate_liquidity(
+                    program_id,
+                    pool_token_amount,
+                    minimum_pool_token_amount,
+                    accounts,
+                )
+            }
+            SwapInstruction::SwapUnderlying(SwapUnderlying {
+                amount_in,
+                minimum_amount_out,
+            }) => {
+                msg!("Instruction: SwapUnderlying");
+                Self::process_swap_underlying(program_id, amount_in, minimum_amount_out, accounts)
+            }
+            SwapInstruction::DepositUnderlying(DepositUnderlying {
+                base_pool_token_amount,
+                pool_token_amount,
+                maximum_token_a_amount,
+                maximum_base_token_a_amount,
+                maximum_base_token_b_amount,
+            }) => {
+                msg!("Instruction: DepositUnderlying");
+                Self::process_deposit_underlying(
+                    program_id,
+                    base_pool_token_amount,
+                    pool_token_amount,
+                    maximum_token_a_amount,
+                    maximum_base_token_a_amount,
+                    maximum_base_token_b_amount,
+                    accounts,
+                )
+            }
+        }
+    }
+}
+
+fn to_u128(val: u64) -> Result<u128, SwapError> {
+    val.try_into().map_err(|_| SwapError::ConversionFailure)
+}
+
+fn to_u64(val: u128) -> Result<u64, SwapError> {
+    val.try_into().map_err(|_| SwapError::ConversionFailure)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        state::SwapV1,
+        swap::calculator::{CurveCalculator, SPOT_PRICE_PRECISION},
+        swap::constant_price::ConstantPriceCurve,
+        swap::crypto::{MA_HALF_TIME, PRECISION},
+        swap::hybrid::HybridCurve,
+        swap::offset::OffsetCurve,
+        swap::pegged::{PeggedCurve, TARGET_PRICE_PRECISION},
+        swap::stable::{StableCurve, MAX_AMP, MIN_AMP},
+        swap::weighted::WeightedCurve,
+        instruction::{
+            close_pool, deposit_underlying, distribute_fees, initialize, migrate_liquidity,
+            rescue_tokens, set_allowlist_entry, set_deposit_caps, set_deposit_gate,
+            set_fee_recipients, set_oracle, set_price_band, set_program_config, skim, swap,
+            swap_underlying, sync, withdraw_admin_fees, withdraw_all_token_types,
+            withdraw_single_token_type_exact_amount_out,
+        },
+        oracle::PriceConf,
+        state::MAX_CONFIG_MINTS,
+    };
+    use solana_program::{
+        entrypoint::SUCCESS, instruction::Instruction, program_stubs, rent::Rent,
+    };
+    use solana_sdk::account::{create_account_for_test, create_is_signer_account_infos, Account};
+    use spl_token::{
+        error::TokenError,
+        instruction::{
+            approve, initialize_account, initialize_mint, mint_to, revoke, set_authority, transfer,
+            AuthorityType,
+        },
+    };
+
+    // Test program id for the swap program.
+    const SWAP_PROGRAM_ID: Pubkey = Pubkey::new_from_array([2u8; 32]);
+
+    struct TestSyscallStubs {}
+    impl program_stubs::SyscallStubs for TestSyscallStubs {
+        fn sol_invoke_signed(
+            &self,
+            instruction: &Instruction,
+            account_infos: &[AccountInfo],
+            signers_seeds: &[&[&[u8]]],
+        ) -> ProgramResult {
+            msg!("TestSyscallStubs::sol_invoke_signed()");
+
+            let mut new_account_infos = vec![];
+
+            // mimic check for token program in accounts
+            if !account_infos.iter().any(|x| *x.key == spl_token::id()) {
+                return Err(ProgramError::InvalidAccountData);
+            }
+
+            for meta in instruction.accounts.iter() {
+                for account_info in account_infos.iter() {
+                    if meta.pubkey == *account_info.key {
+                        let mut new_account_info = account_info.clone();
+                        for seeds in signers_seeds.iter() {
+                            let signer =
+                                Pubkey::create_program_address(seeds, &SWAP_PROGRAM_ID).unwrap();
+                            if *account_info.key == signer {
+                                new_account_info.is_signer = true;
+                            }
+                        }
+                        new_account_infos.push(new_account_info);
+                    }
+                }
+            }
+
+            if instruction.program_id == SWAP_PROGRAM_ID {
+                Processor::process(
+                    &instruction.program_id,
+                    &new_account_infos,
+                    &instruction.data,
+                )
+            } else {
+                spl_token::processor::Processor::process(
+                    &instruction.program_id,
+                    &new_account_infos,
+                    &instruction.data,
+                )
+            }
+        }
+
+        fn sol_get_clock_sysvar(&self, var_addr: *mut u8) -> u64 {
+            unsafe {
+                *(var_addr as *mut Clock) = Clock::default();
+            }
+            SUCCESS
+        }
+    }
+
+    fn test_syscall_stubs() {
+        use std::sync::Once;
+        static ONCE: Once = Once::new();
+
+        ONCE.call_once(|| {
+            program_stubs::set_syscall_stubs(Box::new(TestSyscallStubs {}));
+        });
+    }
+
+    struct SwapAccountInfo {
+        bump_seed: u8,
+        authority_key: Pubkey,
+        fees: Fees,
+        swap_curve: SwapCurve,
+        swap_key: Pubkey,
+        swap_account: Account,
+        pool_mint_key: Pubkey,
+        pool_mint_account: Account,
+        pool_fee_key: Pubkey,
+        pool_fee_account: Account,
+        pool_token_key: Pubkey,
+        pool_token_account: Account,
+        pool_locked_key: Pubkey,
+        pool_locked_account: Account,
+        config_key: Pubkey,
+        config_account: Account,
+        token_a_key: Pubkey,
+        token_a_account: Account,
+        token_a_mint_key: Pubkey,
+        token_a_mint_account: Account,
+        token_b_key: Pubkey,
+        token_b_account: Account,
+        token_b_mint_key: Pubkey,
+        token_b_mint_account: Account,
+    }
+
+    impl SwapAccountInfo {
+        pub fn new(
+            user_key: &Pubkey,
+            fees: Fees,
+            swap_curve: SwapCurve,
+            token_a_amount: u64,
+            token_b_amount: u64,
+        ) -> Self {
+            let swap_key = Pubkey::new_unique();
+            let swap_account = Account::new(0, SwapVersion::LATEST_LEN, &SWAP_PROGRAM_ID);
+            let (authority_key, bump_seed) =
+                Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
+
+            let (pool_mint_key, mut pool_mint_account) =
+                create_mint(&spl_token::id(), &authority_key, None);
+            let (pool_token_key, pool_token_account) = mint_token(
+                &spl_token::id(),
+                &pool_mint_key,
+                &mut pool_mint_account,
+                &authority_key,
+                user_key,
+                0,
+            );
+            let (pool_fee_key, pool_fee_account) = mint_token(
+                &spl_token::id(),
+                &pool_mint_key,
+                &mut pool_mint_account,
+                &authority_key,
+                user_key,
+                0,
+            );
+            let (pool_locked_key, pool_locked_account) = mint_token(
+                &spl_token::id(),
+                &pool_mint_key,
+                &mut pool_mint_account,
+                &authority_key,
+                &authority_key,
+                0,
+            );
+            let (config_key, _bump_seed) = ProgramConfig::find_address(&SWAP_PROGRAM_ID);
+            let config_account = Account::default();
+            let (token_a_mint_key, mut token_a_mint_account) =
+                create_mint(&spl_token::id(), user_key, None);
+            let (token_a_key, token_a_account) = mint_token(
+                &spl_token::id(),
+                &token_a_mint_key,
+                &mut token_a_mint_account,
+                user_key,
+                &authority_key,
+                token_a_amount,
+            );
+            let (token_b_mint_key, mut token_b_mint_account) =
+                create_mint(&spl_token::id(), user_key, None);
+            let (token_b_key, token_b_account) = mint_token(
+                &spl_token::id(),
+                &token_b_mint_key,
+                &mut token_b_mint_account,
+                user_key,
+                &authority_key,
+                token_b_amount,
+            );
+
+            SwapAccountInfo {
+                bump_seed,
+                authority_key,
+                fees,
+                swap_curve,
+                swap_key,
+                swap_account,
+                pool_mint_key,
+                pool_mint_account,
+                pool_fee_key,
+                pool_fee_account,
+                pool_token_key,
+                pool_token_account,
+                pool_locked_key,
+                pool_locked_account,
+                config_key,
+                config_account,
+                token_a_key,
+                token_a_account,
+                token_a_mint_key,
+                token_a_mint_account,
+                token_b_key,
+                token_b_account,
+                token_b_mint_key,
+                token_b_mint_account,
+            }
+        }
+
+        pub fn initialize_swap(&mut self) -> ProgramResult {
+            do_process_instruction(
+                initialize(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    &self.pool_mint_key,
+                    &self.pool_fee_key,
+                    &self.pool_token_key,
+                    &self.pool_locked_key,
+                    &self.config_key,
+                    None,
+                    None,
+                    self.fees.clone(),
+                    self.swap_curve.clone(),
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.pool_mint_account,
+                    &mut self.pool_fee_account,
+                    &mut self.pool_token_account,
+                    &mut self.pool_locked_account,
+                    &mut Account::default(),
+                    &mut self.config_account,
+                ],
+            )
+        }
+
+        pub fn initialize_swap_with_admin_fees(
+            &mut self,
+            admin_fee_a_key: &Pubkey,
+            admin_fee_a_account: &mut Account,
+            admin_fee_b_key: &Pubkey,
+            admin_fee_b_account: &mut Account,
+        ) -> ProgramResult {
+            do_process_instruction(
+                initialize(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    &self.pool_mint_key,
+                    &self.pool_fee_key,
+                    &self.pool_token_key,
+                    &self.pool_locked_key,
+                    &self.config_key,
+                    Some((admin_fee_a_key, admin_fee_b_key)),
+                    None,
+                    self.fees.clone(),
+                    self.swap_curve.clone(),
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.pool_mint_account,
+                    &mut self.pool_fee_account,
+                    &mut self.pool_token_account,
+                    &mut self.pool_locked_account,
+                    &mut Account::default(),
+                    &mut self.config_account,
+                    admin_fee_a_account,
+                    admin_fee_b_account,
+                ],
+            )
+        }
+
+        pub fn initialize_metapool(&mut self, base: &mut SwapAccountInfo) -> ProgramResult {
+            do_process_instruction(
+                initialize(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    &self.pool_mint_key,
+                    &self.pool_fee_key,
+                    &self.pool_token_key,
+                    &self.pool_locked_key,
+                    &self.config_key,
+                    None,
+                    Some((&base.swap_key, &base.pool_mint_key)),
+                    self.fees.clone(),
+                    self.swap_curve.clone(),
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.pool_mint_account,
+                    &mut self.pool_fee_account,
+                    &mut self.pool_token_account,
+                    &mut self.pool_locked_account,
+                    &mut Account::default(),
+                    &mut self.config_account,
+                    &mut base.swap_account,
+                    &mut base.pool_mint_account,
+                ],
+            )
+        }
+
+        pub fn setup_token_accounts(
+            &mut self,
+            mint_owner: &Pubkey,
+            account_owner: &Pubkey,
+            a_amount: u64,
+            b_amount: u64,
+            pool_amount: u64,
+        ) -> (Pubkey, Account, Pubkey, Account, Pubkey, Account) {
+            let (token_a_key, token_a_account) = mint_token(
+                &spl_token::id(),
+                &self.token_a_mint_key,
+                &mut self.token_a_mint_account,
+                mint_owner,
+                account_owner,
+                a_amount,
+            );
+            let (token_b_key, token_b_account) = mint_token(
+                &spl_token::id(),
+                &self.token_b_mint_key,
+                &mut self.token_b_mint_account,
+                mint_owner,
+                account_owner,
+                b_amount,
+            );
+            let (pool_key, pool_account) = mint_token(
+                &spl_token::id(),
+                &self.pool_mint_key,
+                &mut self.pool_mint_account,
+                &self.authority_key,
+                account_owner,
+                pool_amount,
+            );
+            (
+                token_a_key,
+                token_a_account,
+                token_b_key,
+                token_b_account,
+                pool_key,
+                pool_account,
+            )
+        }
+
+        fn get_token_account(&self, account_key: &Pubkey) -> &Account {
+            if *account_key == self.token_a_key {
+                return &self.token_a_account;
+            } else if *account_key == self.token_b_key {
+                return &self.token_b_account;
+            }
+            panic!("Could not find matching swap token account");
+        }
+
+        fn set_token_account(&mut self, account_key: &Pubkey, account: Account) {
+            if *account_key == self.token_a_key {
+                self.token_a_account = account;
+                return;
+            } else if *account_key == self.token_b_key {
+                self.token_b_account = account;
+                return;
+            }
+            panic!("Could not find matching swap token account");
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn swap(
+            &mut self,
+            user_key: &Pubkey,
+            user_source_key: &Pubkey,
+            user_source_account: &mut Account,
+            swap_source_key: &Pubkey,
+            swap_destination_key: &Pubkey,
+            user_destination_key: &Pubkey,
+            user_destination_account: &mut Account,
+            amount_in: u64,
+            minimum_amount_out: u64,
+        ) -> ProgramResult {
+            self.swap_with_oracle(
+                user_key,
+                user_source_key,
+                user_source_account,
+                swap_source_key,
+                swap_destination_key,
+                user_destination_key,
+                user_destination_account,
+                None,
+                amount_in,
+                minimum_amount_out,
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn swap_with_oracle(
+            &mut self,
+            user_key: &Pubkey,
+            user_source_key: &Pubkey,
+            user_source_account: &mut Account,
+            swap_source_key: &Pubkey,
+            swap_destination_key: &Pubkey,
+            user_destination_key: &Pubkey,
+            user_destination_account: &mut Account,
+            oracle: Option<(&Pubkey, &mut Account)>,
+            amount_in: u64,
+            minimum_amount_out: u64,
+        ) -> ProgramResult {
+            let user_transfer_key = Pubkey::new_unique();
+            // approve moving from user source account
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    user_source_key,
+                    &user_transfer_key,
+                    user_key,
+                    &[],
+                    amount_in,
+                )
+                .unwrap(),
+                vec![
+                    user_source_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            let mut swap_source_account = self.get_token_account(swap_source_key).clone();
+            let mut swap_destination_account = self.get_token_account(swap_destination_key).clone();
+
+            let (oracle_key, oracle_account) = match oracle {
+                Some((oracle_key, oracle_account)) => (Some(oracle_key), Some(oracle_account)),
+                None => (None, None),
+            };
+            let mut authority_account = Account::default();
+            let mut user_transfer_account = Account::default();
+            let mut token_program_account = Account::default();
+            let mut swap_accounts = vec![
+                &mut self.swap_account,
+                &mut authority_account,
+                &mut user_transfer_account,
+                user_source_account,
+                &mut swap_source_account,
+                &mut swap_destination_account,
+                user_destination_account,
+                &mut self.pool_mint_account,
+                &mut self.pool_fee_account,
+                &mut token_program_account,
+            ];
+            if let Some(oracle_account) = oracle_account {
+                swap_accounts.push(oracle_account);
+            }
+
+            // perform the swap
+            do_process_instruction(
+                swap(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_key,
+                    user_source_key,
+                    swap_source_key,
+                    swap_destination_key,
+                    user_destination_key,
+                    &self.pool_mint_key,
+                    &self.pool_fee_key,
+                    None,
+                    None,
+                    oracle_key,
+                    Swap {
+                        amount_in,
+                        minimum_amount_out,
+                    },
+                )
+                .unwrap(),
+                swap_accounts,
+            )?;
+
+            self.set_token_account(swap_source_key, swap_source_account);
+            self.set_token_account(swap_destination_key, swap_destination_account);
+
+            Ok(())
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn swap_metapool(
+            &mut self,
+            base: &SwapAccountInfo,
+            user_key: &Pubkey,
+            user_source_key: &Pubkey,
+            user_source_account: &mut Account,
+            swap_source_key: &Pubkey,
+            swap_destination_key: &Pubkey,
+            user_destination_key: &Pubkey,
+            user_destination_account: &mut Account,
+            amount_in: u64,
+            minimum_amount_out: u64,
+        ) -> ProgramResult {
+            let user_transfer_key = Pubkey::new_unique();
+            // approve moving from user source account
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    user_source_key,
+                    &user_transfer_key,
+                    user_key,
+                    &[],
+                    amount_in,
+                )
+                .unwrap(),
+                vec![
+                    user_source_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            let mut swap_source_account = self.get_token_account(swap_source_key).clone();
+            let mut swap_destination_account = self.get_token_account(swap_destination_key).clone();
+
+            do_process_instruction(
+                swap(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_key,
+                    user_source_key,
+                    swap_source_key,
+                    swap_destination_key,
+                    user_destination_key,
+                    &self.pool_mint_key,
+                    &self.pool_fee_key,
+                    Some((&base.swap_key, &base.pool_mint_key)),
+                    None,
+                    None,
+                    Swap {
+                        amount_in,
+                        minimum_amount_out,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    user_source_account,
+                    &mut swap_source_account,
+                    &mut swap_destination_account,
+                    user_destination_account,
+                    &mut self.pool_mint_account,
+                    &mut self.pool_fee_account,
+                    &mut Account::default(),
+                    &mut base.swap_account.clone(),
+                    &mut base.pool_mint_account.clone(),
+                ],
+            )?;
+
+            self.set_token_account(swap_source_key, swap_source_account);
+            self.set_token_account(swap_destination_key, swap_destination_account);
+
+            Ok(())
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn deposit_all_token_types(
+            &mut self,
+            depositor_key: &Pubkey,
+            depositor_token_a_key: &Pubkey,
+            depositor_token_a_account: &mut Account,
+            depositor_token_b_key: &Pubkey,
+            depositor_token_b_account: &mut Account,
+            depositor_pool_key: &Pubkey,
+            depositor_pool_account: &mut Account,
+            pool_token_amount: u64,
+            maximum_token_a_amount: u64,
+            maximum_token_b_amount: u64,
+        ) -> ProgramResult {
+            let user_transfer_authority = Pubkey::new_unique();
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    depositor_token_a_key,
+                    &user_transfer_authority,
+                    depositor_key,
+                    &[],
+                    maximum_token_a_amount,
+                )
+                .unwrap(),
+                vec![
+                    depositor_token_a_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    depositor_token_b_key,
+                    &user_transfer_authority,
+                    depositor_key,
+                    &[],
+                    maximum_token_b_amount,
+                )
+                .unwrap(),
+                vec![
+                    depositor_token_b_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            do_process_instruction(
+                deposit_all_token_types(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_authority,
+                    depositor_token_a_key,
+                    depositor_token_b_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    &self.pool_mint_key,
+                    depositor_pool_key,
+                    None,
+                    DepositAllTokenTypes {
+                        pool_token_amount,
+                        maximum_token_a_amount,
+                        maximum_token_b_amount,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    depositor_token_a_account,
+                    depositor_token_b_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.pool_mint_account,
+                    depositor_pool_account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn withdraw_all_token_types(
+            &mut self,
+            user_key: &Pubkey,
+            pool_key: &Pubkey,
+            pool_account: &mut Account,
+            token_a_key: &Pubkey,
+            token_a_account: &mut Account,
+            token_b_key: &Pubkey,
+            token_b_account: &mut Account,
+            pool_token_amount: u64,
+            minimum_token_a_amount: u64,
+            minimum_token_b_amount: u64,
+        ) -> ProgramResult {
+            let user_transfer_authority_key = Pubkey::new_unique();
+            // approve user transfer authority to take out pool tokens
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    pool_key,
+                    &user_transfer_authority_key,
+                    user_key,
+                    &[],
+                    pool_token_amount,
+                )
+                .unwrap(),
+                vec![
+                    pool_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            // withdraw token a and b correctly
+            do_process_instruction(
+                withdraw_all_token_types(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_authority_key,
+                    &self.pool_mint_key,
+                    &self.pool_fee_key,
+                    pool_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    token_a_key,
+                    token_b_key,
+                    WithdrawAllTokenTypes {
+                        pool_token_amount,
+                        minimum_token_a_amount,
+                        minimum_token_b_amount,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut self.pool_mint_account,
+                    pool_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    token_a_account,
+                    token_b_account,
+                    &mut self.pool_fee_account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn migrate_liquidity(
+            &mut self,
+            destination: &mut SwapAccountInfo,
+            user_key: &Pubkey,
+            pool_key: &Pubkey,
+            pool_account: &mut Account,
+            destination_pool_key: &Pubkey,
+            destination_pool_account: &mut Account,
+            token_a_key: &Pubkey,
+            token_a_account: &mut Account,
+            token_b_key: &Pubkey,
+            token_b_account: &mut Account,
+            pool_token_amount: u64,
+            minimum_pool_token_amount: u64,
+        ) -> ProgramResult {
+            let user_transfer_authority_key = Pubkey::new_unique();
+            // approve user transfer authority to take out pool tokens
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    pool_key,
+                    &user_transfer_authority_key,
+                    user_key,
+                    &[],
+                    pool_token_amount,
+                )
+                .unwrap(),
+                vec![
+                    pool_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            do_process_instruction(
+                migrate_liquidity(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_authority_key,
+                    &self.pool_mint_key,
+                    pool_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    &self.pool_fee_key,
+                    &destination.swap_key,
+                    &destination.authority_key,
+                    &destination.token_a_key,
+                    &destination.token_b_key,
+                    &destination.pool_mint_key,
+                    destination_pool_key,
+                    token_a_key,
+                    token_b_key,
+                    None,
+                    MigrateLiquidity {
+                        pool_token_amount,
+                        minimum_pool_token_amount,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut self.pool_mint_account,
+                    pool_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.pool_fee_account,
+                    &mut destination.swap_account,
+                    &mut Account::default(),
+                    &mut destination.token_a_account,
+                    &mut destination.token_b_account,
+                    &mut destination.pool_mint_account,
+                    destination_pool_account,
+                    token_a_account,
+                    token_b_account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn swap_underlying(
+            &mut self,
+            base: &mut SwapAccountInfo,
+            user_key: &Pubkey,
+            source_key: &Pubkey,
+            source_account: &mut Account,
+            base_pool_key: &Pubkey,
+            base_pool_account: &mut Account,
+            destination_key: &Pubkey,
+            destination_account: &mut Account,
+            amount_in: u64,
+            minimum_amount_out: u64,
+        ) -> ProgramResult {
+            let user_transfer_authority_key = Pubkey::new_unique();
+            // approve user transfer authority to take out token A and the
+            // swapped base swap pool tokens
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    source_key,
+                    &user_transfer_authority_key,
+                    user_key,
+                    &[],
+                    amount_in,
+                )
+                .unwrap(),
+                vec![
+                    source_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    base_pool_key,
+                    &user_transfer_authority_key,
+                    user_key,
+                    &[],
+                    u64::MAX,
+                )
+                .unwrap(),
+                vec![
+                    base_pool_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            do_process_instruction(
+                swap_underlying(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_authority_key,
+                    source_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    base_pool_key,
+                    &self.pool_mint_key,
+                    &self.pool_fee_key,
+                    &base.swap_key,
+                    &base.authority_key,
+                    &base.pool_mint_key,
+                    &base.token_a_key,
+                    &base.token_b_key,
+                    destination_key,
+                    &base.pool_fee_key,
+                    None,
+                    SwapUnderlying {
+                        amount_in,
+                        minimum_amount_out,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    source_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    base_pool_account,
+                    &mut self.pool_mint_account,
+                    &mut self.pool_fee_account,
+                    &mut base.swap_account,
+                    &mut Account::default(),
+                    &mut base.pool_mint_account,
+                    &mut base.token_a_account,
+                    &mut base.token_b_account,
+                    destination_account,
+                    &mut base.pool_fee_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn deposit_underlying(
+            &mut self,
+            base: &mut SwapAccountInfo,
+            user_key: &Pubkey,
+            token_a_key: &Pubkey,
+            token_a_account: &mut Account,
+            base_pool_key: &Pubkey,
+            base_pool_account: &mut Account,
+            pool_key: &Pubkey,
+            pool_account: &mut Account,
+            base_token_a_key: &Pubkey,
+            base_token_a_account: &mut Account,
+            base_token_b_key: &Pubkey,
+            base_token_b_account: &mut Account,
+            instruction: DepositUnderlying,
+        ) -> ProgramResult {
+            let user_transfer_authority_key = Pubkey::new_unique();
+            // approve user transfer authority to take out all the deposited
+            // tokens, including the base swap pool tokens received
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    token_a_key,
+                    &user_transfer_authority_key,
+                    user_key,
+                    &[],
+                    instruction.maximum_token_a_amount,
+                )
+                .unwrap(),
+                vec![
+                    token_a_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    base_pool_key,
+                    &user_transfer_authority_key,
+                    user_key,
+                    &[],
+                    instruction.base_pool_token_amount,
+                )
+                .unwrap(),
+                vec![
+                    base_pool_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    base_token_a_key,
+                    &user_transfer_authority_key,
+                    user_key,
+                    &[],
+                    instruction.maximum_base_token_a_amount,
+                )
+                .unwrap(),
+                vec![
+                    base_token_a_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    base_token_b_key,
+                    &user_transfer_authority_key,
+                    user_key,
+                    &[],
+                    instruction.maximum_base_token_b_amount,
+                )
+                .unwrap(),
+                vec![
+                    base_token_b_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            do_process_instruction(
+                deposit_underlying(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_authority_key,
+                    token_a_key,
+                    base_pool_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    &self.pool_mint_key,
+                    pool_key,
+                    &base.swap_key,
+                    &base.authority_key,
+                    base_token_a_key,
+                    base_token_b_key,
+                    &base.token_a_key,
+                    &base.token_b_key,
+                    &base.pool_mint_key,
+                    None,
+                    None,
+                    instruction,
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    token_a_account,
+                    base_pool_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    &mut self.pool_mint_account,
+                    pool_account,
+                    &mut base.swap_account,
+                    &mut Account::default(),
+                    base_token_a_account,
+                    base_token_b_account,
+                    &mut base.token_a_account,
+                    &mut base.token_b_account,
+                    &mut base.pool_mint_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn withdraw_single_token_type_exact_amount_out(
+            &mut self,
+            user_key: &Pubkey,
+            pool_key: &Pubkey,
+            pool_account: &mut Account,
+            destination_key: &Pubkey,
+            destination_account: &mut Account,
+            destination_token_amount: u64,
+            maximum_pool_token_amount: u64,
+        ) -> ProgramResult {
+            let user_transfer_authority_key = Pubkey::new_unique();
+            // approve user transfer authority to take out pool tokens
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    pool_key,
+                    &user_transfer_authority_key,
+                    user_key,
+                    &[],
+                    maximum_pool_token_amount,
+                )
+                .unwrap(),
+                vec![
+                    pool_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            do_process_instruction(
+                withdraw_single_token_type_exact_amount_out(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_authority_key,
+                    &self.pool_mint_key,
+                    &self.pool_fee_key,
+                    pool_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    destination_key,
+                    WithdrawSingleTokenTypeExactAmountOut {
+                        destination_token_amount,
+                        maximum_pool_token_amount,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut self.pool_mint_account,
+                    pool_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    destination_account,
+                    &mut self.pool_fee_account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+
+        #[allow(clippy::too_many_arguments)]
+        pub fn withdraw_single_token_type_exact_amount_in(
+            &mut self,
+            user_key: &Pubkey,
+            pool_key: &Pubkey,
+            pool_account: &mut Account,
+            destination_key: &Pubkey,
+            destination_account: &mut Account,
+            pool_token_amount: u64,
+            minimum_token_amount: u64,
+        ) -> ProgramResult {
+            let user_transfer_authority_key = Pubkey::new_unique();
+            // approve user transfer authority to take out pool tokens
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    pool_key,
+                    &user_transfer_authority_key,
+                    user_key,
+                    &[],
+                    pool_token_amount,
+                )
+                .unwrap(),
+                vec![
+                    pool_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+
+            do_process_instruction(
+                withdraw_single_token_type_exact_amount_in(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &self.swap_key,
+                    &self.authority_key,
+                    &user_transfer_authority_key,
+                    &self.pool_mint_key,
+                    &self.pool_fee_key,
+                    pool_key,
+                    &self.token_a_key,
+                    &self.token_b_key,
+                    destination_key,
+                    WithdrawSingleTokenTypeExactAmountIn {
+                        pool_token_amount,
+                        minimum_token_amount,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut self.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut self.pool_mint_account,
+                    pool_account,
+                    &mut self.token_a_account,
+                    &mut self.token_b_account,
+                    destination_account,
+                    &mut self.pool_fee_account,
+                    &mut Account::default(),
+                ],
+            )
+        }
+    }
+
+    fn mint_minimum_balance() -> u64 {
+        Rent::default().minimum_balance(spl_token::state::Mint::get_packed_len())
+    }
+
+    fn account_minimum_balance() -> u64 {
+        Rent::default().minimum_balance(spl_token::state::Account::get_packed_len())
+    }
+
+    fn do_process_instruction_with_fee_constraints(
+        instruction: Instruction,
+        accounts: Vec<&mut Account>,
+        swap_constraints: &Option<SwapConstraints>,
+    ) -> ProgramResult {
+        test_syscall_stubs();
+
+        // approximate the logic in the actual runtime which runs the instruction
+        // and only updates accounts if the instruction is successful
+        let mut account_clones = accounts.iter().map(|x| (*x).clone()).collect::<Vec<_>>();
+        let mut meta = instruction
+            .accounts
+            .iter()
+            .zip(account_clones.iter_mut())
+            .map(|(account_meta, account)| (&account_meta.pubkey, account_meta.is_signer, account))
+            .collect::<Vec<_>>();
+        let mut account_infos = create_is_signer_account_infos(&mut meta);
+        let res = if instruction.program_id == SWAP_PROGRAM_ID {
+            Processor::process_with_constraints(
+                &instruction.program_id,
+                &account_infos,
+                &instruction.data,
+                swap_constraints,
+            )
+        } else {
+            spl_token::processor::Processor::process(
+                &instruction.program_id,
+                &account_infos,
+                &instruction.data,
+            )
+        };
+
+        if res.is_ok() {
+            let mut account_metas = instruction
+                .accounts
+                .iter()
+                .zip(accounts)
+                .map(|(account_meta, account)| (&account_meta.pubkey, account))
+                .collect::<Vec<_>>();
+            for account_info in account_infos.iter_mut() {
+                for account_meta in account_metas.iter_mut() {
+                    if account_info.key == account_meta.0 {
+                        let account = &mut account_meta.1;
+                        account.owner = *account_info.owner;
+                        account.lamports = **account_info.lamports.borrow();
+                        account.data = account_info.data.borrow().to_vec();
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    fn do_process_instruction(
+        instruction: Instruction,
+        accounts: Vec<&mut Account>,
+    ) -> ProgramResult {
+        do_process_instruction_with_fee_constraints(instruction, accounts, &SWAP_CONSTRAINTS)
+    }
+
+    fn mint_token(
+        program_id: &Pubkey,
+        mint_key: &Pubkey,
+        mint_account: &mut Account,
+        mint_authority_key: &Pubkey,
+        account_owner_key: &Pubkey,
+        amount: u64,
+    ) -> (Pubkey, Account) {
+        let account_key = Pubkey::new_unique();
+        let mut account_account = Account::new(
+            account_minimum_balance(),
+            spl_token::state::Account::get_packed_len(),
+            program_id,
+        );
+        let mut mint_authority_account = Account::default();
+        let mut rent_sysvar_account = create_account_for_test(&Rent::free());
+
+        do_process_instruction(
+            initialize_account(program_id, &account_key, mint_key, account_owner_key).unwrap(),
+            vec![
+                &mut account_account,
+                mint_account,
+                &mut mint_authority_account,
+                &mut rent_sysvar_account,
+            ],
+        )
+        .unwrap();
+
+        if amount > 0 {
+            do_process_instruction(
+                mint_to(
+                    program_id,
+                    mint_key,
+                    &account_key,
+                    mint_authority_key,
+                    &[],
+                    amount,
+                )
+                .unwrap(),
+                vec![
+                    mint_account,
+                    &mut account_account,
+                    &mut mint_authority_account,
+                ],
+            )
+            .unwrap();
+        }
+
+        (account_key, account_account)
+    }
+
+    fn create_mint(
+        program_id: &Pubkey,
+        authority_key: &Pubkey,
+        freeze_authority: Option<&Pubkey>,
+    ) -> (Pubkey, Account) {
+        let mint_key = Pubkey::new_unique();
+        let mut mint_account = Account::new(
+            mint_minimum_balance(),
+            spl_token::state::Mint::get_packed_len(),
+            program_id,
+        );
+        let mut rent_sysvar_account = create_account_for_test(&Rent::free());
+
+        do_process_instruction(
+            initialize_mint(program_id, &mint_key, authority_key, freeze_authority, 2).unwrap(),
+            vec![&mut mint_account, &mut rent_sysvar_account],
+        )
+        .unwrap();
+
+        (mint_key, mint_account)
+    }
+
+    #[test]
+    fn test_token_program_id_error() {
+        test_syscall_stubs();
+        let swap_key = Pubkey::new_unique();
+        let mut mint = (Pubkey::new_unique(), Account::default());
+        let mut destination = (Pubkey::new_unique(), Account::default());
+        let token_program = (spl_token::id(), Account::default());
+        let (authority_key, bump_seed) =
+            Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
+        let mut authority = (authority_key, Account::default());
+        let swap_bytes = swap_key.to_bytes();
+        let authority_signature_seeds = [&swap_bytes[..32], &[bump_seed]];
+        let signers = &[&authority_signature_seeds[..]];
+        let ix = mint_to(
+            &token_program.0,
+            &mint.0,
+            &destination.0,
+            &authority.0,
+            &[],
+            10,
+        )
+        .unwrap();
+        let mint = (&mut mint).into();
+        let destination = (&mut destination).into();
+        let authority = (&mut authority).into();
+
+        let err = invoke_signed(&ix, &[mint, destination, authority], signers).unwrap_err();
+        assert_eq!(err, ProgramError::InvalidAccountData);
+    }
+
+    #[test]
+    fn test_initialize() {
+        let user_key = Pubkey::new_unique();
+        let trade_fee_numerator = 1;
+        let trade_fee_denominator = 2;
+        let owner_trade_fee_numerator = 1;
+        let owner_trade_fee_denominator = 10;
+        let owner_withdraw_fee_numerator = 1;
+        let owner_withdraw_fee_denominator = 5;
+        let host_fee_numerator = 20;
+        let host_fee_denominator = 100;
+        let fees = Fees {
+            trade_fee_numerator,
+            trade_fee_denominator,
+            owner_trade_fee_numerator,
+            owner_trade_fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+            host_fee_numerator,
+            host_fee_denominator,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
+        };
+
+        let token_a_amount = 1000;
+        let token_b_amount = 2000;
+        let pool_token_amount = 10;
+        let amp = 200;
+        let curve_type = CurveType::Stable;
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator: Arc::new(StableCurve { amp }),
+        };
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+
+        // uninitialized token a account
+        {
+            let old_account = accounts.token_a_account;
+            accounts.token_a_account = Account::new(0, 0, &spl_token::id());
+            assert_eq!(
+                Err(SwapError::ExpectedAccount.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_a_account = old_account;
+        }
+
+        // uninitialized token b account
+        {
+            let old_account = accounts.token_b_account;
+            accounts.token_b_account = Account::new(0, 0, &spl_token::id());
+            assert_eq!(
+                Err(SwapError::ExpectedAccount.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_b_account = old_account;
+        }
+
+        // uninitialized pool mint
+        {
+            let old_account = accounts.pool_mint_account;
+            accounts.pool_mint_account = Account::new(0, 0, &spl_token::id());
+            assert_eq!(
+                Err(SwapError::ExpectedMint.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_mint_account = old_account;
+        }
+
+        // token A account owner is not swap authority
+        {
+            let (_token_a_key, token_a_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &user_key,
+                0,
+            );
+            let old_account = accounts.token_a_account;
+            accounts.token_a_account = token_a_account;
+            assert_eq!(
+                Err(SwapError::InvalidOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_a_account = old_account;
+        }
+
+        // token B account owner is not swap authority
+        {
+            let (_token_b_key, token_b_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_b_mint_key,
+                &mut accounts.token_b_mint_account,
+                &user_key,
+                &user_key,
+                0,
+            );
+            let old_account = accounts.token_b_account;
+            accounts.token_b_account = token_b_account;
+            assert_eq!(
+                Err(SwapError::InvalidOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_b_account = old_account;
+        }
+
+        // pool token account owner is swap authority
+        {
+            let (_pool_token_key, pool_token_account) = mint_token(
+                &spl_token::id(),
+                &accounts.pool_mint_key,
+                &mut accounts.pool_mint_account,
+                &accounts.authority_key,
+                &accounts.authority_key,
+                0,
+            );
+            let old_account = accounts.pool_token_account;
+            accounts.pool_token_account = pool_token_account;
+            assert_eq!(
+                Err(SwapError::InvalidOutputOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_token_account = old_account;
+        }
+
+        // pool fee account owner is swap authority
+        {
+            let (_pool_fee_key, pool_fee_account) = mint_token(
+                &spl_token::id(),
+                &accounts.pool_mint_key,
+                &mut accounts.pool_mint_account,
+                &accounts.authority_key,
+                &accounts.authority_key,
+                0,
+            );
+            let old_account = accounts.pool_fee_account;
+            accounts.pool_fee_account = pool_fee_account;
+            assert_eq!(
+                Err(SwapError::InvalidOutputOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_fee_account = old_account;
+        }
+
+        // pool locked account owner is not swap authority
+        {
+            let (_pool_locked_key, pool_locked_account) = mint_token(
+                &spl_token::id(),
+                &accounts.pool_mint_key,
+                &mut accounts.pool_mint_account,
+                &accounts.authority_key,
+                &user_key,
+                0,
+            );
+            let old_account = accounts.pool_locked_account;
+            accounts.pool_locked_account = pool_locked_account;
+            assert_eq!(
+                Err(SwapError::InvalidOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_locked_account = old_account;
+        }
+
+        // pool mint authority is not swap authority
+        {
+            let (_pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &user_key, None);
+            let old_mint = accounts.pool_mint_account;
+            accounts.pool_mint_account = pool_mint_account;
+            assert_eq!(
+                Err(SwapError::InvalidOwner.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_mint_account = old_mint;
+        }
+
+        // pool mint token has freeze authority
+        {
+            let (_pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &accounts.authority_key, Some(&user_key));
+            let old_mint = accounts.pool_mint_account;
+            accounts.pool_mint_account = pool_mint_account;
+            assert_eq!(
+                Err(SwapError::InvalidFreezeAuthority.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_mint_account = old_mint;
+        }
+
+        // token A account owned by wrong program
+        {
+            let (_token_a_key, mut token_a_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &accounts.authority_key,
+                token_a_amount,
+            );
+            token_a_account.owner = SWAP_PROGRAM_ID;
+            let old_account = accounts.token_a_account;
+            accounts.token_a_account = token_a_account;
+            assert_eq!(
+                Err(SwapError::IncorrectTokenProgramId.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_a_account = old_account;
+        }
+
+        // token B account owned by wrong program
+        {
+            let (_token_b_key, mut token_b_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_b_mint_key,
+                &mut accounts.token_b_mint_account,
+                &user_key,
+                &accounts.authority_key,
+                token_b_amount,
+            );
+            token_b_account.owner = SWAP_PROGRAM_ID;
+            let old_account = accounts.token_b_account;
+            accounts.token_b_account = token_b_account;
+            assert_eq!(
+                Err(SwapError::IncorrectTokenProgramId.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_b_account = old_account;
+        }
+
+        // empty token A account
+        {
+            let (_token_a_key, token_a_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &accounts.authority_key,
+                0,
+            );
+            let old_account = accounts.token_a_account;
+            accounts.token_a_account = token_a_account;
+            assert_eq!(
+                Err(SwapError::EmptySupply.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_a_account = old_account;
+        }
+
+        // empty token B account
+        {
+            let (_token_b_key, token_b_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_b_mint_key,
+                &mut accounts.token_b_mint_account,
+                &user_key,
+                &accounts.authority_key,
+                0,
+            );
+            let old_account = accounts.token_b_account;
+            accounts.token_b_account = token_b_account;
+            assert_eq!(
+                Err(SwapError::EmptySupply.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_b_account = old_account;
+        }
+
+        // invalid pool tokens
+        {
+            let old_mint = accounts.pool_mint_account;
+            let old_pool_account = accounts.pool_token_account;
+
+            let (_pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &accounts.authority_key, None);
+            accounts.pool_mint_account = pool_mint_account;
+
+            let (_empty_pool_token_key, empty_pool_token_account) = mint_token(
+                &spl_token::id(),
+                &accounts.pool_mint_key,
+                &mut accounts.pool_mint_account,
+                &accounts.authority_key,
+                &user_key,
+                0,
+            );
+
+            let (_pool_token_key, pool_token_account) = mint_token(
+                &spl_token::id(),
+                &accounts.pool_mint_key,
+                &mut accounts.pool_mint_account,
+                &accounts.authority_key,
+                &user_key,
+                pool_token_amount,
+            );
+
+            // non-empty pool token account
+            accounts.pool_token_account = pool_token_account;
+            assert_eq!(
+                Err(SwapError::InvalidSupply.into()),
+                accounts.initialize_swap()
+            );
+
+            // pool tokens already in circulation
+            accounts.pool_token_account = empty_pool_token_account;
+            assert_eq!(
+                Err(SwapError::InvalidSupply.into()),
+                accounts.initialize_swap()
+            );
+
+            accounts.pool_mint_account = old_mint;
+            accounts.pool_token_account = old_pool_account;
+        }
+
+        // pool fee account has wrong mint
+        {
+            let (_pool_fee_key, pool_fee_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &user_key,
+                0,
+            );
+            let old_account = accounts.pool_fee_account;
+            accounts.pool_fee_account = pool_fee_account;
+            assert_eq!(
+                Err(SwapError::IncorrectPoolMint.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_fee_account = old_account;
+        }
+
+        // pool locked account has wrong mint
+        {
+            let (_pool_locked_key, pool_locked_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &accounts.authority_key,
+                0,
+            );
+            let old_account = accounts.pool_locked_account;
+            accounts.pool_locked_account = pool_locked_account;
+            assert_eq!(
+                Err(SwapError::IncorrectPoolMint.into()),
+                accounts.initialize_swap()
+            );
+            accounts.pool_locked_account = old_account;
+        }
+
+        // token A account is delegated
+        {
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    &accounts.token_a_key,
+                    &user_key,
+                    &accounts.authority_key,
+                    &[],
+                    1,
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.token_a_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+            assert_eq!(
+                Err(SwapError::InvalidDelegate.into()),
+                accounts.initialize_swap()
+            );
+
+            do_process_instruction(
+                revoke(
+                    &spl_token::id(),
+                    &accounts.token_a_key,
+                    &accounts.authority_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_a_account, &mut Account::default()],
+            )
+            .unwrap();
+        }
+
+        // token B account is delegated
+        {
+            do_process_instruction(
+                approve(
+                    &spl_token::id(),
+                    &accounts.token_b_key,
+                    &user_key,
+                    &accounts.authority_key,
+                    &[],
+                    1,
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.token_b_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+            assert_eq!(
+                Err(SwapError::InvalidDelegate.into()),
+                accounts.initialize_swap()
+            );
+
+            do_process_instruction(
+                revoke(
+                    &spl_token::id(),
+                    &accounts.token_b_key,
+                    &accounts.authority_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_b_account, &mut Account::default()],
+            )
+            .unwrap();
+        }
+
+        // token A account has close authority
+        {
+            do_process_instruction(
+                set_authority(
+                    &spl_token::id(),
+                    &accounts.token_a_key,
+                    Some(&user_key),
+                    AuthorityType::CloseAccount,
+                    &accounts.authority_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_a_account, &mut Account::default()],
+            )
+            .unwrap();
+            assert_eq!(
+                Err(SwapError::InvalidCloseAuthority.into()),
+                accounts.initialize_swap()
+            );
+
+            do_process_instruction(
+                set_authority(
+                    &spl_token::id(),
+                    &accounts.token_a_key,
+                    None,
+                    AuthorityType::CloseAccount,
+                    &user_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_a_account, &mut Account::default()],
+            )
+            .unwrap();
+        }
+
+        // token B account has close authority
+        {
+            do_process_instruction(
+                set_authority(
+                    &spl_token::id(),
+                    &accounts.token_b_key,
+                    Some(&user_key),
+                    AuthorityType::CloseAccount,
+                    &accounts.authority_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_b_account, &mut Account::default()],
+            )
+            .unwrap();
+            assert_eq!(
+                Err(SwapError::InvalidCloseAuthority.into()),
+                accounts.initialize_swap()
+            );
+
+            do_process_instruction(
+                set_authority(
+                    &spl_token::id(),
+                    &accounts.token_b_key,
+                    None,
+                    AuthorityType::CloseAccount,
+                    &user_key,
+                    &[],
+                )
+                .unwrap(),
+                vec![&mut accounts.token_b_account, &mut Account::default()],
+            )
+            .unwrap();
+        }
+
+        // wrong token program id
+        {
+            let wrong_program_id = Pubkey::new_unique();
+            assert_eq!(
+                Err(SwapError::IncorrectTokenProgramId.into()),
+                do_process_instruction(
+                    initialize(
+                        &SWAP_PROGRAM_ID,
+                        &wrong_program_id,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &accounts.pool_fee_key,
+                        &accounts.pool_token_key,
+                        &accounts.pool_locked_key,
+                        &accounts.config_key,
+                        None,
+                        None,
+                        accounts.fees.clone(),
+                        accounts.swap_curve.clone(),
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut accounts.pool_fee_account,
+                        &mut accounts.pool_token_account,
+                        &mut accounts.pool_locked_account,
+                        &mut Account::default(),
+                        &mut accounts.config_account,
+                    ],
+                )
+            );
+        }
+
+        // create swap with same token A and B
+        {
+            let (_token_a_repeat_key, token_a_repeat_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &accounts.authority_key,
+                10,
+            );
+            let old_account = accounts.token_b_account;
+            accounts.token_b_account = token_a_repeat_account;
+            assert_eq!(
+                Err(SwapError::RepeatedMint.into()),
+                accounts.initialize_swap()
+            );
+            accounts.token_b_account = old_account;
+        }
+
+        // create valid swap
+        accounts.initialize_swap().unwrap();
+
+
+        // wrong owner key in constraint
+        {
+            let new_key = Pubkey::new_unique();
+            let trade_fee_numerator = 25;
+            let trade_fee_denominator = 10000;
+            let owner_trade_fee_numerator = 5;
+            let owner_trade_fee_denominator = 10000;
+            let host_fee_numerator = 20;
+            let host_fee_denominator = 100;
+            let fees = Fees {
+                trade_fee_numerator,
+                trade_fee_denominator,
+                owner_trade_fee_numerator,
+                owner_trade_fee_denominator,
+                owner_withdraw_fee_numerator,
+                owner_withdraw_fee_denominator,
+                host_fee_numerator,
+                host_fee_denominator,
+                offpeg_fee_multiplier_numerator: 0,
+                offpeg_fee_multiplier_denominator: 0,
+            };
+            let amp = 200;
+            let curve = StableCurve { amp };
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::Stable,
+                calculator: Arc::new(curve),
+            };
+            let owner_key = &new_key.to_string();
+            let valid_curve_types = &[CurveType::Stable];
+            let constraints = Some(SwapConstraints {
+                owner_key,
+                valid_curve_types,
+                fees: &fees,
+                max_fees: &fees,
+                min_amp: MIN_AMP,
+                max_amp: MAX_AMP,
+                allowed_mints: &[],
+                denied_mints: &[],
+            });
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                fees.clone(),
+                swap_curve,
+                token_a_amount,
+                token_b_amount,
+            );
+            assert_eq!(
+                Err(SwapError::InvalidOwner.into()),
+                do_process_instruction_with_fee_constraints(
+                    initialize(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &accounts.pool_fee_key,
+                        &accounts.pool_token_key,
+                        &accounts.pool_locked_key,
+                        &accounts.config_key,
+                        None,
+                        None,
+                        accounts.fees.clone(),
+                        accounts.swap_curve.clone(),
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut accounts.pool_fee_account,
+                        &mut accounts.pool_token_account,
+                        &mut accounts.pool_locked_account,
+                        &mut Account::default(),
+                        &mut accounts.config_account,
+                    ],
+                    &constraints,
+                )
+            );
+        }
+
+        // wrong fee in constraint
+        {
+            let trade_fee_numerator = 25;
+            let trade_fee_denominator = 10000;
+            let owner_trade_fee_numerator = 5;
+            let owner_trade_fee_denominator = 10000;
+            let host_fee_numerator = 20;
+            let host_fee_denominator = 100;
+            let fees = Fees {
+                trade_fee_numerator,
+                trade_fee_denominator,
+                owner_trade_fee_numerator,
+                owner_trade_fee_denominator,
+                owner_withdraw_fee_numerator,
+                owner_withdraw_fee_denominator,
+                host_fee_numerator,
+                host_fee_denominator,
+                offpeg_fee_multiplier_numerator: 0,
+                offpeg_fee_multiplier_denominator: 0,
+            };
+            let amp = 200;
+            let curve = StableCurve { amp };
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::Stable,
+                calculator: Arc::new(curve),
+            };
+            let owner_key = &user_key.to_string();
+            let valid_curve_types = &[CurveType::Stable];
+            let constraints = Some(SwapConstraints {
+                owner_key,
+                valid_curve_types,
+                fees: &fees,
+                max_fees: &fees,
+                min_amp: MIN_AMP,
+                max_amp: MAX_AMP,
+                allowed_mints: &[],
+                denied_mints: &[],
+            });
+            let mut bad_fees = fees.clone();
+            bad_fees.trade_fee_numerator = trade_fee_numerator - 1;
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                bad_fees,
+                swap_curve,
+                token_a_amount,
+                token_b_amount,
+            );
+            assert_eq!(
+                Err(SwapError::InvalidFee.into()),
+                do_process_instruction_with_fee_constraints(
+                    initialize(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &accounts.pool_fee_key,
+                        &accounts.pool_token_key,
+                        &accounts.pool_locked_key,
+                        &accounts.config_key,
+                        None,
+                        None,
+                        accounts.fees.clone(),
+                        accounts.swap_curve.clone(),
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut accounts.pool_fee_account,
+                        &mut accounts.pool_token_account,
+                        &mut accounts.pool_locked_account,
+                        &mut Account::default(),
+                        &mut accounts.config_account,
+                    ],
+                    &constraints,
+                )
+            );
+        }
+
+        // mint not allowed or denied in constraint
+        {
+            let trade_fee_numerator = 25;
+            let trade_fee_denominator = 10000;
+            let owner_trade_fee_numerator = 5;
+            let owner_trade_fee_denominator = 10000;
+            let host_fee_numerator = 20;
+            let host_fee_denominator = 100;
+            let fees = Fees {
+                trade_fee_numerator,
+                trade_fee_denominator,
+                owner_trade_fee_numerator,
+                owner_trade_fee_denominator,
+                owner_withdraw_fee_numerator,
+                owner_withdraw_fee_denominator,
+                host_fee_numerator,
+                host_fee_denominator,
+                offpeg_fee_multiplier_numerator: 0,
+                offpeg_fee_multiplier_denominator: 0,
+            };
+            let amp = 200;
+            let curve = StableCurve { amp };
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::Stable,
+                calculator: Arc::new(curve),
+            };
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                fees.clone(),
+                swap_curve,
+                token_a_amount,
+                token_b_amount,
+            );
+            let owner_key = &user_key.to_string();
+            let valid_curve_types = &[CurveType::Stable];
+            let allowed_mints = [accounts.token_a_mint_key];
+            let denied_mints = [accounts.token_b_mint_key];
+            for (allowed_mints, denied_mints) in
+                [(&allowed_mints[..], &[][..]), (&[][..], &denied_mints[..])].iter()
+            {
+                let constraints = Some(SwapConstraints {
+                    owner_key,
+                    valid_curve_types,
+                    fees: &fees,
+                    max_fees: &fees,
+                    min_amp: MIN_AMP,
+                    max_amp: MAX_AMP,
+                    allowed_mints,
+                    denied_mints,
+                });
+                assert_eq!(
+                    Err(SwapError::UnsupportedMint.into()),
+                    do_process_instruction_with_fee_constraints(
+                        initialize(
+                            &SWAP_PROGRAM_ID,
+                            &spl_token::id(),
+                            &accounts.swap_key,
+                            &accounts.authority_key,
+                            &accounts.token_a_key,
+                            &accounts.token_b_key,
+                            &accounts.pool_mint_key,
+                            &accounts.pool_fee_key,
+                            &accounts.pool_token_key,
+                            &accounts.pool_locked_key,
+                            &accounts.config_key,
+                            None,
+                            None,
+                            accounts.fees.clone(),
+                            accounts.swap_curve.clone(),
+                        )
+                        .unwrap(),
+                        vec![
+                            &mut accounts.swap_account,
+                            &mut Account::default(),
+                            &mut accounts.token_a_account,
+                            &mut accounts.token_b_account,
+                            &mut accounts.pool_mint_account,
+                            &mut accounts.pool_fee_account,
+                            &mut accounts.pool_token_account,
+                            &mut accounts.pool_locked_account,
+                            &mut Account::default(),
+                            &mut accounts.config_account,
+                        ],
+                        &constraints,
+                    )
+                );
+            }
+        }
+
+        // create valid swap with constraints
+        {
+            let trade_fee_numerator = 25;
+            let trade_fee_denominator = 10000;
+            let owner_trade_fee_numerator = 5;
+            let owner_trade_fee_denominator = 10000;
+            let host_fee_numerator = 20;
+            let host_fee_denominator = 100;
+            let fees = Fees {
+                trade_fee_numerator,
+                trade_fee_denominator,
+                owner_trade_fee_numerator,
+                owner_trade_fee_denominator,
+                owner_withdraw_fee_numerator,
+                owner_withdraw_fee_denominator,
+                host_fee_numerator,
+                host_fee_denominator,
+                offpeg_fee_multiplier_numerator: 0,
+                offpeg_fee_multiplier_denominator: 0,
+            };
+            let amp = 200;
+            let curve = StableCurve { amp };
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::Stable,
+                calculator: Arc::new(curve),
+            };
+            let owner_key = &user_key.to_string();
+            let valid_curve_types = &[CurveType::Stable];
+            let constraints = Some(SwapConstraints {
+                owner_key,
+                valid_curve_types,
+                fees: &fees,
+                max_fees: &fees,
+                min_amp: MIN_AMP,
+                max_amp: MAX_AMP,
+                allowed_mints: &[],
+                denied_mints: &[],
+            });
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                fees.clone(),
+                swap_curve,
+                token_a_amount,
+                token_b_amount,
+            );
+            do_process_instruction_with_fee_constraints(
+                initialize(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &accounts.token_a_key,
+                    &accounts.token_b_key,
+                    &accounts.pool_mint_key,
+                    &accounts.pool_fee_key,
+                    &accounts.pool_token_key,
+                    &accounts.pool_locked_key,
+                    &accounts.config_key,
+                    None,
+                    None,
+                    accounts.fees,
+                    accounts.swap_curve.clone(),
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut accounts.token_a_account,
+                    &mut accounts.token_b_account,
+                    &mut accounts.pool_mint_account,
+                    &mut accounts.pool_fee_account,
+                    &mut accounts.pool_token_account,
+                    &mut accounts.pool_locked_account,
+                    &mut Account::default(),
+                    &mut accounts.config_account,
+                ],
+                &constraints,
+            )
+            .unwrap();
+        }
+
+        // create again
+        {
+            assert_eq!(
+                Err(SwapError::AlreadyInUse.into()),
+                accounts.initialize_swap()
+            );
+        }
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert!(swap_state.is_initialized());
+        assert_eq!(swap_state.bump_seed(), accounts.bump_seed);
+        assert_eq!(
+            swap_state.swap_curve().curve_type,
+            accounts.swap_curve.curve_type
+        );
+        assert_eq!(*swap_state.token_a_account(), accounts.token_a_key);
+        assert_eq!(*swap_state.token_b_account(), accounts.token_b_key);
+        assert_eq!(*swap_state.pool_mint(), accounts.pool_mint_key);
+        assert_eq!(*swap_state.token_a_mint(), accounts.token_a_mint_key);
+        assert_eq!(*swap_state.token_b_mint(), accounts.token_b_mint_key);
+        assert_eq!(*swap_state.pool_fee_account(), accounts.pool_fee_key);
+        let token_a = spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        assert_eq!(token_a.amount, token_a_amount);
+        let token_b = spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+        assert_eq!(token_b.amount, token_b_amount);
+        let pool_account =
+            spl_token::state::Account::unpack(&accounts.pool_token_account.data).unwrap();
+        let pool_locked =
+            spl_token::state::Account::unpack(&accounts.pool_locked_account.data).unwrap();
+        assert_eq!(pool_locked.amount, to_u64(MINIMUM_LIQUIDITY).unwrap());
+        let pool_mint = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+        assert_eq!(pool_mint.supply, pool_account.amount + pool_locked.amount);
+    }
+
+    fn program_data_account(upgrade_authority_key: &Pubkey) -> (Pubkey, Account) {
+        let (program_data_key, _) = Pubkey::find_program_address(
+            &[SWAP_PROGRAM_ID.as_ref()],
+            &bpf_loader_upgradeable::id(),
+        );
+        let program_data_account = Account::new_data(
+            0,
+            &UpgradeableLoaderState::ProgramData {
+                slot: 0,
+                upgrade_authority_address: Some(*upgrade_authority_key),
+            },
+            &bpf_loader_upgradeable::id(),
+        )
+        .unwrap();
+        (program_data_key, program_data_account)
+    }
+
+    #[test]
+    fn test_program_config() {
+        let user_key = Pubkey::new_unique();
+        let upgrade_authority_key = Pubkey::new_unique();
+        let (program_data_key, mut program_data_account) =
+            program_data_account(&upgrade_authority_key);
+        let (config_key, bump_seed) = ProgramConfig::find_address(&SWAP_PROGRAM_ID);
+        let mut config_account = Account::new(0, ProgramConfig::LEN, &SWAP_PROGRAM_ID);
+        let payer_key = Pubkey::new_unique();
+
+        let config_fees = Fees {
+            trade_fee_numerator: 2,
+            trade_fee_denominator: 1000,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 1000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+            host_fee_numerator: 20,
+            host_fee_denominator: 100,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
+        };
+        let config_max_fees = Fees {
+            trade_fee_numerator: 10,
+            owner_trade_fee_numerator: 5,
+            ..config_fees.clone()
+        };
+        let min_amp = 10;
+        let max_amp = 1_000;
+        let instruction = SetProgramConfig {
+            owner_key: user_key,
+            valid_curve_types: 1 << CurveType::Stable as u8,
+            min_amp,
+            max_amp,
+            fees: config_fees.clone(),
+            max_fees: config_max_fees.clone(),
+            allowed_mints: [Pubkey::default(); MAX_CONFIG_MINTS],
+            denied_mints: [Pubkey::default(); MAX_CONFIG_MINTS],
+        };
+
+        // wrong upgrade authority
+        {
+            let wrong_authority_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(SwapError::InvalidUpgradeAuthority.into()),
+                do_process_instruction(
+                    set_program_config(
+                        &SWAP_PROGRAM_ID,
+                        &config_key,
+                        &wrong_authority_key,
+                        &program_data_key,
+                        &payer_key,
+                        instruction.clone(),
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut config_account,
+                        &mut Account::default(),
+                        &mut program_data_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // wrong program data account
+        {
+            let mut wrong_program_data_account = program_data_account.clone();
+            wrong_program_data_account.owner = SWAP_PROGRAM_ID;
+            assert_eq!(
+                Err(SwapError::InvalidUpgradeAuthority.into()),
+                do_process_instruction(
+                    set_program_config(
+                        &SWAP_PROGRAM_ID,
+                        &config_key,
+                        &upgrade_authority_key,
+                        &program_data_key,
+                        &payer_key,
+                        instruction.clone(),
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut config_account,
+                        &mut Account::default(),
+                        &mut wrong_program_data_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // invalid amp bounds
+        {
+            let bad_instruction = SetProgramConfig {
+                min_amp: max_amp + 1,
+                ..instruction.clone()
+            };
+            assert_eq!(
+                Err(SwapError::InvalidCurve.into()),
+                do_process_instruction(
+                    set_program_config(
+                        &SWAP_PROGRAM_ID,
+                        &config_key,
+                        &upgrade_authority_key,
+                        &program_data_key,
+                        &payer_key,
+                        bad_instruction,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut config_account,
+                        &mut Account::default(),
+                        &mut program_data_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // wrong config address
+        {
+            let wrong_config_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(SwapError::InvalidProgramAddress.into()),
+                do_process_instruction(
+                    set_program_config(
+                        &SWAP_PROGRAM_ID,
+                        &wrong_config_key,
+                        &upgrade_authority_key,
+                        &program_data_key,
+                        &payer_key,
+                        instruction.clone(),
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut config_account,
+                        &mut Account::default(),
+                        &mut program_data_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // set the config
+        do_process_instruction(
+            set_program_config(
+                &SWAP_PROGRAM_ID,
+                &config_key,
+                &upgrade_authority_key,
+                &program_data_key,
+                &payer_key,
+                instruction,
+            )
+            .unwrap(),
+            vec![
+                &mut config_account,
+                &mut Account::default(),
+                &mut program_data_account,
+                &mut Account::default(),
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+        let program_config = ProgramConfig::unpack(&config_account.data).unwrap();
+        assert_eq!(
+            program_config,
+            ProgramConfig {
+                is_initialized: true,
+                bump_seed,
+                owner_key: user_key,
+                valid_curve_types: 1,
+                min_amp,
+                max_amp,
+                fees: config_fees.clone(),
+                max_fees: config_max_fees,
+                allowed_mints: [Pubkey::default(); MAX_CONFIG_MINTS],
+                denied_mints: [Pubkey::default(); MAX_CONFIG_MINTS],
+            }
+        );
+
+        let token_a_amount = 1000;
+        let token_b_amount = 2000;
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 100 }),
+        };
+
+        // fees below the config minimum fail, even without built-in constraints
+        {
+            let fees = Fees {
+                trade_fee_numerator: 1,
+                ..config_fees.clone()
+            };
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                fees,
+                swap_curve.clone(),
+                token_a_amount,
+                token_b_amount,
+            );
+            accounts.config_account = config_account.clone();
+            assert_eq!(
+                Err(SwapError::InvalidFee.into()),
+                accounts.initialize_swap()
+            );
+        }
+
+        // fees above the config maximum fail
+        {
+            let fees = Fees {
+                trade_fee_numerator: 11,
+                ..config_fees.clone()
+            };
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                fees,
+                swap_curve.clone(),
+                token_a_amount,
+                token_b_amount,
+            );
+            accounts.config_account = config_account.clone();
+            assert_eq!(
+                Err(SwapError::InvalidFee.into()),
+                accounts.initialize_swap()
+            );
+        }
+
+        // amp outside of the config bounds fails
+        {
+            let amp_curve = SwapCurve {
+                curve_type: CurveType::Stable,
+                calculator: Arc::new(StableCurve { amp: max_amp + 1 }),
+            };
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                config_fees.clone(),
+                amp_curve,
+                token_a_amount,
+                token_b_amount,
+            );
+            accounts.config_account = config_account.clone();
+            assert_eq!(
+                Err(SwapError::InvalidCurve.into()),
+                accounts.initialize_swap()
+            );
+        }
+
+        // pool fee account not owned by the config owner fails
+        {
+            let mut accounts = SwapAccountInfo::new(
+                &Pubkey::new_unique(),
+                config_fees.clone(),
+                swap_curve.clone(),
+                token_a_amount,
+                token_b_amount,
+            );
+            accounts.config_account = config_account.clone();
+            assert_eq!(
+                Err(SwapError::InvalidOwner.into()),
+                accounts.initialize_swap()
+            );
+        }
+
+        // the config account cannot be left out to use the built-in
+        // constraints instead
+        {
+            let fees = Fees {
+                trade_fee_numerator: 11,
+                ..config_fees.clone()
+            };
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                fees,
+                swap_curve.clone(),
+                token_a_amount,
+                token_b_amount,
+            );
+            let mut instruction = initialize(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &accounts.swap_key,
+                &accounts.authority_key,
+                &accounts.token_a_key,
+                &accounts.token_b_key,
+                &accounts.pool_mint_key,
+                &accounts.pool_fee_key,
+                &accounts.pool_token_key,
+                &accounts.pool_locked_key,
+                &accounts.config_key,
+                None,
+                None,
+                accounts.fees.clone(),
+                accounts.swap_curve.clone(),
+            )
+            .unwrap();
+            instruction.accounts.pop();
+            let result = do_process_instruction(
+                instruction,
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut accounts.token_a_account,
+                    &mut accounts.token_b_account,
+                    &mut accounts.pool_mint_account,
+                    &mut accounts.pool_fee_account,
+                    &mut accounts.pool_token_account,
+                    &mut accounts.pool_locked_account,
+                    &mut Account::default(),
+                ],
+            );
+            assert_eq!(Err(ProgramError::NotEnoughAccountKeys), result);
+        }
+
+        // fees within the config bounds succeed
+        {
+            let fees = Fees {
+                trade_fee_numerator: 10,
+                owner_trade_fee_numerator: 3,
+                ..config_fees
+            };
+            let mut accounts =
+                SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+            accounts.config_account = config_account;
+            accounts.initialize_swap().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_deposit() {
+        let user_key = Pubkey::new_unique();
+        let depositor_key = Pubkey::new_unique();
+        let trade_fee_numerator = 1;
+        let trade_fee_denominator = 2;
+        let owner_trade_fee_numerator = 1;
+        let owner_trade_fee_denominator = 10;
+        let owner_withdraw_fee_numerator = 1;
+        let owner_withdraw_fee_denominator = 5;
+        let host_fee_numerator = 20;
+        let host_fee_denominator = 100;
+
+        let fees = Fees {
+            trade_fee_numerator,
+            trade_fee_denominator,
+            owner_trade_fee_numerator,
+            owner_trade_fee_denominator,
+            owner_withdraw_fee_numerator,
+            owner_withdraw_fee_denominator,
+            host_fee_numerator,
+            host_fee_denominator,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
+        };
+
+        let token_a_amount = 1000;
+        let token_b_amount = 9000;
+        let amp = 200;
+        let curve_type = CurveType::Stable;
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator: Arc::new(StableCurve { amp }),
+        };
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+
+        // depositing 10% of the current pool amount in token A and B means
+        // that our pool tokens will be worth 1 / 10 of the current pool amount
+        let pool_amount = accounts
+            .swap_curve
+            .calculator
+            .new_pool_supply(
+                to_u128(token_a_amount).unwrap(),
+                to_u128(token_b_amount).unwrap(),
+            )
+            .unwrap()
+            / 10;
+        let deposit_a = token_a_amount / 10;
+        let deposit_b = token_b_amount / 10;
+
+        // swap not initialized
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(ProgramError::UninitializedAccount),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        accounts.initialize_swap().unwrap();
+
+        // wrong owner for swap account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let old_swap_account = accounts.swap_account;
+            let mut wrong_swap_account = old_swap_account.clone();
+            wrong_swap_account.owner = spl_token::id();
+            accounts.swap_account = wrong_swap_account;
+            assert_eq!(
+                Err(ProgramError::IncorrectProgramId),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+            accounts.swap_account = old_swap_account;
+        }
+
+        // wrong bump seed for authority_key
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let old_authority = accounts.authority_key;
+            let (bad_authority_key, _bump_seed) = Pubkey::find_program_address(
+                &[&accounts.swap_key.to_bytes()[..]],
+                &spl_token::id(),
+            );
+            accounts.authority_key = bad_authority_key;
+            assert_eq!(
+                Err(SwapError::InvalidProgramAddress.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+            accounts.authority_key = old_authority;
+        }
+
+        // not enough token A
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &depositor_key,
+                deposit_a / 2,
+                deposit_b,
+                0,
+            );
+            assert_eq!(
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        // not enough token B
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &depositor_key,
+                deposit_a,
+                deposit_b / 2,
+                0,
+            );
+            assert_eq!(
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        // wrong swap token accounts
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        // wrong pool token account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                mut _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let (
+                wrong_token_key,
+                mut wrong_token_account,
+                _token_b_key,
+                mut _token_b_account,
+                _pool_key,
+                mut _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &wrong_token_key,
+                    &mut wrong_token_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        // no approval
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let user_transfer_authority_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(TokenError::OwnerMismatch.into()),
+                do_process_instruction(
+                    deposit_all_token_types(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_transfer_authority_key,
+                        &token_a_key,
+                        &token_b_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &pool_key,
+                        None,
+                        DepositAllTokenTypes {
+                            pool_token_amount: pool_amount.try_into().unwrap(),
+                            maximum_token_a_amount: deposit_a,
+                            maximum_token_b_amount: deposit_b,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut token_a_account,
+                        &mut token_b_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // wrong token program id
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let wrong_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(SwapError::IncorrectTokenProgramId.into()),
+                do_process_instruction(
+                    deposit_all_token_types(
+                        &SWAP_PROGRAM_ID,
+                        &wrong_key,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.authority_key,
+                        &token_a_key,
+                        &token_b_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &accounts.pool_mint_key,
+                        &pool_key,
+                        None,
+                        DepositAllTokenTypes {
+                            pool_token_amount: pool_amount.try_into().unwrap(),
+                            maximum_token_a_amount: deposit_a,
+                            maximum_token_b_amount: deposit_b,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut token_a_account,
+                        &mut token_b_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // wrong swap token accounts
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+
+            let old_a_key = accounts.token_a_key;
+            let old_a_account = accounts.token_a_account;
+
+            accounts.token_a_key = token_a_key;
+            accounts.token_a_account = token_a_account.clone();
+
+            // wrong swap token a account
+            assert_eq!(
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+
+            accounts.token_a_key = old_a_key;
+            accounts.token_a_account = old_a_account;
+
+            let old_b_key = accounts.token_b_key;
+            let old_b_account = accounts.token_b_account;
+
+            accounts.token_b_key = token_b_key;
+            accounts.token_b_account = token_b_account.clone();
+
+            // wrong swap token b account
+            assert_eq!(
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+
+            accounts.token_b_key = old_b_key;
+            accounts.token_b_account = old_b_account;
+        }
+
+        // wrong mint
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let (pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &accounts.authority_key, None);
+            let old_pool_key = accounts.pool_mint_key;
+            let old_pool_account = accounts.pool_mint_account;
+            accounts.pool_mint_key = pool_mint_key;
+            accounts.pool_mint_account = pool_mint_account;
+
+            assert_eq!(
+                Err(SwapError::IncorrectPoolMint.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
 
-    struct SwapAccountInfo {
-        bump_seed: u8,
-        authority_key: Pubkey,
-        fees: Fees,
-        swap_curve: SwapCurve,
-        swap_key: Pubkey,
-        swap_account: Account,
-        pool_mint_key: Pubkey,
-        pool_mint_account: Account,
-        pool_fee_key: Pubkey,
-        pool_fee_account: Account,
-        pool_token_key: Pubkey,
-        pool_token_account: Account,
-        token_a_key: Pubkey,
-        token_a_account: Account,
-        token_a_mint_key: Pubkey,
-        token_a_mint_account: Account,
-        token_b_key: Pubkey,
-        token_b_account: Account,
-        token_b_mint_key: Pubkey,
-        token_b_mint_account: Account,
-    }
+            accounts.pool_mint_key = old_pool_key;
+            accounts.pool_mint_account = old_pool_account;
+        }
 
-    impl SwapAccountInfo {
-        pub fn new(
-            user_key: &Pubkey,
-            fees: Fees,
-            swap_curve: SwapCurve,
-            token_a_amount: u64,
-            token_b_amount: u64,
-        ) -> Self {
-            let swap_key = Pubkey::new_unique();
-            let swap_account = Account::new(0, SwapVersion::LATEST_LEN, &SWAP_PROGRAM_ID);
-            let (authority_key, bump_seed) =
-                Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
+        // deposit 1 pool token fails beacuse it equates to 0 swap tokens
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            assert_eq!(
+                Err(SwapError::ZeroTradingTokens.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    1,
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
 
-            let (pool_mint_key, mut pool_mint_account) =
-                create_mint(&spl_token::id(), &authority_key, None);
-            let (pool_token_key, pool_token_account) = mint_token(
-                &spl_token::id(),
-                &pool_mint_key,
-                &mut pool_mint_account,
-                &authority_key,
-                user_key,
-                0,
+        // slippage exceeded
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            // maximum A amount in too low
+            assert_eq!(
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a / 10,
+                    deposit_b,
+                )
             );
-            let (pool_fee_key, pool_fee_account) = mint_token(
-                &spl_token::id(),
-                &pool_mint_key,
-                &mut pool_mint_account,
-                &authority_key,
-                user_key,
-                0,
+            // maximum B amount in too low
+            assert_eq!(
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b / 10,
+                )
             );
-            let (token_a_mint_key, mut token_a_mint_account) =
-                create_mint(&spl_token::id(), user_key, None);
-            let (token_a_key, token_a_account) = mint_token(
+        }
+
+        // invalid input: can't use swap pool tokens as source
+        {
+            let (
+                _token_a_key,
+                _token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let swap_token_a_key = accounts.token_a_key;
+            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
+            let swap_token_b_key = accounts.token_b_key;
+            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
+            let authority_key = accounts.authority_key;
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.deposit_all_token_types(
+                    &authority_key,
+                    &swap_token_a_key,
+                    &mut swap_token_a_account,
+                    &swap_token_b_key,
+                    &mut swap_token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+            );
+        }
+
+        // correctly deposit
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            accounts
+                .deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount.try_into().unwrap(),
+                    deposit_a,
+                    deposit_b,
+                )
+                .unwrap();
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+            assert_eq!(swap_token_a.amount, deposit_a + token_a_amount);
+            let swap_token_b =
+                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+            assert_eq!(swap_token_b.amount, deposit_b + token_b_amount);
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, 0);
+            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+            assert_eq!(token_b.amount, 0);
+            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+            let swap_pool_account =
+                spl_token::state::Account::unpack(&accounts.pool_token_account.data).unwrap();
+            let pool_locked_account =
+                spl_token::state::Account::unpack(&accounts.pool_locked_account.data).unwrap();
+            let pool_mint =
+                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+            assert_eq!(
+                pool_mint.supply,
+                pool_account.amount + swap_pool_account.amount + pool_locked_account.amount
+            );
+        }
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    fn deposit_with_allowlist_entry(
+        accounts: &mut SwapAccountInfo,
+        depositor_key: &Pubkey,
+        token_a_key: &Pubkey,
+        token_a_account: &mut Account,
+        token_b_key: &Pubkey,
+        token_b_account: &mut Account,
+        pool_key: &Pubkey,
+        pool_account: &mut Account,
+        allowlist_entry: Option<(&Pubkey, &mut Account)>,
+        instruction: DepositAllTokenTypes,
+    ) -> ProgramResult {
+        let mut authority_account = Account::default();
+        let mut depositor_account = Account::default();
+        let mut token_program_account = Account::default();
+        let mut account_infos = vec![
+            &mut accounts.swap_account,
+            &mut authority_account,
+            &mut depositor_account,
+            token_a_account,
+            token_b_account,
+            &mut accounts.token_a_account,
+            &mut accounts.token_b_account,
+            &mut accounts.pool_mint_account,
+            pool_account,
+            &mut token_program_account,
+        ];
+        let allowlist_entry_key = match allowlist_entry {
+            Some((key, account)) => {
+                account_infos.push(account);
+                Some(key)
+            }
+            None => None,
+        };
+        do_process_instruction(
+            deposit_all_token_types(
+                &SWAP_PROGRAM_ID,
                 &spl_token::id(),
-                &token_a_mint_key,
-                &mut token_a_mint_account,
-                user_key,
-                &authority_key,
-                token_a_amount,
+                &accounts.swap_key,
+                &accounts.authority_key,
+                depositor_key,
+                token_a_key,
+                token_b_key,
+                &accounts.token_a_key,
+                &accounts.token_b_key,
+                &accounts.pool_mint_key,
+                pool_key,
+                allowlist_entry_key,
+                instruction,
+            )
+            .unwrap(),
+            account_infos,
+        )
+    }
+
+    #[test]
+    fn test_deposit_allowlist() {
+        let user_key = Pubkey::new_unique();
+        let depositor_key = Pubkey::new_unique();
+        let other_key = Pubkey::new_unique();
+        let payer_key = Pubkey::new_unique();
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 1000,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 1000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+            host_fee_numerator: 0,
+            host_fee_denominator: 0,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
+        };
+        let token_a_amount = 1000;
+        let token_b_amount = 1000;
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 100 }),
+        };
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        accounts.initialize_swap().unwrap();
+
+        let deposit_a = token_a_amount / 10;
+        let deposit_b = token_b_amount / 10;
+        let deposit = DepositAllTokenTypes {
+            pool_token_amount: 10,
+            maximum_token_a_amount: deposit_a,
+            maximum_token_b_amount: deposit_b,
+        };
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+
+        let (allowlist_entry_key, _) =
+            AllowlistEntry::find_address(&SWAP_PROGRAM_ID, &accounts.swap_key, &depositor_key);
+        let entry_rent = Rent::default().minimum_balance(AllowlistEntry::LEN);
+        let mut allowlist_entry_account =
+            Account::new(entry_rent, AllowlistEntry::LEN, &SWAP_PROGRAM_ID);
+        let (other_entry_key, _) =
+            AllowlistEntry::find_address(&SWAP_PROGRAM_ID, &accounts.swap_key, &other_key);
+        let mut other_entry_account =
+            Account::new(entry_rent, AllowlistEntry::LEN, &SWAP_PROGRAM_ID);
+
+        // wrong admin
+        {
+            assert_eq!(
+                Err(SwapError::InvalidAdmin.into()),
+                do_process_instruction(
+                    set_deposit_gate(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &depositor_key,
+                        SetDepositGate { enabled: true },
+                    )
+                    .unwrap(),
+                    vec![&mut accounts.swap_account, &mut Account::default()],
+                )
             );
-            let (token_b_mint_key, mut token_b_mint_account) =
-                create_mint(&spl_token::id(), user_key, None);
-            let (token_b_key, token_b_account) = mint_token(
-                &spl_token::id(),
-                &token_b_mint_key,
-                &mut token_b_mint_account,
-                user_key,
-                &authority_key,
-                token_b_amount,
+            assert_eq!(
+                Err(SwapError::InvalidAdmin.into()),
+                do_process_instruction(
+                    set_allowlist_entry(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &depositor_key,
+                        &allowlist_entry_key,
+                        &depositor_key,
+                        &payer_key,
+                        SetAllowlistEntry { allowed: true },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut allowlist_entry_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
             );
-
-            SwapAccountInfo {
-                bump_seed,
-                authority_key,
-                fees,
-                swap_curve,
-                swap_key,
-                swap_account,
-                pool_mint_key,
-                pool_mint_account,
-                pool_fee_key,
-                pool_fee_account,
-                pool_token_key,
-                pool_token_account,
-                token_a_key,
-                token_a_account,
-                token_a_mint_key,
-                token_a_mint_account,
-                token_b_key,
-                token_b_account,
-                token_b_mint_key,
-                token_b_mint_account,
-            }
         }
 
-        pub fn initialize_swap(&mut self) -> ProgramResult {
-            do_process_instruction(
-                initialize(
-                    &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
-                    &self.swap_key,
-                    &self.authority_key,
-                    &self.token_a_key,
-                    &self.token_b_key,
-                    &self.pool_mint_key,
-                    &self.pool_fee_key,
-                    &self.pool_token_key,
-                    self.fees.clone(),
-                    self.swap_curve.clone(),
-                )
-                .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut self.token_a_account,
-                    &mut self.token_b_account,
-                    &mut self.pool_mint_account,
-                    &mut self.pool_fee_account,
-                    &mut self.pool_token_account,
-                    &mut Account::default(),
-                ],
+        // payer does not sign
+        {
+            let mut instruction = set_allowlist_entry(
+                &SWAP_PROGRAM_ID,
+                &accounts.swap_key,
+                &user_key,
+                &allowlist_entry_key,
+                &depositor_key,
+                &payer_key,
+                SetAllowlistEntry { allowed: true },
             )
+            .unwrap();
+            instruction.accounts[4].is_signer = false;
+            assert_eq!(
+                Err(ProgramError::MissingRequiredSignature),
+                do_process_instruction(
+                    instruction,
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut allowlist_entry_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
+            );
         }
 
-        pub fn setup_token_accounts(
-            &mut self,
-            mint_owner: &Pubkey,
-            account_owner: &Pubkey,
-            a_amount: u64,
-            b_amount: u64,
-            pool_amount: u64,
-        ) -> (Pubkey, Account, Pubkey, Account, Pubkey, Account) {
-            let (token_a_key, token_a_account) = mint_token(
-                &spl_token::id(),
-                &self.token_a_mint_key,
-                &mut self.token_a_mint_account,
-                mint_owner,
-                account_owner,
-                a_amount,
-            );
-            let (token_b_key, token_b_account) = mint_token(
-                &spl_token::id(),
-                &self.token_b_mint_key,
-                &mut self.token_b_mint_account,
-                mint_owner,
-                account_owner,
-                b_amount,
-            );
-            let (pool_key, pool_account) = mint_token(
-                &spl_token::id(),
-                &self.pool_mint_key,
-                &mut self.pool_mint_account,
-                &self.authority_key,
-                account_owner,
-                pool_amount,
+        // entry not at the expected address
+        {
+            assert_eq!(
+                Err(SwapError::InvalidProgramAddress.into()),
+                do_process_instruction(
+                    set_allowlist_entry(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &user_key,
+                        &other_entry_key,
+                        &depositor_key,
+                        &payer_key,
+                        SetAllowlistEntry { allowed: true },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut other_entry_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
             );
-            (
-                token_a_key,
-                token_a_account,
-                token_b_key,
-                token_b_account,
-                pool_key,
-                pool_account,
-            )
         }
 
-        fn get_token_account(&self, account_key: &Pubkey) -> &Account {
-            if *account_key == self.token_a_key {
-                return &self.token_a_account;
-            } else if *account_key == self.token_b_key {
-                return &self.token_b_account;
-            }
-            panic!("Could not find matching swap token account");
+        do_process_instruction(
+            set_deposit_gate(
+                &SWAP_PROGRAM_ID,
+                &accounts.swap_key,
+                &user_key,
+                SetDepositGate { enabled: true },
+            )
+            .unwrap(),
+            vec![&mut accounts.swap_account, &mut Account::default()],
+        )
+        .unwrap();
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert!(swap_state.deposit_gate());
+
+        // no allowlist entry
+        {
+            assert_eq!(
+                Err(ProgramError::NotEnoughAccountKeys),
+                deposit_with_allowlist_entry(
+                    &mut accounts,
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    None,
+                    deposit.clone(),
+                )
+            );
         }
 
-        fn set_token_account(&mut self, account_key: &Pubkey, account: Account) {
-            if *account_key == self.token_a_key {
-                self.token_a_account = account;
-                return;
-            } else if *account_key == self.token_b_key {
-                self.token_b_account = account;
-                return;
-            }
-            panic!("Could not find matching swap token account");
+        // uninitialized allowlist entry
+        {
+            assert_eq!(
+                Err(SwapError::DepositNotAllowed.into()),
+                deposit_with_allowlist_entry(
+                    &mut accounts,
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    Some((&allowlist_entry_key, &mut allowlist_entry_account)),
+                    deposit.clone(),
+                )
+            );
         }
 
-        #[allow(clippy::too_many_arguments)]
-        pub fn swap(
-            &mut self,
-            user_key: &Pubkey,
-            user_source_key: &Pubkey,
-            user_source_account: &mut Account,
-            swap_source_key: &Pubkey,
-            swap_destination_key: &Pubkey,
-            user_destination_key: &Pubkey,
-            user_destination_account: &mut Account,
-            amount_in: u64,
-            minimum_amount_out: u64,
-        ) -> ProgramResult {
-            let user_transfer_key = Pubkey::new_unique();
-            // approve moving from user source account
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    user_source_key,
-                    &user_transfer_key,
-                    user_key,
-                    &[],
-                    amount_in,
+        do_process_instruction(
+            set_allowlist_entry(
+                &SWAP_PROGRAM_ID,
+                &accounts.swap_key,
+                &user_key,
+                &allowlist_entry_key,
+                &depositor_key,
+                &payer_key,
+                SetAllowlistEntry { allowed: true },
+            )
+            .unwrap(),
+            vec![
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut allowlist_entry_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+        let allowlist_entry = AllowlistEntry::unpack(&allowlist_entry_account.data).unwrap();
+        assert_eq!(allowlist_entry.swap, accounts.swap_key);
+        assert_eq!(allowlist_entry.user, depositor_key);
+
+        do_process_instruction(
+            set_allowlist_entry(
+                &SWAP_PROGRAM_ID,
+                &accounts.swap_key,
+                &user_key,
+                &other_entry_key,
+                &other_key,
+                &payer_key,
+                SetAllowlistEntry { allowed: true },
+            )
+            .unwrap(),
+            vec![
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut other_entry_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+
+        // entry for another user
+        {
+            assert_eq!(
+                Err(SwapError::DepositNotAllowed.into()),
+                deposit_with_allowlist_entry(
+                    &mut accounts,
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    Some((&other_entry_key, &mut other_entry_account)),
+                    deposit.clone(),
                 )
-                .unwrap(),
-                vec![
-                    user_source_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
+            );
+        }
+
+        // correctly deposit
+        {
+            deposit_with_allowlist_entry(
+                &mut accounts,
+                &depositor_key,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                &pool_key,
+                &mut pool_account,
+                Some((&allowlist_entry_key, &mut allowlist_entry_account)),
+                deposit.clone(),
             )
             .unwrap();
+            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+            assert_eq!(pool_account.amount, deposit.pool_token_amount);
+        }
 
-            let mut swap_source_account = self.get_token_account(swap_source_key).clone();
-            let mut swap_destination_account = self.get_token_account(swap_destination_key).clone();
-
-            // perform the swap
+        // removed allowlist entry
+        {
             do_process_instruction(
-                swap(
+                set_allowlist_entry(
                     &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
-                    &self.swap_key,
-                    &self.authority_key,
-                    &user_transfer_key,
-                    user_source_key,
-                    swap_source_key,
-                    swap_destination_key,
-                    user_destination_key,
-                    &self.pool_mint_key,
-                    &self.pool_fee_key,
-                    None,
-                    Swap {
-                        amount_in,
-                        minimum_amount_out,
-                    },
+                    &accounts.swap_key,
+                    &user_key,
+                    &allowlist_entry_key,
+                    &depositor_key,
+                    &payer_key,
+                    SetAllowlistEntry { allowed: false },
                 )
                 .unwrap(),
                 vec![
-                    &mut self.swap_account,
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut allowlist_entry_account,
                     &mut Account::default(),
                     &mut Account::default(),
-                    user_source_account,
-                    &mut swap_source_account,
-                    &mut swap_destination_account,
-                    user_destination_account,
-                    &mut self.pool_mint_account,
-                    &mut self.pool_fee_account,
                     &mut Account::default(),
                 ],
-            )?;
-
-            self.set_token_account(swap_source_key, swap_source_account);
-            self.set_token_account(swap_destination_key, swap_destination_account);
+            )
+            .unwrap();
+            assert_eq!(allowlist_entry_account.lamports, 0);
 
-            Ok(())
+            // allowing the closed entry again in the same transaction
+            assert_eq!(
+                Err(ProgramError::AccountNotRentExempt),
+                do_process_instruction(
+                    set_allowlist_entry(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &user_key,
+                        &allowlist_entry_key,
+                        &depositor_key,
+                        &payer_key,
+                        SetAllowlistEntry { allowed: true },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut allowlist_entry_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut Account::default(),
+                    ],
+                )
+            );
+            assert_eq!(
+                Err(SwapError::DepositNotAllowed.into()),
+                deposit_with_allowlist_entry(
+                    &mut accounts,
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    Some((&allowlist_entry_key, &mut allowlist_entry_account)),
+                    deposit.clone(),
+                )
+            );
         }
 
-        #[allow(clippy::too_many_arguments)]
-        pub fn deposit_all_token_types(
-            &mut self,
-            depositor_key: &Pubkey,
-            depositor_token_a_key: &Pubkey,
-            depositor_token_a_account: &mut Account,
-            depositor_token_b_key: &Pubkey,
-            depositor_token_b_account: &mut Account,
-            depositor_pool_key: &Pubkey,
-            depositor_pool_account: &mut Account,
-            pool_token_amount: u64,
-            maximum_token_a_amount: u64,
-            maximum_token_b_amount: u64,
-        ) -> ProgramResult {
-            let user_transfer_authority = Pubkey::new_unique();
+        // gate removed
+        {
             do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    depositor_token_a_key,
-                    &user_transfer_authority,
-                    depositor_key,
-                    &[],
-                    maximum_token_a_amount,
+                set_deposit_gate(
+                    &SWAP_PROGRAM_ID,
+                    &accounts.swap_key,
+                    &user_key,
+                    SetDepositGate { enabled: false },
                 )
                 .unwrap(),
-                vec![
-                    depositor_token_a_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
+                vec![&mut accounts.swap_account, &mut Account::default()],
             )
             .unwrap();
-
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    depositor_token_b_key,
-                    &user_transfer_authority,
-                    depositor_key,
-                    &[],
-                    maximum_token_b_amount,
-                )
-                .unwrap(),
-                vec![
-                    depositor_token_b_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
+            deposit_with_allowlist_entry(
+                &mut accounts,
+                &depositor_key,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                &pool_key,
+                &mut pool_account,
+                None,
+                deposit,
             )
             .unwrap();
+        }
+    }
+
+    #[test]
+    fn test_deposit_caps() {
+        let user_key = Pubkey::new_unique();
+        let depositor_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 1000,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 1000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+            host_fee_numerator: 0,
+            host_fee_denominator: 0,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
+        };
+        let token_a_amount = 10_000;
+        let token_b_amount = 10_000;
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 100 }),
+        };
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        accounts.initialize_swap().unwrap();
+
+        let pool_mint = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+        let initial_supply = pool_mint.supply;
+        let pool_amount = initial_supply / 10;
+        let deposit_a = token_a_amount / 10;
+        let deposit_b = token_b_amount / 10;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(
+            &user_key,
+            &depositor_key,
+            deposit_a * 2,
+            deposit_b * 2,
+            0,
+        );
+
+        // wrong admin
+        {
+            assert_eq!(
+                Err(SwapError::InvalidAdmin.into()),
+                do_process_instruction(
+                    set_deposit_caps(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &depositor_key,
+                        SetDepositCaps {
+                            max_token_a_amount: 0,
+                            max_token_b_amount: 0,
+                            max_pool_supply: 0,
+                        },
+                    )
+                    .unwrap(),
+                    vec![&mut accounts.swap_account, &mut Account::default()],
+                )
+            );
+        }
 
+        // token A balance over the cap
+        {
             do_process_instruction(
-                deposit_all_token_types(
+                set_deposit_caps(
                     &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
-                    &self.swap_key,
-                    &self.authority_key,
-                    &user_transfer_authority,
-                    depositor_token_a_key,
-                    depositor_token_b_key,
-                    &self.token_a_key,
-                    &self.token_b_key,
-                    &self.pool_mint_key,
-                    depositor_pool_key,
-                    DepositAllTokenTypes {
-                        pool_token_amount,
-                        maximum_token_a_amount,
-                        maximum_token_b_amount,
+                    &accounts.swap_key,
+                    &user_key,
+                    SetDepositCaps {
+                        max_token_a_amount: token_a_amount,
+                        max_token_b_amount: 0,
+                        max_pool_supply: 0,
                     },
                 )
                 .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                    depositor_token_a_account,
-                    depositor_token_b_account,
-                    &mut self.token_a_account,
-                    &mut self.token_b_account,
-                    &mut self.pool_mint_account,
-                    depositor_pool_account,
-                    &mut Account::default(),
-                ],
+                vec![&mut accounts.swap_account, &mut Account::default()],
             )
-        }
-
-        #[allow(clippy::too_many_arguments)]
-        pub fn withdraw_all_token_types(
-            &mut self,
-            user_key: &Pubkey,
-            pool_key: &Pubkey,
-            pool_account: &mut Account,
-            token_a_key: &Pubkey,
-            token_a_account: &mut Account,
-            token_b_key: &Pubkey,
-            token_b_account: &mut Account,
-            pool_token_amount: u64,
-            minimum_token_a_amount: u64,
-            minimum_token_b_amount: u64,
-        ) -> ProgramResult {
-            let user_transfer_authority_key = Pubkey::new_unique();
-            // approve user transfer authority to take out pool tokens
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    pool_key,
-                    &user_transfer_authority_key,
-                    user_key,
-                    &[],
-                    pool_token_amount,
+            .unwrap();
+            let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_state.max_token_a_amount(), token_a_amount);
+            assert_eq!(
+                Err(SwapError::DepositCapExceeded.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount,
+                    deposit_a,
+                    deposit_b,
                 )
-                .unwrap(),
-                vec![
-                    pool_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
-            )
-            .unwrap();
+            );
+        }
 
-            // withdraw token a and b correctly
+        // pool token supply over the cap
+        {
             do_process_instruction(
-                withdraw_all_token_types(
+                set_deposit_caps(
                     &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
-                    &self.swap_key,
-                    &self.authority_key,
-                    &user_transfer_authority_key,
-                    &self.pool_mint_key,
-                    &self.pool_fee_key,
-                    pool_key,
-                    &self.token_a_key,
-                    &self.token_b_key,
-                    token_a_key,
-                    token_b_key,
-                    WithdrawAllTokenTypes {
-                        pool_token_amount,
-                        minimum_token_a_amount,
-                        minimum_token_b_amount,
+                    &accounts.swap_key,
+                    &user_key,
+                    SetDepositCaps {
+                        max_token_a_amount: 0,
+                        max_token_b_amount: token_b_amount + deposit_b,
+                        max_pool_supply: initial_supply + pool_amount - 1,
                     },
                 )
                 .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                    &mut self.pool_mint_account,
-                    pool_account,
-                    &mut self.token_a_account,
-                    &mut self.token_b_account,
-                    token_a_account,
-                    token_b_account,
-                    &mut self.pool_fee_account,
-                    &mut Account::default(),
-                ],
+                vec![&mut accounts.swap_account, &mut Account::default()],
             )
+            .unwrap();
+            assert_eq!(
+                Err(SwapError::DepositCapExceeded.into()),
+                accounts.deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount,
+                    deposit_a,
+                    deposit_b,
+                )
+            );
         }
 
-        #[allow(clippy::too_many_arguments)]
-        pub fn withdraw_single_token_type_exact_amount_out(
-            &mut self,
-            user_key: &Pubkey,
-            pool_key: &Pubkey,
-            pool_account: &mut Account,
-            destination_key: &Pubkey,
-            destination_account: &mut Account,
-            destination_token_amount: u64,
-            maximum_pool_token_amount: u64,
-        ) -> ProgramResult {
-            let user_transfer_authority_key = Pubkey::new_unique();
-            // approve user transfer authority to take out pool tokens
+        // deposit up to the caps, tokens sent straight to the swap token
+        // account not counting
+        {
+            let (donor_key, mut donor_account) = mint_token(
+                &spl_token::id(),
+                &accounts.token_a_mint_key,
+                &mut accounts.token_a_mint_account,
+                &user_key,
+                &swapper_key,
+                deposit_a,
+            );
             do_process_instruction(
-                approve(
+                transfer(
                     &spl_token::id(),
-                    pool_key,
-                    &user_transfer_authority_key,
-                    user_key,
+                    &donor_key,
+                    &accounts.token_a_key,
+                    &swapper_key,
                     &[],
-                    maximum_pool_token_amount,
+                    deposit_a,
                 )
                 .unwrap(),
                 vec![
-                    pool_account,
-                    &mut Account::default(),
+                    &mut donor_account,
+                    &mut accounts.token_a_account,
                     &mut Account::default(),
                 ],
             )
             .unwrap();
-
             do_process_instruction(
-                withdraw_single_token_type_exact_amount_out(
+                set_deposit_caps(
                     &SWAP_PROGRAM_ID,
-                    &spl_token::id(),
-                    &self.swap_key,
-                    &self.authority_key,
-                    &user_transfer_authority_key,
-                    &self.pool_mint_key,
-                    &self.pool_fee_key,
-                    pool_key,
-                    &self.token_a_key,
-                    &self.token_b_key,
-                    destination_key,
-                    WithdrawSingleTokenTypeExactAmountOut {
-                        destination_token_amount,
-                        maximum_pool_token_amount,
+                    &accounts.swap_key,
+                    &user_key,
+                    SetDepositCaps {
+                        max_token_a_amount: token_a_amount + deposit_a,
+                        max_token_b_amount: token_b_amount + deposit_b,
+                        max_pool_supply: initial_supply + pool_amount,
                     },
                 )
                 .unwrap(),
-                vec![
-                    &mut self.swap_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                    &mut self.pool_mint_account,
-                    pool_account,
-                    &mut self.token_a_account,
-                    &mut self.token_b_account,
-                    destination_account,
-                    &mut self.pool_fee_account,
-                    &mut Account::default(),
-                ],
+                vec![&mut accounts.swap_account, &mut Account::default()],
             )
+            .unwrap();
+            accounts
+                .deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount,
+                    deposit_a,
+                    deposit_b,
+                )
+                .unwrap();
+            let pool_mint =
+                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+            assert_eq!(pool_mint.supply, initial_supply + pool_amount);
         }
-    }
-
-    fn mint_minimum_balance() -> u64 {
-        Rent::default().minimum_balance(spl_token::state::Mint::get_packed_len())
-    }
-
-    fn account_minimum_balance() -> u64 {
-        Rent::default().minimum_balance(spl_token::state::Account::get_packed_len())
-    }
-
-    fn do_process_instruction_with_fee_constraints(
-        instruction: Instruction,
-        accounts: Vec<&mut Account>,
-        swap_constraints: &Option<SwapConstraints>,
-    ) -> ProgramResult {
-        test_syscall_stubs();
-
-        // approximate the logic in the actual runtime which runs the instruction
-        // and only updates accounts if the instruction is successful
-        let mut account_clones = accounts.iter().map(|x| (*x).clone()).collect::<Vec<_>>();
-        let mut meta = instruction
-            .accounts
-            .iter()
-            .zip(account_clones.iter_mut())
-            .map(|(account_meta, account)| (&account_meta.pubkey, account_meta.is_signer, account))
-            .collect::<Vec<_>>();
-        let mut account_infos = create_is_signer_account_infos(&mut meta);
-        let res = if instruction.program_id == SWAP_PROGRAM_ID {
-            Processor::process_with_constraints(
-                &instruction.program_id,
-                &account_infos,
-                &instruction.data,
-                swap_constraints,
-            )
-        } else {
-            spl_token::processor::Processor::process(
-                &instruction.program_id,
-                &account_infos,
-                &instruction.data,
-            )
-        };
 
-        if res.is_ok() {
-            let mut account_metas = instruction
-                .accounts
-                .iter()
-                .zip(accounts)
-                .map(|(account_meta, account)| (&account_meta.pubkey, account))
-                .collect::<Vec<_>>();
-            for account_info in account_infos.iter_mut() {
-                for account_meta in account_metas.iter_mut() {
-                    if account_info.key == account_meta.0 {
-                        let account = &mut account_meta.1;
-                        account.owner = *account_info.owner;
-                        account.lamports = **account_info.lamports.borrow();
-                        account.data = account_info.data.borrow().to_vec();
-                    }
-                }
-            }
+        // swaps are not capped
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &swapper_key, deposit_a, 0, 0);
+            let swap_token_a_key = accounts.token_a_key;
+            let swap_token_b_key = accounts.token_b_key;
+            accounts
+                .swap(
+                    &swapper_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    deposit_a,
+                    0,
+                )
+                .unwrap();
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+            assert!(swap_token_a.amount > token_a_amount + deposit_a);
         }
-        res
-    }
-
-    fn do_process_instruction(
-        instruction: Instruction,
-        accounts: Vec<&mut Account>,
-    ) -> ProgramResult {
-        do_process_instruction_with_fee_constraints(instruction, accounts, &SWAP_CONSTRAINTS)
-    }
-
-    fn mint_token(
-        program_id: &Pubkey,
-        mint_key: &Pubkey,
-        mint_account: &mut Account,
-        mint_authority_key: &Pubkey,
-        account_owner_key: &Pubkey,
-        amount: u64,
-    ) -> (Pubkey, Account) {
-        let account_key = Pubkey::new_unique();
-        let mut account_account = Account::new(
-            account_minimum_balance(),
-            spl_token::state::Account::get_packed_len(),
-            program_id,
-        );
-        let mut mint_authority_account = Account::default();
-        let mut rent_sysvar_account = create_account_for_test(&Rent::free());
-
-        do_process_instruction(
-            initialize_account(program_id, &account_key, mint_key, account_owner_key).unwrap(),
-            vec![
-                &mut account_account,
-                mint_account,
-                &mut mint_authority_account,
-                &mut rent_sysvar_account,
-            ],
-        )
-        .unwrap();
 
-        if amount > 0 {
+        // uncapped again
+        {
             do_process_instruction(
-                mint_to(
-                    program_id,
-                    mint_key,
-                    &account_key,
-                    mint_authority_key,
-                    &[],
-                    amount,
+                set_deposit_caps(
+                    &SWAP_PROGRAM_ID,
+                    &accounts.swap_key,
+                    &user_key,
+                    SetDepositCaps {
+                        max_token_a_amount: 0,
+                        max_token_b_amount: 0,
+                        max_pool_supply: 0,
+                    },
                 )
                 .unwrap(),
-                vec![
-                    mint_account,
-                    &mut account_account,
-                    &mut mint_authority_account,
-                ],
+                vec![&mut accounts.swap_account, &mut Account::default()],
             )
             .unwrap();
+            accounts
+                .deposit_all_token_types(
+                    &depositor_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    &pool_key,
+                    &mut pool_account,
+                    pool_amount / 2,
+                    deposit_a,
+                    deposit_b,
+                )
+                .unwrap();
         }
-
-        (account_key, account_account)
     }
 
-    fn create_mint(
-        program_id: &Pubkey,
-        authority_key: &Pubkey,
-        freeze_authority: Option<&Pubkey>,
-    ) -> (Pubkey, Account) {
-        let mint_key = Pubkey::new_unique();
-        let mut mint_account = Account::new(
-            mint_minimum_balance(),
-            spl_token::state::Mint::get_packed_len(),
-            program_id,
-        );
-        let mut rent_sysvar_account = create_account_for_test(&Rent::free());
+    #[test]
+    fn test_price_band() {
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
+        let withdrawer_key = Pubkey::new_unique();
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 1000,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 1000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+            host_fee_numerator: 0,
+            host_fee_denominator: 0,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
+        };
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 1_000_000;
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 1 }),
+        };
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        accounts.initialize_swap().unwrap();
+
+        let small_amount = 10;
+        let large_amount = token_a_amount / 100;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, large_amount * 2, 0, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+
+        // wrong admin
+        {
+            assert_eq!(
+                Err(SwapError::InvalidAdmin.into()),
+                do_process_instruction(
+                    set_price_band(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &swapper_key,
+                        SetPriceBand { price_band: 1 },
+                    )
+                    .unwrap(),
+                    vec![&mut accounts.swap_account, &mut Account::default()],
+                )
+            );
+        }
+
+        // band wider than the price
+        {
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                do_process_instruction(
+                    set_price_band(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &user_key,
+                        SetPriceBand {
+                            price_band: PRICE_BAND_DENOMINATOR + 1
+                        },
+                    )
+                    .unwrap(),
+                    vec![&mut accounts.swap_account, &mut Account::default()],
+                )
+            );
+        }
 
         do_process_instruction(
-            initialize_mint(program_id, &mint_key, authority_key, freeze_authority, 2).unwrap(),
-            vec![&mut mint_account, &mut rent_sysvar_account],
+            set_price_band(
+                &SWAP_PROGRAM_ID,
+                &accounts.swap_key,
+                &user_key,
+                SetPriceBand { price_band: 1 },
+            )
+            .unwrap(),
+            vec![&mut accounts.swap_account, &mut Account::default()],
         )
         .unwrap();
 
-        (mint_key, mint_account)
-    }
+        // small swap within the band records the reference price
+        {
+            accounts
+                .swap(
+                    &swapper_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    small_amount,
+                    0,
+                )
+                .unwrap();
+            let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_state.reference_slot(), Clock::default().slot);
+            assert_eq!(swap_state.reference_price(), SPOT_PRICE_PRECISION);
+        }
 
-    #[test]
-    fn test_token_program_id_error() {
-        test_syscall_stubs();
-        let swap_key = Pubkey::new_unique();
-        let mut mint = (Pubkey::new_unique(), Account::default());
-        let mut destination = (Pubkey::new_unique(), Account::default());
-        let token_program = (spl_token::id(), Account::default());
-        let (authority_key, bump_seed) =
-            Pubkey::find_program_address(&[&swap_key.to_bytes()[..]], &SWAP_PROGRAM_ID);
-        let mut authority = (authority_key, Account::default());
-        let swap_bytes = swap_key.to_bytes();
-        let authority_signature_seeds = [&swap_bytes[..32], &[bump_seed]];
-        let signers = &[&authority_signature_seeds[..]];
-        let ix = mint_to(
-            &token_program.0,
-            &mint.0,
-            &destination.0,
-            &authority.0,
-            &[],
-            10,
-        )
-        .unwrap();
-        let mint = (&mut mint).into();
-        let destination = (&mut destination).into();
-        let authority = (&mut authority).into();
+        // large swap out of the band
+        {
+            assert_eq!(
+                Err(SwapError::PriceBandExceeded.into()),
+                accounts.swap(
+                    &swapper_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    large_amount,
+                    0,
+                )
+            );
+        }
 
-        let err = invoke_signed(&ix, &[mint, destination, authority], signers).unwrap_err();
-        assert_eq!(err, ProgramError::InvalidAccountData);
+        // large single token withdrawals out of the band
+        {
+            let (
+                _token_a_key,
+                _token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, 0, 0, large_amount);
+            assert_eq!(
+                Err(SwapError::PriceBandExceeded.into()),
+                accounts.withdraw_single_token_type_exact_amount_in(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    large_amount,
+                    0,
+                )
+            );
+            assert_eq!(
+                Err(SwapError::PriceBandExceeded.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    large_amount / 2,
+                    large_amount,
+                )
+            );
+        }
+
+        // the reference price is recorded again in a new slot
+        {
+            let mut swap_v2 = SwapVersion::unpack_latest(&accounts.swap_account.data).unwrap();
+            swap_v2.reference_slot = Clock::default().slot + 1;
+            swap_v2.reference_price = SPOT_PRICE_PRECISION * 2;
+            SwapVersion::pack(
+                SwapVersion::SwapV2(swap_v2),
+                &mut accounts.swap_account.data,
+            )
+            .unwrap();
+            accounts
+                .swap(
+                    &swapper_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    small_amount,
+                    0,
+                )
+                .unwrap();
+            let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_state.reference_slot(), Clock::default().slot);
+            assert!(swap_state.reference_price() < SPOT_PRICE_PRECISION);
+        }
+
+        // circuit breaker off
+        {
+            do_process_instruction(
+                set_price_band(
+                    &SWAP_PROGRAM_ID,
+                    &accounts.swap_key,
+                    &user_key,
+                    SetPriceBand { price_band: 0 },
+                )
+                .unwrap(),
+                vec![&mut accounts.swap_account, &mut Account::default()],
+            )
+            .unwrap();
+            accounts
+                .swap(
+                    &swapper_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    large_amount,
+                    0,
+                )
+                .unwrap();
+        }
     }
 
     #[test]
-    fn test_initialize() {
+    fn test_oracle_guard() {
         let user_key = Pubkey::new_unique();
-        let trade_fee_numerator = 1;
-        let trade_fee_denominator = 2;
-        let owner_trade_fee_numerator = 1;
-        let owner_trade_fee_denominator = 10;
-        let owner_withdraw_fee_numerator = 1;
-        let owner_withdraw_fee_denominator = 5;
-        let host_fee_numerator = 20;
-        let host_fee_denominator = 100;
+        let swapper_key = Pubkey::new_unique();
         let fees = Fees {
-            trade_fee_numerator,
-            trade_fee_denominator,
-            owner_trade_fee_numerator,
-            owner_trade_fee_denominator,
-            owner_withdraw_fee_numerator,
-            owner_withdraw_fee_denominator,
-            host_fee_numerator,
-            host_fee_denominator,
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 1000,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 1000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+            host_fee_numerator: 0,
+            host_fee_denominator: 0,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
         };
-
-        let token_a_amount = 1000;
-        let token_b_amount = 2000;
-        let pool_token_amount = 10;
-        let amp = 200;
-        let curve_type = CurveType::Stable;
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 1_000_000;
         let swap_curve = SwapCurve {
-            curve_type,
-            calculator: Arc::new(StableCurve { amp }),
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 1 }),
         };
 
-        let mut accounts =
-            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        accounts.initialize_swap().unwrap();
+
+        let small_amount = 10;
+        let large_amount = token_a_amount / 10;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, large_amount * 2, 0, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+
+        let oracle_key = Pubkey::new_unique();
+        let mut oracle_account = Account::new(0, PriceConf::LEN, &Pubkey::new_unique());
+        PriceConf {
+            price: 100_000_000,
+            conf: 10_000,
+            expo: -8,
+        }
+        .pack_into_slice(&mut oracle_account.data);
+        let max_deviation = 50;
 
-        // uninitialized token a account
+        // wrong admin
         {
-            let old_account = accounts.token_a_account;
-            accounts.token_a_account = Account::new(0, 0, &spl_token::id());
             assert_eq!(
-                Err(SwapError::ExpectedAccount.into()),
-                accounts.initialize_swap()
+                Err(SwapError::InvalidAdmin.into()),
+                do_process_instruction(
+                    set_oracle(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &swapper_key,
+                        &oracle_key,
+                        SetOracle {
+                            oracle_type: OracleType::PriceConf,
+                            max_deviation,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut oracle_account,
+                    ],
+                )
             );
-            accounts.token_a_account = old_account;
         }
 
-        // uninitialized token b account
+        // deviation wider than the price
         {
-            let old_account = accounts.token_b_account;
-            accounts.token_b_account = Account::new(0, 0, &spl_token::id());
             assert_eq!(
-                Err(SwapError::ExpectedAccount.into()),
-                accounts.initialize_swap()
+                Err(SwapError::InvalidInput.into()),
+                do_process_instruction(
+                    set_oracle(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &user_key,
+                        &oracle_key,
+                        SetOracle {
+                            oracle_type: OracleType::PriceConf,
+                            max_deviation: ORACLE_DEVIATION_DENOMINATOR + 1,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut oracle_account,
+                    ],
+                )
             );
-            accounts.token_b_account = old_account;
         }
 
-        // uninitialized pool mint
+        // account without a valid price
         {
-            let old_account = accounts.pool_mint_account;
-            accounts.pool_mint_account = Account::new(0, 0, &spl_token::id());
+            let mut bad_oracle_account = Account::new(0, PriceConf::LEN, &Pubkey::new_unique());
+            PriceConf {
+                price: -1,
+                conf: 0,
+                expo: -8,
+            }
+            .pack_into_slice(&mut bad_oracle_account.data);
             assert_eq!(
-                Err(SwapError::ExpectedMint.into()),
-                accounts.initialize_swap()
+                Err(SwapError::InvalidOracle.into()),
+                do_process_instruction(
+                    set_oracle(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &user_key,
+                        &oracle_key,
+                        SetOracle {
+                            oracle_type: OracleType::PriceConf,
+                            max_deviation,
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut bad_oracle_account,
+                    ],
+                )
             );
-            accounts.pool_mint_account = old_account;
         }
 
-        // token A account owner is not swap authority
-        {
-            let (_token_a_key, token_a_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_a_mint_key,
-                &mut accounts.token_a_mint_account,
-                &user_key,
+        do_process_instruction(
+            set_oracle(
+                &SWAP_PROGRAM_ID,
+                &accounts.swap_key,
                 &user_key,
-                0,
-            );
-            let old_account = accounts.token_a_account;
-            accounts.token_a_account = token_a_account;
+                &oracle_key,
+                SetOracle {
+                    oracle_type: OracleType::PriceConf,
+                    max_deviation,
+                },
+            )
+            .unwrap(),
+            vec![
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut oracle_account,
+            ],
+        )
+        .unwrap();
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(*swap_state.oracle(), oracle_key);
+        assert_eq!(swap_state.oracle_type(), OracleType::PriceConf);
+        assert_eq!(swap_state.max_oracle_deviation(), max_deviation);
+
+        // swap without the oracle account
+        {
             assert_eq!(
-                Err(SwapError::InvalidOwner.into()),
-                accounts.initialize_swap()
+                Err(ProgramError::NotEnoughAccountKeys),
+                accounts.swap(
+                    &swapper_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    small_amount,
+                    0,
+                )
             );
-            accounts.token_a_account = old_account;
         }
 
-        // token B account owner is not swap authority
+        // swap with the wrong oracle account
         {
-            let (_token_b_key, token_b_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_b_mint_key,
-                &mut accounts.token_b_mint_account,
-                &user_key,
-                &user_key,
-                0,
-            );
-            let old_account = accounts.token_b_account;
-            accounts.token_b_account = token_b_account;
+            let wrong_oracle_key = Pubkey::new_unique();
+            let mut wrong_oracle_account = oracle_account.clone();
             assert_eq!(
-                Err(SwapError::InvalidOwner.into()),
-                accounts.initialize_swap()
+                Err(SwapError::InvalidOracle.into()),
+                accounts.swap_with_oracle(
+                    &swapper_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    Some((&wrong_oracle_key, &mut wrong_oracle_account)),
+                    small_amount,
+                    0,
+                )
             );
-            accounts.token_b_account = old_account;
         }
 
-        // pool token account owner is swap authority
+        // small swap close to the oracle price
         {
-            let (_pool_token_key, pool_token_account) = mint_token(
-                &spl_token::id(),
-                &accounts.pool_mint_key,
-                &mut accounts.pool_mint_account,
-                &accounts.authority_key,
-                &accounts.authority_key,
-                0,
-            );
-            let old_account = accounts.pool_token_account;
-            accounts.pool_token_account = pool_token_account;
-            assert_eq!(
-                Err(SwapError::InvalidOutputOwner.into()),
-                accounts.initialize_swap()
-            );
-            accounts.pool_token_account = old_account;
+            accounts
+                .swap_with_oracle(
+                    &swapper_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    Some((&oracle_key, &mut oracle_account)),
+                    small_amount,
+                    0,
+                )
+                .unwrap();
         }
 
-        // pool fee account owner is swap authority
+        // large swap deviating from the oracle price
         {
-            let (_pool_fee_key, pool_fee_account) = mint_token(
-                &spl_token::id(),
-                &accounts.pool_mint_key,
-                &mut accounts.pool_mint_account,
-                &accounts.authority_key,
-                &accounts.authority_key,
-                0,
-            );
-            let old_account = accounts.pool_fee_account;
-            accounts.pool_fee_account = pool_fee_account;
             assert_eq!(
-                Err(SwapError::InvalidOutputOwner.into()),
-                accounts.initialize_swap()
+                Err(SwapError::OracleDeviationExceeded.into()),
+                accounts.swap_with_oracle(
+                    &swapper_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    Some((&oracle_key, &mut oracle_account)),
+                    large_amount,
+                    0,
+                )
             );
-            accounts.pool_fee_account = old_account;
         }
 
-        // pool mint authority is not swap authority
+        // oracle too unsure of its price
         {
-            let (_pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &user_key, None);
-            let old_mint = accounts.pool_mint_account;
-            accounts.pool_mint_account = pool_mint_account;
+            let mut unsure_oracle_account = oracle_account.clone();
+            PriceConf {
+                price: 100_000_000,
+                conf: 1_000_000,
+                expo: -8,
+            }
+            .pack_into_slice(&mut unsure_oracle_account.data);
             assert_eq!(
-                Err(SwapError::InvalidOwner.into()),
-                accounts.initialize_swap()
+                Err(SwapError::InvalidOracle.into()),
+                accounts.swap_with_oracle(
+                    &swapper_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    Some((&oracle_key, &mut unsure_oracle_account)),
+                    small_amount,
+                    0,
+                )
             );
-            accounts.pool_mint_account = old_mint;
         }
 
-        // pool mint token has freeze authority
+        // oracle guard off
         {
-            let (_pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &accounts.authority_key, Some(&user_key));
-            let old_mint = accounts.pool_mint_account;
-            accounts.pool_mint_account = pool_mint_account;
-            assert_eq!(
-                Err(SwapError::InvalidFreezeAuthority.into()),
-                accounts.initialize_swap()
-            );
-            accounts.pool_mint_account = old_mint;
+            do_process_instruction(
+                set_oracle(
+                    &SWAP_PROGRAM_ID,
+                    &accounts.swap_key,
+                    &user_key,
+                    &oracle_key,
+                    SetOracle {
+                        oracle_type: OracleType::PriceConf,
+                        max_deviation: 0,
+                    },
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut oracle_account,
+                ],
+            )
+            .unwrap();
+            let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(*swap_state.oracle(), Pubkey::default());
+            accounts
+                .swap(
+                    &swapper_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &token_b_key,
+                    &mut token_b_account,
+                    large_amount,
+                    0,
+                )
+                .unwrap();
         }
+    }
+
+    #[test]
+    fn test_skim_and_sync() {
+        let user_key = Pubkey::new_unique();
+        let donor_key = Pubkey::new_unique();
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 1000,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 1000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+            host_fee_numerator: 0,
+            host_fee_denominator: 0,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
+        };
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 1_000_000;
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 100 }),
+        };
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        accounts.initialize_swap().unwrap();
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_state.reserve_a(), Some(token_a_amount));
+        assert_eq!(swap_state.reserve_b(), Some(token_b_amount));
+
+        let donation = 50_000;
+        let (
+            donor_a_key,
+            mut donor_a_account,
+            _donor_b_key,
+            _donor_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &donor_key, donation * 2, 0, 0);
+        let (
+            destination_a_key,
+            mut destination_a_account,
+            destination_b_key,
+            mut destination_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &user_key, 0, 0, 0);
+
+        // tokens transferred directly to the swap token account do not move
+        // the reserves
+        do_process_instruction(
+            transfer(
+                &spl_token::id(),
+                &donor_a_key,
+                &accounts.token_a_key,
+                &donor_key,
+                &[],
+                donation,
+            )
+            .unwrap(),
+            vec![
+                &mut donor_a_account,
+                &mut accounts.token_a_account,
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_state.reserve_a(), Some(token_a_amount));
 
-        // token A account owned by wrong program
+        // wrong admin
         {
-            let (_token_a_key, mut token_a_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_a_mint_key,
-                &mut accounts.token_a_mint_account,
-                &user_key,
-                &accounts.authority_key,
-                token_a_amount,
+            let wrong_admin_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(SwapError::InvalidAdmin.into()),
+                do_process_instruction(
+                    skim(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &wrong_admin_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &destination_a_key,
+                        &destination_b_key,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut destination_a_account,
+                        &mut destination_b_account,
+                        &mut Account::default(),
+                    ],
+                )
             );
-            token_a_account.owner = SWAP_PROGRAM_ID;
-            let old_account = accounts.token_a_account;
-            accounts.token_a_account = token_a_account;
             assert_eq!(
-                Err(SwapError::IncorrectTokenProgramId.into()),
-                accounts.initialize_swap()
+                Err(SwapError::InvalidAdmin.into()),
+                do_process_instruction(
+                    sync(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &wrong_admin_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                    ],
+                )
             );
-            accounts.token_a_account = old_account;
         }
 
-        // token B account owned by wrong program
+        // skim the donation
         {
-            let (_token_b_key, mut token_b_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_b_mint_key,
-                &mut accounts.token_b_mint_account,
-                &user_key,
-                &accounts.authority_key,
-                token_b_amount,
-            );
-            token_b_account.owner = SWAP_PROGRAM_ID;
-            let old_account = accounts.token_b_account;
-            accounts.token_b_account = token_b_account;
-            assert_eq!(
-                Err(SwapError::IncorrectTokenProgramId.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_b_account = old_account;
+            do_process_instruction(
+                skim(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &user_key,
+                    &accounts.token_a_key,
+                    &accounts.token_b_key,
+                    &destination_a_key,
+                    &destination_b_key,
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut accounts.token_a_account,
+                    &mut accounts.token_b_account,
+                    &mut destination_a_account,
+                    &mut destination_b_account,
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+            assert_eq!(swap_token_a.amount, token_a_amount);
+            let destination_a =
+                spl_token::state::Account::unpack(&destination_a_account.data).unwrap();
+            assert_eq!(destination_a.amount, donation);
+            let destination_b =
+                spl_token::state::Account::unpack(&destination_b_account.data).unwrap();
+            assert_eq!(destination_b.amount, 0);
         }
 
-        // empty token A account
+        // sync the donation into the reserves
         {
-            let (_token_a_key, token_a_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_a_mint_key,
-                &mut accounts.token_a_mint_account,
-                &user_key,
-                &accounts.authority_key,
-                0,
-            );
-            let old_account = accounts.token_a_account;
-            accounts.token_a_account = token_a_account;
-            assert_eq!(
-                Err(SwapError::EmptySupply.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_a_account = old_account;
+            do_process_instruction(
+                transfer(
+                    &spl_token::id(),
+                    &donor_a_key,
+                    &accounts.token_a_key,
+                    &donor_key,
+                    &[],
+                    donation,
+                )
+                .unwrap(),
+                vec![
+                    &mut donor_a_account,
+                    &mut accounts.token_a_account,
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+            do_process_instruction(
+                sync(
+                    &SWAP_PROGRAM_ID,
+                    &accounts.swap_key,
+                    &user_key,
+                    &accounts.token_a_key,
+                    &accounts.token_b_key,
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut accounts.token_a_account,
+                    &mut accounts.token_b_account,
+                ],
+            )
+            .unwrap();
+            let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_state.reserve_a(), Some(token_a_amount + donation));
+            assert_eq!(swap_state.reserve_b(), Some(token_b_amount));
+
+            // nothing left to skim
+            do_process_instruction(
+                skim(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &user_key,
+                    &accounts.token_a_key,
+                    &accounts.token_b_key,
+                    &destination_a_key,
+                    &destination_b_key,
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut accounts.token_a_account,
+                    &mut accounts.token_b_account,
+                    &mut destination_a_account,
+                    &mut destination_b_account,
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
+            let destination_a =
+                spl_token::state::Account::unpack(&destination_a_account.data).unwrap();
+            assert_eq!(destination_a.amount, donation);
         }
+    }
 
-        // empty token B account
+    #[test]
+    fn test_rescue_tokens() {
+        let user_key = Pubkey::new_unique();
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 1000,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 1000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+            host_fee_numerator: 0,
+            host_fee_denominator: 0,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
+        };
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 1_000_000;
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 100 }),
+        };
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        accounts.initialize_swap().unwrap();
+
+        let stray_amount = 5_000;
+        let (stray_mint_key, mut stray_mint_account) =
+            create_mint(&spl_token::id(), &user_key, None);
+        let (stray_key, mut stray_account) = mint_token(
+            &spl_token::id(),
+            &stray_mint_key,
+            &mut stray_mint_account,
+            &user_key,
+            &accounts.authority_key,
+            stray_amount,
+        );
+        let (destination_key, mut destination_account) = mint_token(
+            &spl_token::id(),
+            &stray_mint_key,
+            &mut stray_mint_account,
+            &user_key,
+            &user_key,
+            0,
+        );
+
+        // wrong admin
         {
-            let (_token_b_key, token_b_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_b_mint_key,
-                &mut accounts.token_b_mint_account,
-                &user_key,
-                &accounts.authority_key,
-                0,
-            );
-            let old_account = accounts.token_b_account;
-            accounts.token_b_account = token_b_account;
+            let wrong_admin_key = Pubkey::new_unique();
             assert_eq!(
-                Err(SwapError::EmptySupply.into()),
-                accounts.initialize_swap()
+                Err(SwapError::InvalidAdmin.into()),
+                do_process_instruction(
+                    rescue_tokens(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &wrong_admin_key,
+                        &stray_key,
+                        &destination_key,
+                        RescueTokens {
+                            amount: stray_amount
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut stray_account,
+                        &mut destination_account,
+                        &mut Account::default(),
+                    ],
+                )
             );
-            accounts.token_b_account = old_account;
         }
 
-        // invalid pool tokens
+        // swap token accounts cannot be rescued
         {
-            let old_mint = accounts.pool_mint_account;
-            let old_pool_account = accounts.pool_token_account;
-
-            let (_pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &accounts.authority_key, None);
-            accounts.pool_mint_account = pool_mint_account;
-
-            let (_empty_pool_token_key, empty_pool_token_account) = mint_token(
-                &spl_token::id(),
-                &accounts.pool_mint_key,
-                &mut accounts.pool_mint_account,
-                &accounts.authority_key,
-                &user_key,
-                0,
+            let (swap_token_a_key, mut swap_token_a_account) =
+                (accounts.token_a_key, accounts.token_a_account.clone());
+            assert_eq!(
+                Err(SwapError::IncorrectSwapAccount.into()),
+                do_process_instruction(
+                    rescue_tokens(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_key,
+                        &swap_token_a_key,
+                        &destination_key,
+                        RescueTokens { amount: 1 },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut swap_token_a_account,
+                        &mut destination_account,
+                        &mut Account::default(),
+                    ],
+                )
             );
-
-            let (_pool_token_key, pool_token_account) = mint_token(
-                &spl_token::id(),
-                &accounts.pool_mint_key,
-                &mut accounts.pool_mint_account,
-                &accounts.authority_key,
-                &user_key,
-                pool_token_amount,
+            let (swap_token_b_key, mut swap_token_b_account) =
+                (accounts.token_b_key, accounts.token_b_account.clone());
+            assert_eq!(
+                Err(SwapError::IncorrectSwapAccount.into()),
+                do_process_instruction(
+                    rescue_tokens(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_key,
+                        &swap_token_b_key,
+                        &destination_key,
+                        RescueTokens { amount: 1 },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut swap_token_b_account,
+                        &mut destination_account,
+                        &mut Account::default(),
+                    ],
+                )
             );
+        }
 
-            // non-empty pool token account
-            accounts.pool_token_account = pool_token_account;
+        // pool fee account cannot be rescued
+        {
+            let (pool_fee_key, mut pool_fee_account) =
+                (accounts.pool_fee_key, accounts.pool_fee_account.clone());
             assert_eq!(
-                Err(SwapError::InvalidSupply.into()),
-                accounts.initialize_swap()
+                Err(SwapError::IncorrectFeeAccount.into()),
+                do_process_instruction(
+                    rescue_tokens(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_key,
+                        &pool_fee_key,
+                        &destination_key,
+                        RescueTokens { amount: 1 },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut pool_fee_account,
+                        &mut destination_account,
+                        &mut Account::default(),
+                    ],
+                )
             );
+        }
 
-            // pool tokens already in circulation
-            accounts.pool_token_account = empty_pool_token_account;
+        // locked minimum liquidity cannot be rescued
+        {
+            let (pool_locked_key, mut pool_locked_account) = (
+                accounts.pool_locked_key,
+                accounts.pool_locked_account.clone(),
+            );
             assert_eq!(
-                Err(SwapError::InvalidSupply.into()),
-                accounts.initialize_swap()
+                Err(SwapError::IncorrectPoolMint.into()),
+                do_process_instruction(
+                    rescue_tokens(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_key,
+                        &pool_locked_key,
+                        &destination_key,
+                        RescueTokens { amount: 1 },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut pool_locked_account,
+                        &mut destination_account,
+                        &mut Account::default(),
+                    ],
+                )
             );
-
-            accounts.pool_mint_account = old_mint;
-            accounts.pool_token_account = old_pool_account;
         }
 
-        // pool fee account has wrong mint
+        // source not owned by the swap authority
         {
-            let (_pool_fee_key, pool_fee_account) = mint_token(
+            let (user_stray_key, mut user_stray_account) = mint_token(
                 &spl_token::id(),
-                &accounts.token_a_mint_key,
-                &mut accounts.token_a_mint_account,
+                &stray_mint_key,
+                &mut stray_mint_account,
                 &user_key,
                 &user_key,
-                0,
+                stray_amount,
             );
-            let old_account = accounts.pool_fee_account;
-            accounts.pool_fee_account = pool_fee_account;
             assert_eq!(
-                Err(SwapError::IncorrectPoolMint.into()),
-                accounts.initialize_swap()
+                Err(SwapError::InvalidOwner.into()),
+                do_process_instruction(
+                    rescue_tokens(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_key,
+                        &user_stray_key,
+                        &destination_key,
+                        RescueTokens {
+                            amount: stray_amount
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut user_stray_account,
+                        &mut destination_account,
+                        &mut Account::default(),
+                    ],
+                )
             );
-            accounts.pool_fee_account = old_account;
         }
 
-        // token A account is delegated
+        // rescue the stray tokens
         {
             do_process_instruction(
-                approve(
+                rescue_tokens(
+                    &SWAP_PROGRAM_ID,
                     &spl_token::id(),
-                    &accounts.token_a_key,
-                    &user_key,
+                    &accounts.swap_key,
                     &accounts.authority_key,
-                    &[],
-                    1,
+                    &user_key,
+                    &stray_key,
+                    &destination_key,
+                    RescueTokens {
+                        amount: stray_amount,
+                    },
                 )
                 .unwrap(),
                 vec![
-                    &mut accounts.token_a_account,
+                    &mut accounts.swap_account,
                     &mut Account::default(),
                     &mut Account::default(),
+                    &mut stray_account,
+                    &mut destination_account,
+                    &mut Account::default(),
                 ],
             )
             .unwrap();
-            assert_eq!(
-                Err(SwapError::InvalidDelegate.into()),
-                accounts.initialize_swap()
-            );
-
-            do_process_instruction(
-                revoke(
-                    &spl_token::id(),
-                    &accounts.token_a_key,
-                    &accounts.authority_key,
-                    &[],
-                )
-                .unwrap(),
-                vec![&mut accounts.token_a_account, &mut Account::default()],
-            )
-            .unwrap();
+            let stray = spl_token::state::Account::unpack(&stray_account.data).unwrap();
+            assert_eq!(stray.amount, 0);
+            let destination = spl_token::state::Account::unpack(&destination_account.data).unwrap();
+            assert_eq!(destination.amount, stray_amount);
         }
+    }
 
-        // token B account is delegated
-        {
-            do_process_instruction(
-                approve(
-                    &spl_token::id(),
-                    &accounts.token_b_key,
-                    &user_key,
-                    &accounts.authority_key,
-                    &[],
-                    1,
-                )
-                .unwrap(),
-                vec![
-                    &mut accounts.token_b_account,
-                    &mut Account::default(),
-                    &mut Account::default(),
-                ],
-            )
-            .unwrap();
-            assert_eq!(
-                Err(SwapError::InvalidDelegate.into()),
-                accounts.initialize_swap()
-            );
+    #[test]
+    fn test_close_pool() {
+        let user_key = Pubkey::new_unique();
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 1000,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 1000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+            host_fee_numerator: 0,
+            host_fee_denominator: 0,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
+        };
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 1_000_000;
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 100 }),
+        };
 
-            do_process_instruction(
-                revoke(
-                    &spl_token::id(),
-                    &accounts.token_b_key,
-                    &accounts.authority_key,
-                    &[],
-                )
-                .unwrap(),
-                vec![&mut accounts.token_b_account, &mut Account::default()],
-            )
-            .unwrap();
-        }
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        accounts.initialize_swap().unwrap();
 
-        // token A account has close authority
+        let (
+            destination_a_key,
+            mut destination_a_account,
+            destination_b_key,
+            mut destination_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &user_key, 0, 0, 0);
+        let recipient_key = Pubkey::new_unique();
+        let mut recipient_account = Account::default();
+
+        // wrong admin
         {
-            do_process_instruction(
-                set_authority(
-                    &spl_token::id(),
-                    &accounts.token_a_key,
-                    Some(&user_key),
-                    AuthorityType::CloseAccount,
-                    &accounts.authority_key,
-                    &[],
-                )
-                .unwrap(),
-                vec![&mut accounts.token_a_account, &mut Account::default()],
-            )
-            .unwrap();
+            let wrong_admin_key = Pubkey::new_unique();
             assert_eq!(
-                Err(SwapError::InvalidCloseAuthority.into()),
-                accounts.initialize_swap()
-            );
-
-            do_process_instruction(
-                set_authority(
-                    &spl_token::id(),
-                    &accounts.token_a_key,
-                    None,
-                    AuthorityType::CloseAccount,
-                    &user_key,
-                    &[],
+                Err(SwapError::InvalidAdmin.into()),
+                do_process_instruction(
+                    close_pool(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &wrong_admin_key,
+                        &accounts.pool_mint_key,
+                        &accounts.pool_locked_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &destination_a_key,
+                        &destination_b_key,
+                        &recipient_key,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.pool_mint_account,
+                        &mut accounts.pool_locked_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut destination_a_account,
+                        &mut destination_b_account,
+                        &mut recipient_account,
+                        &mut Account::default(),
+                    ],
                 )
-                .unwrap(),
-                vec![&mut accounts.token_a_account, &mut Account::default()],
-            )
-            .unwrap();
+            );
         }
 
-        // token B account has close authority
+        // pool with liquidity
         {
-            do_process_instruction(
-                set_authority(
-                    &spl_token::id(),
-                    &accounts.token_b_key,
-                    Some(&user_key),
-                    AuthorityType::CloseAccount,
-                    &accounts.authority_key,
-                    &[],
-                )
-                .unwrap(),
-                vec![&mut accounts.token_b_account, &mut Account::default()],
-            )
-            .unwrap();
             assert_eq!(
-                Err(SwapError::InvalidCloseAuthority.into()),
-                accounts.initialize_swap()
-            );
-
-            do_process_instruction(
-                set_authority(
-                    &spl_token::id(),
-                    &accounts.token_b_key,
-                    None,
-                    AuthorityType::CloseAccount,
-                    &user_key,
-                    &[],
+                Err(SwapError::PoolNotEmpty.into()),
+                do_process_instruction(
+                    close_pool(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_key,
+                        &accounts.pool_mint_key,
+                        &accounts.pool_locked_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &destination_a_key,
+                        &destination_b_key,
+                        &recipient_key,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.pool_mint_account,
+                        &mut accounts.pool_locked_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut destination_a_account,
+                        &mut destination_b_account,
+                        &mut recipient_account,
+                        &mut Account::default(),
+                    ],
                 )
-                .unwrap(),
-                vec![&mut accounts.token_b_account, &mut Account::default()],
-            )
-            .unwrap();
+            );
         }
 
-        // wrong token program id
+        // pool tokens not locked by the swap authority
         {
-            let wrong_program_id = Pubkey::new_unique();
+            let pool_key = accounts.pool_token_key;
+            let mut pool_account = accounts.pool_token_account.clone();
             assert_eq!(
-                Err(SwapError::IncorrectTokenProgramId.into()),
+                Err(SwapError::InvalidOwner.into()),
                 do_process_instruction(
-                    initialize(
+                    close_pool(
                         &SWAP_PROGRAM_ID,
-                        &wrong_program_id,
+                        &spl_token::id(),
                         &accounts.swap_key,
                         &accounts.authority_key,
+                        &user_key,
+                        &accounts.pool_mint_key,
+                        &pool_key,
                         &accounts.token_a_key,
                         &accounts.token_b_key,
-                        &accounts.pool_mint_key,
-                        &accounts.pool_fee_key,
-                        &accounts.pool_token_key,
-                        accounts.fees.clone(),
-                        accounts.swap_curve.clone(),
+                        &destination_a_key,
+                        &destination_b_key,
+                        &recipient_key,
                     )
                     .unwrap(),
                     vec![
                         &mut accounts.swap_account,
                         &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
                         &mut accounts.token_a_account,
                         &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut accounts.pool_fee_account,
-                        &mut accounts.pool_token_account,
+                        &mut destination_a_account,
+                        &mut destination_b_account,
+                        &mut recipient_account,
                         &mut Account::default(),
                     ],
                 )
             );
         }
 
-        // create swap with same token A and B
-        {
-            let (_token_a_repeat_key, token_a_repeat_account) = mint_token(
-                &spl_token::id(),
-                &accounts.token_a_mint_key,
-                &mut accounts.token_a_mint_account,
+        // withdraw everything but the locked minimum liquidity
+        let pool_key = accounts.pool_token_key;
+        let mut pool_account = accounts.pool_token_account.clone();
+        let pool_amount = spl_token::state::Account::unpack(&pool_account.data)
+            .unwrap()
+            .amount;
+        let (
+            withdraw_a_key,
+            mut withdraw_a_account,
+            withdraw_b_key,
+            mut withdraw_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &user_key, 0, 0, 0);
+        accounts
+            .withdraw_all_token_types(
                 &user_key,
-                &accounts.authority_key,
-                10,
-            );
-            let old_account = accounts.token_b_account;
-            accounts.token_b_account = token_a_repeat_account;
-            assert_eq!(
-                Err(SwapError::RepeatedMint.into()),
-                accounts.initialize_swap()
-            );
-            accounts.token_b_account = old_account;
-        }
-
-        // create valid swap
-        accounts.initialize_swap().unwrap();
-
+                &pool_key,
+                &mut pool_account,
+                &withdraw_a_key,
+                &mut withdraw_a_account,
+                &withdraw_b_key,
+                &mut withdraw_b_account,
+                pool_amount,
+                0,
+                0,
+            )
+            .unwrap();
+        let pool_mint = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+        assert_eq!(pool_mint.supply, to_u64(MINIMUM_LIQUIDITY).unwrap());
 
-        // wrong owner key in constraint
+        // admin fees not withdrawn
         {
-            let new_key = Pubkey::new_unique();
-            let trade_fee_numerator = 25;
-            let trade_fee_denominator = 10000;
-            let owner_trade_fee_numerator = 5;
-            let owner_trade_fee_denominator = 10000;
-            let host_fee_numerator = 20;
-            let host_fee_denominator = 100;
-            let fees = Fees {
-                trade_fee_numerator,
-                trade_fee_denominator,
-                owner_trade_fee_numerator,
-                owner_trade_fee_denominator,
-                owner_withdraw_fee_numerator,
-                owner_withdraw_fee_denominator,
-                host_fee_numerator,
-                host_fee_denominator,
-            };
-            let amp = 200;
-            let curve = StableCurve { amp };
-            let swap_curve = SwapCurve {
-                curve_type: CurveType::Stable,
-                calculator: Arc::new(curve),
-            };
-            let owner_key = &new_key.to_string();
-            let valid_curve_types = &[CurveType::Stable];
-            let constraints = Some(SwapConstraints {
-                owner_key,
-                valid_curve_types,
-                fees: &fees,
-            });
-            let mut accounts = SwapAccountInfo::new(
-                &user_key,
-                fees.clone(),
-                swap_curve,
-                token_a_amount,
-                token_b_amount,
-            );
+            let mut swap_account = accounts.swap_account.clone();
+            let mut swap_v2 = SwapVersion::unpack_latest(&swap_account.data).unwrap();
+            swap_v2.admin_fee_a = 1;
+            SwapVersion::pack(SwapVersion::SwapV2(swap_v2), &mut swap_account.data).unwrap();
             assert_eq!(
-                Err(SwapError::InvalidOwner.into()),
-                do_process_instruction_with_fee_constraints(
-                    initialize(
+                Err(SwapError::AdminFeesNotWithdrawn.into()),
+                do_process_instruction(
+                    close_pool(
                         &SWAP_PROGRAM_ID,
                         &spl_token::id(),
                         &accounts.swap_key,
                         &accounts.authority_key,
+                        &user_key,
+                        &accounts.pool_mint_key,
+                        &accounts.pool_locked_key,
                         &accounts.token_a_key,
                         &accounts.token_b_key,
-                        &accounts.pool_mint_key,
-                        &accounts.pool_fee_key,
-                        &accounts.pool_token_key,
-                        accounts.fees.clone(),
-                        accounts.swap_curve.clone(),
+                        &destination_a_key,
+                        &destination_b_key,
+                        &recipient_key,
                     )
                     .unwrap(),
                     vec![
-                        &mut accounts.swap_account,
+                        &mut swap_account,
+                        &mut Account::default(),
                         &mut Account::default(),
+                        &mut accounts.pool_mint_account,
+                        &mut accounts.pool_locked_account,
                         &mut accounts.token_a_account,
                         &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut accounts.pool_fee_account,
-                        &mut accounts.pool_token_account,
+                        &mut destination_a_account,
+                        &mut destination_b_account,
+                        &mut recipient_account,
                         &mut Account::default(),
                     ],
-                    &constraints,
                 )
             );
         }
 
-        // wrong fee in constraint
+        // destination accounts of the wrong mints
         {
-            let trade_fee_numerator = 25;
-            let trade_fee_denominator = 10000;
-            let owner_trade_fee_numerator = 5;
-            let owner_trade_fee_denominator = 10000;
-            let host_fee_numerator = 20;
-            let host_fee_denominator = 100;
-            let fees = Fees {
-                trade_fee_numerator,
-                trade_fee_denominator,
-                owner_trade_fee_numerator,
-                owner_trade_fee_denominator,
-                owner_withdraw_fee_numerator,
-                owner_withdraw_fee_denominator,
-                host_fee_numerator,
-                host_fee_denominator,
-            };
-            let amp = 200;
-            let curve = StableCurve { amp };
-            let swap_curve = SwapCurve {
-                curve_type: CurveType::Stable,
-                calculator: Arc::new(curve),
-            };
-            let owner_key = &user_key.to_string();
-            let valid_curve_types = &[CurveType::Stable];
-            let constraints = Some(SwapConstraints {
-                owner_key,
-                valid_curve_types,
-                fees: &fees,
-            });
-            let mut bad_fees = fees.clone();
-            bad_fees.trade_fee_numerator = trade_fee_numerator - 1;
-            let mut accounts = SwapAccountInfo::new(
-                &user_key,
-                bad_fees,
-                swap_curve,
-                token_a_amount,
-                token_b_amount,
-            );
             assert_eq!(
-                Err(SwapError::InvalidFee.into()),
-                do_process_instruction_with_fee_constraints(
-                    initialize(
+                Err(SwapError::IncorrectDestinationMint.into()),
+                do_process_instruction(
+                    close_pool(
                         &SWAP_PROGRAM_ID,
                         &spl_token::id(),
                         &accounts.swap_key,
                         &accounts.authority_key,
+                        &user_key,
+                        &accounts.pool_mint_key,
+                        &accounts.pool_locked_key,
                         &accounts.token_a_key,
                         &accounts.token_b_key,
-                        &accounts.pool_mint_key,
-                        &accounts.pool_fee_key,
-                        &accounts.pool_token_key,
-                        accounts.fees.clone(),
-                        accounts.swap_curve.clone(),
+                        &destination_b_key,
+                        &destination_a_key,
+                        &recipient_key,
                     )
                     .unwrap(),
                     vec![
                         &mut accounts.swap_account,
                         &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.pool_mint_account,
+                        &mut accounts.pool_locked_account,
                         &mut accounts.token_a_account,
                         &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut accounts.pool_fee_account,
-                        &mut accounts.pool_token_account,
+                        &mut destination_b_account.clone(),
+                        &mut destination_a_account.clone(),
+                        &mut recipient_account,
                         &mut Account::default(),
                     ],
-                    &constraints,
                 )
             );
         }
 
-        // create valid swap with constraints
+        // close the pool
         {
-            let trade_fee_numerator = 25;
-            let trade_fee_denominator = 10000;
-            let owner_trade_fee_numerator = 5;
-            let owner_trade_fee_denominator = 10000;
-            let host_fee_numerator = 20;
-            let host_fee_denominator = 100;
-            let fees = Fees {
-                trade_fee_numerator,
-                trade_fee_denominator,
-                owner_trade_fee_numerator,
-                owner_trade_fee_denominator,
-                owner_withdraw_fee_numerator,
-                owner_withdraw_fee_denominator,
-                host_fee_numerator,
-                host_fee_denominator,
-            };
-            let amp = 200;
-            let curve = StableCurve { amp };
-            let swap_curve = SwapCurve {
-                curve_type: CurveType::Stable,
-                calculator: Arc::new(curve),
-            };
-            let owner_key = &user_key.to_string();
-            let valid_curve_types = &[CurveType::Stable];
-            let constraints = Some(SwapConstraints {
-                owner_key,
-                valid_curve_types,
-                fees: &fees,
-            });
-            let mut accounts = SwapAccountInfo::new(
-                &user_key,
-                fees.clone(),
-                swap_curve,
-                token_a_amount,
-                token_b_amount,
-            );
-            do_process_instruction_with_fee_constraints(
-                initialize(
+            let remaining_a = spl_token::state::Account::unpack(&accounts.token_a_account.data)
+                .unwrap()
+                .amount;
+            let remaining_b = spl_token::state::Account::unpack(&accounts.token_b_account.data)
+                .unwrap()
+                .amount;
+            let lamports = accounts.swap_account.lamports
+                + accounts.pool_locked_account.lamports
+                + accounts.token_a_account.lamports
+                + accounts.token_b_account.lamports;
+            do_process_instruction(
+                close_pool(
                     &SWAP_PROGRAM_ID,
                     &spl_token::id(),
                     &accounts.swap_key,
                     &accounts.authority_key,
+                    &user_key,
+                    &accounts.pool_mint_key,
+                    &accounts.pool_locked_key,
                     &accounts.token_a_key,
                     &accounts.token_b_key,
-                    &accounts.pool_mint_key,
-                    &accounts.pool_fee_key,
-                    &accounts.pool_token_key,
-                    accounts.fees,
-                    accounts.swap_curve.clone(),
+                    &destination_a_key,
+                    &destination_b_key,
+                    &recipient_key,
                 )
                 .unwrap(),
                 vec![
                     &mut accounts.swap_account,
                     &mut Account::default(),
+                    &mut Account::default(),
+                    &mut accounts.pool_mint_account,
+                    &mut accounts.pool_locked_account,
                     &mut accounts.token_a_account,
                     &mut accounts.token_b_account,
-                    &mut accounts.pool_mint_account,
-                    &mut accounts.pool_fee_account,
-                    &mut accounts.pool_token_account,
+                    &mut destination_a_account,
+                    &mut destination_b_account,
+                    &mut recipient_account,
                     &mut Account::default(),
                 ],
-                &constraints,
             )
             .unwrap();
-        }
-
-        // create again
-        {
+            let destination_a =
+                spl_token::state::Account::unpack(&destination_a_account.data).unwrap();
+            assert_eq!(destination_a.amount, remaining_a);
+            let destination_b =
+                spl_token::state::Account::unpack(&destination_b_account.data).unwrap();
+            assert_eq!(destination_b.amount, remaining_b);
+            assert_eq!(recipient_account.lamports, lamports);
+            assert_eq!(accounts.swap_account.lamports, 0);
+            assert_eq!(accounts.pool_locked_account.lamports, 0);
+            assert_eq!(accounts.token_a_account.lamports, 0);
+            assert_eq!(accounts.token_b_account.lamports, 0);
+            let pool_mint =
+                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+            assert_eq!(pool_mint.supply, 0);
             assert_eq!(
-                Err(SwapError::AlreadyInUse.into()),
-                accounts.initialize_swap()
+                SwapVersion::unpack(&accounts.swap_account.data).err(),
+                Some(ProgramError::UninitializedAccount)
             );
         }
-        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
-        assert!(swap_state.is_initialized());
-        assert_eq!(swap_state.bump_seed(), accounts.bump_seed);
-        assert_eq!(
-            swap_state.swap_curve().curve_type,
-            accounts.swap_curve.curve_type
-        );
-        assert_eq!(*swap_state.token_a_account(), accounts.token_a_key);
-        assert_eq!(*swap_state.token_b_account(), accounts.token_b_key);
-        assert_eq!(*swap_state.pool_mint(), accounts.pool_mint_key);
-        assert_eq!(*swap_state.token_a_mint(), accounts.token_a_mint_key);
-        assert_eq!(*swap_state.token_b_mint(), accounts.token_b_mint_key);
-        assert_eq!(*swap_state.pool_fee_account(), accounts.pool_fee_key);
-        let token_a = spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-        assert_eq!(token_a.amount, token_a_amount);
-        let token_b = spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-        assert_eq!(token_b.amount, token_b_amount);
-        let pool_account =
-            spl_token::state::Account::unpack(&accounts.pool_token_account.data).unwrap();
-        let pool_mint = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
-        assert_eq!(pool_mint.supply, pool_account.amount);
     }
 
-    #[test]
-    fn test_deposit() {
-        let user_key = Pubkey::new_unique();
-        let depositor_key = Pubkey::new_unique();
-        let trade_fee_numerator = 1;
-        let trade_fee_denominator = 2;
-        let owner_trade_fee_numerator = 1;
-        let owner_trade_fee_denominator = 10;
-        let owner_withdraw_fee_numerator = 1;
-        let owner_withdraw_fee_denominator = 5;
-        let host_fee_numerator = 20;
-        let host_fee_denominator = 100;
-
+    #[test]
+    fn test_migrate_liquidity() {
+        let user_key = Pubkey::new_unique();
         let fees = Fees {
-            trade_fee_numerator,
-            trade_fee_denominator,
-            owner_trade_fee_numerator,
-            owner_trade_fee_denominator,
-            owner_withdraw_fee_numerator,
-            owner_withdraw_fee_denominator,
-            host_fee_numerator,
-            host_fee_denominator,
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 1000,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 1000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+            host_fee_numerator: 0,
+            host_fee_denominator: 0,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
         };
-
-        let token_a_amount = 1000;
-        let token_b_amount = 9000;
-        let amp = 200;
-        let curve_type = CurveType::Stable;
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 1_000_000;
         let swap_curve = SwapCurve {
-            curve_type,
-            calculator: Arc::new(StableCurve { amp }),
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 100 }),
         };
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            swap_curve,
+            token_a_amount,
+            token_b_amount,
+        );
+        accounts.initialize_swap().unwrap();
 
-        let mut accounts =
-            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
-
-        // depositing 10% of the current pool amount in token A and B means
-        // that our pool tokens will be worth 1 / 10 of the current pool amount
-        let pool_amount = INITIAL_SWAP_POOL_AMOUNT / 10;
-        let deposit_a = token_a_amount / 10;
-        let deposit_b = token_b_amount / 10;
+        // new swap of the same mints, with another amp and ratio
+        let new_token_a_amount = 2_000_000;
+        let new_token_b_amount = 1_000_000;
+        let new_swap_curve = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 200 }),
+        };
+        let mut new_accounts = SwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            new_swap_curve,
+            new_token_a_amount,
+            new_token_b_amount,
+        );
+        let (new_token_a_key, new_token_a_account) = mint_token(
+            &spl_token::id(),
+            &accounts.token_a_mint_key,
+            &mut accounts.token_a_mint_account,
+            &user_key,
+            &new_accounts.authority_key,
+            new_token_a_amount,
+        );
+        new_accounts.token_a_key = new_token_a_key;
+        new_accounts.token_a_account = new_token_a_account;
+        let (new_token_b_key, new_token_b_account) = mint_token(
+            &spl_token::id(),
+            &accounts.token_b_mint_key,
+            &mut accounts.token_b_mint_account,
+            &user_key,
+            &new_accounts.authority_key,
+            new_token_b_amount,
+        );
+        new_accounts.token_b_key = new_token_b_key;
+        new_accounts.token_b_account = new_token_b_account;
+        new_accounts.initialize_swap().unwrap();
+
+        let pool_key = accounts.pool_token_key;
+        let mut pool_account = accounts.pool_token_account.clone();
+        let pool_amount = spl_token::state::Account::unpack(&pool_account.data)
+            .unwrap()
+            .amount;
+        let migrate_amount = pool_amount / 2;
+        let (token_a_key, mut token_a_account, token_b_key, mut token_b_account, _, _) =
+            accounts.setup_token_accounts(&user_key, &user_key, 0, 0, 0);
+        let (new_pool_key, mut new_pool_account) = mint_token(
+            &spl_token::id(),
+            &new_accounts.pool_mint_key,
+            &mut new_accounts.pool_mint_account,
+            &new_accounts.authority_key,
+            &user_key,
+            0,
+        );
 
-        // swap not initialized
+        // swaps of other mints
         {
+            let mut other_accounts = SwapAccountInfo::new(
+                &user_key,
+                fees,
+                SwapCurve {
+                    curve_type: CurveType::Stable,
+                    calculator: Arc::new(StableCurve { amp: 100 }),
+                },
+                token_a_amount,
+                token_b_amount,
+            );
+            other_accounts.initialize_swap().unwrap();
             let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+                _token_a_key,
+                _token_a_account,
+                _token_b_key,
+                _token_b_account,
+                other_pool_key,
+                mut other_pool_account,
+            ) = other_accounts.setup_token_accounts(&user_key, &user_key, 0, 0, 0);
             assert_eq!(
-                Err(ProgramError::UninitializedAccount),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
+                Err(SwapError::MismatchedSwapMints.into()),
+                accounts.migrate_liquidity(
+                    &mut other_accounts,
+                    &user_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &other_pool_key,
+                    &mut other_pool_account,
                     &token_a_key,
                     &mut token_a_account,
                     &token_b_key,
                     &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+                    migrate_amount,
+                    0,
                 )
             );
         }
 
-        accounts.initialize_swap().unwrap();
+        let pool_mint = spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+        let withdrawn = accounts
+            .swap_curve
+            .calculator
+            .pool_tokens_to_trading_tokens(
+                migrate_amount.into(),
+                pool_mint.supply.into(),
+                token_a_amount.into(),
+                token_b_amount.into(),
+                RoundDirection::Floor,
+            )
+            .unwrap();
+        let new_pool_mint =
+            spl_token::state::Mint::unpack(&new_accounts.pool_mint_account.data).unwrap();
+        let new_pool_amount = std::cmp::min(
+            withdrawn.token_a_amount * u128::from(new_pool_mint.supply)
+                / u128::from(new_token_a_amount),
+            withdrawn.token_b_amount * u128::from(new_pool_mint.supply)
+                / u128::from(new_token_b_amount),
+        );
+        let deposited = new_accounts
+            .swap_curve
+            .calculator
+            .pool_tokens_to_trading_tokens(
+                new_pool_amount,
+                new_pool_mint.supply.into(),
+                new_token_a_amount.into(),
+                new_token_b_amount.into(),
+                RoundDirection::Ceiling,
+            )
+            .unwrap();
 
-        // wrong owner for swap account
+        // minimum pool token amount too high
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let old_swap_account = accounts.swap_account;
-            let mut wrong_swap_account = old_swap_account.clone();
-            wrong_swap_account.owner = spl_token::id();
-            accounts.swap_account = wrong_swap_account;
             assert_eq!(
-                Err(ProgramError::IncorrectProgramId),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.migrate_liquidity(
+                    &mut new_accounts,
+                    &user_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &new_pool_key,
+                    &mut new_pool_account,
                     &token_a_key,
                     &mut token_a_account,
                     &token_b_key,
                     &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+                    migrate_amount,
+                    to_u64(new_pool_amount).unwrap() + 1,
                 )
             );
-            accounts.swap_account = old_swap_account;
         }
 
-        // wrong bump seed for authority_key
+        // migrate half of the liquidity
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let old_authority = accounts.authority_key;
-            let (bad_authority_key, _bump_seed) = Pubkey::find_program_address(
-                &[&accounts.swap_key.to_bytes()[..]],
-                &spl_token::id(),
-            );
-            accounts.authority_key = bad_authority_key;
-            assert_eq!(
-                Err(SwapError::InvalidProgramAddress.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
+            accounts
+                .migrate_liquidity(
+                    &mut new_accounts,
+                    &user_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &new_pool_key,
+                    &mut new_pool_account,
                     &token_a_key,
                     &mut token_a_account,
                     &token_b_key,
                     &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+                    migrate_amount,
+                    to_u64(new_pool_amount).unwrap(),
                 )
+                .unwrap();
+            let pool = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+            assert_eq!(pool.amount, pool_amount - migrate_amount);
+            let new_pool = spl_token::state::Account::unpack(&new_pool_account.data).unwrap();
+            assert_eq!(u128::from(new_pool.amount), new_pool_amount);
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(
+                u128::from(token_a.amount),
+                withdrawn.token_a_amount - deposited.token_a_amount
             );
-            accounts.authority_key = old_authority;
+            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+            assert_eq!(
+                u128::from(token_b.amount),
+                withdrawn.token_b_amount - deposited.token_b_amount
+            );
+
+            let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(
+                u128::from(swap_state.reserve_a().unwrap()),
+                u128::from(token_a_amount) - withdrawn.token_a_amount
+            );
+            assert_eq!(
+                u128::from(swap_state.reserve_b().unwrap()),
+                u128::from(token_b_amount) - withdrawn.token_b_amount
+            );
+            let new_swap_state = SwapVersion::unpack(&new_accounts.swap_account.data).unwrap();
+            assert_eq!(
+                u128::from(new_swap_state.reserve_a().unwrap()),
+                u128::from(new_token_a_amount) + deposited.token_a_amount
+            );
+            assert_eq!(
+                u128::from(new_swap_state.reserve_b().unwrap()),
+                u128::from(new_token_b_amount) + deposited.token_b_amount
+            );
+            let new_swap_token_a =
+                spl_token::state::Account::unpack(&new_accounts.token_a_account.data).unwrap();
+            assert_eq!(Some(new_swap_token_a.amount), new_swap_state.reserve_a());
         }
+    }
 
-        // not enough token A
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
+    #[test]
+    fn test_metapool_underlying() {
+        let user_key = Pubkey::new_unique();
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 1000,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 1000,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+            host_fee_numerator: 0,
+            host_fee_denominator: 0,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
+        };
+        let amp = 100;
+        let base_token_amount = 1_000_000;
+        let mut base = SwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            SwapCurve {
+                curve_type: CurveType::Stable,
+                calculator: Arc::new(StableCurve { amp }),
+            },
+            base_token_amount,
+            base_token_amount,
+        );
+        base.initialize_swap().unwrap();
+
+        // metapool of token A and the base swap pool token, created with a
+        // stale virtual price
+        let token_a_amount = 500_000;
+        let token_b_amount = 500_000;
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            fees.clone(),
+            SwapCurve {
+                curve_type: CurveType::Meta,
+                calculator: Arc::new(MetaCurve {
+                    amp,
+                    base_virtual_price: 2 * VIRTUAL_PRICE_PRECISION,
+                }),
+            },
+            token_a_amount,
+            0,
+        );
+        let (token_b_key, mut token_b_account) = mint_token(
+            &spl_token::id(),
+            &base.pool_mint_key,
+            &mut base.pool_mint_account,
+            &base.authority_key,
+            &accounts.authority_key,
+            0,
+        );
+        do_process_instruction(
+            transfer(
+                &spl_token::id(),
+                &base.pool_token_key,
+                &token_b_key,
                 &user_key,
-                &depositor_key,
-                deposit_a / 2,
-                deposit_b,
-                0,
-            );
+                &[],
+                token_b_amount,
+            )
+            .unwrap(),
+            vec![
+                &mut base.pool_token_account,
+                &mut token_b_account,
+                &mut Account::default(),
+            ],
+        )
+        .unwrap();
+        accounts.token_b_key = token_b_key;
+        accounts.token_b_account = token_b_account;
+        accounts.token_b_mint_key = base.pool_mint_key;
+        accounts.token_b_mint_account = base.pool_mint_account.clone();
+
+        // metapool without its base swap
+        assert_eq!(
+            Err(ProgramError::NotEnoughAccountKeys),
+            accounts.initialize_swap()
+        );
+
+        // the virtual price is refreshed on creation
+        accounts.initialize_metapool(&mut base).unwrap();
+        let base_pool_mint = spl_token::state::Mint::unpack(&base.pool_mint_account.data).unwrap();
+        let base_virtual_price = base
+            .swap_curve
+            .calculator
+            .new_pool_supply(base_token_amount.into(), base_token_amount.into())
+            .unwrap()
+            * u128::from(VIRTUAL_PRICE_PRECISION)
+            / u128::from(base_pool_mint.supply);
+        let refreshed_curve = SwapCurve {
+            curve_type: CurveType::Meta,
+            calculator: Arc::new(MetaCurve {
+                amp,
+                base_virtual_price: base_virtual_price as u64,
+            }),
+        };
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(*swap_state.swap_curve(), refreshed_curve);
+
+        let (
+            token_a_key,
+            mut token_a_account,
+            base_pool_key,
+            mut base_pool_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &user_key, 100_000, 0, 0);
+        let (
+            base_token_a_key,
+            mut base_token_a_account,
+            base_token_b_key,
+            mut base_token_b_account,
+            _base_pool_key,
+            _base_pool_account,
+        ) = base.setup_token_accounts(&user_key, &user_key, 100_000, 100_000, 0);
+
+        // metapools only swap with the base swap accounts, and do not
+        // withdraw a single token type
+        {
+            let swap_token_a_key = accounts.token_a_key;
+            let swap_token_b_key = accounts.token_b_key;
             assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
+                Err(ProgramError::NotEnoughAccountKeys),
+                accounts.swap(
+                    &user_key,
                     &token_a_key,
                     &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &base_pool_key,
+                    &mut base_pool_account,
+                    10_000,
+                    0,
                 )
             );
-        }
-
-        // not enough token B
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &depositor_key,
-                deposit_a,
-                deposit_b / 2,
-                0,
+            assert_eq!(
+                Err(SwapError::UnsupportedCurveOperation.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &user_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &base_pool_key,
+                    &mut base_pool_account,
+                    1,
+                    u64::MAX,
+                )
             );
             assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
+                Err(SwapError::UnsupportedCurveOperation.into()),
+                accounts.withdraw_single_token_type_exact_amount_in(
+                    &user_key,
                     &pool_key,
                     &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+                    &base_pool_key,
+                    &mut base_pool_account,
+                    1,
+                    0,
                 )
             );
         }
 
-        // wrong swap token accounts
+        // base swap of another pool token
         {
+            let mut other_base = SwapAccountInfo::new(
+                &user_key,
+                fees,
+                SwapCurve {
+                    curve_type: CurveType::Stable,
+                    calculator: Arc::new(StableCurve { amp }),
+                },
+                base_token_amount,
+                base_token_amount,
+            );
+            other_base.initialize_swap().unwrap();
             let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+                other_token_a_key,
+                mut other_token_a_account,
+                _other_token_b_key,
+                _other_token_b_account,
+                _other_pool_key,
+                _other_pool_account,
+            ) = other_base.setup_token_accounts(&user_key, &user_key, 0, 0, 0);
             assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_b_key,
-                    &mut token_b_account,
+                Err(SwapError::IncorrectBaseSwap.into()),
+                accounts.swap_underlying(
+                    &mut other_base,
+                    &user_key,
                     &token_a_key,
                     &mut token_a_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+                    &base_pool_key,
+                    &mut base_pool_account,
+                    &other_token_a_key,
+                    &mut other_token_a_account,
+                    10_000,
+                    0,
                 )
             );
         }
 
-        // wrong pool token account
+        // swap token A for base token A, too much slippage
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                _pool_key,
-                mut _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let (
-                wrong_token_key,
-                mut wrong_token_account,
-                _token_b_key,
-                mut _token_b_account,
-                _pool_key,
-                mut _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
             assert_eq!(
-                Err(TokenError::MintMismatch.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.swap_underlying(
+                    &mut base,
+                    &user_key,
                     &token_a_key,
                     &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &wrong_token_key,
-                    &mut wrong_token_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+                    &base_pool_key,
+                    &mut base_pool_account,
+                    &base_token_a_key,
+                    &mut base_token_a_account,
+                    10_000,
+                    10_000,
                 )
             );
         }
 
-        // no approval
+        // swap token A for base token A
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let user_transfer_authority_key = Pubkey::new_unique();
-            assert_eq!(
-                Err(TokenError::OwnerMismatch.into()),
-                do_process_instruction(
-                    deposit_all_token_types(
-                        &SWAP_PROGRAM_ID,
-                        &spl_token::id(),
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &user_transfer_authority_key,
-                        &token_a_key,
-                        &token_b_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &accounts.pool_mint_key,
-                        &pool_key,
-                        DepositAllTokenTypes {
-                            pool_token_amount: pool_amount.try_into().unwrap(),
-                            maximum_token_a_amount: deposit_a,
-                            maximum_token_b_amount: deposit_b,
-                        },
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut Account::default(),
-                        &mut token_a_account,
-                        &mut token_b_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut Account::default(),
-                    ],
+            let amount_in = 10_000;
+            let result = refreshed_curve
+                .swap(
+                    amount_in.into(),
+                    token_a_amount.into(),
+                    token_b_amount.into(),
+                    TradeDirection::AtoB,
+                    &accounts.fees,
+                )
+                .unwrap();
+            accounts
+                .swap_underlying(
+                    &mut base,
+                    &user_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &base_pool_key,
+                    &mut base_pool_account,
+                    &base_token_a_key,
+                    &mut base_token_a_account,
+                    amount_in,
+                    9_000,
                 )
+                .unwrap();
+
+            let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(*swap_state.swap_curve(), refreshed_curve);
+            assert_eq!(
+                u128::from(swap_state.reserve_b().unwrap()),
+                u128::from(token_b_amount) - result.destination_amount_swapped
+            );
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, 100_000 - amount_in);
+            // all the swapped base swap pool tokens are withdrawn
+            let base_pool = spl_token::state::Account::unpack(&base_pool_account.data).unwrap();
+            assert_eq!(base_pool.amount, 0);
+            let base_token_a =
+                spl_token::state::Account::unpack(&base_token_a_account.data).unwrap();
+            assert!(base_token_a.amount > 100_000 + 9_000);
+            assert!(base_token_a.amount < 100_000 + amount_in);
+            let base_swap_state = SwapVersion::unpack(&base.swap_account.data).unwrap();
+            assert_eq!(
+                base_swap_state.reserve_a(),
+                Some(base_token_amount - (base_token_a.amount - 100_000))
             );
         }
 
-        // wrong token program id
+        // swaps on the metapool itself refresh the virtual price, changed by
+        // the withdrawal fee left in the base swap
+        let fresh_curve = |base: &SwapAccountInfo| {
+            let base_swap_state = SwapVersion::unpack(&base.swap_account.data).unwrap();
+            let base_pool_mint =
+                spl_token::state::Mint::unpack(&base.pool_mint_account.data).unwrap();
+            let base_virtual_price = base
+                .swap_curve
+                .calculator
+                .new_pool_supply(
+                    base_swap_state.reserve_a().unwrap().into(),
+                    base_swap_state.reserve_b().unwrap().into(),
+                )
+                .unwrap()
+                * u128::from(VIRTUAL_PRICE_PRECISION)
+                / u128::from(base_pool_mint.supply);
+            SwapCurve {
+                curve_type: CurveType::Meta,
+                calculator: Arc::new(MetaCurve {
+                    amp,
+                    base_virtual_price: base_virtual_price as u64,
+                }),
+            }
+        };
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+
+        // swap token A for base swap pool tokens
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let wrong_key = Pubkey::new_unique();
-            assert_eq!(
-                Err(SwapError::IncorrectTokenProgramId.into()),
-                do_process_instruction(
-                    deposit_all_token_types(
-                        &SWAP_PROGRAM_ID,
-                        &wrong_key,
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.authority_key,
-                        &token_a_key,
-                        &token_b_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &accounts.pool_mint_key,
-                        &pool_key,
-                        DepositAllTokenTypes {
-                            pool_token_amount: pool_amount.try_into().unwrap(),
-                            maximum_token_a_amount: deposit_a,
-                            maximum_token_b_amount: deposit_b,
-                        },
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut Account::default(),
-                        &mut token_a_account,
-                        &mut token_b_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut Account::default(),
-                    ],
+            let curve = fresh_curve(&base);
+            assert_ne!(curve, refreshed_curve);
+            let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+            let reserve_a = swap_state.reserve_a().unwrap();
+            let reserve_b = swap_state.reserve_b().unwrap();
+            let amount_in = 10_000;
+            let result = curve
+                .swap(
+                    amount_in.into(),
+                    reserve_a.into(),
+                    reserve_b.into(),
+                    TradeDirection::AtoB,
+                    &accounts.fees,
+                )
+                .unwrap();
+            accounts
+                .swap_metapool(
+                    &base,
+                    &user_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
+                    &base_pool_key,
+                    &mut base_pool_account,
+                    amount_in,
+                    0,
                 )
+                .unwrap();
+
+            let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(*swap_state.swap_curve(), curve);
+            assert_eq!(
+                u128::from(swap_state.reserve_b().unwrap()),
+                u128::from(reserve_b) - result.destination_amount_swapped
+            );
+            let base_pool = spl_token::state::Account::unpack(&base_pool_account.data).unwrap();
+            assert_eq!(
+                u128::from(base_pool.amount),
+                result.destination_amount_swapped
             );
         }
 
-        // wrong swap token accounts
+        // swap base swap pool tokens for token A
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-
-            let old_a_key = accounts.token_a_key;
-            let old_a_account = accounts.token_a_account;
-
-            accounts.token_a_key = token_a_key;
-            accounts.token_a_account = token_a_account.clone();
-
-            // wrong swap token a account
-            assert_eq!(
-                Err(SwapError::IncorrectSwapAccount.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
+            let curve = fresh_curve(&base);
+            let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+            let reserve_a = swap_state.reserve_a().unwrap();
+            let reserve_b = swap_state.reserve_b().unwrap();
+            let amount_in = spl_token::state::Account::unpack(&base_pool_account.data)
+                .unwrap()
+                .amount;
+            let result = curve
+                .swap(
+                    amount_in.into(),
+                    reserve_b.into(),
+                    reserve_a.into(),
+                    TradeDirection::BtoA,
+                    &accounts.fees,
+                )
+                .unwrap();
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            accounts
+                .swap_metapool(
+                    &base,
+                    &user_key,
+                    &base_pool_key,
+                    &mut base_pool_account,
+                    &swap_token_b_key,
+                    &swap_token_a_key,
                     &token_a_key,
                     &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+                    amount_in,
+                    0,
                 )
-            );
-
-            accounts.token_a_key = old_a_key;
-            accounts.token_a_account = old_a_account;
-
-            let old_b_key = accounts.token_b_key;
-            let old_b_account = accounts.token_b_account;
+                .unwrap();
 
-            accounts.token_b_key = token_b_key;
-            accounts.token_b_account = token_b_account.clone();
+            let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(*swap_state.swap_curve(), curve);
+            assert_eq!(
+                u128::from(swap_state.reserve_a().unwrap()),
+                u128::from(reserve_a) - result.destination_amount_swapped
+            );
+            let new_token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(
+                u128::from(new_token_a.amount - token_a.amount),
+                result.destination_amount_swapped
+            );
+            let base_pool = spl_token::state::Account::unpack(&base_pool_account.data).unwrap();
+            assert_eq!(base_pool.amount, 0);
+        }
 
-            // wrong swap token b account
+        // deposit base tokens, too much slippage on the base swap
+        let base_pool_token_amount = 20_000;
+        let pool_token_amount = 10_000;
+        {
             assert_eq!(
-                Err(SwapError::IncorrectSwapAccount.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.deposit_underlying(
+                    &mut base,
+                    &user_key,
                     &token_a_key,
                     &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
+                    &base_pool_key,
+                    &mut base_pool_account,
                     &pool_key,
                     &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+                    &base_token_a_key,
+                    &mut base_token_a_account,
+                    &base_token_b_key,
+                    &mut base_token_b_account,
+                    DepositUnderlying {
+                        base_pool_token_amount,
+                        pool_token_amount,
+                        maximum_token_a_amount: 100_000,
+                        maximum_base_token_a_amount: 1,
+                        maximum_base_token_b_amount: 100_000,
+                    },
                 )
             );
-
-            accounts.token_b_key = old_b_key;
-            accounts.token_b_account = old_b_account;
         }
 
-        // wrong mint
+        // deposit base tokens and token A
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let (pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &accounts.authority_key, None);
-            let old_pool_key = accounts.pool_mint_key;
-            let old_pool_account = accounts.pool_mint_account;
-            accounts.pool_mint_key = pool_mint_key;
-            accounts.pool_mint_account = pool_mint_account;
-
-            assert_eq!(
-                Err(SwapError::IncorrectPoolMint.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
+            let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+            let pool_mint =
+                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+            let deposited = swap_state
+                .swap_curve()
+                .calculator
+                .pool_tokens_to_trading_tokens(
+                    pool_token_amount.into(),
+                    pool_mint.supply.into(),
+                    swap_state.reserve_a().unwrap().into(),
+                    swap_state.reserve_b().unwrap().into(),
+                    RoundDirection::Ceiling,
+                )
+                .unwrap();
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            accounts
+                .deposit_underlying(
+                    &mut base,
+                    &user_key,
                     &token_a_key,
                     &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
+                    &base_pool_key,
+                    &mut base_pool_account,
                     &pool_key,
                     &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
+                    &base_token_a_key,
+                    &mut base_token_a_account,
+                    &base_token_b_key,
+                    &mut base_token_b_account,
+                    DepositUnderlying {
+                        base_pool_token_amount,
+                        pool_token_amount,
+                        maximum_token_a_amount: 100_000,
+                        maximum_base_token_a_amount: 100_000,
+                        maximum_base_token_b_amount: 100_000,
+                    },
                 )
+                .unwrap();
+
+            let pool = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+            assert_eq!(pool.amount, pool_token_amount);
+            let new_token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(
+                u128::from(token_a.amount - new_token_a.amount),
+                deposited.token_a_amount
+            );
+            // base swap pool tokens not needed by the metapool stay with the
+            // user
+            let base_pool = spl_token::state::Account::unpack(&base_pool_account.data).unwrap();
+            assert_eq!(
+                u128::from(base_pool.amount),
+                u128::from(base_pool_token_amount) - deposited.token_b_amount
+            );
+            let new_swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(
+                u128::from(new_swap_state.reserve_b().unwrap()),
+                u128::from(swap_state.reserve_b().unwrap()) + deposited.token_b_amount
             );
+        }
+    }
 
-            accounts.pool_mint_key = old_pool_key;
-            accounts.pool_mint_account = old_pool_account;
+    #[test]
+    fn test_crypto_curve_repeg() {
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 100,
+            owner_trade_fee_numerator: 0,
+            owner_trade_fee_denominator: 0,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+            host_fee_numerator: 0,
+            host_fee_denominator: 0,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
+        };
+        // token B is worth two of token A
+        let curve = CryptoCurve {
+            a: 400_000,
+            gamma: 145_000_000_000_000,
+            price_scale: 2 * PRECISION,
+        };
+        let token_a_amount = 2_000_000_000;
+        let token_b_amount = 1_000_000_000;
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Crypto,
+            calculator: Arc::new(curve.clone()),
+        };
+
+        // the off-peg fee only applies to the 1:1 stable curve
+        {
+            let offpeg_fees = Fees {
+                offpeg_fee_multiplier_numerator: 2,
+                offpeg_fee_multiplier_denominator: 1,
+                ..fees.clone()
+            };
+            let mut accounts = SwapAccountInfo::new(
+                &user_key,
+                offpeg_fees,
+                swap_curve.clone(),
+                token_a_amount,
+                token_b_amount,
+            );
+            assert_eq!(
+                Err(SwapError::InvalidFee.into()),
+                accounts.initialize_swap()
+            );
         }
 
-        // deposit 1 pool token fails beacuse it equates to 0 swap tokens
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        accounts.initialize_swap().unwrap();
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
+            let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_state.price_oracle(), 2 * PRECISION);
+            assert_eq!(swap_state.last_price(), 2 * PRECISION);
             assert_eq!(
-                Err(SwapError::ZeroTradingTokens.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
+                swap_state.last_price_timestamp(),
+                Clock::default().unix_timestamp
+            );
+            assert_eq!(swap_state.virtual_price(), PRECISION);
+            assert_eq!(swap_state.xcp_profit(), PRECISION);
+        }
+
+        let a_to_b_amount = 10_000_000;
+        let b_to_a_amount = 5_000_000;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, a_to_b_amount, b_to_a_amount, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+
+        // the trade records the last price and the fees grow the profit, but
+        // the price oracle has not moved from the price scale
+        {
+            accounts
+                .swap(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
+                    &swap_token_a_key,
+                    &swap_token_b_key,
                     &token_b_key,
                     &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    1,
-                    deposit_a,
-                    deposit_b,
+                    a_to_b_amount,
+                    0,
                 )
+                .unwrap();
+            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+            assert_eq!(token_b.amount, b_to_a_amount + 4_948_460);
+
+            let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(
+                *swap_state.swap_curve(),
+                SwapCurve {
+                    curve_type: CurveType::Crypto,
+                    calculator: Arc::new(curve.clone()),
+                }
             );
+            assert_eq!(swap_state.price_oracle(), 2 * PRECISION);
+            assert_eq!(swap_state.last_price(), 2_020_830_723_093_649_337);
+            assert_eq!(swap_state.virtual_price(), 1_000_024_990_537_268_336);
+            assert_eq!(swap_state.xcp_profit(), 1_000_024_990_537_268_336);
         }
 
-        // slippage exceeded
+        // the market moved 10% above the price scale some time ago
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            // maximum A amount in too low
-            assert_eq!(
-                Err(SwapError::ExceededSlippage.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
+            let mut swap_v2 = SwapVersion::unpack_latest(&accounts.swap_account.data).unwrap();
+            swap_v2.price_oracle = 22 * PRECISION / 10;
+            swap_v2.last_price = 22 * PRECISION / 10;
+            swap_v2.last_price_timestamp = Clock::default().unix_timestamp - MA_HALF_TIME as i64;
+            SwapVersion::pack(
+                SwapVersion::SwapV2(swap_v2),
+                &mut accounts.swap_account.data,
+            )
+            .unwrap();
+        }
+
+        // the next trade repegs towards the price oracle with the profit
+        {
+            accounts
+                .swap(
+                    &swapper_key,
                     &token_b_key,
                     &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a / 10,
-                    deposit_b,
+                    &swap_token_b_key,
+                    &swap_token_a_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    b_to_a_amount,
+                    0,
                 )
+                .unwrap();
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, 9_903_118);
+
+            let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(
+                *swap_state.swap_curve(),
+                SwapCurve {
+                    curve_type: CurveType::Crypto,
+                    calculator: Arc::new(CryptoCurve {
+                        price_scale: 2_040_000_000_000_000_000,
+                        ..curve
+                    }),
+                }
             );
-            // maximum B amount in too low
+            assert_eq!(swap_state.price_oracle(), 22 * PRECISION / 10);
             assert_eq!(
-                Err(SwapError::ExceededSlippage.into()),
-                accounts.deposit_all_token_types(
-                    &depositor_key,
+                swap_state.last_price_timestamp(),
+                Clock::default().unix_timestamp
+            );
+            assert_eq!(swap_state.virtual_price(), 1_000_093_464_877_300_523);
+            assert_eq!(swap_state.xcp_profit(), 1_000_049_990_763_774_064);
+        }
+
+        // deposits record the price on the curve too
+        {
+            let mut swap_v2 = SwapVersion::unpack_latest(&accounts.swap_account.data).unwrap();
+            swap_v2.last_price = 0;
+            SwapVersion::pack(
+                SwapVersion::SwapV2(swap_v2),
+                &mut accounts.swap_account.data,
+            )
+            .unwrap();
+            accounts
+                .deposit_all_token_types(
+                    &swapper_key,
                     &token_a_key,
                     &mut token_a_account,
                     &token_b_key,
                     &mut token_b_account,
                     &pool_key,
                     &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b / 10,
+                    1_000_000,
+                    u64::MAX,
+                    u64::MAX,
                 )
-            );
+                .unwrap();
+
+            let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+            assert!(swap_state.last_price() > 2 * PRECISION);
+            assert!(swap_state.last_price() < 22 * PRECISION / 10);
+            assert!(swap_state.virtual_price() >= 1_000_093_464_877_300_523);
         }
+    }
+
+    #[test]
+    fn test_constant_price_curve() {
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
+        // token B is redeemable for one and a half of token A
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::ConstantPrice,
+            calculator: Arc::new(ConstantPriceCurve {
+                token_b_price_numerator: 3,
+                token_b_price_denominator: 2,
+                allow_deposits: false,
+            }),
+        };
+        let token_a_amount = 3_000_000_000;
+        let token_b_amount = 2_000_000_000;
+
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            Fees::default(),
+            swap_curve,
+            token_a_amount,
+            token_b_amount,
+        );
+        accounts.initialize_swap().unwrap();
+
+        let a_to_b_amount = 1_500_000;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, a_to_b_amount, 0, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+
+        // swaps trade at the fixed price without slippage
+        accounts
+            .swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                a_to_b_amount,
+                1_000_000,
+            )
+            .unwrap();
+        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+        assert_eq!(token_a.amount, 0);
+        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+        assert_eq!(token_b.amount, 1_000_000);
+        let swap_token_a =
+            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        assert_eq!(swap_token_a.amount, token_a_amount + a_to_b_amount);
+        let swap_token_b =
+            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+        assert_eq!(swap_token_b.amount, token_b_amount - 1_000_000);
+
+        // deposits are closed on the pool
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, 1_500_000, 1_000_000, 0);
+        assert_eq!(
+            Err(SwapError::UnsupportedCurveOperation.into()),
+            accounts.deposit_all_token_types(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                &pool_key,
+                &mut pool_account,
+                1_000_000,
+                1_500_000,
+                1_000_000,
+            )
+        );
+    }
+
+    #[test]
+    fn test_offset_curve() {
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
+        // a launch of token A against a virtual balance of token B
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Offset,
+            calculator: Arc::new(OffsetCurve {
+                token_b_offset: 1_000_000,
+            }),
+        };
+        let token_a_amount = 1_000_000_000;
+        let token_b_amount = 0;
+
+        let mut accounts = SwapAccountInfo::new(
+            &user_key,
+            Fees::default(),
+            swap_curve,
+            token_a_amount,
+            token_b_amount,
+        );
+        accounts.initialize_swap().unwrap();
 
-        // invalid input: can't use swap pool tokens as source
-        {
-            let (
-                _token_a_key,
-                _token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            let swap_token_a_key = accounts.token_a_key;
-            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
-            let swap_token_b_key = accounts.token_b_key;
-            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
-            let authority_key = accounts.authority_key;
-            assert_eq!(
-                Err(SwapError::InvalidInput.into()),
-                accounts.deposit_all_token_types(
-                    &authority_key,
-                    &swap_token_a_key,
-                    &mut swap_token_a_account,
-                    &swap_token_b_key,
-                    &mut swap_token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
-                )
-            );
-        }
+        let b_to_a_amount = 1_000;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, 0, b_to_a_amount, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
 
-        // correctly deposit
-        {
-            let (
-                token_a_key,
-                mut token_a_account,
-                token_b_key,
-                mut token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &depositor_key, deposit_a, deposit_b, 0);
-            accounts
-                .deposit_all_token_types(
-                    &depositor_key,
-                    &token_a_key,
-                    &mut token_a_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    &pool_key,
-                    &mut pool_account,
-                    pool_amount.try_into().unwrap(),
-                    deposit_a,
-                    deposit_b,
-                )
-                .unwrap();
+        // token A is bought against the offset
+        accounts
+            .swap(
+                &swapper_key,
+                &token_b_key,
+                &mut token_b_account,
+                &swap_token_b_key,
+                &swap_token_a_key,
+                &token_a_key,
+                &mut token_a_account,
+                b_to_a_amount,
+                0,
+            )
+            .unwrap();
+        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+        assert_eq!(token_a.amount, 999_000);
+        let swap_token_b =
+            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+        assert_eq!(swap_token_b.amount, b_to_a_amount);
 
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-            assert_eq!(swap_token_a.amount, deposit_a + token_a_amount);
-            let swap_token_b =
-                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-            assert_eq!(swap_token_b.amount, deposit_b + token_b_amount);
-            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-            assert_eq!(token_a.amount, 0);
-            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
-            assert_eq!(token_b.amount, 0);
-            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
-            let swap_pool_account =
-                spl_token::state::Account::unpack(&accounts.pool_token_account.data).unwrap();
-            let pool_mint =
-                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
-            assert_eq!(
-                pool_mint.supply,
-                pool_account.amount + swap_pool_account.amount
-            );
-        }
+        // and sold back for no more token B than was swapped in
+        accounts
+            .swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                token_a.amount,
+                0,
+            )
+            .unwrap();
+        let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+        assert_eq!(token_a.amount, 998);
+        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+        assert_eq!(token_b.amount, 999);
+        let swap_token_b =
+            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+        assert_eq!(swap_token_b.amount, 1);
+
+        // deposits are closed on the pool
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, 1_000_000, 1_000, 0);
+        assert_eq!(
+            Err(SwapError::UnsupportedCurveOperation.into()),
+            accounts.deposit_all_token_types(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                &pool_key,
+                &mut pool_account,
+                1_000_000,
+                1_000_000,
+                1_000,
+            )
+        );
     }
 
     #[test]
@@ -3079,6 +9776,8 @@ mod tests {
             owner_withdraw_fee_denominator,
             host_fee_numerator,
             host_fee_denominator,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
         };
 
         let token_a_amount = 1000;
@@ -3093,7 +9792,14 @@ mod tests {
         let withdrawer_key = Pubkey::new_unique();
         let initial_a = token_a_amount / 10;
         let initial_b = token_b_amount / 10;
-        let initial_pool = swap_curve.calculator.new_pool_supply() / 10;
+        let initial_pool = swap_curve
+            .calculator
+            .new_pool_supply(
+                to_u128(token_a_amount).unwrap(),
+                to_u128(token_b_amount).unwrap(),
+            )
+            .unwrap()
+            / 10;
         let withdraw_amount = initial_pool / 4;
         let minimum_token_a_amount = initial_a / 40;
         let minimum_token_b_amount = initial_b / 40;
@@ -3887,6 +10593,8 @@ mod tests {
             owner_withdraw_fee_denominator,
             host_fee_numerator,
             host_fee_denominator,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
         };
 
         let token_a_amount = 100_000;
@@ -3901,26 +10609,203 @@ mod tests {
         let withdrawer_key = Pubkey::new_unique();
         let initial_a = token_a_amount / 10;
         let initial_b = token_b_amount / 10;
-        let initial_pool = swap_curve.calculator.new_pool_supply() / 10;
+        let initial_pool = swap_curve
+            .calculator
+            .new_pool_supply(
+                to_u128(token_a_amount).unwrap(),
+                to_u128(token_b_amount).unwrap(),
+            )
+            .unwrap()
+            / 10;
         let maximum_pool_token_amount = to_u64(initial_pool / 4).unwrap();
         let destination_a_amount = initial_a / 40;
         let destination_b_amount = initial_b / 40;
 
-        let mut accounts =
-            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+
+        // swap not initialized
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            assert_eq!(
+                Err(ProgramError::UninitializedAccount),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+        }
+
+        accounts.initialize_swap().unwrap();
+
+        // wrong owner for swap account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            let old_swap_account = accounts.swap_account;
+            let mut wrong_swap_account = old_swap_account.clone();
+            wrong_swap_account.owner = spl_token::id();
+            accounts.swap_account = wrong_swap_account;
+            assert_eq!(
+                Err(ProgramError::IncorrectProgramId),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+            accounts.swap_account = old_swap_account;
+        }
+
+        // wrong bump seed for authority_key
+        {
+            let (
+                _token_a_key,
+                _token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            let old_authority = accounts.authority_key;
+            let (bad_authority_key, _bump_seed) = Pubkey::find_program_address(
+                &[&accounts.swap_key.to_bytes()[..]],
+                &spl_token::id(),
+            );
+            accounts.authority_key = bad_authority_key;
+            assert_eq!(
+                Err(SwapError::InvalidProgramAddress.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    destination_b_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+            accounts.authority_key = old_authority;
+        }
+
+        // not enough pool tokens
+        {
+            let (
+                _token_a_key,
+                _token_a_account,
+                token_b_key,
+                mut token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                maximum_pool_token_amount / 1000,
+            );
+            assert_eq!(
+                Err(TokenError::InsufficientFunds.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    destination_b_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+        }
+
+        // wrong pool token account
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                token_b_key,
+                mut token_b_account,
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                maximum_pool_token_amount,
+                initial_b,
+                maximum_pool_token_amount,
+            );
+            assert_eq!(
+                Err(TokenError::MintMismatch.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &token_a_key,
+                    &mut token_a_account,
+                    &token_b_key,
+                    &mut token_b_account,
+                    destination_b_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+        }
 
-        // swap not initialized
+        // wrong pool fee account
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 _token_b_key,
                 _token_b_account,
+                wrong_pool_key,
+                wrong_pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                maximum_pool_token_amount,
+            );
+            let (
+                _token_a_key,
+                _token_a_account,
+                _token_b_key,
+                _token_b_account,
                 pool_key,
                 mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                maximum_pool_token_amount,
+            );
+            let old_pool_fee_account = accounts.pool_fee_account;
+            let old_pool_fee_key = accounts.pool_fee_key;
+            accounts.pool_fee_account = wrong_pool_account;
+            accounts.pool_fee_key = wrong_pool_key;
             assert_eq!(
-                Err(ProgramError::UninitializedAccount),
+                Err(SwapError::IncorrectFeeAccount.into()),
                 accounts.withdraw_single_token_type_exact_amount_out(
                     &withdrawer_key,
                     &pool_key,
@@ -3931,11 +10816,65 @@ mod tests {
                     maximum_pool_token_amount,
                 )
             );
+            accounts.pool_fee_account = old_pool_fee_account;
+            accounts.pool_fee_key = old_pool_fee_key;
         }
 
-        accounts.initialize_swap().unwrap();
+        // no approval
+        {
+            let (
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                0,
+                0,
+                maximum_pool_token_amount,
+            );
+            let user_transfer_authority_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(TokenError::OwnerMismatch.into()),
+                do_process_instruction(
+                    withdraw_single_token_type_exact_amount_out(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_transfer_authority_key,
+                        &accounts.pool_mint_key,
+                        &accounts.pool_fee_key,
+                        &pool_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &token_a_key,
+                        WithdrawSingleTokenTypeExactAmountOut {
+                            destination_token_amount: destination_a_amount,
+                            maximum_pool_token_amount,
+                        }
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut token_a_account,
+                        &mut accounts.pool_fee_account,
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
 
-        // wrong owner for swap account
+        // wrong token program id
         {
             let (
                 token_a_key,
@@ -3944,44 +10883,100 @@ mod tests {
                 _token_b_account,
                 pool_key,
                 mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
-            let old_swap_account = accounts.swap_account;
-            let mut wrong_swap_account = old_swap_account.clone();
-            wrong_swap_account.owner = spl_token::id();
-            accounts.swap_account = wrong_swap_account;
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                maximum_pool_token_amount,
+            );
+            let wrong_key = Pubkey::new_unique();
             assert_eq!(
-                Err(ProgramError::IncorrectProgramId),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount,
+                Err(SwapError::IncorrectTokenProgramId.into()),
+                do_process_instruction(
+                    withdraw_single_token_type_exact_amount_out(
+                        &SWAP_PROGRAM_ID,
+                        &wrong_key,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.authority_key,
+                        &accounts.pool_mint_key,
+                        &accounts.pool_fee_key,
+                        &pool_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &token_a_key,
+                        WithdrawSingleTokenTypeExactAmountOut {
+                            destination_token_amount: destination_a_amount,
+                            maximum_pool_token_amount,
+                        }
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.pool_mint_account,
+                        &mut pool_account,
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut token_a_account,
+                        &mut accounts.pool_fee_account,
+                        &mut Account::default(),
+                    ],
                 )
             );
-            accounts.swap_account = old_swap_account;
         }
 
-        // wrong bump seed for authority_key
+        // wrong swap token accounts
         {
             let (
-                _token_a_key,
-                _token_a_account,
+                token_a_key,
+                mut token_a_account,
                 token_b_key,
                 mut token_b_account,
                 pool_key,
                 mut pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
-            let old_authority = accounts.authority_key;
-            let (bad_authority_key, _bump_seed) = Pubkey::find_program_address(
-                &[&accounts.swap_key.to_bytes()[..]],
-                &spl_token::id(),
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
             );
-            accounts.authority_key = bad_authority_key;
+
+            let old_a_key = accounts.token_a_key;
+            let old_a_account = accounts.token_a_account;
+
+            accounts.token_a_key = token_a_key;
+            accounts.token_a_account = token_a_account.clone();
+
+            // wrong swap token a account
             assert_eq!(
-                Err(SwapError::InvalidProgramAddress.into()),
+                Err(SwapError::IncorrectSwapAccount.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount,
+                )
+            );
+
+            accounts.token_a_key = old_a_key;
+            accounts.token_a_account = old_a_account;
+
+            let old_b_key = accounts.token_b_key;
+            let old_b_account = accounts.token_b_account;
+
+            accounts.token_b_key = token_b_key;
+            accounts.token_b_account = token_b_account.clone();
+
+            // wrong swap token b account
+            assert_eq!(
+                Err(SwapError::IncorrectSwapAccount.into()),
                 accounts.withdraw_single_token_type_exact_amount_out(
                     &withdrawer_key,
                     &pool_key,
@@ -3992,16 +10987,18 @@ mod tests {
                     maximum_pool_token_amount,
                 )
             );
-            accounts.authority_key = old_authority;
+
+            accounts.token_b_key = old_b_key;
+            accounts.token_b_account = old_b_account;
         }
 
-        // not enough pool tokens
+        // wrong mint
         {
             let (
-                _token_a_key,
-                _token_a_account,
-                token_b_key,
-                mut token_b_account,
+                token_a_key,
+                mut token_a_account,
+                _token_b_key,
+                _token_b_account,
                 pool_key,
                 mut pool_account,
             ) = accounts.setup_token_accounts(
@@ -4009,68 +11006,78 @@ mod tests {
                 &withdrawer_key,
                 initial_a,
                 initial_b,
-                maximum_pool_token_amount / 1000,
+                initial_pool.try_into().unwrap(),
             );
+            let (pool_mint_key, pool_mint_account) =
+                create_mint(&spl_token::id(), &accounts.authority_key, None);
+            let old_pool_key = accounts.pool_mint_key;
+            let old_pool_account = accounts.pool_mint_account;
+            accounts.pool_mint_key = pool_mint_key;
+            accounts.pool_mint_account = pool_mint_account;
+
             assert_eq!(
-                Err(TokenError::InsufficientFunds.into()),
+                Err(SwapError::IncorrectPoolMint.into()),
                 accounts.withdraw_single_token_type_exact_amount_out(
                     &withdrawer_key,
                     &pool_key,
                     &mut pool_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    destination_b_amount,
+                    &token_a_key,
+                    &mut token_a_account,
+                    destination_a_amount,
                     maximum_pool_token_amount,
                 )
             );
+
+            accounts.pool_mint_key = old_pool_key;
+            accounts.pool_mint_account = old_pool_account;
         }
 
-        // wrong pool token account
+        // slippage exceeded
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 token_b_key,
                 mut token_b_account,
-                _pool_key,
-                _pool_account,
+                pool_key,
+                mut pool_account,
             ) = accounts.setup_token_accounts(
                 &user_key,
                 &withdrawer_key,
-                maximum_pool_token_amount,
+                initial_a,
                 initial_b,
                 maximum_pool_token_amount,
             );
+
+            // maximum pool token amount too low
             assert_eq!(
-                Err(TokenError::MintMismatch.into()),
+                Err(SwapError::ExceededSlippage.into()),
                 accounts.withdraw_single_token_type_exact_amount_out(
                     &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
                     &token_a_key,
                     &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount / 1000,
+                )
+            );
+            assert_eq!(
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
                     &token_b_key,
                     &mut token_b_account,
                     destination_b_amount,
-                    maximum_pool_token_amount,
+                    maximum_pool_token_amount / 1000,
                 )
             );
         }
 
-        // wrong pool fee account
+        // invalid input: can't use swap pool tokens as destination
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                wrong_pool_key,
-                wrong_pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                maximum_pool_token_amount,
-            );
             let (
                 _token_a_key,
                 _token_a_account,
@@ -4085,27 +11092,37 @@ mod tests {
                 initial_b,
                 maximum_pool_token_amount,
             );
-            let old_pool_fee_account = accounts.pool_fee_account;
-            let old_pool_fee_key = accounts.pool_fee_key;
-            accounts.pool_fee_account = wrong_pool_account;
-            accounts.pool_fee_key = wrong_pool_key;
+            let swap_token_a_key = accounts.token_a_key;
+            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
             assert_eq!(
-                Err(SwapError::IncorrectFeeAccount.into()),
+                Err(SwapError::InvalidInput.into()),
                 accounts.withdraw_single_token_type_exact_amount_out(
                     &withdrawer_key,
                     &pool_key,
                     &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
+                    &swap_token_a_key,
+                    &mut swap_token_a_account,
                     destination_a_amount,
                     maximum_pool_token_amount,
                 )
             );
-            accounts.pool_fee_account = old_pool_fee_account;
-            accounts.pool_fee_key = old_pool_fee_key;
+            let swap_token_b_key = accounts.token_b_key;
+            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
+            assert_eq!(
+                Err(SwapError::InvalidInput.into()),
+                accounts.withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &swap_token_b_key,
+                    &mut swap_token_b_account,
+                    destination_b_amount,
+                    maximum_pool_token_amount,
+                )
+            );
         }
 
-        // no approval
+        // correct withdrawal
         {
             let (
                 token_a_key,
@@ -4117,167 +11134,200 @@ mod tests {
             ) = accounts.setup_token_accounts(
                 &user_key,
                 &withdrawer_key,
-                0,
-                0,
-                maximum_pool_token_amount,
+                initial_a,
+                initial_b,
+                initial_pool.try_into().unwrap(),
             );
-            let user_transfer_authority_key = Pubkey::new_unique();
-            assert_eq!(
-                Err(TokenError::OwnerMismatch.into()),
-                do_process_instruction(
-                    withdraw_single_token_type_exact_amount_out(
-                        &SWAP_PROGRAM_ID,
-                        &spl_token::id(),
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &user_transfer_authority_key,
-                        &accounts.pool_mint_key,
-                        &accounts.pool_fee_key,
-                        &pool_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_a_key,
-                        WithdrawSingleTokenTypeExactAmountOut {
-                            destination_token_amount: destination_a_amount,
-                            maximum_pool_token_amount,
-                        }
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut Account::default(),
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut token_a_account,
-                        &mut accounts.pool_fee_account,
-                        &mut Account::default(),
-                    ],
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+            let swap_token_b =
+                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+            let pool_mint =
+                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+
+            let pool_token_amount = accounts
+                .swap_curve
+                .withdraw_single_token_type_exact_out(
+                    destination_a_amount.try_into().unwrap(),
+                    swap_token_a.amount.try_into().unwrap(),
+                    swap_token_b.amount.try_into().unwrap(),
+                    pool_mint.supply.try_into().unwrap(),
+                    TradeDirection::AtoB,
+                    &accounts.fees,
+                )
+                .unwrap();
+            let withdraw_fee = accounts.fees.owner_withdraw_fee(pool_token_amount).unwrap();
+
+            accounts
+                .withdraw_single_token_type_exact_amount_out(
+                    &withdrawer_key,
+                    &pool_key,
+                    &mut pool_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    destination_a_amount,
+                    maximum_pool_token_amount,
                 )
+                .unwrap();
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+
+            assert_eq!(swap_token_a.amount, token_a_amount - destination_a_amount);
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, initial_a + destination_a_amount);
+
+            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+            assert_eq!(
+                pool_account.amount,
+                to_u64(initial_pool - pool_token_amount - withdraw_fee).unwrap()
             );
+            let fee_account =
+                spl_token::state::Account::unpack(&accounts.pool_fee_account.data).unwrap();
+            assert_eq!(fee_account.amount, to_u64(withdraw_fee).unwrap());
         }
 
-        // wrong token program id
+        // correct withdrawal from fee account
         {
             let (
                 token_a_key,
                 mut token_a_account,
                 _token_b_key,
                 _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                maximum_pool_token_amount,
-            );
-            let wrong_key = Pubkey::new_unique();
-            assert_eq!(
-                Err(SwapError::IncorrectTokenProgramId.into()),
-                do_process_instruction(
-                    withdraw_single_token_type_exact_amount_out(
-                        &SWAP_PROGRAM_ID,
-                        &wrong_key,
-                        &accounts.swap_key,
-                        &accounts.authority_key,
-                        &accounts.authority_key,
-                        &accounts.pool_mint_key,
-                        &accounts.pool_fee_key,
-                        &pool_key,
-                        &accounts.token_a_key,
-                        &accounts.token_b_key,
-                        &token_a_key,
-                        WithdrawSingleTokenTypeExactAmountOut {
-                            destination_token_amount: destination_a_amount,
-                            maximum_pool_token_amount,
-                        }
-                    )
-                    .unwrap(),
-                    vec![
-                        &mut accounts.swap_account,
-                        &mut Account::default(),
-                        &mut Account::default(),
-                        &mut accounts.pool_mint_account,
-                        &mut pool_account,
-                        &mut accounts.token_a_account,
-                        &mut accounts.token_b_account,
-                        &mut token_a_account,
-                        &mut accounts.pool_fee_account,
-                        &mut Account::default(),
-                    ],
+                _pool_key,
+                _pool_account,
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+
+            let fee_a_amount = 2;
+            let pool_fee_key = accounts.pool_fee_key;
+            let mut pool_fee_account = accounts.pool_fee_account.clone();
+            let fee_account = spl_token::state::Account::unpack(&pool_fee_account.data).unwrap();
+            let pool_fee_amount = fee_account.amount;
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+
+            let token_a_amount = swap_token_a.amount;
+            accounts
+                .withdraw_single_token_type_exact_amount_out(
+                    &user_key,
+                    &pool_fee_key,
+                    &mut pool_fee_account,
+                    &token_a_key,
+                    &mut token_a_account,
+                    fee_a_amount,
+                    pool_fee_amount,
                 )
-            );
+                .unwrap();
+
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+
+            assert_eq!(swap_token_a.amount, token_a_amount - fee_a_amount);
+            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
+            assert_eq!(token_a.amount, initial_a + fee_a_amount);
         }
+    }
+
+    #[test]
+    fn test_withdraw_one_exact_in() {
+        let user_key = Pubkey::new_unique();
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 100,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 50,
+            owner_withdraw_fee_numerator: 1,
+            owner_withdraw_fee_denominator: 5,
+            host_fee_numerator: 0,
+            host_fee_denominator: 0,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
+        };
+
+        let token_a_amount = 100_000;
+        let token_b_amount = 200_000;
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 200 }),
+        };
+
+        let withdrawer_key = Pubkey::new_unique();
+        let initial_a = token_a_amount / 10;
+        let initial_b = token_b_amount / 10;
+        let initial_pool = swap_curve
+            .calculator
+            .new_pool_supply(
+                to_u128(token_a_amount).unwrap(),
+                to_u128(token_b_amount).unwrap(),
+            )
+            .unwrap()
+            / 10;
+        let pool_token_amount = to_u64(initial_pool / 4).unwrap();
 
-        // wrong swap token accounts
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+
+        // swap not initialized
         {
             let (
                 token_a_key,
                 mut token_a_account,
-                token_b_key,
-                mut token_b_account,
+                _token_b_key,
+                _token_b_account,
                 pool_key,
                 mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
-            );
-
-            let old_a_key = accounts.token_a_key;
-            let old_a_account = accounts.token_a_account;
-
-            accounts.token_a_key = token_a_key;
-            accounts.token_a_account = token_a_account.clone();
-
-            // wrong swap token a account
+            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
             assert_eq!(
-                Err(SwapError::IncorrectSwapAccount.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
+                Err(ProgramError::UninitializedAccount),
+                accounts.withdraw_single_token_type_exact_amount_in(
                     &withdrawer_key,
                     &pool_key,
                     &mut pool_account,
                     &token_a_key,
                     &mut token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount,
+                    pool_token_amount,
+                    0,
                 )
             );
+        }
 
-            accounts.token_a_key = old_a_key;
-            accounts.token_a_account = old_a_account;
-
-            let old_b_key = accounts.token_b_key;
-            let old_b_account = accounts.token_b_account;
-
-            accounts.token_b_key = token_b_key;
-            accounts.token_b_account = token_b_account.clone();
+        accounts.initialize_swap().unwrap();
 
-            // wrong swap token b account
+        // destination is not a swap token
+        {
+            let (
+                _token_a_key,
+                _token_a_account,
+                _token_b_key,
+                _token_b_account,
+                pool_key,
+                mut pool_account,
+            ) = accounts.setup_token_accounts(
+                &user_key,
+                &withdrawer_key,
+                initial_a,
+                initial_b,
+                pool_token_amount,
+            );
+            let wrong_key = pool_key;
+            let mut wrong_account = pool_account.clone();
             assert_eq!(
                 Err(SwapError::IncorrectSwapAccount.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
+                accounts.withdraw_single_token_type_exact_amount_in(
                     &withdrawer_key,
                     &pool_key,
                     &mut pool_account,
-                    &token_b_key,
-                    &mut token_b_account,
-                    destination_b_amount,
-                    maximum_pool_token_amount,
+                    &wrong_key,
+                    &mut wrong_account,
+                    pool_token_amount,
+                    0,
                 )
             );
-
-            accounts.token_b_key = old_b_key;
-            accounts.token_b_account = old_b_account;
         }
 
-        // wrong mint
+        // slippage exceeded
         {
             let (
                 token_a_key,
@@ -4291,37 +11341,27 @@ mod tests {
                 &withdrawer_key,
                 initial_a,
                 initial_b,
-                initial_pool.try_into().unwrap(),
+                pool_token_amount,
             );
-            let (pool_mint_key, pool_mint_account) =
-                create_mint(&spl_token::id(), &accounts.authority_key, None);
-            let old_pool_key = accounts.pool_mint_key;
-            let old_pool_account = accounts.pool_mint_account;
-            accounts.pool_mint_key = pool_mint_key;
-            accounts.pool_mint_account = pool_mint_account;
-
             assert_eq!(
-                Err(SwapError::IncorrectPoolMint.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
+                Err(SwapError::ExceededSlippage.into()),
+                accounts.withdraw_single_token_type_exact_amount_in(
                     &withdrawer_key,
                     &pool_key,
                     &mut pool_account,
                     &token_a_key,
                     &mut token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount,
+                    pool_token_amount,
+                    token_a_amount,
                 )
             );
-
-            accounts.pool_mint_key = old_pool_key;
-            accounts.pool_mint_account = old_pool_account;
         }
 
-        // slippage exceeded
+        // correct withdrawal of token B
         {
             let (
-                token_a_key,
-                mut token_a_account,
+                _token_a_key,
+                _token_a_account,
                 token_b_key,
                 mut token_b_account,
                 pool_key,
@@ -4331,188 +11371,838 @@ mod tests {
                 &withdrawer_key,
                 initial_a,
                 initial_b,
-                maximum_pool_token_amount,
+                pool_token_amount,
             );
 
-            // maximum pool token amount too low
-            assert_eq!(
-                Err(SwapError::ExceededSlippage.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount / 1000,
+            let swap_token_a =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+            let swap_token_b =
+                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+            let pool_mint =
+                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+            let fee_account =
+                spl_token::state::Account::unpack(&accounts.pool_fee_account.data).unwrap();
+
+            let withdraw_fee = accounts
+                .fees
+                .owner_withdraw_fee(to_u128(pool_token_amount).unwrap())
+                .unwrap();
+            let destination_b_amount = accounts
+                .swap_curve
+                .withdraw_single_token_type_exact_in(
+                    to_u128(pool_token_amount).unwrap() - withdraw_fee,
+                    to_u128(swap_token_a.amount).unwrap(),
+                    to_u128(swap_token_b.amount).unwrap(),
+                    to_u128(pool_mint.supply).unwrap(),
+                    TradeDirection::BtoA,
+                    &accounts.fees,
                 )
-            );
-            assert_eq!(
-                Err(SwapError::ExceededSlippage.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
+                .unwrap();
+            let destination_b_amount = to_u64(destination_b_amount).unwrap();
+            assert!(destination_b_amount > 0);
+
+            accounts
+                .withdraw_single_token_type_exact_amount_in(
                     &withdrawer_key,
                     &pool_key,
                     &mut pool_account,
                     &token_b_key,
                     &mut token_b_account,
+                    pool_token_amount,
                     destination_b_amount,
-                    maximum_pool_token_amount / 1000,
+                )
+                .unwrap();
+
+            let new_swap_token_b =
+                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+            assert_eq!(
+                new_swap_token_b.amount,
+                swap_token_b.amount - destination_b_amount
+            );
+            let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+            assert_eq!(token_b.amount, initial_b + destination_b_amount);
+
+            // the whole pool token balance is gone, with the withdraw fee
+            // sent to the fee account
+            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+            assert_eq!(pool_account.amount, 0);
+            let new_pool_mint =
+                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+            assert_eq!(
+                new_pool_mint.supply,
+                pool_mint.supply - pool_token_amount + to_u64(withdraw_fee).unwrap()
+            );
+            let new_fee_account =
+                spl_token::state::Account::unpack(&accounts.pool_fee_account.data).unwrap();
+            assert_eq!(
+                new_fee_account.amount,
+                fee_account.amount + to_u64(withdraw_fee).unwrap()
+            );
+        }
+    }
+
+    #[test]
+    fn test_withdraw_admin_fees() {
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 100,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 50,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+            host_fee_numerator: 0,
+            host_fee_denominator: 0,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
+        };
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 1_000_000;
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 100 }),
+        };
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        let (admin_fee_a_key, mut admin_fee_a_account) = mint_token(
+            &spl_token::id(),
+            &accounts.token_a_mint_key,
+            &mut accounts.token_a_mint_account,
+            &user_key,
+            &user_key,
+            0,
+        );
+        let (admin_fee_b_key, mut admin_fee_b_account) = mint_token(
+            &spl_token::id(),
+            &accounts.token_b_mint_key,
+            &mut accounts.token_b_mint_account,
+            &user_key,
+            &user_key,
+            0,
+        );
+
+        // admin fee accounts with the wrong mints
+        {
+            assert_eq!(
+                Err(SwapError::IncorrectFeeAccount.into()),
+                accounts.initialize_swap_with_admin_fees(
+                    &admin_fee_b_key,
+                    &mut admin_fee_b_account.clone(),
+                    &admin_fee_a_key,
+                    &mut admin_fee_a_account.clone(),
                 )
             );
         }
 
-        // invalid input: can't use swap pool tokens as destination
+        accounts
+            .initialize_swap_with_admin_fees(
+                &admin_fee_a_key,
+                &mut admin_fee_a_account,
+                &admin_fee_b_key,
+                &mut admin_fee_b_account,
+            )
+            .unwrap();
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert!(swap_state.admin_fees_in_tokens());
+        assert_eq!(*swap_state.admin(), user_key);
+        assert_eq!(*swap_state.admin_fee_account_a(), admin_fee_a_key);
+        assert_eq!(*swap_state.admin_fee_account_b(), admin_fee_b_key);
+
+        let initial_a = token_a_amount / 10;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, 0, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        let a_to_b_amount = initial_a / 2;
+        let owner_fee = to_u64(
+            accounts
+                .fees
+                .owner_trading_fee(to_u128(a_to_b_amount).unwrap())
+                .unwrap(),
+        )
+        .unwrap();
+
+        accounts
+            .swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                a_to_b_amount,
+                0,
+            )
+            .unwrap();
+
+        // the owner fee is kept in token A instead of minting pool tokens
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_state.admin_fee_a(), owner_fee);
+        assert_eq!(swap_state.admin_fee_b(), 0);
+        let pool_fee_account =
+            spl_token::state::Account::unpack(&accounts.pool_fee_account.data).unwrap();
+        assert_eq!(pool_fee_account.amount, 0);
+
+        // admin fees are not part of the reserves used for the next trade
+        let swap_token_a =
+            spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        let swap_token_b =
+            spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
+        let results = accounts
+            .swap_curve
+            .swap(
+                to_u128(a_to_b_amount).unwrap(),
+                to_u128(swap_token_a.amount - owner_fee).unwrap(),
+                to_u128(swap_token_b.amount).unwrap(),
+                TradeDirection::AtoB,
+                &accounts.fees,
+            )
+            .unwrap();
+        let token_b_before = spl_token::state::Account::unpack(&token_b_account.data)
+            .unwrap()
+            .amount;
+        accounts
+            .swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                a_to_b_amount,
+                0,
+            )
+            .unwrap();
+        let token_b = spl_token::state::Account::unpack(&token_b_account.data).unwrap();
+        assert_eq!(
+            to_u128(token_b.amount - token_b_before).unwrap(),
+            results.destination_amount_swapped
+        );
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(swap_state.admin_fee_a(), 2 * owner_fee);
+
+        // wrong admin
+        {
+            let wrong_admin_key = Pubkey::new_unique();
+            assert_eq!(
+                Err(SwapError::InvalidAdmin.into()),
+                do_process_instruction(
+                    withdraw_admin_fees(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &wrong_admin_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &admin_fee_a_key,
+                        &admin_fee_b_key,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut admin_fee_a_account,
+                        &mut admin_fee_b_account,
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
+
+        // admin is not a signer
         {
-            let (
-                _token_a_key,
-                _token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
+            let mut instruction = withdraw_admin_fees(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &accounts.swap_key,
+                &accounts.authority_key,
                 &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                maximum_pool_token_amount,
-            );
-            let swap_token_a_key = accounts.token_a_key;
-            let mut swap_token_a_account = accounts.get_token_account(&swap_token_a_key).clone();
+                &accounts.token_a_key,
+                &accounts.token_b_key,
+                &admin_fee_a_key,
+                &admin_fee_b_key,
+            )
+            .unwrap();
+            instruction.accounts[2].is_signer = false;
             assert_eq!(
-                Err(SwapError::InvalidInput.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &swap_token_a_key,
-                    &mut swap_token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount,
+                Err(SwapError::InvalidAdmin.into()),
+                do_process_instruction(
+                    instruction,
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut admin_fee_a_account,
+                        &mut admin_fee_b_account,
+                        &mut Account::default(),
+                    ],
                 )
             );
-            let swap_token_b_key = accounts.token_b_key;
-            let mut swap_token_b_account = accounts.get_token_account(&swap_token_b_key).clone();
+        }
+
+        // wrong admin fee account
+        {
             assert_eq!(
-                Err(SwapError::InvalidInput.into()),
-                accounts.withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &swap_token_b_key,
-                    &mut swap_token_b_account,
-                    destination_b_amount,
-                    maximum_pool_token_amount,
+                Err(SwapError::IncorrectFeeAccount.into()),
+                do_process_instruction(
+                    withdraw_admin_fees(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_key,
+                        &accounts.token_a_key,
+                        &accounts.token_b_key,
+                        &token_a_key,
+                        &admin_fee_b_key,
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.token_a_account,
+                        &mut accounts.token_b_account,
+                        &mut token_a_account,
+                        &mut admin_fee_b_account,
+                        &mut Account::default(),
+                    ],
                 )
             );
         }
 
         // correct withdrawal
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                pool_key,
-                mut pool_account,
-            ) = accounts.setup_token_accounts(
-                &user_key,
-                &withdrawer_key,
-                initial_a,
-                initial_b,
-                initial_pool.try_into().unwrap(),
-            );
+            let swap_token_a_before =
+                spl_token::state::Account::unpack(&accounts.token_a_account.data)
+                    .unwrap()
+                    .amount;
+            do_process_instruction(
+                withdraw_admin_fees(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &user_key,
+                    &accounts.token_a_key,
+                    &accounts.token_b_key,
+                    &admin_fee_a_key,
+                    &admin_fee_b_key,
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut accounts.token_a_account,
+                    &mut accounts.token_b_account,
+                    &mut admin_fee_a_account,
+                    &mut admin_fee_b_account,
+                    &mut Account::default(),
+                ],
+            )
+            .unwrap();
 
+            let admin_fee_a = spl_token::state::Account::unpack(&admin_fee_a_account.data).unwrap();
+            assert_eq!(admin_fee_a.amount, 2 * owner_fee);
+            let admin_fee_b = spl_token::state::Account::unpack(&admin_fee_b_account.data).unwrap();
+            assert_eq!(admin_fee_b.amount, 0);
             let swap_token_a =
                 spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
-            let swap_token_b =
-                spl_token::state::Account::unpack(&accounts.token_b_account.data).unwrap();
-            let pool_mint =
-                spl_token::state::Mint::unpack(&accounts.pool_mint_account.data).unwrap();
+            assert_eq!(swap_token_a.amount, swap_token_a_before - 2 * owner_fee);
+            let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+            assert_eq!(swap_state.admin_fee_a(), 0);
+            assert_eq!(swap_state.admin_fee_b(), 0);
+        }
+    }
 
-            let pool_token_amount = accounts
-                .swap_curve
-                .withdraw_single_token_type_exact_out(
-                    destination_a_amount.try_into().unwrap(),
-                    swap_token_a.amount.try_into().unwrap(),
-                    swap_token_b.amount.try_into().unwrap(),
-                    pool_mint.supply.try_into().unwrap(),
-                    TradeDirection::AtoB,
-                    &accounts.fees,
+    #[test]
+    fn test_swap_v1_state() {
+        let user_key = Pubkey::new_unique();
+        let depositor_key = Pubkey::new_unique();
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 100,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 50,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+            host_fee_numerator: 0,
+            host_fee_denominator: 0,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
+        };
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 1_000_000;
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 100 }),
+        };
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        accounts.initialize_swap().unwrap();
+
+        // rewrite the swap as it was packed before SwapV2
+        let latest = SwapVersion::unpack_latest(&accounts.swap_account.data).unwrap();
+        let original_state = SwapV1 {
+            is_initialized: latest.is_initialized,
+            bump_seed: latest.bump_seed,
+            token_program_id: latest.token_program_id,
+            token_a: latest.token_a,
+            token_b: latest.token_b,
+            pool_mint: latest.pool_mint,
+            token_a_mint: latest.token_a_mint,
+            token_b_mint: latest.token_b_mint,
+            pool_fee_account: latest.pool_fee_account,
+            fees: latest.fees,
+            swap_curve: latest.swap_curve,
+        };
+        accounts.swap_account.data = vec![0; 1 + SwapV1::LEN];
+        SwapVersion::pack(
+            SwapVersion::SwapV1(original_state),
+            &mut accounts.swap_account.data,
+        )
+        .unwrap();
+
+        let initial_a = token_a_amount / 10;
+        let initial_b = token_b_amount / 10;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            pool_key,
+            mut pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &depositor_key, initial_a, initial_b, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+
+        // existing swaps keep trading and taking liquidity
+        accounts
+            .swap(
+                &depositor_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                initial_a / 2,
+                0,
+            )
+            .unwrap();
+        let pool_token_amount = 10_000;
+        accounts
+            .deposit_all_token_types(
+                &depositor_key,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                &pool_key,
+                &mut pool_account,
+                pool_token_amount,
+                u64::MAX,
+                u64::MAX,
+            )
+            .unwrap();
+        accounts
+            .withdraw_all_token_types(
+                &depositor_key,
+                &pool_key,
+                &mut pool_account,
+                &token_a_key,
+                &mut token_a_account,
+                &token_b_key,
+                &mut token_b_account,
+                pool_token_amount,
+                0,
+                0,
+            )
+            .unwrap();
+        assert_eq!(accounts.swap_account.data.len(), 1 + SwapV1::LEN);
+
+        // but cannot hold admin settings
+        let (admin_fee_a_key, mut admin_fee_a_account) = mint_token(
+            &spl_token::id(),
+            &accounts.token_a_mint_key,
+            &mut accounts.token_a_mint_account,
+            &user_key,
+            &user_key,
+            0,
+        );
+        let (admin_fee_b_key, mut admin_fee_b_account) = mint_token(
+            &spl_token::id(),
+            &accounts.token_b_mint_key,
+            &mut accounts.token_b_mint_account,
+            &user_key,
+            &user_key,
+            0,
+        );
+        assert_eq!(
+            Err(SwapError::OutdatedSwapVersion.into()),
+            do_process_instruction(
+                withdraw_admin_fees(
+                    &SWAP_PROGRAM_ID,
+                    &spl_token::id(),
+                    &accounts.swap_key,
+                    &accounts.authority_key,
+                    &user_key,
+                    &accounts.token_a_key,
+                    &accounts.token_b_key,
+                    &admin_fee_a_key,
+                    &admin_fee_b_key,
+                )
+                .unwrap(),
+                vec![
+                    &mut accounts.swap_account,
+                    &mut Account::default(),
+                    &mut Account::default(),
+                    &mut accounts.token_a_account,
+                    &mut accounts.token_b_account,
+                    &mut admin_fee_a_account,
+                    &mut admin_fee_b_account,
+                    &mut Account::default(),
+                ],
+            )
+        );
+    }
+
+    #[test]
+    fn test_distribute_fees() {
+        let user_key = Pubkey::new_unique();
+        let swapper_key = Pubkey::new_unique();
+        let fees = Fees {
+            trade_fee_numerator: 1,
+            trade_fee_denominator: 100,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 20,
+            owner_withdraw_fee_numerator: 0,
+            owner_withdraw_fee_denominator: 0,
+            host_fee_numerator: 0,
+            host_fee_denominator: 0,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
+        };
+        let token_a_amount = 1_000_000;
+        let token_b_amount = 1_000_000;
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 100 }),
+        };
+
+        let mut accounts =
+            SwapAccountInfo::new(&user_key, fees, swap_curve, token_a_amount, token_b_amount);
+        accounts.initialize_swap().unwrap();
+
+        let authority_key = accounts.authority_key;
+        let (fee_vault_key, mut fee_vault_account) = mint_token(
+            &spl_token::id(),
+            &accounts.pool_mint_key,
+            &mut accounts.pool_mint_account,
+            &authority_key,
+            &authority_key,
+            0,
+        );
+        let (treasury_key, mut treasury_account) = mint_token(
+            &spl_token::id(),
+            &accounts.pool_mint_key,
+            &mut accounts.pool_mint_account,
+            &authority_key,
+            &user_key,
+            0,
+        );
+        let (partner_key, mut partner_account) = mint_token(
+            &spl_token::id(),
+            &accounts.pool_mint_key,
+            &mut accounts.pool_mint_account,
+            &authority_key,
+            &swapper_key,
+            0,
+        );
+
+        // weights do not add up to 100%
+        {
+            assert_eq!(
+                Err(SwapError::InvalidFeeRecipients.into()),
+                do_process_instruction(
+                    set_fee_recipients(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_key,
+                        &fee_vault_key,
+                        &[&treasury_key, &partner_key],
+                        SetFeeRecipients {
+                            weights: [7_000, 2_000, 0, 0],
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut fee_vault_account,
+                        &mut treasury_account,
+                        &mut partner_account,
+                    ],
                 )
-                .unwrap();
-            let withdraw_fee = accounts.fees.owner_withdraw_fee(pool_token_amount).unwrap();
+            );
+        }
 
-            accounts
-                .withdraw_single_token_type_exact_amount_out(
-                    &withdrawer_key,
-                    &pool_key,
-                    &mut pool_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    destination_a_amount,
-                    maximum_pool_token_amount,
+        // unused weight in between recipients
+        {
+            assert_eq!(
+                Err(SwapError::InvalidFeeRecipients.into()),
+                do_process_instruction(
+                    set_fee_recipients(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_key,
+                        &fee_vault_key,
+                        &[&treasury_key, &partner_key],
+                        SetFeeRecipients {
+                            weights: [7_000, 0, 3_000, 0],
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut fee_vault_account,
+                        &mut treasury_account,
+                        &mut partner_account,
+                    ],
                 )
-                .unwrap();
-
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+            );
+        }
 
-            assert_eq!(swap_token_a.amount, token_a_amount - destination_a_amount);
-            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-            assert_eq!(token_a.amount, initial_a + destination_a_amount);
+        // fee account not owned by swap authority
+        {
+            assert_eq!(
+                Err(SwapError::InvalidOwner.into()),
+                do_process_instruction(
+                    set_fee_recipients(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &user_key,
+                        &accounts.pool_fee_key,
+                        &[&treasury_key, &partner_key],
+                        SetFeeRecipients {
+                            weights: [7_000, 3_000, 0, 0],
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut accounts.pool_fee_account,
+                        &mut treasury_account,
+                        &mut partner_account,
+                    ],
+                )
+            );
+        }
 
-            let pool_account = spl_token::state::Account::unpack(&pool_account.data).unwrap();
+        // wrong admin
+        {
             assert_eq!(
-                pool_account.amount,
-                to_u64(initial_pool - pool_token_amount - withdraw_fee).unwrap()
+                Err(SwapError::InvalidAdmin.into()),
+                do_process_instruction(
+                    set_fee_recipients(
+                        &SWAP_PROGRAM_ID,
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &swapper_key,
+                        &fee_vault_key,
+                        &[&treasury_key, &partner_key],
+                        SetFeeRecipients {
+                            weights: [7_000, 3_000, 0, 0],
+                        },
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut Account::default(),
+                        &mut fee_vault_account,
+                        &mut treasury_account,
+                        &mut partner_account,
+                    ],
+                )
             );
-            let fee_account =
-                spl_token::state::Account::unpack(&accounts.pool_fee_account.data).unwrap();
-            assert_eq!(fee_account.amount, to_u64(withdraw_fee).unwrap());
         }
 
-        // correct withdrawal from fee account
+        // distributing before the recipients are set
         {
-            let (
-                token_a_key,
-                mut token_a_account,
-                _token_b_key,
-                _token_b_account,
-                _pool_key,
-                _pool_account,
-            ) = accounts.setup_token_accounts(&user_key, &withdrawer_key, initial_a, initial_b, 0);
+            assert_eq!(
+                Err(SwapError::InvalidFeeRecipients.into()),
+                do_process_instruction(
+                    distribute_fees(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.pool_fee_key,
+                        &[],
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.pool_fee_account,
+                        &mut Account::default(),
+                    ],
+                )
+            );
+        }
 
-            let fee_a_amount = 2;
-            let pool_fee_key = accounts.pool_fee_key;
-            let mut pool_fee_account = accounts.pool_fee_account.clone();
-            let fee_account = spl_token::state::Account::unpack(&pool_fee_account.data).unwrap();
-            let pool_fee_amount = fee_account.amount;
+        do_process_instruction(
+            set_fee_recipients(
+                &SWAP_PROGRAM_ID,
+                &accounts.swap_key,
+                &accounts.authority_key,
+                &user_key,
+                &fee_vault_key,
+                &[&treasury_key, &partner_key],
+                SetFeeRecipients {
+                    weights: [7_000, 3_000, 0, 0],
+                },
+            )
+            .unwrap(),
+            vec![
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut Account::default(),
+                &mut fee_vault_account,
+                &mut treasury_account,
+                &mut partner_account,
+            ],
+        )
+        .unwrap();
+        let swap_state = SwapVersion::unpack(&accounts.swap_account.data).unwrap();
+        assert_eq!(*swap_state.pool_fee_account(), fee_vault_key);
+        assert_eq!(
+            *swap_state.fee_recipients(),
+            [
+                treasury_key,
+                partner_key,
+                Pubkey::default(),
+                Pubkey::default()
+            ]
+        );
+        assert_eq!(*swap_state.fee_recipient_weights(), [7_000, 3_000, 0, 0]);
 
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        // trading fees are now minted to the fee vault
+        accounts.pool_fee_key = fee_vault_key;
+        accounts.pool_fee_account = fee_vault_account;
+        let initial_a = token_a_amount / 10;
+        let (
+            token_a_key,
+            mut token_a_account,
+            token_b_key,
+            mut token_b_account,
+            _pool_key,
+            _pool_account,
+        ) = accounts.setup_token_accounts(&user_key, &swapper_key, initial_a, 0, 0);
+        let swap_token_a_key = accounts.token_a_key;
+        let swap_token_b_key = accounts.token_b_key;
+        accounts
+            .swap(
+                &swapper_key,
+                &token_a_key,
+                &mut token_a_account,
+                &swap_token_a_key,
+                &swap_token_b_key,
+                &token_b_key,
+                &mut token_b_account,
+                initial_a,
+                0,
+            )
+            .unwrap();
+        let fee_amount = spl_token::state::Account::unpack(&accounts.pool_fee_account.data)
+            .unwrap()
+            .amount;
+        assert!(fee_amount > 0);
 
-            let token_a_amount = swap_token_a.amount;
-            accounts
-                .withdraw_single_token_type_exact_amount_out(
-                    &user_key,
-                    &pool_fee_key,
-                    &mut pool_fee_account,
-                    &token_a_key,
-                    &mut token_a_account,
-                    fee_a_amount,
-                    pool_fee_amount,
+        // recipients in the wrong order
+        {
+            assert_eq!(
+                Err(SwapError::IncorrectFeeAccount.into()),
+                do_process_instruction(
+                    distribute_fees(
+                        &SWAP_PROGRAM_ID,
+                        &spl_token::id(),
+                        &accounts.swap_key,
+                        &accounts.authority_key,
+                        &accounts.pool_fee_key,
+                        &[&partner_key, &treasury_key],
+                    )
+                    .unwrap(),
+                    vec![
+                        &mut accounts.swap_account,
+                        &mut Account::default(),
+                        &mut accounts.pool_fee_account,
+                        &mut Account::default(),
+                        &mut partner_account,
+                        &mut treasury_account,
+                    ],
                 )
-                .unwrap();
+            );
+        }
 
-            let swap_token_a =
-                spl_token::state::Account::unpack(&accounts.token_a_account.data).unwrap();
+        // anyone can distribute
+        do_process_instruction(
+            distribute_fees(
+                &SWAP_PROGRAM_ID,
+                &spl_token::id(),
+                &accounts.swap_key,
+                &accounts.authority_key,
+                &accounts.pool_fee_key,
+                &[&treasury_key, &partner_key],
+            )
+            .unwrap(),
+            vec![
+                &mut accounts.swap_account,
+                &mut Account::default(),
+                &mut accounts.pool_fee_account,
+                &mut Account::default(),
+                &mut treasury_account,
+                &mut partner_account,
+            ],
+        )
+        .unwrap();
 
-            assert_eq!(swap_token_a.amount, token_a_amount - fee_a_amount);
-            let token_a = spl_token::state::Account::unpack(&token_a_account.data).unwrap();
-            assert_eq!(token_a.amount, initial_a + fee_a_amount);
-        }
+        let treasury = spl_token::state::Account::unpack(&treasury_account.data).unwrap();
+        assert_eq!(treasury.amount, fee_amount * 7_000 / 10_000);
+        let partner = spl_token::state::Account::unpack(&partner_account.data).unwrap();
+        assert_eq!(partner.amount, fee_amount * 3_000 / 10_000);
+        let fee_vault = spl_token::state::Account::unpack(&accounts.pool_fee_account.data).unwrap();
+        assert_eq!(
+            fee_vault.amount,
+            fee_amount - treasury.amount - partner.amount
+        );
     }
 
     fn check_valid_swap_curve(
@@ -4715,6 +12405,8 @@ mod tests {
             owner_withdraw_fee_denominator,
             host_fee_numerator,
             host_fee_denominator,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
         };
 
         let token_a_amount = 10_000_000_000;
@@ -4729,6 +12421,39 @@ mod tests {
             token_a_amount,
             token_b_amount,
         );
+
+        check_valid_swap_curve(
+            fees.clone(),
+            CurveType::Weighted,
+            Arc::new(WeightedCurve {
+                token_a_weight: 80,
+                token_b_weight: 20,
+            }),
+            token_a_amount,
+            token_b_amount,
+        );
+
+        check_valid_swap_curve(
+            fees.clone(),
+            CurveType::Hybrid,
+            Arc::new(HybridCurve {
+                amp,
+                imbalance_threshold: 1_000,
+            }),
+            token_a_amount,
+            token_b_amount,
+        );
+
+        check_valid_swap_curve(
+            fees,
+            CurveType::Pegged,
+            Arc::new(PeggedCurve {
+                amp,
+                target_price: TARGET_PRICE_PRECISION * 21 / 20,
+            }),
+            token_a_amount,
+            token_b_amount,
+        );
     }
 
     #[test]
@@ -4750,6 +12475,8 @@ mod tests {
             owner_withdraw_fee_denominator,
             host_fee_numerator,
             host_fee_denominator,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
         };
 
         let token_a_amount = 10_000_000_000;
@@ -4764,6 +12491,39 @@ mod tests {
             token_a_amount,
             token_b_amount,
         );
+
+        check_valid_swap_curve(
+            fees.clone(),
+            CurveType::Weighted,
+            Arc::new(WeightedCurve {
+                token_a_weight: 80,
+                token_b_weight: 20,
+            }),
+            token_a_amount,
+            token_b_amount,
+        );
+
+        check_valid_swap_curve(
+            fees.clone(),
+            CurveType::Hybrid,
+            Arc::new(HybridCurve {
+                amp,
+                imbalance_threshold: 1_000,
+            }),
+            token_a_amount,
+            token_b_amount,
+        );
+
+        check_valid_swap_curve(
+            fees,
+            CurveType::Pegged,
+            Arc::new(PeggedCurve {
+                amp,
+                target_price: TARGET_PRICE_PRECISION * 21 / 20,
+            }),
+            token_a_amount,
+            token_b_amount,
+        );
     }
 
     #[test]
@@ -4791,6 +12551,8 @@ mod tests {
             owner_withdraw_fee_denominator,
             host_fee_numerator,
             host_fee_denominator,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
         };
 
         let amp = 200;
@@ -4807,6 +12569,11 @@ mod tests {
             owner_key: owner_key_str,
             valid_curve_types,
             fees: &fees,
+            max_fees: &fees,
+            min_amp: MIN_AMP,
+            max_amp: MAX_AMP,
+            allowed_mints: &[],
+            denied_mints: &[],
         });
         let mut accounts = SwapAccountInfo::new(
             &owner_key,
@@ -4828,6 +12595,10 @@ mod tests {
                 &accounts.pool_mint_key,
                 &accounts.pool_fee_key,
                 &accounts.pool_token_key,
+                &accounts.pool_locked_key,
+                &accounts.config_key,
+                None,
+                None,
                 accounts.fees.clone(),
                 accounts.swap_curve.clone(),
             )
@@ -4840,7 +12611,9 @@ mod tests {
                 &mut accounts.pool_mint_account,
                 &mut accounts.pool_fee_account,
                 &mut accounts.pool_token_account,
+                &mut accounts.pool_locked_account,
                 &mut Account::default(),
+                &mut accounts.config_account,
             ],
             &constraints,
         )
@@ -4880,7 +12653,9 @@ mod tests {
                 &token_b_key,
                 &accounts.pool_mint_key,
                 &accounts.pool_fee_key,
+                None,
                 Some(&pool_key),
+                None,
                 Swap {
                     amount_in,
                     minimum_amount_out,
@@ -4937,6 +12712,8 @@ mod tests {
             owner_withdraw_fee_denominator,
             host_fee_numerator,
             host_fee_denominator,
+            offpeg_fee_multiplier_numerator: 0,
+            offpeg_fee_multiplier_denominator: 0,
         };
 
         let amp = 200;
@@ -5077,6 +12854,8 @@ mod tests {
                         &accounts.pool_mint_key,
                         &accounts.pool_fee_key,
                         None,
+                        None,
+                        None,
                         Swap {
                             amount_in: initial_a,
                             minimum_amount_out: minimum_token_b_amount,
@@ -5152,6 +12931,8 @@ mod tests {
                         &accounts.pool_mint_key,
                         &accounts.pool_fee_key,
                         None,
+                        None,
+                        None,
                         Swap {
                             amount_in: initial_a,
                             minimum_amount_out: minimum_token_b_amount,
@@ -5321,6 +13102,8 @@ mod tests {
                         &accounts.pool_mint_key,
                         &accounts.pool_fee_key,
                         None,
+                        None,
+                        None,
                         Swap {
                             amount_in: initial_a,
                             minimum_amount_out: minimum_token_b_amount,
@@ -5459,11 +13242,18 @@ mod tests {
                 owner_withdraw_fee_denominator,
                 host_fee_numerator,
                 host_fee_denominator,
+                offpeg_fee_multiplier_numerator: 0,
+                offpeg_fee_multiplier_denominator: 0,
             };
             let constraints = Some(SwapConstraints {
                 owner_key,
                 valid_curve_types: &[],
                 fees: &fees,
+                max_fees: &fees,
+                min_amp: MIN_AMP,
+                max_amp: MAX_AMP,
+                allowed_mints: &[],
+                denied_mints: &[],
             });
             do_process_instruction_with_fee_constraints(
                 swap(
@@ -5479,6 +13269,8 @@ mod tests {
                     &accounts.pool_mint_key,
                     &accounts.pool_fee_key,
                     None,
+                    None,
+                    None,
                     Swap {
                         amount_in: initial_a,
                         minimum_amount_out: minimum_token_b_amount,
@@ -5531,11 +13323,18 @@ mod tests {
                 owner_withdraw_fee_denominator,
                 host_fee_numerator,
                 host_fee_denominator,
+                offpeg_fee_multiplier_numerator: 0,
+                offpeg_fee_multiplier_denominator: 0,
             };
             let constraints = Some(SwapConstraints {
                 owner_key,
                 valid_curve_types: &[],
                 fees: &fees,
+                max_fees: &fees,
+                min_amp: MIN_AMP,
+                max_amp: MAX_AMP,
+                allowed_mints: &[],
+                denied_mints: &[],
             });
             assert_eq!(
                 Err(SwapError::IncorrectPoolMint.into()),
@@ -5552,7 +13351,9 @@ mod tests {
                         &token_b_key,
                         &accounts.pool_mint_key,
                         &accounts.pool_fee_key,
+                        None,
                         Some(&bad_token_a_key),
+                        None,
                         Swap {
                             amount_in: initial_a,
                             minimum_amount_out: 0,
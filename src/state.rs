@@ -1,6 +1,13 @@
 //! State transition types
 
-use crate::swap::{base::SwapCurve, fees::Fees};
+use crate::{
+    error::SwapError,
+    oracle::OracleType,
+    swap::{
+        base::{CurveType, SwapCurve},
+        fees::Fees,
+    },
+};
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
 use enum_dispatch::enum_dispatch;
 use solana_program::{
@@ -8,7 +15,29 @@ use solana_program::{
     program_pack::{IsInitialized, Pack, Sealed},
     pubkey::Pubkey,
 };
-use std::sync::Arc;
+use std::{convert::TryFrom, sync::Arc};
+
+/// Maximum number of accounts that pool fees can be distributed to
+pub const MAX_FEE_RECIPIENTS: usize = 4;
+
+/// Denominator of the fee recipient weights, which are in basis points
+pub const FEE_WEIGHTS_DENOMINATOR: u16 = 10_000;
+
+/// Denominator of the circuit breaker price band, which is in basis points
+pub const PRICE_BAND_DENOMINATOR: u16 = 10_000;
+
+/// Denominator of the oracle guard maximum deviation, which is in basis
+/// points
+pub const ORACLE_DEVIATION_DENOMINATOR: u16 = 10_000;
+
+/// Seed of the program address holding the program config
+pub const PROGRAM_CONFIG_SEED: &[u8] = b"config";
+
+/// Seed of the program addresses holding the deposit allowlist entries
+pub const ALLOWLIST_SEED: &[u8] = b"allowlist";
+
+/// Maximum number of mints in each of the program config mint lists
+pub const MAX_CONFIG_MINTS: usize = 8;
 
 /// Trait representing access to program state across all versions
 #[enum_dispatch]
@@ -34,6 +63,79 @@ pub trait SwapState {
     /// Address of pool fee account
     fn pool_fee_account(&self) -> &Pubkey;
 
+    /// Address of the pool admin, allowed to run admin-only instructions
+    fn admin(&self) -> &Pubkey;
+    /// Address of token A account receiving admin fees held in tokens
+    fn admin_fee_account_a(&self) -> &Pubkey;
+    /// Address of token B account receiving admin fees held in tokens
+    fn admin_fee_account_b(&self) -> &Pubkey;
+    /// Amount of admin fees held in the token A account, excluded from the
+    /// curve reserves
+    fn admin_fee_a(&self) -> u64;
+    /// Amount of admin fees held in the token B account, excluded from the
+    /// curve reserves
+    fn admin_fee_b(&self) -> u64;
+    /// Are admin fees kept in the swap token accounts instead of being minted
+    /// as pool tokens to the pool fee account
+    fn admin_fees_in_tokens(&self) -> bool {
+        *self.admin_fee_account_a() != Pubkey::default()
+    }
+
+    /// Pool token accounts sharing the pool tokens held by the pool fee account
+    fn fee_recipients(&self) -> &[Pubkey; MAX_FEE_RECIPIENTS];
+    /// Share of each fee recipient in basis points, zero for unused entries
+    fn fee_recipient_weights(&self) -> &[u16; MAX_FEE_RECIPIENTS];
+
+    /// Are deposits restricted to the user transfer authorities with an
+    /// allowlist entry for the swap
+    fn deposit_gate(&self) -> bool;
+
+    /// Maximum token A reserve after a deposit, zero if uncapped
+    fn max_token_a_amount(&self) -> u64;
+    /// Maximum token B reserve after a deposit, zero if uncapped
+    fn max_token_b_amount(&self) -> u64;
+    /// Maximum pool token supply after a deposit, zero if uncapped
+    fn max_pool_supply(&self) -> u64;
+
+    /// Maximum move of the spot price within a slot in basis points, zero if
+    /// the circuit breaker is off
+    fn price_band(&self) -> u16;
+    /// Slot of the first swap or withdrawal checked by the circuit breaker
+    fn reference_slot(&self) -> u64;
+    /// Spot price before the first swap or withdrawal of the reference slot
+    fn reference_price(&self) -> u128;
+
+    /// Oracle price account guarding swaps against a depeg, the default
+    /// pubkey if there is none
+    fn oracle(&self) -> &Pubkey;
+    /// Layout of the oracle price account
+    fn oracle_type(&self) -> OracleType;
+    /// Maximum deviation of the pool price after a swap from the oracle
+    /// price in basis points, zero if the oracle guard is off
+    fn max_oracle_deviation(&self) -> u16;
+
+    /// Accounted amount of token A backing the curve, excluding admin fees
+    /// and tokens transferred directly to the swap token account, if the
+    /// swap version tracks it
+    fn reserve_a(&self) -> Option<u64>;
+    /// Accounted amount of token B backing the curve, excluding admin fees
+    /// and tokens transferred directly to the swap token account, if the
+    /// swap version tracks it
+    fn reserve_b(&self) -> Option<u64>;
+
+    /// Exponential moving average of the price of token B in token A, toward
+    /// which the crypto curve repegs
+    fn price_oracle(&self) -> u128;
+    /// Price of token B in token A after the last trade or withdrawal on the
+    /// crypto curve
+    fn last_price(&self) -> u128;
+    /// Unix timestamp of the last update of the price oracle
+    fn last_price_timestamp(&self) -> i64;
+    /// Value of the crypto curve pool per pool token
+    fn virtual_price(&self) -> u128;
+    /// Growth of the virtual price of the crypto curve pool from fees
+    fn xcp_profit(&self) -> u128;
+
     /// Fees associated with swap
     fn fees(&self) -> &Fees;
     /// Curve associated with swap
@@ -42,9 +144,12 @@ pub trait SwapState {
 
 /// All versions of SwapState
 #[enum_dispatch(SwapState)]
+#[allow(clippy::large_enum_variant)]
 pub enum SwapVersion {
-    /// Latest version, used for all new swaps
+    /// Original version, still held by swaps created before SwapV2
     SwapV1,
+    /// Latest version, used for all new swaps
+    SwapV2,
 }
 
 /// SwapVersion does not implement program_pack::Pack because there are size
@@ -52,7 +157,7 @@ pub enum SwapVersion {
 /// special implementations are provided here
 impl SwapVersion {
     /// Size of the latest version of the SwapState
-    pub const LATEST_LEN: usize = 1 + SwapV1::LEN; // add one for the version enum
+    pub const LATEST_LEN: usize = 1 + SwapV2::LEN; // add one for the version enum
 
     /// Pack a swap into a byte array, based on its version
     pub fn pack(src: Self, dst: &mut [u8]) -> Result<(), ProgramError> {
@@ -61,6 +166,24 @@ impl SwapVersion {
                 dst[0] = 1;
                 SwapV1::pack(swap_info, &mut dst[1..])
             }
+            Self::SwapV2(swap_info) => {
+                dst[0] = 2;
+                SwapV2::pack(swap_info, &mut dst[1..])
+            }
+        }
+    }
+
+    /// Unpack the swap account into the latest version, for instructions that
+    /// need to write back an updated state.  SwapV1 accounts are too small to
+    /// hold the latest state, so are rejected.
+    pub fn unpack_latest(input: &[u8]) -> Result<SwapV2, ProgramError> {
+        let (&version, rest) = input
+            .split_first()
+            .ok_or(ProgramError::InvalidAccountData)?;
+        match version {
+            1 => Err(SwapError::OutdatedSwapVersion.into()),
+            2 => SwapV2::unpack(rest),
+            _ => Err(ProgramError::UninitializedAccount),
         }
     }
 
@@ -72,6 +195,7 @@ impl SwapVersion {
             .ok_or(ProgramError::InvalidAccountData)?;
         match version {
             1 => Ok(Arc::new(SwapV1::unpack(rest)?)),
+            2 => Ok(Arc::new(SwapV2::unpack(rest)?)),
             _ => Err(ProgramError::UninitializedAccount),
         }
     }
@@ -86,6 +210,10 @@ impl SwapVersion {
     }
 }
 
+/// Unset pubkey returned by [SwapV1](struct.SwapV1.html) for the settings it
+/// predates
+const UNSET_PUBKEY: Pubkey = Pubkey::new_from_array([0u8; 32]);
+
 /// Program states.
 #[repr(C)]
 #[derive(Debug, Default, PartialEq)]
@@ -164,6 +292,102 @@ impl SwapState for SwapV1 {
         &self.pool_fee_account
     }
 
+    fn admin(&self) -> &Pubkey {
+        &UNSET_PUBKEY
+    }
+
+    fn admin_fee_account_a(&self) -> &Pubkey {
+        &UNSET_PUBKEY
+    }
+
+    fn admin_fee_account_b(&self) -> &Pubkey {
+        &UNSET_PUBKEY
+    }
+
+    fn admin_fee_a(&self) -> u64 {
+        0
+    }
+
+    fn admin_fee_b(&self) -> u64 {
+        0
+    }
+
+    fn fee_recipients(&self) -> &[Pubkey; MAX_FEE_RECIPIENTS] {
+        &[UNSET_PUBKEY; MAX_FEE_RECIPIENTS]
+    }
+
+    fn fee_recipient_weights(&self) -> &[u16; MAX_FEE_RECIPIENTS] {
+        &[0; MAX_FEE_RECIPIENTS]
+    }
+
+    fn deposit_gate(&self) -> bool {
+        false
+    }
+
+    fn max_token_a_amount(&self) -> u64 {
+        0
+    }
+
+    fn max_token_b_amount(&self) -> u64 {
+        0
+    }
+
+    fn max_pool_supply(&self) -> u64 {
+        0
+    }
+
+    fn price_band(&self) -> u16 {
+        0
+    }
+
+    fn reference_slot(&self) -> u64 {
+        0
+    }
+
+    fn reference_price(&self) -> u128 {
+        0
+    }
+
+    fn oracle(&self) -> &Pubkey {
+        &UNSET_PUBKEY
+    }
+
+    fn oracle_type(&self) -> OracleType {
+        OracleType::default()
+    }
+
+    fn max_oracle_deviation(&self) -> u16 {
+        0
+    }
+
+    fn reserve_a(&self) -> Option<u64> {
+        None
+    }
+
+    fn reserve_b(&self) -> Option<u64> {
+        None
+    }
+
+    fn price_oracle(&self) -> u128 {
+        0
+    }
+
+    fn last_price(&self) -> u128 {
+        0
+    }
+
+    fn last_price_timestamp(&self) -> i64 {
+        0
+    }
+
+    fn virtual_price(&self) -> u128 {
+        0
+    }
+
+    fn xcp_profit(&self) -> u128 {
+        0
+    }
+
     fn fees(&self) -> &Fees {
         &self.fees
     }
@@ -207,7 +431,10 @@ impl Pack for SwapV1 {
         token_a_mint.copy_from_slice(self.token_a_mint.as_ref());
         token_b_mint.copy_from_slice(self.token_b_mint.as_ref());
         pool_fee_account.copy_from_slice(self.pool_fee_account.as_ref());
-        self.fees.pack_into_slice(&mut fees[..]);
+        // the offpeg fee multiplier is not stored, so stays disabled
+        let mut packed_fees = [0u8; Fees::LEN];
+        self.fees.pack_into_slice(&mut packed_fees);
+        fees.copy_from_slice(&packed_fees[..64]);
         self.swap_curve.pack_into_slice(&mut swap_curve[..]);
     }
 
@@ -228,6 +455,443 @@ impl Pack for SwapV1 {
             fees,
             swap_curve,
         ) = array_refs![input, 1, 1, 32, 32, 32, 32, 32, 32, 32, 64, 33];
+        let mut packed_fees = [0u8; Fees::LEN];
+        packed_fees[..64].copy_from_slice(fees);
+        Ok(Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            bump_seed: bump_seed[0],
+            token_program_id: Pubkey::new_from_array(*token_program_id),
+            token_a: Pubkey::new_from_array(*token_a),
+            token_b: Pubkey::new_from_array(*token_b),
+            pool_mint: Pubkey::new_from_array(*pool_mint),
+            token_a_mint: Pubkey::new_from_array(*token_a_mint),
+            token_b_mint: Pubkey::new_from_array(*token_b_mint),
+            pool_fee_account: Pubkey::new_from_array(*pool_fee_account),
+            fees: Fees::unpack_from_slice(&packed_fees)?,
+            swap_curve: SwapCurve::unpack_from_slice(swap_curve)?,
+        })
+    }
+}
+
+/// Program states, adding the pool admin and settings to
+/// [SwapV1](struct.SwapV1.html).
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct SwapV2 {
+    /// Initialized state.
+    pub is_initialized: bool,
+    /// Bump seed used in program address.
+    /// The program address is created deterministically with the bump seed,
+    /// swap program id, and swap account pubkey.  This program address has
+    /// authority over the swap's token A account, token B account, and pool
+    /// token mint.
+    pub bump_seed: u8,
+
+    /// Program ID of the tokens being exchanged.
+    pub token_program_id: Pubkey,
+
+    /// Token A
+    pub token_a: Pubkey,
+    /// Token B
+    pub token_b: Pubkey,
+
+    /// Pool tokens are issued when A or B tokens are deposited.
+    /// Pool tokens can be withdrawn back to the original A or B token.
+    pub pool_mint: Pubkey,
+
+    /// Mint information for token A
+    pub token_a_mint: Pubkey,
+    /// Mint information for token B
+    pub token_b_mint: Pubkey,
+
+    /// Pool token account to receive trading and / or withdrawal fees
+    pub pool_fee_account: Pubkey,
+
+    /// All fee information
+    pub fees: Fees,
+
+    /// Swap curve parameters, to be unpacked and used by the SwapCurve, which
+    /// calculates swaps, deposits, and withdrawals
+    pub swap_curve: SwapCurve,
+
+    /// Pool admin, set to the owner of the pool fee account on initialization
+    pub admin: Pubkey,
+    /// Token A account to receive admin fees held in tokens.  The default
+    /// pubkey if admin fees are minted as pool tokens to the pool fee account.
+    pub admin_fee_account_a: Pubkey,
+    /// Token B account to receive admin fees held in tokens.  The default
+    /// pubkey if admin fees are minted as pool tokens to the pool fee account.
+    pub admin_fee_account_b: Pubkey,
+    /// Admin fees accrued in the token A account, not part of the reserves
+    pub admin_fee_a: u64,
+    /// Admin fees accrued in the token B account, not part of the reserves
+    pub admin_fee_b: u64,
+
+    /// Pool token accounts to distribute the pool fee account to, only set
+    /// once the pool fee account is owned by the swap authority
+    pub fee_recipients: [Pubkey; MAX_FEE_RECIPIENTS],
+    /// Share of each fee recipient in basis points, zero for unused entries
+    pub fee_recipient_weights: [u16; MAX_FEE_RECIPIENTS],
+
+    /// Are deposits restricted to the user transfer authorities with an
+    /// allowlist entry, set by the pool admin
+    pub deposit_gate: bool,
+
+    /// Maximum token A reserve after a deposit, set by the pool admin,
+    /// zero if uncapped
+    pub max_token_a_amount: u64,
+    /// Maximum token B reserve after a deposit, set by the pool admin,
+    /// zero if uncapped
+    pub max_token_b_amount: u64,
+    /// Maximum pool token supply after a deposit, set by the pool admin, zero
+    /// if uncapped
+    pub max_pool_supply: u64,
+
+    /// Maximum move of the spot price within a slot in basis points, set by
+    /// the pool admin, zero if the circuit breaker is off
+    pub price_band: u16,
+    /// Slot of the first swap or withdrawal checked by the circuit breaker
+    pub reference_slot: u64,
+    /// Spot price before the first swap or withdrawal of the reference slot,
+    /// scaled by `SPOT_PRICE_PRECISION`
+    pub reference_price: u128,
+
+    /// Oracle price account guarding swaps against a depeg, set by the pool
+    /// admin, the default pubkey if there is none
+    pub oracle: Pubkey,
+    /// Layout of the oracle price account
+    pub oracle_type: OracleType,
+    /// Maximum deviation of the pool price after a swap from the oracle
+    /// price in basis points, zero if the oracle guard is off
+    pub max_oracle_deviation: u16,
+
+    /// Accounted amount of token A backing the curve, excluding admin fees
+    /// and tokens transferred directly to the swap token account
+    pub reserve_a: u64,
+    /// Accounted amount of token B backing the curve, excluding admin fees
+    /// and tokens transferred directly to the swap token account
+    pub reserve_b: u64,
+
+    /// Exponential moving average of the price of token B in token A, toward
+    /// which the crypto curve repegs, scaled by the crypto curve `PRECISION`,
+    /// zero for other curves
+    pub price_oracle: u128,
+    /// Price of token B in token A after the last trade or withdrawal on the
+    /// crypto curve, scaled by the crypto curve `PRECISION`
+    pub last_price: u128,
+    /// Unix timestamp of the last update of the price oracle
+    pub last_price_timestamp: i64,
+    /// Value of the crypto curve pool per pool token, scaled by the crypto
+    /// curve `PRECISION`
+    pub virtual_price: u128,
+    /// Growth of the virtual price of the crypto curve pool from fees,
+    /// ignoring the losses of repegs, scaled by the crypto curve `PRECISION`
+    pub xcp_profit: u128,
+}
+
+impl SwapState for SwapV2 {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+
+    fn bump_seed(&self) -> u8 {
+        self.bump_seed
+    }
+
+    fn token_program_id(&self) -> &Pubkey {
+        &self.token_program_id
+    }
+
+    fn token_a_account(&self) -> &Pubkey {
+        &self.token_a
+    }
+
+    fn token_b_account(&self) -> &Pubkey {
+        &self.token_b
+    }
+
+    fn pool_mint(&self) -> &Pubkey {
+        &self.pool_mint
+    }
+
+    fn token_a_mint(&self) -> &Pubkey {
+        &self.token_a_mint
+    }
+
+    fn token_b_mint(&self) -> &Pubkey {
+        &self.token_b_mint
+    }
+
+    fn pool_fee_account(&self) -> &Pubkey {
+        &self.pool_fee_account
+    }
+
+    fn admin(&self) -> &Pubkey {
+        &self.admin
+    }
+
+    fn admin_fee_account_a(&self) -> &Pubkey {
+        &self.admin_fee_account_a
+    }
+
+    fn admin_fee_account_b(&self) -> &Pubkey {
+        &self.admin_fee_account_b
+    }
+
+    fn admin_fee_a(&self) -> u64 {
+        self.admin_fee_a
+    }
+
+    fn admin_fee_b(&self) -> u64 {
+        self.admin_fee_b
+    }
+
+    fn fee_recipients(&self) -> &[Pubkey; MAX_FEE_RECIPIENTS] {
+        &self.fee_recipients
+    }
+
+    fn fee_recipient_weights(&self) -> &[u16; MAX_FEE_RECIPIENTS] {
+        &self.fee_recipient_weights
+    }
+
+    fn deposit_gate(&self) -> bool {
+        self.deposit_gate
+    }
+
+    fn max_token_a_amount(&self) -> u64 {
+        self.max_token_a_amount
+    }
+
+    fn max_token_b_amount(&self) -> u64 {
+        self.max_token_b_amount
+    }
+
+    fn max_pool_supply(&self) -> u64 {
+        self.max_pool_supply
+    }
+
+    fn price_band(&self) -> u16 {
+        self.price_band
+    }
+
+    fn reference_slot(&self) -> u64 {
+        self.reference_slot
+    }
+
+    fn reference_price(&self) -> u128 {
+        self.reference_price
+    }
+
+    fn oracle(&self) -> &Pubkey {
+        &self.oracle
+    }
+
+    fn oracle_type(&self) -> OracleType {
+        self.oracle_type
+    }
+
+    fn max_oracle_deviation(&self) -> u16 {
+        self.max_oracle_deviation
+    }
+
+    fn reserve_a(&self) -> Option<u64> {
+        Some(self.reserve_a)
+    }
+
+    fn reserve_b(&self) -> Option<u64> {
+        Some(self.reserve_b)
+    }
+
+    fn price_oracle(&self) -> u128 {
+        self.price_oracle
+    }
+
+    fn last_price(&self) -> u128 {
+        self.last_price
+    }
+
+    fn last_price_timestamp(&self) -> i64 {
+        self.last_price_timestamp
+    }
+
+    fn virtual_price(&self) -> u128 {
+        self.virtual_price
+    }
+
+    fn xcp_profit(&self) -> u128 {
+        self.xcp_profit
+    }
+
+    fn fees(&self) -> &Fees {
+        &self.fees
+    }
+
+    fn swap_curve(&self) -> &SwapCurve {
+        &self.swap_curve
+    }
+}
+
+impl Sealed for SwapV2 {}
+impl IsInitialized for SwapV2 {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for SwapV2 {
+    const LEN: usize = 761;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 761];
+        let (
+            is_initialized,
+            bump_seed,
+            token_program_id,
+            token_a,
+            token_b,
+            pool_mint,
+            token_a_mint,
+            token_b_mint,
+            pool_fee_account,
+            fees,
+            swap_curve,
+            admin,
+            admin_fee_account_a,
+            admin_fee_account_b,
+            admin_fee_a,
+            admin_fee_b,
+            fee_recipients,
+            fee_recipient_weights,
+            deposit_gate,
+            max_token_a_amount,
+            max_token_b_amount,
+            max_pool_supply,
+            price_band,
+            reference_slot,
+            reference_price,
+            oracle,
+            oracle_type,
+            max_oracle_deviation,
+            reserve_a,
+            reserve_b,
+            price_oracle,
+            last_price,
+            last_price_timestamp,
+            virtual_price,
+            xcp_profit,
+        ) = mut_array_refs![
+            output, 1, 1, 32, 32, 32, 32, 32, 32, 32, 80, 33, 32, 32, 32, 8, 8, 128, 8, 1, 8, 8, 8,
+            2, 8, 16, 32, 1, 2, 8, 8, 16, 16, 8, 16, 16
+        ];
+        is_initialized[0] = self.is_initialized as u8;
+        bump_seed[0] = self.bump_seed;
+        token_program_id.copy_from_slice(self.token_program_id.as_ref());
+        token_a.copy_from_slice(self.token_a.as_ref());
+        token_b.copy_from_slice(self.token_b.as_ref());
+        pool_mint.copy_from_slice(self.pool_mint.as_ref());
+        token_a_mint.copy_from_slice(self.token_a_mint.as_ref());
+        token_b_mint.copy_from_slice(self.token_b_mint.as_ref());
+        pool_fee_account.copy_from_slice(self.pool_fee_account.as_ref());
+        self.fees.pack_into_slice(&mut fees[..]);
+        self.swap_curve.pack_into_slice(&mut swap_curve[..]);
+        admin.copy_from_slice(self.admin.as_ref());
+        admin_fee_account_a.copy_from_slice(self.admin_fee_account_a.as_ref());
+        admin_fee_account_b.copy_from_slice(self.admin_fee_account_b.as_ref());
+        *admin_fee_a = self.admin_fee_a.to_le_bytes();
+        *admin_fee_b = self.admin_fee_b.to_le_bytes();
+        for (dst, recipient) in fee_recipients
+            .chunks_mut(32)
+            .zip(self.fee_recipients.iter())
+        {
+            dst.copy_from_slice(recipient.as_ref());
+        }
+        for (dst, weight) in fee_recipient_weights
+            .chunks_mut(2)
+            .zip(self.fee_recipient_weights.iter())
+        {
+            dst.copy_from_slice(&weight.to_le_bytes());
+        }
+        deposit_gate[0] = self.deposit_gate as u8;
+        *max_token_a_amount = self.max_token_a_amount.to_le_bytes();
+        *max_token_b_amount = self.max_token_b_amount.to_le_bytes();
+        *max_pool_supply = self.max_pool_supply.to_le_bytes();
+        *price_band = self.price_band.to_le_bytes();
+        *reference_slot = self.reference_slot.to_le_bytes();
+        *reference_price = self.reference_price.to_le_bytes();
+        oracle.copy_from_slice(self.oracle.as_ref());
+        oracle_type[0] = self.oracle_type as u8;
+        *max_oracle_deviation = self.max_oracle_deviation.to_le_bytes();
+        *reserve_a = self.reserve_a.to_le_bytes();
+        *reserve_b = self.reserve_b.to_le_bytes();
+        *price_oracle = self.price_oracle.to_le_bytes();
+        *last_price = self.last_price.to_le_bytes();
+        *last_price_timestamp = self.last_price_timestamp.to_le_bytes();
+        *virtual_price = self.virtual_price.to_le_bytes();
+        *xcp_profit = self.xcp_profit.to_le_bytes();
+    }
+
+    /// Unpacks a byte buffer into a [SwapV2](struct.SwapV2.html).
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, 761];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            is_initialized,
+            bump_seed,
+            token_program_id,
+            token_a,
+            token_b,
+            pool_mint,
+            token_a_mint,
+            token_b_mint,
+            pool_fee_account,
+            fees,
+            swap_curve,
+            admin,
+            admin_fee_account_a,
+            admin_fee_account_b,
+            admin_fee_a,
+            admin_fee_b,
+            fee_recipients_data,
+            fee_recipient_weights_data,
+            deposit_gate,
+            max_token_a_amount,
+            max_token_b_amount,
+            max_pool_supply,
+            price_band,
+            reference_slot,
+            reference_price,
+            oracle,
+            oracle_type,
+            max_oracle_deviation,
+            reserve_a,
+            reserve_b,
+            price_oracle,
+            last_price,
+            last_price_timestamp,
+            virtual_price,
+            xcp_profit,
+        ) = array_refs![
+            input, 1, 1, 32, 32, 32, 32, 32, 32, 32, 80, 33, 32, 32, 32, 8, 8, 128, 8, 1, 8, 8, 8,
+            2, 8, 16, 32, 1, 2, 8, 8, 16, 16, 8, 16, 16
+        ];
+        let mut fee_recipients = [Pubkey::default(); MAX_FEE_RECIPIENTS];
+        for (recipient, src) in fee_recipients
+            .iter_mut()
+            .zip(fee_recipients_data.chunks(32))
+        {
+            *recipient = Pubkey::new_from_array(*array_ref![src, 0, 32]);
+        }
+        let mut fee_recipient_weights = [0u16; MAX_FEE_RECIPIENTS];
+        for (weight, src) in fee_recipient_weights
+            .iter_mut()
+            .zip(fee_recipient_weights_data.chunks(2))
+        {
+            *weight = u16::from_le_bytes(*array_ref![src, 0, 2]);
+        }
         Ok(Self {
             is_initialized: match is_initialized {
                 [0] => false,
@@ -244,6 +908,238 @@ impl Pack for SwapV1 {
             pool_fee_account: Pubkey::new_from_array(*pool_fee_account),
             fees: Fees::unpack_from_slice(fees)?,
             swap_curve: SwapCurve::unpack_from_slice(swap_curve)?,
+            admin: Pubkey::new_from_array(*admin),
+            admin_fee_account_a: Pubkey::new_from_array(*admin_fee_account_a),
+            admin_fee_account_b: Pubkey::new_from_array(*admin_fee_account_b),
+            admin_fee_a: u64::from_le_bytes(*admin_fee_a),
+            admin_fee_b: u64::from_le_bytes(*admin_fee_b),
+            fee_recipients,
+            fee_recipient_weights,
+            deposit_gate: match deposit_gate {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            max_token_a_amount: u64::from_le_bytes(*max_token_a_amount),
+            max_token_b_amount: u64::from_le_bytes(*max_token_b_amount),
+            max_pool_supply: u64::from_le_bytes(*max_pool_supply),
+            price_band: u16::from_le_bytes(*price_band),
+            reference_slot: u64::from_le_bytes(*reference_slot),
+            reference_price: u128::from_le_bytes(*reference_price),
+            oracle: Pubkey::new_from_array(*oracle),
+            oracle_type: OracleType::try_from(oracle_type[0])?,
+            max_oracle_deviation: u16::from_le_bytes(*max_oracle_deviation),
+            reserve_a: u64::from_le_bytes(*reserve_a),
+            reserve_b: u64::from_le_bytes(*reserve_b),
+            price_oracle: u128::from_le_bytes(*price_oracle),
+            last_price: u128::from_le_bytes(*last_price),
+            last_price_timestamp: i64::from_le_bytes(*last_price_timestamp),
+            virtual_price: u128::from_le_bytes(*virtual_price),
+            xcp_profit: u128::from_le_bytes(*xcp_profit),
+        })
+    }
+}
+
+/// Program-level configuration, set by the upgrade authority of the program.
+/// When initialized, its constraints on new swaps are used instead of the
+/// ones built into the program.
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct ProgramConfig {
+    /// Initialized state
+    pub is_initialized: bool,
+    /// Bump seed used in program address.
+    pub bump_seed: u8,
+    /// Required owner of the pool fee account of new swaps
+    pub owner_key: Pubkey,
+    /// Curve types allowed for new swaps, as a bitmask where bit N allows the
+    /// curve type with value N
+    pub valid_curve_types: u32,
+    /// Minimum amplification coefficient of new swaps
+    pub min_amp: u64,
+    /// Maximum amplification coefficient of new swaps
+    pub max_amp: u64,
+    /// Minimum fees of new swaps, with the host fee split they must use
+    pub fees: Fees,
+    /// Maximum fees of new swaps, only the numerators are used
+    pub max_fees: Fees,
+    /// Mints that new swaps may pair, `Pubkey::default()` for unused entries.
+    /// Any mint is allowed if there are none.
+    pub allowed_mints: [Pubkey; MAX_CONFIG_MINTS],
+    /// Mints that new swaps may not pair, `Pubkey::default()` for unused
+    /// entries
+    pub denied_mints: [Pubkey; MAX_CONFIG_MINTS],
+}
+
+impl ProgramConfig {
+    /// Find the address of the program config for the given program
+    pub fn find_address(program_id: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[PROGRAM_CONFIG_SEED], program_id)
+    }
+
+    /// Curve types allowed for new swaps
+    pub fn valid_curve_types(&self) -> Vec<CurveType> {
+        (0..32u8)
+            .filter(|bit| self.valid_curve_types & (1 << *bit) != 0)
+            .filter_map(|bit| CurveType::try_from(bit).ok())
+            .collect()
+    }
+
+    /// Mints that new swaps may pair, any mint is allowed if empty
+    pub fn allowed_mints(&self) -> Vec<Pubkey> {
+        Self::used_mints(&self.allowed_mints)
+    }
+
+    /// Mints that new swaps may not pair
+    pub fn denied_mints(&self) -> Vec<Pubkey> {
+        Self::used_mints(&self.denied_mints)
+    }
+
+    fn used_mints(mints: &[Pubkey; MAX_CONFIG_MINTS]) -> Vec<Pubkey> {
+        mints
+            .iter()
+            .filter(|mint| **mint != Pubkey::default())
+            .cloned()
+            .collect()
+    }
+}
+
+impl Sealed for ProgramConfig {}
+impl IsInitialized for ProgramConfig {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for ProgramConfig {
+    const LEN: usize = 726;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 726];
+        let (
+            is_initialized,
+            bump_seed,
+            owner_key,
+            valid_curve_types,
+            min_amp,
+            max_amp,
+            fees,
+            max_fees,
+            allowed_mints,
+            denied_mints,
+        ) = mut_array_refs![output, 1, 1, 32, 4, 8, 8, 80, 80, 256, 256];
+        is_initialized[0] = self.is_initialized as u8;
+        bump_seed[0] = self.bump_seed;
+        owner_key.copy_from_slice(self.owner_key.as_ref());
+        *valid_curve_types = self.valid_curve_types.to_le_bytes();
+        *min_amp = self.min_amp.to_le_bytes();
+        *max_amp = self.max_amp.to_le_bytes();
+        self.fees.pack_into_slice(&mut fees[..]);
+        self.max_fees.pack_into_slice(&mut max_fees[..]);
+        for (dst, mint) in allowed_mints.chunks_mut(32).zip(self.allowed_mints.iter()) {
+            dst.copy_from_slice(mint.as_ref());
+        }
+        for (dst, mint) in denied_mints.chunks_mut(32).zip(self.denied_mints.iter()) {
+            dst.copy_from_slice(mint.as_ref());
+        }
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, 726];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (
+            is_initialized,
+            bump_seed,
+            owner_key,
+            valid_curve_types,
+            min_amp,
+            max_amp,
+            fees,
+            max_fees,
+            allowed_mints_data,
+            denied_mints_data,
+        ) = array_refs![input, 1, 1, 32, 4, 8, 8, 80, 80, 256, 256];
+        let mut allowed_mints = [Pubkey::default(); MAX_CONFIG_MINTS];
+        for (mint, src) in allowed_mints.iter_mut().zip(allowed_mints_data.chunks(32)) {
+            *mint = Pubkey::new_from_array(*array_ref![src, 0, 32]);
+        }
+        let mut denied_mints = [Pubkey::default(); MAX_CONFIG_MINTS];
+        for (mint, src) in denied_mints.iter_mut().zip(denied_mints_data.chunks(32)) {
+            *mint = Pubkey::new_from_array(*array_ref![src, 0, 32]);
+        }
+        Ok(Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            bump_seed: bump_seed[0],
+            owner_key: Pubkey::new_from_array(*owner_key),
+            valid_curve_types: u32::from_le_bytes(*valid_curve_types),
+            min_amp: u64::from_le_bytes(*min_amp),
+            max_amp: u64::from_le_bytes(*max_amp),
+            fees: Fees::unpack_from_slice(fees)?,
+            max_fees: Fees::unpack_from_slice(max_fees)?,
+            allowed_mints,
+            denied_mints,
+        })
+    }
+}
+
+/// Deposit allowlist entry of a user transfer authority for a swap, created
+/// and closed by the pool admin
+#[repr(C)]
+#[derive(Debug, Default, PartialEq)]
+pub struct AllowlistEntry {
+    /// Initialized state
+    pub is_initialized: bool,
+    /// Bump seed used in program address.
+    pub bump_seed: u8,
+    /// Swap that the user may deposit into
+    pub swap: Pubkey,
+    /// User transfer authority allowed to deposit
+    pub user: Pubkey,
+}
+
+impl AllowlistEntry {
+    /// Find the address of the allowlist entry of a user for a swap
+    pub fn find_address(program_id: &Pubkey, swap: &Pubkey, user: &Pubkey) -> (Pubkey, u8) {
+        Pubkey::find_program_address(&[ALLOWLIST_SEED, swap.as_ref(), user.as_ref()], program_id)
+    }
+}
+
+impl Sealed for AllowlistEntry {}
+impl IsInitialized for AllowlistEntry {
+    fn is_initialized(&self) -> bool {
+        self.is_initialized
+    }
+}
+
+impl Pack for AllowlistEntry {
+    const LEN: usize = 66;
+
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 66];
+        let (is_initialized, bump_seed, swap, user) = mut_array_refs![output, 1, 1, 32, 32];
+        is_initialized[0] = self.is_initialized as u8;
+        bump_seed[0] = self.bump_seed;
+        swap.copy_from_slice(self.swap.as_ref());
+        user.copy_from_slice(self.user.as_ref());
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<Self, ProgramError> {
+        let input = array_ref![input, 0, 66];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (is_initialized, bump_seed, swap, user) = array_refs![input, 1, 1, 32, 32];
+        Ok(Self {
+            is_initialized: match is_initialized {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+            bump_seed: bump_seed[0],
+            swap: Pubkey::new_from_array(*swap),
+            user: Pubkey::new_from_array(*user),
         })
     }
 }
@@ -264,6 +1160,15 @@ mod tests {
         owner_withdraw_fee_denominator: 7,
         host_fee_numerator: 5,
         host_fee_denominator: 20,
+        offpeg_fee_multiplier_numerator: 3,
+        offpeg_fee_multiplier_denominator: 2,
+    };
+
+    // SwapV1 does not store the offpeg fee multiplier
+    const TEST_FEES_V1: Fees = Fees {
+        offpeg_fee_multiplier_numerator: 0,
+        offpeg_fee_multiplier_denominator: 0,
+        ..TEST_FEES
     };
 
     const TEST_BUMP_SEED: u8 = 255;
@@ -274,6 +1179,33 @@ mod tests {
     const TEST_TOKEN_A_MINT: Pubkey = Pubkey::new_from_array([5u8; 32]);
     const TEST_TOKEN_B_MINT: Pubkey = Pubkey::new_from_array([6u8; 32]);
     const TEST_POOL_FEE_ACCOUNT: Pubkey = Pubkey::new_from_array([7u8; 32]);
+    const TEST_ADMIN: Pubkey = Pubkey::new_from_array([8u8; 32]);
+    const TEST_ADMIN_FEE_ACCOUNT_A: Pubkey = Pubkey::new_from_array([9u8; 32]);
+    const TEST_ADMIN_FEE_ACCOUNT_B: Pubkey = Pubkey::new_from_array([10u8; 32]);
+    const TEST_ADMIN_FEE_A: u64 = 1_000;
+    const TEST_ADMIN_FEE_B: u64 = 2_000;
+    const TEST_FEE_RECIPIENTS: [Pubkey; MAX_FEE_RECIPIENTS] = [
+        Pubkey::new_from_array([11u8; 32]),
+        Pubkey::new_from_array([12u8; 32]),
+        Pubkey::new_from_array([0u8; 32]),
+        Pubkey::new_from_array([0u8; 32]),
+    ];
+    const TEST_FEE_RECIPIENT_WEIGHTS: [u16; MAX_FEE_RECIPIENTS] = [7_500, 2_500, 0, 0];
+    const TEST_MAX_TOKEN_A_AMOUNT: u64 = 3_000_000;
+    const TEST_MAX_TOKEN_B_AMOUNT: u64 = 4_000_000;
+    const TEST_MAX_POOL_SUPPLY: u64 = 5_000_000;
+    const TEST_PRICE_BAND: u16 = 500;
+    const TEST_REFERENCE_SLOT: u64 = 42;
+    const TEST_REFERENCE_PRICE: u128 = 1_010_000_000_000;
+    const TEST_ORACLE: Pubkey = Pubkey::new_from_array([11u8; 32]);
+    const TEST_MAX_ORACLE_DEVIATION: u16 = 50;
+    const TEST_RESERVE_A: u64 = 6_000_000;
+    const TEST_RESERVE_B: u64 = 7_000_000;
+    const TEST_PRICE_ORACLE: u128 = 2_100_000_000_000_000_000;
+    const TEST_LAST_PRICE: u128 = 2_200_000_000_000_000_000;
+    const TEST_LAST_PRICE_TIMESTAMP: i64 = 1_650_000_000;
+    const TEST_VIRTUAL_PRICE: u128 = 1_010_000_000_000_000_000;
+    const TEST_XCP_PROFIT: u128 = 1_020_000_000_000_000_000;
 
     const TEST_CURVE_TYPE: u8 = 0;
     const TEST_AMP: u64 = 1;
@@ -287,7 +1219,7 @@ mod tests {
             curve_type,
             calculator,
         };
-        let swap_info = SwapVersion::SwapV1(SwapV1 {
+        let swap_info = SwapVersion::SwapV2(SwapV2 {
             is_initialized: true,
             bump_seed: TEST_BUMP_SEED,
             token_program_id: TEST_TOKEN_PROGRAM_ID,
@@ -299,6 +1231,30 @@ mod tests {
             pool_fee_account: TEST_POOL_FEE_ACCOUNT,
             fees: TEST_FEES,
             swap_curve: swap_curve.clone(),
+            admin: TEST_ADMIN,
+            admin_fee_account_a: TEST_ADMIN_FEE_ACCOUNT_A,
+            admin_fee_account_b: TEST_ADMIN_FEE_ACCOUNT_B,
+            admin_fee_a: TEST_ADMIN_FEE_A,
+            admin_fee_b: TEST_ADMIN_FEE_B,
+            fee_recipients: TEST_FEE_RECIPIENTS,
+            fee_recipient_weights: TEST_FEE_RECIPIENT_WEIGHTS,
+            deposit_gate: true,
+            max_token_a_amount: TEST_MAX_TOKEN_A_AMOUNT,
+            max_token_b_amount: TEST_MAX_TOKEN_B_AMOUNT,
+            max_pool_supply: TEST_MAX_POOL_SUPPLY,
+            price_band: TEST_PRICE_BAND,
+            reference_slot: TEST_REFERENCE_SLOT,
+            reference_price: TEST_REFERENCE_PRICE,
+            oracle: TEST_ORACLE,
+            oracle_type: OracleType::PriceConf,
+            max_oracle_deviation: TEST_MAX_ORACLE_DEVIATION,
+            reserve_a: TEST_RESERVE_A,
+            reserve_b: TEST_RESERVE_B,
+            price_oracle: TEST_PRICE_ORACLE,
+            last_price: TEST_LAST_PRICE,
+            last_price_timestamp: TEST_LAST_PRICE_TIMESTAMP,
+            virtual_price: TEST_VIRTUAL_PRICE,
+            xcp_profit: TEST_XCP_PROFIT,
         });
 
         let mut packed = [0u8; SwapVersion::LATEST_LEN];
@@ -316,6 +1272,75 @@ mod tests {
         assert_eq!(*unpacked.pool_fee_account(), TEST_POOL_FEE_ACCOUNT);
         assert_eq!(*unpacked.fees(), TEST_FEES);
         assert_eq!(*unpacked.swap_curve(), swap_curve);
+        assert_eq!(*unpacked.admin(), TEST_ADMIN);
+        assert_eq!(*unpacked.admin_fee_account_a(), TEST_ADMIN_FEE_ACCOUNT_A);
+        assert_eq!(*unpacked.admin_fee_account_b(), TEST_ADMIN_FEE_ACCOUNT_B);
+        assert_eq!(unpacked.admin_fee_a(), TEST_ADMIN_FEE_A);
+        assert_eq!(unpacked.admin_fee_b(), TEST_ADMIN_FEE_B);
+        assert!(unpacked.admin_fees_in_tokens());
+        assert_eq!(*unpacked.fee_recipients(), TEST_FEE_RECIPIENTS);
+        assert_eq!(
+            *unpacked.fee_recipient_weights(),
+            TEST_FEE_RECIPIENT_WEIGHTS
+        );
+        assert!(unpacked.deposit_gate());
+        assert_eq!(unpacked.max_token_a_amount(), TEST_MAX_TOKEN_A_AMOUNT);
+        assert_eq!(unpacked.max_token_b_amount(), TEST_MAX_TOKEN_B_AMOUNT);
+        assert_eq!(unpacked.max_pool_supply(), TEST_MAX_POOL_SUPPLY);
+        assert_eq!(unpacked.price_band(), TEST_PRICE_BAND);
+        assert_eq!(unpacked.reference_slot(), TEST_REFERENCE_SLOT);
+        assert_eq!(unpacked.reference_price(), TEST_REFERENCE_PRICE);
+        assert_eq!(*unpacked.oracle(), TEST_ORACLE);
+        assert_eq!(unpacked.oracle_type(), OracleType::PriceConf);
+        assert_eq!(unpacked.max_oracle_deviation(), TEST_MAX_ORACLE_DEVIATION);
+        assert_eq!(unpacked.reserve_a(), Some(TEST_RESERVE_A));
+        assert_eq!(unpacked.reserve_b(), Some(TEST_RESERVE_B));
+        assert_eq!(unpacked.price_oracle(), TEST_PRICE_ORACLE);
+        assert_eq!(unpacked.last_price(), TEST_LAST_PRICE);
+        assert_eq!(unpacked.last_price_timestamp(), TEST_LAST_PRICE_TIMESTAMP);
+        assert_eq!(unpacked.virtual_price(), TEST_VIRTUAL_PRICE);
+        assert_eq!(unpacked.xcp_profit(), TEST_XCP_PROFIT);
+
+        let latest = SwapVersion::unpack_latest(&packed).unwrap();
+        assert_eq!(latest.admin_fee_a, TEST_ADMIN_FEE_A);
+        assert_eq!(latest.admin_fee_b, TEST_ADMIN_FEE_B);
+    }
+
+    #[test]
+    fn swap_version_unpack_v1() {
+        let curve_type = TEST_CURVE_TYPE.try_into().unwrap();
+        let calculator = Arc::new(TEST_CURVE);
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator,
+        };
+        let swap_info = SwapVersion::SwapV1(SwapV1 {
+            is_initialized: true,
+            bump_seed: TEST_BUMP_SEED,
+            token_program_id: TEST_TOKEN_PROGRAM_ID,
+            token_a: TEST_TOKEN_A,
+            token_b: TEST_TOKEN_B,
+            pool_mint: TEST_POOL_MINT,
+            token_a_mint: TEST_TOKEN_A_MINT,
+            token_b_mint: TEST_TOKEN_B_MINT,
+            pool_fee_account: TEST_POOL_FEE_ACCOUNT,
+            fees: TEST_FEES_V1,
+            swap_curve: swap_curve.clone(),
+        });
+
+        let mut packed = [0u8; 1 + SwapV1::LEN];
+        SwapVersion::pack(swap_info, &mut packed).unwrap();
+        let unpacked = SwapVersion::unpack(&packed).unwrap();
+
+        assert!(unpacked.is_initialized());
+        assert_eq!(*unpacked.pool_mint(), TEST_POOL_MINT);
+        assert_eq!(*unpacked.fees(), TEST_FEES_V1);
+        assert_eq!(*unpacked.swap_curve(), swap_curve);
+        assert_eq!(*unpacked.admin(), Pubkey::default());
+        assert!(!unpacked.admin_fees_in_tokens());
+
+        let err = SwapVersion::unpack_latest(&packed).unwrap_err();
+        assert_eq!(err, SwapError::OutdatedSwapVersion.into());
     }
 
     #[test]
@@ -336,7 +1361,7 @@ mod tests {
             token_a_mint: TEST_TOKEN_A_MINT,
             token_b_mint: TEST_TOKEN_B_MINT,
             pool_fee_account: TEST_POOL_FEE_ACCOUNT,
-            fees: TEST_FEES,
+            fees: TEST_FEES_V1,
             swap_curve,
         };
 
@@ -374,4 +1399,213 @@ mod tests {
         let err = SwapV1::unpack(&packed).unwrap_err();
         assert_eq!(err, ProgramError::UninitializedAccount);
     }
+
+    #[test]
+    fn swap_v2_pack() {
+        let curve_type = TEST_CURVE_TYPE.try_into().unwrap();
+        let calculator = Arc::new(TEST_CURVE);
+        let swap_curve = SwapCurve {
+            curve_type,
+            calculator,
+        };
+        let swap_info = SwapV2 {
+            is_initialized: true,
+            bump_seed: TEST_BUMP_SEED,
+            token_program_id: TEST_TOKEN_PROGRAM_ID,
+            token_a: TEST_TOKEN_A,
+            token_b: TEST_TOKEN_B,
+            pool_mint: TEST_POOL_MINT,
+            token_a_mint: TEST_TOKEN_A_MINT,
+            token_b_mint: TEST_TOKEN_B_MINT,
+            pool_fee_account: TEST_POOL_FEE_ACCOUNT,
+            fees: TEST_FEES,
+            swap_curve,
+            admin: TEST_ADMIN,
+            admin_fee_account_a: TEST_ADMIN_FEE_ACCOUNT_A,
+            admin_fee_account_b: TEST_ADMIN_FEE_ACCOUNT_B,
+            admin_fee_a: TEST_ADMIN_FEE_A,
+            admin_fee_b: TEST_ADMIN_FEE_B,
+            fee_recipients: TEST_FEE_RECIPIENTS,
+            fee_recipient_weights: TEST_FEE_RECIPIENT_WEIGHTS,
+            deposit_gate: true,
+            max_token_a_amount: TEST_MAX_TOKEN_A_AMOUNT,
+            max_token_b_amount: TEST_MAX_TOKEN_B_AMOUNT,
+            max_pool_supply: TEST_MAX_POOL_SUPPLY,
+            price_band: TEST_PRICE_BAND,
+            reference_slot: TEST_REFERENCE_SLOT,
+            reference_price: TEST_REFERENCE_PRICE,
+            oracle: TEST_ORACLE,
+            oracle_type: OracleType::PriceConf,
+            max_oracle_deviation: TEST_MAX_ORACLE_DEVIATION,
+            reserve_a: TEST_RESERVE_A,
+            reserve_b: TEST_RESERVE_B,
+            price_oracle: TEST_PRICE_ORACLE,
+            last_price: TEST_LAST_PRICE,
+            last_price_timestamp: TEST_LAST_PRICE_TIMESTAMP,
+            virtual_price: TEST_VIRTUAL_PRICE,
+            xcp_profit: TEST_XCP_PROFIT,
+        };
+
+        let mut packed = [0u8; SwapV2::LEN];
+        SwapV2::pack_into_slice(&swap_info, &mut packed);
+        let unpacked = SwapV2::unpack(&packed).unwrap();
+        assert_eq!(swap_info, unpacked);
+
+        let mut packed = vec![1u8, TEST_BUMP_SEED];
+        packed.extend_from_slice(&TEST_TOKEN_PROGRAM_ID.to_bytes());
+        packed.extend_from_slice(&TEST_TOKEN_A.to_bytes());
+        packed.extend_from_slice(&TEST_TOKEN_B.to_bytes());
+        packed.extend_from_slice(&TEST_POOL_MINT.to_bytes());
+        packed.extend_from_slice(&TEST_TOKEN_A_MINT.to_bytes());
+        packed.extend_from_slice(&TEST_TOKEN_B_MINT.to_bytes());
+        packed.extend_from_slice(&TEST_POOL_FEE_ACCOUNT.to_bytes());
+        packed.extend_from_slice(&TEST_FEES.trade_fee_numerator.to_le_bytes());
+        packed.extend_from_slice(&TEST_FEES.trade_fee_denominator.to_le_bytes());
+        packed.extend_from_slice(&TEST_FEES.owner_trade_fee_numerator.to_le_bytes());
+        packed.extend_from_slice(&TEST_FEES.owner_trade_fee_denominator.to_le_bytes());
+        packed.extend_from_slice(&TEST_FEES.owner_withdraw_fee_numerator.to_le_bytes());
+        packed.extend_from_slice(&TEST_FEES.owner_withdraw_fee_denominator.to_le_bytes());
+        packed.extend_from_slice(&TEST_FEES.host_fee_numerator.to_le_bytes());
+        packed.extend_from_slice(&TEST_FEES.host_fee_denominator.to_le_bytes());
+        packed.extend_from_slice(&TEST_FEES.offpeg_fee_multiplier_numerator.to_le_bytes());
+        packed.extend_from_slice(&TEST_FEES.offpeg_fee_multiplier_denominator.to_le_bytes());
+        packed.push(TEST_CURVE_TYPE);
+        packed.extend_from_slice(&TEST_AMP.to_le_bytes());
+        packed.extend_from_slice(&[0u8; 24]);
+        packed.extend_from_slice(&TEST_ADMIN.to_bytes());
+        packed.extend_from_slice(&TEST_ADMIN_FEE_ACCOUNT_A.to_bytes());
+        packed.extend_from_slice(&TEST_ADMIN_FEE_ACCOUNT_B.to_bytes());
+        packed.extend_from_slice(&TEST_ADMIN_FEE_A.to_le_bytes());
+        packed.extend_from_slice(&TEST_ADMIN_FEE_B.to_le_bytes());
+        for recipient in TEST_FEE_RECIPIENTS.iter() {
+            packed.extend_from_slice(&recipient.to_bytes());
+        }
+        for weight in TEST_FEE_RECIPIENT_WEIGHTS.iter() {
+            packed.extend_from_slice(&weight.to_le_bytes());
+        }
+        packed.push(1u8);
+        packed.extend_from_slice(&TEST_MAX_TOKEN_A_AMOUNT.to_le_bytes());
+        packed.extend_from_slice(&TEST_MAX_TOKEN_B_AMOUNT.to_le_bytes());
+        packed.extend_from_slice(&TEST_MAX_POOL_SUPPLY.to_le_bytes());
+        packed.extend_from_slice(&TEST_PRICE_BAND.to_le_bytes());
+        packed.extend_from_slice(&TEST_REFERENCE_SLOT.to_le_bytes());
+        packed.extend_from_slice(&TEST_REFERENCE_PRICE.to_le_bytes());
+        packed.extend_from_slice(&TEST_ORACLE.to_bytes());
+        packed.push(OracleType::PriceConf as u8);
+        packed.extend_from_slice(&TEST_MAX_ORACLE_DEVIATION.to_le_bytes());
+        packed.extend_from_slice(&TEST_RESERVE_A.to_le_bytes());
+        packed.extend_from_slice(&TEST_RESERVE_B.to_le_bytes());
+        packed.extend_from_slice(&TEST_PRICE_ORACLE.to_le_bytes());
+        packed.extend_from_slice(&TEST_LAST_PRICE.to_le_bytes());
+        packed.extend_from_slice(&TEST_LAST_PRICE_TIMESTAMP.to_le_bytes());
+        packed.extend_from_slice(&TEST_VIRTUAL_PRICE.to_le_bytes());
+        packed.extend_from_slice(&TEST_XCP_PROFIT.to_le_bytes());
+        let unpacked = SwapV2::unpack(&packed).unwrap();
+        assert_eq!(swap_info, unpacked);
+
+        let packed = [0u8; SwapV2::LEN];
+        let swap_info: SwapV2 = Default::default();
+        let unpack_unchecked = SwapV2::unpack_unchecked(&packed).unwrap();
+        assert_eq!(unpack_unchecked, swap_info);
+        let err = SwapV2::unpack(&packed).unwrap_err();
+        assert_eq!(err, ProgramError::UninitializedAccount);
+    }
+
+    #[test]
+    fn program_config_pack() {
+        let max_fees = Fees {
+            trade_fee_numerator: 3,
+            owner_trade_fee_numerator: 9,
+            owner_withdraw_fee_numerator: 6,
+            host_fee_numerator: 5,
+            offpeg_fee_multiplier_numerator: 6,
+            ..TEST_FEES
+        };
+        let mut allowed_mints = [Pubkey::default(); MAX_CONFIG_MINTS];
+        allowed_mints[0] = TEST_TOKEN_A_MINT;
+        allowed_mints[1] = TEST_TOKEN_B_MINT;
+        let mut denied_mints = [Pubkey::default(); MAX_CONFIG_MINTS];
+        denied_mints[MAX_CONFIG_MINTS - 1] = TEST_POOL_MINT;
+        let config = ProgramConfig {
+            is_initialized: true,
+            bump_seed: TEST_BUMP_SEED,
+            owner_key: TEST_ADMIN,
+            valid_curve_types: 1 << TEST_CURVE_TYPE,
+            min_amp: 1,
+            max_amp: 1_000,
+            fees: TEST_FEES,
+            max_fees: max_fees.clone(),
+            allowed_mints,
+            denied_mints,
+        };
+
+        let mut packed = [0u8; ProgramConfig::LEN];
+        ProgramConfig::pack_into_slice(&config, &mut packed);
+        let unpacked = ProgramConfig::unpack(&packed).unwrap();
+        assert_eq!(config, unpacked);
+        assert_eq!(
+            unpacked.valid_curve_types(),
+            vec![TEST_CURVE_TYPE.try_into().unwrap()]
+        );
+        assert_eq!(
+            unpacked.allowed_mints(),
+            vec![TEST_TOKEN_A_MINT, TEST_TOKEN_B_MINT]
+        );
+        assert_eq!(unpacked.denied_mints(), vec![TEST_POOL_MINT]);
+
+        let mut packed = vec![1u8, TEST_BUMP_SEED];
+        packed.extend_from_slice(&TEST_ADMIN.to_bytes());
+        packed.extend_from_slice(&(1u32 << TEST_CURVE_TYPE).to_le_bytes());
+        packed.extend_from_slice(&1u64.to_le_bytes());
+        packed.extend_from_slice(&1_000u64.to_le_bytes());
+        let mut fees = [0u8; Fees::LEN];
+        TEST_FEES.pack_into_slice(&mut fees);
+        packed.extend_from_slice(&fees);
+        max_fees.pack_into_slice(&mut fees);
+        packed.extend_from_slice(&fees);
+        for mint in allowed_mints.iter().chain(denied_mints.iter()) {
+            packed.extend_from_slice(&mint.to_bytes());
+        }
+        let unpacked = ProgramConfig::unpack(&packed).unwrap();
+        assert_eq!(config, unpacked);
+
+        // unknown curve types are ignored
+        let config = ProgramConfig {
+            valid_curve_types: (1 << TEST_CURVE_TYPE) | (1 << 31),
+            ..ProgramConfig::default()
+        };
+        assert_eq!(
+            config.valid_curve_types(),
+            vec![TEST_CURVE_TYPE.try_into().unwrap()]
+        );
+
+        let packed = [0u8; ProgramConfig::LEN];
+        let err = ProgramConfig::unpack(&packed).unwrap_err();
+        assert_eq!(err, ProgramError::UninitializedAccount);
+    }
+
+    #[test]
+    fn allowlist_entry_pack() {
+        let entry = AllowlistEntry {
+            is_initialized: true,
+            bump_seed: TEST_BUMP_SEED,
+            swap: TEST_TOKEN_A,
+            user: TEST_ADMIN,
+        };
+
+        let mut packed = [0u8; AllowlistEntry::LEN];
+        AllowlistEntry::pack_into_slice(&entry, &mut packed);
+        let unpacked = AllowlistEntry::unpack(&packed).unwrap();
+        assert_eq!(entry, unpacked);
+
+        let mut packed = vec![1u8, TEST_BUMP_SEED];
+        packed.extend_from_slice(&TEST_TOKEN_A.to_bytes());
+        packed.extend_from_slice(&TEST_ADMIN.to_bytes());
+        let unpacked = AllowlistEntry::unpack(&packed).unwrap();
+        assert_eq!(entry, unpacked);
+
+        let packed = [0u8; AllowlistEntry::LEN];
+        let err = AllowlistEntry::unpack(&packed).unwrap_err();
+        assert_eq!(err, ProgramError::UninitializedAccount);
+    }
 }
@@ -7,8 +7,15 @@ use solana_program::{
 
 use crate::swap::{
     calculator::{CurveCalculator, SwapWithoutFeesResult, TradeDirection},
+    constant_price::ConstantPriceCurve,
+    crypto::CryptoCurve,
     fees::Fees,
+    hybrid::HybridCurve,
+    meta::MetaCurve,
+    offset::OffsetCurve,
+    pegged::PeggedCurve,
     stable::StableCurve,
+    weighted::WeightedCurve,
 };
 use arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs};
 use std::convert::{TryFrom, TryInto};
@@ -25,6 +32,30 @@ use arbitrary::Arbitrary;
 pub enum CurveType {
     /// Stable, like uniswap, but with wide zone of 1:1 instead of one point
     Stable,
+    /// Metapool, stable curve pairing a token with the pool token of a base
+    /// swap, valued at the virtual price of the base swap.  Swaps refresh
+    /// the virtual price from the base swap first, and single token
+    /// withdrawals are not supported
+    Meta,
+    /// Crypto, Curve v2 style curve for pairs that are not pegged, with
+    /// liquidity concentrated around a price that repegs to the market price
+    Crypto,
+    /// Weighted, Balancer style weighted product of the balances, for pools
+    /// holding uneven shares of each token such as 80/20 pools
+    Weighted,
+    /// Hybrid, stable curve whose amplification decays toward a constant
+    /// product curve past an imbalance threshold, to protect liquidity
+    /// providers from a depeg
+    Hybrid,
+    /// Pegged, stable curve for a pair trading around a fixed target price
+    /// other than 1:1, such as a token redeemable at 1.05
+    Pegged,
+    /// Constant price, trading at a fixed price without slippage, such as
+    /// for the migration of a token to a new mint
+    ConstantPrice,
+    /// Offset, constant product on a virtual token B balance, such as for the
+    /// launch of a token against no token B
+    Offset,
 }
 
 /// Encodes all results of swapping from a source token to a destination token
@@ -70,6 +101,33 @@ impl SwapCurve {
         // debit the fee to calculate the amount swapped
         let trade_fee = fees.trading_fee(source_amount)?;
         let owner_fee = fees.owner_trading_fee(source_amount)?;
+        let (trade_fee, owner_fee) = if fees.has_offpeg_fee_multiplier() {
+            // estimate the post-trade reserves using the base fees, then
+            // scale the fees by how imbalanced they leave the pool
+            let estimate = self.calculator.swap_without_fees(
+                source_amount.checked_sub(trade_fee.checked_add(owner_fee)?)?,
+                swap_source_amount,
+                swap_destination_amount,
+                trade_direction,
+            )?;
+            let new_swap_source_amount = swap_source_amount.checked_add(source_amount)?;
+            let new_swap_destination_amount =
+                swap_destination_amount.checked_sub(estimate.destination_amount_swapped)?;
+            (
+                fees.offpeg_fee(
+                    trade_fee,
+                    new_swap_source_amount,
+                    new_swap_destination_amount,
+                )?,
+                fees.offpeg_fee(
+                    owner_fee,
+                    new_swap_source_amount,
+                    new_swap_destination_amount,
+                )?,
+            )
+        } else {
+            (trade_fee, owner_fee)
+        };
 
         let total_fees = trade_fee.checked_add(owner_fee)?;
         let source_amount_less_fees = source_amount.checked_sub(total_fees)?;
@@ -105,22 +163,37 @@ impl SwapCurve {
         trade_direction: TradeDirection,
         fees: &Fees,
     ) -> Option<u128> {
-        if source_amount == 0 {
-            return Some(0);
-        }
-        // Get the trading fee incurred if *half* the source amount is swapped
-        // for the other side. Reference at:
-        // https://github.com/balancer-labs/balancer-core/blob/f4ed5d65362a8d6cec21662fb6eae233b0babc1f/contracts/BMath.sol#L117
-        let half_source_amount = std::cmp::max(1, source_amount.checked_div(2)?);
-        let trade_fee = fees.trading_fee(half_source_amount)?;
-        let source_amount = source_amount.checked_sub(trade_fee)?;
-        self.calculator.withdraw_single_token_type_exact_out(
-            source_amount,
-            swap_token_a_amount,
-            swap_token_b_amount,
-            pool_supply,
-            trade_direction,
-        )
+        self.calculator
+            .withdraw_single_token_type_exact_out_with_fees(
+                source_amount,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                trade_direction,
+                fees,
+            )
+    }
+
+    /// Get the amount of token A or B received for burning the given amount of
+    /// pool tokens
+    pub fn withdraw_single_token_type_exact_in(
+        &self,
+        pool_token_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Option<u128> {
+        self.calculator
+            .withdraw_single_token_type_exact_in_with_fees(
+                pool_token_amount,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                trade_direction,
+                fees,
+            )
     }
 }
 
@@ -179,6 +252,15 @@ impl Pack for SwapCurve {
             curve_type,
             calculator: match curve_type {
                 CurveType::Stable => Arc::new(StableCurve::unpack_from_slice(calculator)?),
+                CurveType::Meta => Arc::new(MetaCurve::unpack_from_slice(calculator)?),
+                CurveType::Crypto => Arc::new(CryptoCurve::unpack_from_slice(calculator)?),
+                CurveType::Weighted => Arc::new(WeightedCurve::unpack_from_slice(calculator)?),
+                CurveType::Hybrid => Arc::new(HybridCurve::unpack_from_slice(calculator)?),
+                CurveType::Pegged => Arc::new(PeggedCurve::unpack_from_slice(calculator)?),
+                CurveType::ConstantPrice => {
+                    Arc::new(ConstantPriceCurve::unpack_from_slice(calculator)?)
+                }
+                CurveType::Offset => Arc::new(OffsetCurve::unpack_from_slice(calculator)?),
             },
         })
     }
@@ -205,6 +287,13 @@ impl TryFrom<u8> for CurveType {
     fn try_from(curve_type: u8) -> Result<Self, Self::Error> {
         match curve_type {
             0 => Ok(CurveType::Stable),
+            1 => Ok(CurveType::Meta),
+            2 => Ok(CurveType::Crypto),
+            3 => Ok(CurveType::Weighted),
+            4 => Ok(CurveType::Hybrid),
+            5 => Ok(CurveType::Pegged),
+            6 => Ok(CurveType::ConstantPrice),
+            7 => Ok(CurveType::Offset),
             _ => Err(ProgramError::InvalidAccountData),
         }
     }
@@ -1,6 +1,10 @@
 //! Swap calculations
 
-use {crate::error::SwapError, spl_math::precise_number::PreciseNumber, std::fmt::Debug};
+use {
+    crate::{error::SwapError, swap::fees::Fees},
+    spl_math::precise_number::PreciseNumber,
+    std::fmt::Debug,
+};
 
 #[cfg(feature = "fuzz")]
 use arbitrary::Arbitrary;
@@ -11,6 +15,17 @@ use arbitrary::Arbitrary;
 /// input amounts, and Balancer uses 100 * 10 ^ 18.
 pub const INITIAL_SWAP_POOL_AMOUNT: u128 = 1_000_000_000;
 
+/// Amount of pool tokens permanently locked in a swap authority owned account
+/// on initialization, so that the pool token supply can never be brought back
+/// down to zero.  This removes the share-price inflation and rounding games
+/// available at very low supply, in the same way that Uniswap V2 burns its
+/// first `MINIMUM_LIQUIDITY` shares.
+pub const MINIMUM_LIQUIDITY: u128 = 1_000;
+
+/// Scale of the spot prices returned by the curves, so that a spot price of
+/// 1.0 is `SPOT_PRICE_PRECISION`
+pub const SPOT_PRICE_PRECISION: u128 = 1_000_000_000_000;
+
 /// Hardcode the number of token types in a pool, used to calculate the
 /// equivalent pool tokens for the owner trading fee.
 pub const TOKENS_IN_POOL: u128 = 2;
@@ -95,10 +110,11 @@ pub trait CurveCalculator: Debug + DynPack {
         trade_direction: TradeDirection,
     ) -> Option<SwapWithoutFeesResult>;
 
-    /// Get the supply for a new pool
+    /// Get the supply for a new pool, given the initial amounts of token A
+    /// and B in the pool.
     /// The default implementation is a Balancer-style fixed initial supply
-    fn new_pool_supply(&self) -> u128 {
-        INITIAL_SWAP_POOL_AMOUNT
+    fn new_pool_supply(&self, _token_a_amount: u128, _token_b_amount: u128) -> Option<u128> {
+        Some(INITIAL_SWAP_POOL_AMOUNT)
     }
 
     /// Get the amount of trading tokens for the given amount of pool tokens,
@@ -122,9 +138,97 @@ pub trait CurveCalculator: Debug + DynPack {
         trade_direction: TradeDirection,
     ) -> Option<u128>;
 
+    /// Get the amount of pool tokens for the withdrawn amount of token A or B,
+    /// including the trading fee for the imbalance created by the withdrawal.
+    /// The default implementation charges the trading fee incurred if *half*
+    /// the source amount is swapped for the other side. Reference at:
+    /// https://github.com/balancer-labs/balancer-core/blob/f4ed5d65362a8d6cec21662fb6eae233b0babc1f/contracts/BMath.sol#L117
+    fn withdraw_single_token_type_exact_out_with_fees(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Option<u128> {
+        if source_amount == 0 {
+            return Some(0);
+        }
+        let half_source_amount = std::cmp::max(1, source_amount.checked_div(2)?);
+        let trade_fee = fees.trading_fee(half_source_amount)?;
+        let source_amount = source_amount.checked_sub(trade_fee)?;
+        self.withdraw_single_token_type_exact_out(
+            source_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+        )
+    }
+
+    /// Get the amount of token A or B received for burning the given amount of
+    /// pool tokens, withdrawing a single token type.
+    fn withdraw_single_token_type_exact_in(
+        &self,
+        pool_token_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128>;
+
+    /// Get the amount of token A or B received for burning the given amount of
+    /// pool tokens, less the trading fee for the imbalance created by the
+    /// withdrawal.
+    /// The default implementation charges the fee as
+    /// `withdraw_single_token_type_exact_out_with_fees` does, on the received
+    /// amount.
+    fn withdraw_single_token_type_exact_in_with_fees(
+        &self,
+        pool_token_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Option<u128> {
+        let token_amount = self.withdraw_single_token_type_exact_in(
+            pool_token_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+        )?;
+        if token_amount == 0 {
+            return Some(0);
+        }
+        let half_token_amount = std::cmp::max(1, token_amount.checked_div(2)?);
+        let trade_fee = fees.trading_fee(half_token_amount)?;
+        token_amount.checked_sub(trade_fee)
+    }
+
     /// Validate that the given curve has no invalid parameters
     fn validate(&self) -> Result<(), SwapError>;
 
+    /// Amplification coefficient of the curve, for curves that have one
+    fn amp(&self) -> Option<u64> {
+        None
+    }
+
+    /// Amplification coefficient as stored by the curve, and the factor it
+    /// is scaled by, for curves that have one
+    fn scaled_amp(&self) -> Option<(u64, u64)> {
+        self.amp().map(|amp| (amp, 1))
+    }
+
+    /// Spot price of token A in token B, i.e. the amount of token B received
+    /// per token A for an infinitesimal trade, scaled by
+    /// `SPOT_PRICE_PRECISION`.  Curves that cannot compute it return None.
+    fn spot_price(&self, _swap_token_a_amount: u128, _swap_token_b_amount: u128) -> Option<u128> {
+        None
+    }
+
     /// Validate the given supply on initialization. This is useful for curves
     /// that allow zero supply on one or both sides, since the standard constant
     /// product curve must have a non-zero supply on both sides.
@@ -168,6 +272,7 @@ pub trait CurveCalculator: Debug + DynPack {
 #[cfg(test)]
 pub mod test {
     use super::*;
+    use crate::swap::base::SwapCurve;
     use proptest::prelude::*;
     use spl_math::uint::U256;
 
@@ -259,6 +364,123 @@ pub mod test {
         );
     }
 
+    /// Test function to check the fee for withdrawing token A against
+    /// withdrawing both and swapping one side with the same fees.
+    ///
+    /// Single-sided withdrawals are never a cheaper way to trade, up to the
+    /// truncation allowed by the curve.  They cost at most a fee-free
+    /// withdrawal of the amount grossed up by the trading fee: half the fee
+    /// is charged on each token's deviation from the ideal balance, the
+    /// withdrawn token deviates by at most the withdrawn amount, and the other
+    /// token's deviation is worth no more than that.  As the withdrawn side
+    /// drains, this costs more than the equivalent swap, which charges its fee
+    /// on the abundant side, so there is no tighter bound on the swap itself.
+    #[allow(clippy::too_many_arguments)]
+    pub fn check_withdraw_token_conversion_with_fees(
+        swap_curve: &SwapCurve,
+        pool_token_amount: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+        epsilon_in_basis_points: u128,
+    ) {
+        // withdraw the pool tokens
+        let withdraw_result = swap_curve
+            .calculator
+            .pool_tokens_to_trading_tokens(
+                pool_token_amount,
+                pool_token_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                RoundDirection::Floor,
+            )
+            .unwrap();
+
+        let new_swap_token_a_amount = swap_token_a_amount - withdraw_result.token_a_amount;
+        let new_swap_token_b_amount = swap_token_b_amount - withdraw_result.token_b_amount;
+
+        // swap one side of them, paying the trading fee
+        let source_token_amount = match trade_direction {
+            TradeDirection::AtoB => {
+                let results = swap_curve
+                    .swap(
+                        withdraw_result.token_a_amount,
+                        new_swap_token_a_amount,
+                        new_swap_token_b_amount,
+                        trade_direction,
+                        fees,
+                    )
+                    .unwrap();
+                withdraw_result.token_b_amount + results.destination_amount_swapped
+            }
+            TradeDirection::BtoA => {
+                let results = swap_curve
+                    .swap(
+                        withdraw_result.token_b_amount,
+                        new_swap_token_b_amount,
+                        new_swap_token_a_amount,
+                        trade_direction,
+                        fees,
+                    )
+                    .unwrap();
+                withdraw_result.token_a_amount + results.destination_amount_swapped
+            }
+        };
+
+        // the cost of a fee-free withdrawal of the amount grossed up by the
+        // trading fee, only defined while that amount can be withdrawn
+        let opposite_direction = trade_direction.opposite();
+        let gross_source_token_amount =
+            source_token_amount + fees.trading_fee(source_token_amount).unwrap();
+        let maximum_pool_token_amount = swap_curve.calculator.withdraw_single_token_type_exact_out(
+            gross_source_token_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_token_supply,
+            opposite_direction,
+        );
+
+        // see how many pool tokens it would cost to withdraw one side for the
+        // total amount of tokens, including fees
+        let pool_token_amount_from_single_side_withdraw = match swap_curve
+            .withdraw_single_token_type_exact_out(
+                source_token_amount,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_token_supply,
+                opposite_direction,
+                fees,
+            ) {
+            Some(pool_token_amount_from_single_side_withdraw) => {
+                pool_token_amount_from_single_side_withdraw
+            }
+            None => {
+                // the imbalance fee only exceeds the remaining balance if the
+                // grossed up amount cannot be withdrawn
+                assert_eq!(maximum_pool_token_amount, None);
+                return;
+            }
+        };
+
+        let epsilon = std::cmp::max(1, pool_token_amount * epsilon_in_basis_points / 10000);
+        assert!(
+            pool_token_amount_from_single_side_withdraw + epsilon >= pool_token_amount,
+            "single side withdrawal expected to cost at least {}, actually {}",
+            pool_token_amount - epsilon,
+            pool_token_amount_from_single_side_withdraw
+        );
+        if let Some(maximum_pool_token_amount) = maximum_pool_token_amount {
+            assert!(
+                pool_token_amount_from_single_side_withdraw <= maximum_pool_token_amount + epsilon,
+                "single side withdrawal expected to cost at most {}, actually {}",
+                maximum_pool_token_amount + epsilon,
+                pool_token_amount_from_single_side_withdraw
+            );
+        }
+    }
+
     /// Test function checking that a swap never reduces the overall value of
     /// the pool.
     ///
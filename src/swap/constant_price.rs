@@ -0,0 +1,553 @@
+//! Constant price curve, trading at a fixed price without slippage, such as
+//! for the migration of a token to a new mint at a fixed rate
+
+use {
+    crate::{
+        error::SwapError,
+        swap::calculator::{
+            map_zero_to_none, CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult,
+            TradeDirection, TradingTokenResult, SPOT_PRICE_PRECISION,
+        },
+    },
+    arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs},
+    solana_program::{
+        program_error::ProgramError,
+        program_pack::{IsInitialized, Pack, Sealed},
+    },
+    spl_math::{precise_number::PreciseNumber, uint::U256},
+    std::convert::TryFrom,
+};
+
+/// ConstantPriceCurve struct implementing CurveCalculator
+///
+/// Token B is worth `token_b_price_numerator / token_b_price_denominator` of
+/// token A.  Swaps are filled at exactly that price until the destination
+/// balance is empty, only taking the source tokens needed for a whole amount
+/// of destination tokens.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConstantPriceCurve {
+    /// Numerator of the price of token B in token A
+    pub token_b_price_numerator: u64,
+    /// Denominator of the price of token B in token A
+    pub token_b_price_denominator: u64,
+    /// Whether deposits are allowed after initialization, which migration
+    /// pools may want to prevent
+    pub allow_deposits: bool,
+}
+
+impl ConstantPriceCurve {
+    /// Amount of destination token received per source token, as a fraction
+    fn exchange_rate(&self, trade_direction: TradeDirection) -> (u128, u128) {
+        let numerator = u128::from(self.token_b_price_numerator);
+        let denominator = u128::from(self.token_b_price_denominator);
+        match trade_direction {
+            TradeDirection::AtoB => (denominator, numerator),
+            TradeDirection::BtoA => (numerator, denominator),
+        }
+    }
+
+    /// Value of one token of the given side, in token A scaled by the price
+    /// denominator
+    fn token_value(&self, trade_direction: TradeDirection) -> u128 {
+        match trade_direction {
+            TradeDirection::AtoB => u128::from(self.token_b_price_denominator),
+            TradeDirection::BtoA => u128::from(self.token_b_price_numerator),
+        }
+    }
+
+    /// Value of the balances, in token A scaled by the price denominator
+    fn total_value(&self, swap_token_a_amount: u128, swap_token_b_amount: u128) -> Option<U256> {
+        U256::from(swap_token_a_amount)
+            .checked_mul(self.token_b_price_denominator.into())?
+            .checked_add(
+                U256::from(swap_token_b_amount).checked_mul(self.token_b_price_numerator.into())?,
+            )
+    }
+}
+
+/// Converts an intermediate value back to u128, failing on overflow
+fn to_u128(value: U256) -> Option<u128> {
+    u128::try_from(value).ok()
+}
+
+impl CurveCalculator for ConstantPriceCurve {
+    /// Constant price, rounded down in destination tokens, and the source
+    /// amount rounded up to what those destination tokens cost
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        _swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        let (numerator, denominator) = self.exchange_rate(trade_direction);
+        let destination_amount_swapped = map_zero_to_none(
+            source_amount
+                .checked_mul(numerator)?
+                .checked_div(denominator)?,
+        )?;
+        if destination_amount_swapped > swap_destination_amount {
+            return None;
+        }
+        let cost = destination_amount_swapped.checked_mul(denominator)?;
+        let mut source_amount_swapped = cost.checked_div(numerator)?;
+        if cost.checked_rem(numerator)? > 0 {
+            source_amount_swapped = source_amount_swapped.checked_add(1)?;
+        }
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped,
+            destination_amount_swapped,
+        })
+    }
+
+    fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        round_direction: RoundDirection,
+    ) -> Option<TradingTokenResult> {
+        let mut token_a_amount = pool_tokens
+            .checked_mul(swap_token_a_amount)?
+            .checked_div(pool_token_supply)?;
+        let mut token_b_amount = pool_tokens
+            .checked_mul(swap_token_b_amount)?
+            .checked_div(pool_token_supply)?;
+        if round_direction == RoundDirection::Ceiling {
+            let token_a_remainder = pool_tokens
+                .checked_mul(swap_token_a_amount)?
+                .checked_rem(pool_token_supply)?;
+            if token_a_remainder > 0 && token_a_amount > 0 {
+                token_a_amount += 1;
+            }
+            let token_b_remainder = pool_tokens
+                .checked_mul(swap_token_b_amount)?
+                .checked_rem(pool_token_supply)?;
+            if token_b_remainder > 0 && token_b_amount > 0 {
+                token_b_amount += 1;
+            }
+        }
+        Some(TradingTokenResult {
+            token_a_amount,
+            token_b_amount,
+        })
+    }
+
+    /// Burns pool tokens in proportion to the value withdrawn, rounded up
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        if source_amount == 0 {
+            return Some(0);
+        }
+        let total_value = self.total_value(swap_token_a_amount, swap_token_b_amount)?;
+        let numerator = U256::from(source_amount)
+            .checked_mul(self.token_value(trade_direction).into())?
+            .checked_mul(pool_supply.into())?;
+        let mut pool_token_amount = numerator.checked_div(total_value)?;
+        if !numerator.checked_rem(total_value)?.is_zero() {
+            pool_token_amount = pool_token_amount.checked_add(U256::one())?;
+        }
+        to_u128(pool_token_amount)
+    }
+
+    /// Pays out the value of the pool tokens in a single token, rounded down
+    fn withdraw_single_token_type_exact_in(
+        &self,
+        pool_token_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        if pool_token_amount == 0 {
+            return Some(0);
+        }
+        let value = self
+            .total_value(swap_token_a_amount, swap_token_b_amount)?
+            .checked_mul(pool_token_amount.into())?
+            .checked_div(pool_supply.into())?;
+        to_u128(value.checked_div(self.token_value(trade_direction).into())?)
+    }
+
+    /// Value of the balances in token A
+    fn normalized_value(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        PreciseNumber::new(to_u128(
+            self.total_value(swap_token_a_amount, swap_token_b_amount)?,
+        )?)?
+        .checked_div(&PreciseNumber::new(self.token_b_price_denominator.into())?)
+    }
+
+    fn validate(&self) -> Result<(), SwapError> {
+        if self.token_b_price_numerator == 0 || self.token_b_price_denominator == 0 {
+            return Err(SwapError::InvalidCurve);
+        }
+        Ok(())
+    }
+
+    fn spot_price(&self, _swap_token_a_amount: u128, _swap_token_b_amount: u128) -> Option<u128> {
+        SPOT_PRICE_PRECISION
+            .checked_mul(self.token_b_price_denominator.into())?
+            .checked_div(self.token_b_price_numerator.into())
+    }
+
+    fn allows_deposits(&self) -> bool {
+        self.allow_deposits
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for ConstantPriceCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for ConstantPriceCurve {}
+impl Pack for ConstantPriceCurve {
+    const LEN: usize = 17;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<ConstantPriceCurve, ProgramError> {
+        let input = array_ref![input, 0, 17];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (token_b_price_numerator, token_b_price_denominator, allow_deposits) =
+            array_refs![input, 8, 8, 1];
+        Ok(Self {
+            token_b_price_numerator: u64::from_le_bytes(*token_b_price_numerator),
+            token_b_price_denominator: u64::from_le_bytes(*token_b_price_denominator),
+            allow_deposits: match allow_deposits {
+                [0] => false,
+                [1] => true,
+                _ => return Err(ProgramError::InvalidAccountData),
+            },
+        })
+    }
+}
+
+impl DynPack for ConstantPriceCurve {
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 17];
+        let (token_b_price_numerator, token_b_price_denominator, allow_deposits) =
+            mut_array_refs![output, 8, 8, 1];
+        *token_b_price_numerator = self.token_b_price_numerator.to_le_bytes();
+        *token_b_price_denominator = self.token_b_price_denominator.to_le_bytes();
+        allow_deposits[0] = self.allow_deposits as u8;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swap::calculator::test::{
+        check_curve_value_from_swap, check_pool_value_from_deposit, check_pool_value_from_withdraw,
+        check_withdraw_token_conversion, total_and_intermediate, CONVERSION_BASIS_POINTS_GUARANTEE,
+    };
+    use proptest::prelude::*;
+
+    /// Token B is worth 1.5 of token A
+    fn test_curve() -> ConstantPriceCurve {
+        ConstantPriceCurve {
+            token_b_price_numerator: 3,
+            token_b_price_denominator: 2,
+            allow_deposits: true,
+        }
+    }
+
+    #[test]
+    fn swap_at_constant_price() {
+        let curve = test_curve();
+        let (swap_token_a_amount, swap_token_b_amount) = (1_000_000, 1_000_000);
+
+        // only the token A needed for whole tokens B is taken
+        let result = curve
+            .swap_without_fees(
+                1_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 999);
+        assert_eq!(result.destination_amount_swapped, 666);
+
+        let result = curve
+            .swap_without_fees(
+                101,
+                swap_token_b_amount,
+                swap_token_a_amount,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 101);
+        assert_eq!(result.destination_amount_swapped, 151);
+
+        // no slippage up to the whole destination balance
+        let result = curve
+            .swap_without_fees(
+                1_500_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 1_500_000);
+        assert_eq!(result.destination_amount_swapped, 1_000_000);
+        assert_eq!(
+            curve.swap_without_fees(
+                1_500_002,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                TradeDirection::AtoB,
+            ),
+            None
+        );
+
+        // too small for a whole destination token
+        assert_eq!(
+            curve.swap_without_fees(
+                1,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                TradeDirection::AtoB,
+            ),
+            None
+        );
+
+        assert_eq!(
+            curve.spot_price(swap_token_a_amount, 0),
+            Some(SPOT_PRICE_PRECISION * 2 / 3)
+        );
+    }
+
+    #[test]
+    fn withdraw_single_token_at_constant_price() {
+        let curve = test_curve();
+        // worth 2_500_000 of token A
+        let (swap_token_a_amount, swap_token_b_amount) = (1_000_000, 1_000_000);
+        let pool_supply = 5_000_000;
+        assert_eq!(
+            curve.withdraw_single_token_type_exact_out(
+                100_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                TradeDirection::AtoB,
+            ),
+            Some(200_000)
+        );
+        assert_eq!(
+            curve.withdraw_single_token_type_exact_out(
+                100_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                TradeDirection::BtoA,
+            ),
+            Some(300_000)
+        );
+        assert_eq!(
+            curve.withdraw_single_token_type_exact_in(
+                300_001,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                TradeDirection::BtoA,
+            ),
+            Some(100_000)
+        );
+    }
+
+    #[test]
+    fn configurable_deposits() {
+        assert!(test_curve().allows_deposits());
+        assert!(!ConstantPriceCurve {
+            allow_deposits: false,
+            ..test_curve()
+        }
+        .allows_deposits());
+    }
+
+    #[test]
+    fn validate_price() {
+        assert_eq!(test_curve().validate(), Ok(()));
+        assert_eq!(
+            ConstantPriceCurve {
+                token_b_price_numerator: 0,
+                ..test_curve()
+            }
+            .validate(),
+            Err(SwapError::InvalidCurve)
+        );
+        assert_eq!(
+            ConstantPriceCurve {
+                token_b_price_denominator: 0,
+                ..test_curve()
+            }
+            .validate(),
+            Err(SwapError::InvalidCurve)
+        );
+    }
+
+    #[test]
+    fn pack_curve() {
+        let curve = ConstantPriceCurve {
+            allow_deposits: false,
+            ..test_curve()
+        };
+
+        let mut packed = [0u8; ConstantPriceCurve::LEN];
+        Pack::pack_into_slice(&curve, &mut packed[..]);
+        let unpacked = ConstantPriceCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+
+        let mut packed = vec![];
+        packed.extend_from_slice(&3u64.to_le_bytes());
+        packed.extend_from_slice(&2u64.to_le_bytes());
+        packed.push(0);
+        let unpacked = ConstantPriceCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+
+        packed[16] = 2;
+        assert_eq!(
+            ConstantPriceCurve::unpack(&packed),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    prop_compose! {
+        fn prices()(token_b_price_numerator in 1..1_000u64,
+                    token_b_price_denominator in 1..1_000u64)
+                    -> ConstantPriceCurve {
+            ConstantPriceCurve {
+                token_b_price_numerator,
+                token_b_price_denominator,
+                allow_deposits: true,
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_swap(
+            curve in prices(),
+            source_token_amount in 1..u64::MAX,
+            swap_source_amount in 1..u64::MAX,
+            swap_destination_amount in 1..u64::MAX,
+        ) {
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA].iter() {
+                let (numerator, denominator) = curve.exchange_rate(*trade_direction);
+                let destination_amount = source_token_amount as u128 * numerator / denominator;
+                if destination_amount == 0 || destination_amount > swap_destination_amount as u128 {
+                    continue;
+                }
+                check_curve_value_from_swap(
+                    &curve,
+                    source_token_amount as u128,
+                    swap_source_amount as u128,
+                    swap_destination_amount as u128,
+                    *trade_direction,
+                );
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_deposit(
+            curve in prices(),
+            pool_token_amount in 1..u64::MAX,
+            pool_token_supply in 1..u64::MAX,
+            swap_token_a_amount in 1..u64::MAX,
+            swap_token_b_amount in 1..u64::MAX,
+        ) {
+            let pool_token_amount = pool_token_amount as u128;
+            let pool_token_supply = pool_token_supply as u128;
+            let swap_token_a_amount = swap_token_a_amount as u128;
+            let swap_token_b_amount = swap_token_b_amount as u128;
+            // Make sure we will get at least one trading token out for each
+            // side, otherwise the calculation fails
+            prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 1);
+            prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 1);
+            check_pool_value_from_deposit(
+                &curve,
+                pool_token_amount,
+                pool_token_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_withdraw(
+            curve in prices(),
+            (pool_token_supply, pool_token_amount) in total_and_intermediate(),
+            swap_token_a_amount in 1..u32::MAX,
+            swap_token_b_amount in 1..u32::MAX,
+        ) {
+            let pool_token_amount = pool_token_amount as u128;
+            let pool_token_supply = pool_token_supply as u128;
+            let swap_token_a_amount = swap_token_a_amount as u128;
+            let swap_token_b_amount = swap_token_b_amount as u128;
+            // Make sure we will get at least one trading token out for each
+            // side, otherwise the calculation fails
+            prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 1);
+            prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 1);
+            check_pool_value_from_withdraw(
+                &curve,
+                pool_token_amount,
+                pool_token_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn withdraw_token_conversion(
+            curve in prices(),
+            pool_token_supply in 1_000..1_000_000_000u128,
+            pool_basis_points in 1..1_000u128,
+            swap_token_a_amount in 1_000_000..1_000_000_000_000u128,
+            value_ratio_percent in 50..200u128,
+        ) {
+            let pool_token_amount =
+                std::cmp::max(1, pool_token_supply * pool_basis_points / 10_000);
+            let swap_token_b_amount = swap_token_a_amount
+                * curve.token_b_price_denominator as u128
+                * value_ratio_percent
+                / (curve.token_b_price_numerator as u128 * 100);
+            prop_assume!(swap_token_b_amount >= 1_000_000);
+            prop_assume!(swap_token_b_amount < 1_000_000_000_000);
+            // Withdraw enough of each token for a token B, which may be worth
+            // up to a thousand tokens A, to be small compared to its value
+            prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 10_000);
+            prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 10_000);
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA].iter() {
+                check_withdraw_token_conversion(
+                    &curve,
+                    pool_token_amount,
+                    pool_token_supply,
+                    swap_token_a_amount,
+                    swap_token_b_amount,
+                    *trade_direction,
+                    CONVERSION_BASIS_POINTS_GUARANTEE,
+                );
+            }
+        }
+    }
+}
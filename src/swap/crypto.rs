@@ -0,0 +1,1134 @@
+//! Curve v2 style invariant, concentrating liquidity around a price scale that
+//! repegs to the market price
+
+use {
+    crate::{
+        error::SwapError,
+        swap::calculator::{
+            CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult, TradeDirection,
+            TradingTokenResult, SPOT_PRICE_PRECISION,
+        },
+    },
+    arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs},
+    solana_program::{
+        program_error::ProgramError,
+        program_pack::{IsInitialized, Pack, Sealed},
+    },
+    spl_math::{precise_number::PreciseNumber, uint::U256},
+    std::convert::TryFrom,
+};
+
+const N_COINS: u8 = 2;
+const ITERATIONS: u8 = 255;
+
+/// Scale of the fixed point numbers of the curve, so that 1.0 is `PRECISION`
+pub const PRECISION: u128 = 1_000_000_000_000_000_000;
+
+/// Multiplier of the amplification coefficient, which is stored as
+/// `A * N**N * A_MULTIPLIER` as in the original contracts
+pub const A_MULTIPLIER: u64 = 10_000;
+/// Minimum amplification coefficient, as in the original contracts
+pub const MIN_A: u64 = 4_000;
+/// Maximum amplification coefficient, as in the original contracts
+pub const MAX_A: u64 = 4_000_000_000;
+
+/// Minimum gamma, scaled by `PRECISION`
+pub const MIN_GAMMA: u64 = 10_000_000_000;
+/// Maximum gamma, scaled by `PRECISION`
+pub const MAX_GAMMA: u64 = 20_000_000_000_000_000;
+
+/// Minimum relative move of the price scale on a repeg, scaled by `PRECISION`
+pub const ADJUSTMENT_STEP: u128 = 146_000_000_000_000;
+/// Profit kept by the pool on top of half of its profit before repegging,
+/// scaled by `PRECISION`
+pub const ALLOWED_EXTRA_PROFIT: u128 = 2_000_000_000_000;
+/// Half-life in seconds of the exponential moving average of the price
+pub const MA_HALF_TIME: u128 = 600;
+
+/// Minimum amount of each token of a trade for its price to be used as the
+/// last price, below which the price of the curve is used instead
+pub const MIN_TRADE_PRICE_AMOUNT: u128 = 1_000_000;
+
+/// Token amounts are scaled up before solving the invariant, as the original
+/// contracts scale all tokens to 18 decimals, so that the tolerance of the
+/// Newton methods is small compared to a token unit
+const AMOUNT_MULTIPLIER: u128 = 1_000_000_000;
+
+/// Minimum ratio of the smaller to the larger balance, in value, for which
+/// the invariant is solved, scaled by `PRECISION`
+const MIN_BALANCE_RATIO: u128 = 100_000_000_000_000;
+
+/// Precision of the series expansion of `halfpow`
+const EXP_PRECISION: u128 = 10_000_000_000;
+
+/// Converts an intermediate value back to u128, failing on overflow
+fn to_u128(value: U256) -> Option<u128> {
+    u128::try_from(value).ok()
+}
+
+/// Square root, rounded down, with Newton's method from a power of two above
+/// the root
+fn integer_sqrt(value: U256) -> Option<U256> {
+    if value.is_zero() {
+        return Some(value);
+    }
+    let mut root = U256::one() << value.bits().div_ceil(2);
+    loop {
+        let next = value.checked_div(root)?.checked_add(root)? >> 1;
+        if next >= root {
+            return Some(root);
+        }
+        root = next;
+    }
+}
+
+/// (1 + gamma - K0) or (K0 - 1 - gamma), plus one to never be zero
+fn compute_g1k0(gamma: u64, k0: U256) -> Option<U256> {
+    let g1k0 = U256::from(gamma).checked_add(PRECISION.into())?;
+    if g1k0 > k0 {
+        g1k0.checked_sub(k0)?.checked_add(U256::one())
+    } else {
+        k0.checked_sub(g1k0)?.checked_add(U256::one())
+    }
+}
+
+/// D / (A * N**N) * g1k0**2 / gamma**2, scaled by `PRECISION`
+fn compute_mul1(ann: u64, gamma: u64, d: U256, g1k0: U256) -> Option<U256> {
+    U256::from(PRECISION)
+        .checked_mul(d)?
+        .checked_div(gamma.into())?
+        .checked_mul(g1k0)?
+        .checked_div(gamma.into())?
+        .checked_mul(g1k0)?
+        .checked_mul(A_MULTIPLIER.into())?
+        .checked_div(ann.into())
+}
+
+/// Finds the invariant D of the scaled balances with Newton's method, as
+/// `newton_D` of the original contracts
+fn compute_d(ann: u64, gamma: u64, x0: U256, x1: U256) -> Option<U256> {
+    let (x0, x1) = if x0 < x1 { (x1, x0) } else { (x0, x1) };
+    if x1.checked_mul(PRECISION.into())?.checked_div(x0)? < U256::from(MIN_BALANCE_RATIO) {
+        return None;
+    }
+    let precision = U256::from(PRECISION);
+    let n_coins = U256::from(N_COINS);
+    let sum_x = x0.checked_add(x1)?;
+    let mut d = n_coins.checked_mul(integer_sqrt(x0.checked_mul(x1)?)?)?;
+    for _ in 0..ITERATIONS {
+        let d_previous = d;
+        let k0 = precision
+            .checked_mul(n_coins.checked_mul(n_coins)?)?
+            .checked_mul(x0)?
+            .checked_div(d)?
+            .checked_mul(x1)?
+            .checked_div(d)?;
+        let g1k0 = compute_g1k0(gamma, k0)?;
+        let mul1 = compute_mul1(ann, gamma, d, g1k0)?;
+        // 2 * N * K0 / g1k0
+        let mul2 = precision
+            .checked_mul(U256::from(2u8))?
+            .checked_mul(n_coins)?
+            .checked_mul(k0)?
+            .checked_div(g1k0)?;
+        let neg_fprime = sum_x
+            .checked_add(sum_x.checked_mul(mul2)?.checked_div(precision)?)?
+            .checked_add(mul1.checked_mul(n_coins)?.checked_div(k0)?)?
+            .checked_sub(mul2.checked_mul(d)?.checked_div(precision)?)?;
+        // D -= f / fprime
+        let d_plus = d
+            .checked_mul(neg_fprime.checked_add(sum_x)?)?
+            .checked_div(neg_fprime)?;
+        let mut d_minus = d.checked_mul(d)?.checked_div(neg_fprime)?;
+        let correction = d
+            .checked_mul(mul1.checked_div(neg_fprime)?)?
+            .checked_div(precision)?;
+        if precision > k0 {
+            d_minus = d_minus.checked_add(
+                correction
+                    .checked_mul(precision.checked_sub(k0)?)?
+                    .checked_div(k0)?,
+            )?;
+        } else {
+            d_minus = d_minus.checked_sub(
+                correction
+                    .checked_mul(k0.checked_sub(precision)?)?
+                    .checked_div(k0)?,
+            )?;
+        }
+        d = if d_plus > d_minus {
+            d_plus.checked_sub(d_minus)?
+        } else {
+            d_minus.checked_sub(d_plus)?.checked_div(U256::from(2u8))?
+        };
+        let diff = if d > d_previous {
+            d.checked_sub(d_previous)?
+        } else {
+            d_previous.checked_sub(d)?
+        };
+        if diff.checked_mul(100_000_000_000_000u64.into())?
+            < std::cmp::max(10_000_000_000_000_000u64.into(), d)
+        {
+            return Some(d);
+        }
+    }
+    None
+}
+
+/// Finds the scaled balance of one token given the scaled balance of the
+/// other and the invariant D with Newton's method, as `newton_y` of the
+/// original contracts
+fn compute_y(ann: u64, gamma: u64, x: U256, d: U256) -> Option<U256> {
+    let precision = U256::from(PRECISION);
+    let n_coins = U256::from(N_COINS);
+    let x_fraction = x.checked_mul(precision)?.checked_div(d)?;
+    if x_fraction < U256::from(PRECISION / 100) || x_fraction > U256::from(PRECISION * 100) {
+        return None;
+    }
+    let mut y = d
+        .checked_mul(d)?
+        .checked_div(x.checked_mul(n_coins.checked_mul(n_coins)?)?)?;
+    let k0_i = precision
+        .checked_mul(n_coins)?
+        .checked_mul(x)?
+        .checked_div(d)?;
+    let convergence_limit = std::cmp::max(
+        std::cmp::max(
+            x.checked_div(100_000_000_000_000u64.into())?,
+            d.checked_div(100_000_000_000_000u64.into())?,
+        ),
+        U256::from(100u8),
+    );
+    for _ in 0..ITERATIONS {
+        let y_previous = y;
+        let k0 = k0_i.checked_mul(y)?.checked_mul(n_coins)?.checked_div(d)?;
+        let sum_x = x.checked_add(y)?;
+        let g1k0 = compute_g1k0(gamma, k0)?;
+        let mul1 = compute_mul1(ann, gamma, d, g1k0)?;
+        // 1 + 2 * K0 / g1k0
+        let mul2 = precision.checked_add(
+            precision
+                .checked_mul(U256::from(2u8))?
+                .checked_mul(k0)?
+                .checked_div(g1k0)?,
+        )?;
+        let y_fprime = precision
+            .checked_mul(y)?
+            .checked_add(sum_x.checked_mul(mul2)?)?
+            .checked_add(mul1)?;
+        let d_y_fprime = d.checked_mul(mul2)?;
+        if y_fprime < d_y_fprime {
+            y = y_previous.checked_div(U256::from(2u8))?;
+            continue;
+        }
+        let y_fprime = y_fprime.checked_sub(d_y_fprime)?;
+        let fprime = y_fprime.checked_div(y)?;
+        let mut y_minus = mul1.checked_div(fprime)?;
+        let y_plus = y_fprime
+            .checked_add(precision.checked_mul(d)?)?
+            .checked_div(fprime)?
+            .checked_add(y_minus.checked_mul(precision)?.checked_div(k0)?)?;
+        y_minus = y_minus.checked_add(precision.checked_mul(sum_x)?.checked_div(fprime)?)?;
+        y = if y_plus < y_minus {
+            y_previous.checked_div(U256::from(2u8))?
+        } else {
+            y_plus.checked_sub(y_minus)?
+        };
+        let diff = if y > y_previous {
+            y.checked_sub(y_previous)?
+        } else {
+            y_previous.checked_sub(y)?
+        };
+        if diff
+            < std::cmp::max(
+                convergence_limit,
+                y.checked_div(100_000_000_000_000u64.into())?,
+            )
+        {
+            return Some(y);
+        }
+    }
+    None
+}
+
+/// 0.5 to the power of the given power, both scaled by `PRECISION`, as
+/// `halfpow` of the original contracts
+fn halfpow(power: u128) -> Option<u128> {
+    let int_power = power.checked_div(PRECISION)?;
+    let other_power = power.checked_sub(int_power.checked_mul(PRECISION)?)?;
+    if int_power > 59 {
+        return Some(0);
+    }
+    let result = PRECISION.checked_div(1u128.checked_shl(u32::try_from(int_power).ok()?)?)?;
+    if other_power == 0 {
+        return Some(result);
+    }
+    let x = PRECISION.checked_div(2)?;
+    let mut term = PRECISION;
+    let mut sum = PRECISION;
+    let mut negative = false;
+    for i in 1..u128::from(ITERATIONS) {
+        let k = i.checked_mul(PRECISION)?;
+        let mut c = k.checked_sub(PRECISION)?;
+        if other_power > c {
+            c = other_power.checked_sub(c)?;
+            negative = !negative;
+        } else {
+            c = c.checked_sub(other_power)?;
+        }
+        term = term
+            .checked_mul(c.checked_mul(x)?.checked_div(PRECISION)?)?
+            .checked_div(k)?;
+        sum = if negative {
+            sum.checked_sub(term)?
+        } else {
+            sum.checked_add(term)?
+        };
+        if term < EXP_PRECISION {
+            return result.checked_mul(sum)?.checked_div(PRECISION);
+        }
+    }
+    None
+}
+
+/// Price and profit tracking of a crypto curve pool, persisted in the swap
+/// state between trades to repeg its price scale
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PriceState {
+    /// Exponential moving average of the last price, scaled by `PRECISION`
+    pub price_oracle: u128,
+    /// Price of token B in token A after the last trade or withdrawal,
+    /// scaled by `PRECISION`
+    pub last_price: u128,
+    /// Unix timestamp of the last update of the price oracle
+    pub last_price_timestamp: i64,
+    /// Value of the pool, balanced at the price scale, per pool token,
+    /// scaled by `PRECISION`
+    pub virtual_price: u128,
+    /// Growth of the virtual price from fees, ignoring the losses of repegs,
+    /// scaled by `PRECISION`
+    pub xcp_profit: u128,
+}
+
+impl PriceState {
+    /// Price state of a new pool, with the price oracle at the initial price
+    /// scale of the curve
+    pub fn new(price_scale: u128, timestamp: i64) -> Self {
+        Self {
+            price_oracle: price_scale,
+            last_price: price_scale,
+            last_price_timestamp: timestamp,
+            virtual_price: PRECISION,
+            xcp_profit: PRECISION,
+        }
+    }
+}
+
+/// CryptoCurve struct implementing CurveCalculator
+///
+/// Implements the Curve v2 invariant, which behaves like the stable curve
+/// close to balance and like a constant product curve far from it, with
+/// token B valued at the price scale.  The price scale is moved toward an
+/// exponential moving average of the pool price by `tweak_price` after
+/// trades, as long as the pool made enough profit to afford it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CryptoCurve {
+    /// Amplification coefficient, as `A * N**N * A_MULTIPLIER`
+    pub a: u64,
+    /// Width of the range around the price scale where liquidity is
+    /// concentrated, scaled by `PRECISION`
+    pub gamma: u64,
+    /// Price of token B in token A around which liquidity is concentrated,
+    /// scaled by `PRECISION`
+    pub price_scale: u128,
+}
+
+impl CryptoCurve {
+    /// Price of token B in token A of a trade, scaled by `PRECISION`, if the
+    /// trade is large enough for its price to be precise
+    pub fn trade_price(token_a_amount: u128, token_b_amount: u128) -> Option<u128> {
+        if token_a_amount < MIN_TRADE_PRICE_AMOUNT || token_b_amount < MIN_TRADE_PRICE_AMOUNT {
+            return None;
+        }
+        token_a_amount
+            .checked_mul(PRECISION)?
+            .checked_div(token_b_amount)
+    }
+
+    /// Scaled balances of the pool, with token B valued at the given price
+    fn scaled_amounts(
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        price_scale: u128,
+    ) -> Option<(U256, U256)> {
+        let x0 = U256::from(swap_token_a_amount).checked_mul(AMOUNT_MULTIPLIER.into())?;
+        let x1 = U256::from(swap_token_b_amount)
+            .checked_mul(AMOUNT_MULTIPLIER.into())?
+            .checked_mul(price_scale.into())?
+            .checked_div(PRECISION.into())?;
+        Some((x0, x1))
+    }
+
+    /// Amount of token A for a scaled amount, rounded down
+    fn unscaled_a(x0: U256) -> Option<u128> {
+        to_u128(x0.checked_div(AMOUNT_MULTIPLIER.into())?)
+    }
+
+    /// Amount of token B for a scaled amount, rounded down
+    fn unscaled_b(&self, x1: U256) -> Option<u128> {
+        to_u128(
+            x1.checked_mul(PRECISION.into())?
+                .checked_div(U256::from(AMOUNT_MULTIPLIER).checked_mul(self.price_scale.into())?)?,
+        )
+    }
+
+    fn compute_d(&self, x0: U256, x1: U256) -> Option<U256> {
+        compute_d(self.a, self.gamma, x0, x1)
+    }
+
+    /// Value of the pool balanced at the given price for the invariant D,
+    /// as `get_xcp` of the original contracts
+    fn compute_xcp(d: U256, price_scale: u128) -> Option<U256> {
+        let n_coins = U256::from(N_COINS);
+        let x0 = d.checked_div(n_coins)?;
+        let x1 = d
+            .checked_mul(PRECISION.into())?
+            .checked_div(n_coins.checked_mul(price_scale.into())?)?;
+        integer_sqrt(x0.checked_mul(x1)?)
+    }
+
+    /// Virtual price of the pool tokens for the invariant D at the given
+    /// price, scaled by `PRECISION`
+    fn compute_virtual_price(d: U256, price_scale: u128, pool_supply: u128) -> Option<u128> {
+        to_u128(
+            Self::compute_xcp(d, price_scale)?
+                .checked_mul(PRECISION.into())?
+                .checked_div(U256::from(pool_supply).checked_mul(AMOUNT_MULTIPLIER.into())?)?,
+        )
+    }
+
+    /// Price of token B in token A on the curve, scaled by `PRECISION`, from
+    /// a trade of a millionth of the balance of token A
+    fn compute_price(&self, x0: U256, x1: U256, d: U256) -> Option<U256> {
+        let dx = x0.checked_div(U256::from(1_000_000u64))?;
+        let y = compute_y(self.a, self.gamma, x0.checked_add(dx)?, d)?;
+        U256::from(self.price_scale)
+            .checked_mul(dx)?
+            .checked_div(x1.checked_sub(y)?)
+    }
+
+    /// Updates the price oracle and the virtual price after a trade or a
+    /// withdrawal, then moves the price scale toward the price oracle if
+    /// the pool has made enough profit to afford the loss of the repeg, as
+    /// `tweak_price` of the original contracts.
+    ///
+    /// The price of the trade, if any, is the last price, otherwise the price
+    /// on the curve is used.  Returns the new price scale and price state.
+    #[allow(clippy::too_many_arguments)]
+    pub fn tweak_price(
+        &self,
+        price_state: &PriceState,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_price: Option<u128>,
+        timestamp: i64,
+    ) -> Option<(u128, PriceState)> {
+        let mut price_oracle = price_state.price_oracle;
+        let mut last_price_timestamp = price_state.last_price_timestamp;
+        if last_price_timestamp < timestamp {
+            let elapsed = u128::try_from(timestamp.checked_sub(last_price_timestamp)?).ok()?;
+            let alpha = halfpow(elapsed.checked_mul(PRECISION)?.checked_div(MA_HALF_TIME)?)?;
+            price_oracle = to_u128(
+                U256::from(price_state.last_price)
+                    .checked_mul(PRECISION.checked_sub(alpha)?.into())?
+                    .checked_add(U256::from(price_oracle).checked_mul(alpha.into())?)?
+                    .checked_div(PRECISION.into())?,
+            )?;
+            last_price_timestamp = timestamp;
+        }
+
+        let (x0, x1) =
+            Self::scaled_amounts(swap_token_a_amount, swap_token_b_amount, self.price_scale)?;
+        let d = self.compute_d(x0, x1)?;
+        let last_price = match trade_price {
+            Some(trade_price) => trade_price,
+            None => to_u128(self.compute_price(x0, x1, d)?)?,
+        };
+
+        let virtual_price = Self::compute_virtual_price(d, self.price_scale, pool_supply)?;
+        let xcp_profit = if price_state.virtual_price > 0 {
+            to_u128(
+                U256::from(price_state.xcp_profit)
+                    .checked_mul(virtual_price.into())?
+                    .checked_div(price_state.virtual_price.into())?,
+            )?
+        } else {
+            PRECISION
+        };
+
+        let mut price_scale = self.price_scale;
+        let mut new_virtual_price = virtual_price;
+        // half of the profit goes to the liquidity providers, the other half
+        // can be spent on repegging
+        let threshold = xcp_profit
+            .checked_add(ALLOWED_EXTRA_PROFIT.checked_mul(2)?)?
+            .checked_add(PRECISION)?;
+        if virtual_price.checked_mul(2)? > threshold {
+            let norm = to_u128(
+                U256::from(price_oracle)
+                    .checked_mul(PRECISION.into())?
+                    .checked_div(self.price_scale.into())?,
+            )?;
+            let norm = if norm > PRECISION {
+                norm.checked_sub(PRECISION)?
+            } else {
+                PRECISION.checked_sub(norm)?
+            };
+            let adjustment_step = std::cmp::max(ADJUSTMENT_STEP, norm.checked_div(5)?);
+            if norm > adjustment_step {
+                let new_price_scale = to_u128(
+                    U256::from(self.price_scale)
+                        .checked_mul(norm.checked_sub(adjustment_step)?.into())?
+                        .checked_add(U256::from(adjustment_step).checked_mul(price_oracle.into())?)?
+                        .checked_div(norm.into())?,
+                )?;
+                let x1 = x1
+                    .checked_mul(new_price_scale.into())?
+                    .checked_div(self.price_scale.into())?;
+                let d = self.compute_d(x0, x1)?;
+                let repegged_virtual_price =
+                    Self::compute_virtual_price(d, new_price_scale, pool_supply)?;
+                if repegged_virtual_price > PRECISION
+                    && repegged_virtual_price
+                        .checked_mul(2)?
+                        .checked_sub(PRECISION)?
+                        > xcp_profit
+                {
+                    price_scale = new_price_scale;
+                    new_virtual_price = repegged_virtual_price;
+                }
+            }
+        }
+
+        Some((
+            price_scale,
+            PriceState {
+                price_oracle,
+                last_price,
+                last_price_timestamp,
+                virtual_price: new_virtual_price,
+                xcp_profit,
+            },
+        ))
+    }
+}
+
+impl CurveCalculator for CryptoCurve {
+    /// Curve v2 invariant on the balances valued at the price scale
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        if source_amount == 0 {
+            return Some(SwapWithoutFeesResult {
+                source_amount_swapped: 0,
+                destination_amount_swapped: 0,
+            });
+        }
+        let (swap_token_a_amount, swap_token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (swap_source_amount, swap_destination_amount),
+            TradeDirection::BtoA => (swap_destination_amount, swap_source_amount),
+        };
+        let (x0, x1) =
+            Self::scaled_amounts(swap_token_a_amount, swap_token_b_amount, self.price_scale)?;
+        let d = self.compute_d(x0, x1)?;
+        // one scaled unit is kept by the pool against rounding, as done by
+        // the original contracts
+        let destination_amount_swapped = match trade_direction {
+            TradeDirection::AtoB => {
+                let (dx, _) = Self::scaled_amounts(source_amount, 0, self.price_scale)?;
+                let y = compute_y(self.a, self.gamma, x0.checked_add(dx)?, d)?;
+                self.unscaled_b(x1.checked_sub(y)?.checked_sub(U256::one())?)?
+            }
+            TradeDirection::BtoA => {
+                let (_, dx) = Self::scaled_amounts(0, source_amount, self.price_scale)?;
+                let y = compute_y(self.a, self.gamma, x1.checked_add(dx)?, d)?;
+                Self::unscaled_a(x0.checked_sub(y)?.checked_sub(U256::one())?)?
+            }
+        };
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped: source_amount,
+            destination_amount_swapped,
+        })
+    }
+
+    /// The initial supply is the value of the initial deposit balanced at
+    /// the price scale, so that the virtual price starts at 1.0
+    fn new_pool_supply(&self, token_a_amount: u128, token_b_amount: u128) -> Option<u128> {
+        let (x0, x1) = Self::scaled_amounts(token_a_amount, token_b_amount, self.price_scale)?;
+        let d = self.compute_d(x0, x1)?;
+        to_u128(Self::compute_xcp(d, self.price_scale)?.checked_div(AMOUNT_MULTIPLIER.into())?)
+    }
+
+    /// Proportional to the balances, as `remove_liquidity` of the original
+    /// contracts
+    fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        round_direction: RoundDirection,
+    ) -> Option<TradingTokenResult> {
+        let mut token_a_amount = pool_tokens
+            .checked_mul(swap_token_a_amount)?
+            .checked_div(pool_token_supply)?;
+        let mut token_b_amount = pool_tokens
+            .checked_mul(swap_token_b_amount)?
+            .checked_div(pool_token_supply)?;
+        if round_direction == RoundDirection::Ceiling {
+            let token_a_remainder = pool_tokens
+                .checked_mul(swap_token_a_amount)?
+                .checked_rem(pool_token_supply)?;
+            if token_a_remainder > 0 && token_a_amount > 0 {
+                token_a_amount += 1;
+            }
+            let token_b_remainder = pool_tokens
+                .checked_mul(swap_token_b_amount)?
+                .checked_rem(pool_token_supply)?;
+            if token_b_remainder > 0 && token_b_amount > 0 {
+                token_b_amount += 1;
+            }
+        }
+        Some(TradingTokenResult {
+            token_a_amount,
+            token_b_amount,
+        })
+    }
+
+    /// Burns pool tokens in proportion to the reduction of the invariant,
+    /// rounded up and never below one pool token, since the invariant is
+    /// only found to about one part in 10**18
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        if source_amount == 0 {
+            return Some(0);
+        }
+        let (x0, x1) =
+            Self::scaled_amounts(swap_token_a_amount, swap_token_b_amount, self.price_scale)?;
+        let d0 = self.compute_d(x0, x1)?;
+        let (new_swap_token_a_amount, new_swap_token_b_amount) = match trade_direction {
+            TradeDirection::AtoB => (
+                swap_token_a_amount.checked_sub(source_amount)?,
+                swap_token_b_amount,
+            ),
+            TradeDirection::BtoA => (
+                swap_token_a_amount,
+                swap_token_b_amount.checked_sub(source_amount)?,
+            ),
+        };
+        let (x0, x1) = Self::scaled_amounts(
+            new_swap_token_a_amount,
+            new_swap_token_b_amount,
+            self.price_scale,
+        )?;
+        let d1 = self.compute_d(x0, x1)?;
+        let numerator = d0.saturating_sub(d1).checked_mul(pool_supply.into())?;
+        let mut pool_token_amount = numerator.checked_div(d0)?;
+        if !numerator.checked_rem(d0)?.is_zero() {
+            pool_token_amount = pool_token_amount.checked_add(U256::one())?;
+        }
+        Some(std::cmp::max(to_u128(pool_token_amount)?, 1))
+    }
+
+    /// Solve for the balance of the withdrawn token at the reduced invariant,
+    /// as `calc_withdraw_one_coin` of the original contracts
+    fn withdraw_single_token_type_exact_in(
+        &self,
+        pool_token_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        if pool_token_amount == 0 {
+            return Some(0);
+        }
+        let (x0, x1) =
+            Self::scaled_amounts(swap_token_a_amount, swap_token_b_amount, self.price_scale)?;
+        let d0 = self.compute_d(x0, x1)?;
+        // round the invariant reduction down, in favor of the pool
+        let d1 = d0.checked_sub(
+            U256::from(pool_token_amount)
+                .checked_mul(d0)?
+                .checked_div(pool_supply.into())?,
+        )?;
+        match trade_direction {
+            TradeDirection::AtoB => {
+                let y = compute_y(self.a, self.gamma, x1, d1)?;
+                Self::unscaled_a(x0.saturating_sub(y.checked_add(U256::one())?))
+            }
+            TradeDirection::BtoA => {
+                let y = compute_y(self.a, self.gamma, x0, d1)?;
+                self.unscaled_b(x1.saturating_sub(y.checked_add(U256::one())?))
+            }
+        }
+    }
+
+    fn normalized_value(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        let (x0, x1) =
+            Self::scaled_amounts(swap_token_a_amount, swap_token_b_amount, self.price_scale)?;
+        let d = to_u128(self.compute_d(x0, x1)?)?;
+        PreciseNumber::new(d)?.checked_div(&PreciseNumber::new(AMOUNT_MULTIPLIER)?)
+    }
+
+    fn validate(&self) -> Result<(), SwapError> {
+        if self.a < MIN_A || self.a > MAX_A {
+            return Err(SwapError::InvalidCurve);
+        }
+        if self.gamma < MIN_GAMMA || self.gamma > MAX_GAMMA {
+            return Err(SwapError::InvalidCurve);
+        }
+        if self.price_scale == 0 {
+            return Err(SwapError::InvalidCurve);
+        }
+        Ok(())
+    }
+
+    /// Amplification coefficient `A`, without the `N**N * A_MULTIPLIER`
+    /// factor of the stored value, rounded down
+    fn amp(&self) -> Option<u64> {
+        let (a, factor) = self.scaled_amp()?;
+        Some(a / factor)
+    }
+
+    fn scaled_amp(&self) -> Option<(u64, u64)> {
+        Some((
+            self.a,
+            u64::from(N_COINS).pow(N_COINS.into()) * A_MULTIPLIER,
+        ))
+    }
+
+    /// The price on the curve is the price of token B in token A, so the
+    /// spot price is its inverse
+    fn spot_price(&self, swap_token_a_amount: u128, swap_token_b_amount: u128) -> Option<u128> {
+        if swap_token_a_amount == 0 || swap_token_b_amount == 0 {
+            return None;
+        }
+        let (x0, x1) =
+            Self::scaled_amounts(swap_token_a_amount, swap_token_b_amount, self.price_scale)?;
+        let d = self.compute_d(x0, x1)?;
+        let price = self.compute_price(x0, x1, d)?;
+        to_u128(
+            U256::from(SPOT_PRICE_PRECISION)
+                .checked_mul(PRECISION.into())?
+                .checked_div(price)?,
+        )
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for CryptoCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for CryptoCurve {}
+impl Pack for CryptoCurve {
+    const LEN: usize = 32;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<CryptoCurve, ProgramError> {
+        let input = array_ref![input, 0, 32];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (a, gamma, price_scale) = array_refs![input, 8, 8, 16];
+        Ok(Self {
+            a: u64::from_le_bytes(*a),
+            gamma: u64::from_le_bytes(*gamma),
+            price_scale: u128::from_le_bytes(*price_scale),
+        })
+    }
+}
+
+impl DynPack for CryptoCurve {
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 32];
+        let (a, gamma, price_scale) = mut_array_refs![output, 8, 8, 16];
+        *a = self.a.to_le_bytes();
+        *gamma = self.gamma.to_le_bytes();
+        *price_scale = self.price_scale.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swap::calculator::test::{
+        check_pool_value_from_deposit, check_withdraw_token_conversion,
+        CONVERSION_BASIS_POINTS_GUARANTEE,
+    };
+    use proptest::prelude::*;
+
+    const TEST_A: u64 = 400_000;
+    const TEST_GAMMA: u64 = 145_000_000_000_000;
+
+    fn test_curve(price_scale: u128) -> CryptoCurve {
+        CryptoCurve {
+            a: TEST_A,
+            gamma: TEST_GAMMA,
+            price_scale,
+        }
+    }
+
+    /// Pools balanced and imbalanced in value, as (price scale, token A
+    /// amount, token B amount)
+    fn test_pools() -> Vec<(u128, u128, u128)> {
+        let mut pools = vec![];
+        for price_scale in [PRECISION, 2 * PRECISION, PRECISION / 3].iter() {
+            for (token_a_amount, token_b_value) in [
+                (1_000_000_000, 1_000_000_000),
+                (5_000_000_000_000, 4_000_000_000_000),
+                (123_456_789_000, 100_000_000_000),
+            ]
+            .iter()
+            {
+                let token_b_amount = token_b_value * PRECISION / price_scale;
+                pools.push((*price_scale, *token_a_amount, token_b_amount));
+            }
+        }
+        pools
+    }
+
+    #[test]
+    fn validate_parameters() {
+        assert_eq!(test_curve(PRECISION).validate(), Ok(()));
+        for curve in [
+            CryptoCurve {
+                a: MIN_A - 1,
+                ..test_curve(PRECISION)
+            },
+            CryptoCurve {
+                a: MAX_A + 1,
+                ..test_curve(PRECISION)
+            },
+            CryptoCurve {
+                gamma: MIN_GAMMA - 1,
+                ..test_curve(PRECISION)
+            },
+            CryptoCurve {
+                gamma: MAX_GAMMA + 1,
+                ..test_curve(PRECISION)
+            },
+            test_curve(0),
+        ]
+        .iter()
+        {
+            assert_eq!(curve.validate(), Err(SwapError::InvalidCurve));
+        }
+    }
+
+    #[test]
+    fn halfpow_values() {
+        assert_eq!(halfpow(0), Some(PRECISION));
+        assert_eq!(halfpow(PRECISION), Some(PRECISION / 2));
+        assert_eq!(halfpow(3 * PRECISION), Some(PRECISION / 8));
+        assert_eq!(halfpow(60 * PRECISION), Some(0));
+        // 0.5 ** 0.5
+        let root = halfpow(PRECISION / 2).unwrap();
+        let expected = 707_106_781_186_547_524;
+        assert!(root > expected - EXP_PRECISION);
+        assert!(root < expected + EXP_PRECISION);
+    }
+
+    #[test]
+    fn swap_at_price_scale() {
+        // token B is worth two of token A, and the pool is balanced in value
+        let curve = test_curve(2 * PRECISION);
+        let (token_a_amount, token_b_amount) = (2_000_000_000_000, 1_000_000_000_000);
+        assert_eq!(
+            curve.new_pool_supply(token_a_amount, token_b_amount),
+            Some(1_414_213_562_373)
+        );
+
+        let result = curve
+            .swap_without_fees(
+                1_000_000,
+                token_a_amount,
+                token_b_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 1_000_000);
+        assert_eq!(result.destination_amount_swapped, 499_999);
+
+        let result = curve
+            .swap_without_fees(
+                500_000,
+                token_b_amount,
+                token_a_amount,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 500_000);
+        assert_eq!(result.destination_amount_swapped, 999_999);
+
+        let result = curve
+            .swap_without_fees(0, token_a_amount, token_b_amount, TradeDirection::AtoB)
+            .unwrap();
+        assert_eq!(result.destination_amount_swapped, 0);
+    }
+
+    #[test]
+    fn spot_price_at_price_scale() {
+        let curve = test_curve(2 * PRECISION);
+        let price = curve
+            .spot_price(2_000_000_000_000, 1_000_000_000_000)
+            .unwrap();
+        let expected = SPOT_PRICE_PRECISION / 2;
+        let difference = price.abs_diff(expected);
+        assert!(difference * 1_000_000 < expected);
+        assert_eq!(curve.spot_price(0, 1_000_000_000_000), None);
+        assert_eq!(curve.spot_price(2_000_000_000_000, 0), None);
+    }
+
+    #[test]
+    fn unsafe_balances() {
+        // too far out of balance in value for the invariant to be solved
+        let curve = test_curve(PRECISION);
+        assert_eq!(curve.new_pool_supply(1_000_000_000_000, 1_000), None);
+        assert_eq!(curve.normalized_value(1_000, 1_000_000_000_000), None);
+    }
+
+    #[test]
+    fn curve_value_does_not_decrease_from_swap() {
+        for (price_scale, token_a_amount, token_b_amount) in test_pools() {
+            let curve = test_curve(price_scale);
+            for source_amount in [1, 1_000, 1_000_000, 50_000_000].iter() {
+                for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA].iter() {
+                    let (swap_source_amount, swap_destination_amount) = match trade_direction {
+                        TradeDirection::AtoB => (token_a_amount, token_b_amount),
+                        TradeDirection::BtoA => (token_b_amount, token_a_amount),
+                    };
+                    let result = curve
+                        .swap_without_fees(
+                            *source_amount,
+                            swap_source_amount,
+                            swap_destination_amount,
+                            *trade_direction,
+                        )
+                        .unwrap();
+                    let (new_token_a_amount, new_token_b_amount) = match trade_direction {
+                        TradeDirection::AtoB => (
+                            token_a_amount + result.source_amount_swapped,
+                            token_b_amount - result.destination_amount_swapped,
+                        ),
+                        TradeDirection::BtoA => (
+                            token_a_amount - result.destination_amount_swapped,
+                            token_b_amount + result.source_amount_swapped,
+                        ),
+                    };
+                    let value = curve
+                        .normalized_value(token_a_amount, token_b_amount)
+                        .unwrap();
+                    let new_value = curve
+                        .normalized_value(new_token_a_amount, new_token_b_amount)
+                        .unwrap();
+                    assert!(new_value.greater_than_or_equal(&value));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn withdraw_token_conversion() {
+        for (price_scale, token_a_amount, token_b_amount) in test_pools() {
+            let curve = test_curve(price_scale);
+            let pool_token_supply = curve
+                .new_pool_supply(token_a_amount, token_b_amount)
+                .unwrap();
+            for pool_token_amount in [1_000, 1_000_000, pool_token_supply / 10].iter() {
+                for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA].iter() {
+                    check_withdraw_token_conversion(
+                        &curve,
+                        *pool_token_amount,
+                        pool_token_supply,
+                        token_a_amount,
+                        token_b_amount,
+                        *trade_direction,
+                        CONVERSION_BASIS_POINTS_GUARANTEE,
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn tweak_price_moves_price_oracle() {
+        let price_scale = 2 * PRECISION;
+        let curve = test_curve(price_scale);
+        let (token_a_amount, token_b_amount) = (2_000_000_000_000, 1_000_000_000_000);
+        let pool_supply = curve
+            .new_pool_supply(token_a_amount, token_b_amount)
+            .unwrap();
+        let price_state = PriceState {
+            last_price: 3 * PRECISION,
+            ..PriceState::new(price_scale, 0)
+        };
+
+        // half way to the last price after one half-life
+        let (new_price_scale, new_price_state) = curve
+            .tweak_price(
+                &price_state,
+                token_a_amount,
+                token_b_amount,
+                pool_supply,
+                None,
+                MA_HALF_TIME as i64,
+            )
+            .unwrap();
+        assert_eq!(new_price_scale, price_scale);
+        assert_eq!(new_price_state.price_oracle, 5 * PRECISION / 2);
+        assert_eq!(new_price_state.last_price_timestamp, MA_HALF_TIME as i64);
+        // the balanced pool prices token B at the price scale
+        assert!(new_price_state.last_price > price_scale);
+        assert!(new_price_state.last_price < price_scale + price_scale / 1_000_000);
+
+        // no time passed
+        let (_, new_price_state) = curve
+            .tweak_price(
+                &price_state,
+                token_a_amount,
+                token_b_amount,
+                pool_supply,
+                Some(3 * PRECISION),
+                0,
+            )
+            .unwrap();
+        assert_eq!(new_price_state.price_oracle, price_scale);
+        assert_eq!(new_price_state.last_price, 3 * PRECISION);
+    }
+
+    #[test]
+    fn tweak_price_repegs_with_profit() {
+        let price_scale = 2 * PRECISION;
+        let curve = test_curve(price_scale);
+        let (token_a_amount, token_b_amount) = (2_000_000_000_000, 1_000_000_000_000);
+        let pool_supply = curve
+            .new_pool_supply(token_a_amount, token_b_amount)
+            .unwrap();
+        // the market price is 10% above the price scale
+        let price_oracle = 11 * price_scale / 10;
+        let price_state = PriceState {
+            price_oracle,
+            last_price: price_oracle,
+            ..PriceState::new(price_scale, 0)
+        };
+
+        // no profit to spend on a repeg
+        let (new_price_scale, new_price_state) = curve
+            .tweak_price(
+                &price_state,
+                token_a_amount,
+                token_b_amount,
+                pool_supply,
+                None,
+                0,
+            )
+            .unwrap();
+        assert_eq!(new_price_scale, price_scale);
+        assert_eq!(new_price_state.xcp_profit, new_price_state.virtual_price);
+
+        // the pool is worth 10% more per pool token, repeg by a fifth of the
+        // distance to the price oracle
+        let profitable_pool_supply = pool_supply * 10 / 11;
+        let (new_price_scale, new_price_state) = curve
+            .tweak_price(
+                &price_state,
+                token_a_amount,
+                token_b_amount,
+                profitable_pool_supply,
+                None,
+                0,
+            )
+            .unwrap();
+        assert_eq!(new_price_scale, 2_040_000_000_000_000_000);
+        assert!(new_price_state.virtual_price > PRECISION);
+        assert!(new_price_state.xcp_profit > PRECISION);
+
+        // the profit was already spent on earlier repegs
+        let spent_price_state = PriceState {
+            xcp_profit: 2 * PRECISION,
+            ..price_state
+        };
+        let (new_price_scale, _) = curve
+            .tweak_price(
+                &spent_price_state,
+                token_a_amount,
+                token_b_amount,
+                profitable_pool_supply,
+                None,
+                0,
+            )
+            .unwrap();
+        assert_eq!(new_price_scale, price_scale);
+    }
+
+    #[test]
+    fn pack_curve() {
+        let curve = test_curve(2 * PRECISION);
+
+        let mut packed = [0u8; CryptoCurve::LEN];
+        Pack::pack_into_slice(&curve, &mut packed[..]);
+        let unpacked = CryptoCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+
+        let mut packed = vec![];
+        packed.extend_from_slice(&TEST_A.to_le_bytes());
+        packed.extend_from_slice(&TEST_GAMMA.to_le_bytes());
+        packed.extend_from_slice(&(2 * PRECISION).to_le_bytes());
+        let unpacked = CryptoCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_deposit(
+            pool_token_amount in 1..u64::MAX,
+            pool_token_supply in 1..u64::MAX,
+            swap_token_a_amount in 1..u64::MAX,
+            swap_token_b_amount in 1..u64::MAX,
+        ) {
+            let pool_token_amount = pool_token_amount as u128;
+            let pool_token_supply = pool_token_supply as u128;
+            let swap_token_a_amount = swap_token_a_amount as u128;
+            let swap_token_b_amount = swap_token_b_amount as u128;
+            // Make sure we will get at least one trading token out for each
+            // side, otherwise the calculation fails
+            prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 1);
+            prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 1);
+            check_pool_value_from_deposit(
+                &test_curve(PRECISION),
+                pool_token_amount,
+                pool_token_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+            );
+        }
+    }
+}
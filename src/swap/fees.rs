@@ -6,8 +6,15 @@ use solana_program::{
     program_error::ProgramError,
     program_pack::{IsInitialized, Pack, Sealed},
 };
+use spl_math::uint::U256;
 use std::convert::TryFrom;
 
+/// Precision used when measuring how balanced the pool reserves are
+const BALANCE_PRECISION: u128 = 1_000_000_000_000_000_000;
+
+/// Maximum off-peg fee multiplier, as in Curve
+const MAX_OFFPEG_FEE_MULTIPLIER: u64 = 10;
+
 /// Encapsulates all fee information and calculations for swap operations
 #[derive(Clone, Debug, Default, PartialEq)]
 pub struct Fees {
@@ -20,7 +27,8 @@ pub struct Fees {
 
     /// Owner trading fees are extra token amounts that are held inside the token
     /// accounts during a trade, with the equivalent in pool tokens minted to
-    /// the owner of the program.
+    /// the owner of the program, or tracked as admin fees to be withdrawn in
+    /// tokens if the swap was initialized with admin fee accounts.
     /// Owner trade fee numerator
     pub owner_trade_fee_numerator: u64,
     /// Owner trade fee denominator
@@ -39,6 +47,18 @@ pub struct Fees {
     pub host_fee_numerator: u64,
     /// Host trading fee denominator
     pub host_fee_denominator: u64,
+
+    /// The off-peg fee multiplier scales the trading and owner trading fees
+    /// by how imbalanced the pool is after the trade, as done by Curve's
+    /// `offpeg_fee_multiplier`.  Fees are unchanged for a balanced pool, and
+    /// tend towards the base fees times the multiplier as one side is
+    /// drained.  Disabled when 0 / 0 or when the multiplier is exactly 1, and
+    /// at most 10, with the fully scaled fees below the traded amount.  Only
+    /// supported by the stable curve, which is balanced at 1:1.
+    /// Off-peg fee multiplier numerator
+    pub offpeg_fee_multiplier_numerator: u64,
+    /// Off-peg fee multiplier denominator
+    pub offpeg_fee_multiplier_denominator: u64,
 }
 
 /// Helper function for calculating swap fee
@@ -71,6 +91,19 @@ fn validate_fraction(numerator: u64, denominator: u64) -> Result<(), SwapError>
     }
 }
 
+fn validate_multiplier(numerator: u64, denominator: u64) -> Result<(), SwapError> {
+    if denominator == 0 && numerator == 0 {
+        Ok(())
+    } else if denominator == 0
+        || numerator < denominator
+        || u128::from(numerator) > u128::from(denominator) * u128::from(MAX_OFFPEG_FEE_MULTIPLIER)
+    {
+        Err(SwapError::InvalidFee)
+    } else {
+        Ok(())
+    }
+}
+
 impl Fees {
     /// Calculate the withdraw fee in pool tokens
     pub fn owner_withdraw_fee(&self, pool_tokens: u128) -> Option<u128> {
@@ -109,6 +142,52 @@ impl Fees {
         )
     }
 
+    /// Whether the trading fees depend on the pool balance after the trade
+    pub fn has_offpeg_fee_multiplier(&self) -> bool {
+        self.offpeg_fee_multiplier_numerator > self.offpeg_fee_multiplier_denominator
+    }
+
+    /// Scale a trading fee by the off-peg multiplier, given the pool
+    /// reserves after the trade:
+    ///
+    /// fee * m / ((m - 1) * 4 * a * b / (a + b)^2 + 1)
+    ///
+    /// The scaled fee is never lower than the given fee.
+    pub fn offpeg_fee(
+        &self,
+        fee: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<u128> {
+        if !self.has_offpeg_fee_multiplier() || fee == 0 {
+            return Some(fee);
+        }
+        let multiplier_numerator = U256::from(self.offpeg_fee_multiplier_numerator);
+        let multiplier_denominator = U256::from(self.offpeg_fee_multiplier_denominator);
+
+        // 4 * a * b / (a + b)^2, which is 1 for a balanced pool and tends
+        // to 0 as either side is drained
+        let sum = U256::from(swap_token_a_amount).checked_add(swap_token_b_amount.into())?;
+        let balance = if sum.is_zero() {
+            U256::zero()
+        } else {
+            U256::from(swap_token_a_amount)
+                .checked_mul(swap_token_b_amount.into())?
+                .checked_mul(U256::from(4u8))?
+                .checked_mul(BALANCE_PRECISION.into())?
+                .checked_div(sum.checked_mul(sum)?)?
+        };
+
+        let numerator = U256::from(fee)
+            .checked_mul(multiplier_numerator)?
+            .checked_mul(BALANCE_PRECISION.into())?;
+        let denominator = multiplier_numerator
+            .checked_sub(multiplier_denominator)?
+            .checked_mul(balance)?
+            .checked_add(multiplier_denominator.checked_mul(BALANCE_PRECISION.into())?)?;
+        u128::try_from(numerator.checked_div(denominator)?).ok()
+    }
+
     /// Validate that the fees are reasonable
     pub fn validate(&self) -> Result<(), SwapError> {
         validate_fraction(self.trade_fee_numerator, self.trade_fee_denominator)?;
@@ -121,6 +200,24 @@ impl Fees {
             self.owner_withdraw_fee_denominator,
         )?;
         validate_fraction(self.host_fee_numerator, self.host_fee_denominator)?;
+        validate_multiplier(
+            self.offpeg_fee_multiplier_numerator,
+            self.offpeg_fee_multiplier_denominator,
+        )?;
+        if self.has_offpeg_fee_multiplier() {
+            // fully scaled, the trading fees must stay below the traded amount
+            let trade_fee_denominator = U256::from(self.trade_fee_denominator.max(1));
+            let owner_trade_fee_denominator = U256::from(self.owner_trade_fee_denominator.max(1));
+            let fee_numerator = U256::from(self.trade_fee_numerator) * owner_trade_fee_denominator
+                + U256::from(self.owner_trade_fee_numerator) * trade_fee_denominator;
+            if fee_numerator * U256::from(self.offpeg_fee_multiplier_numerator)
+                >= trade_fee_denominator
+                    * owner_trade_fee_denominator
+                    * U256::from(self.offpeg_fee_multiplier_denominator)
+            {
+                return Err(SwapError::InvalidFee);
+            }
+        }
         Ok(())
     }
 }
@@ -134,9 +231,9 @@ impl IsInitialized for Fees {
 
 impl Sealed for Fees {}
 impl Pack for Fees {
-    const LEN: usize = 64;
+    const LEN: usize = 80;
     fn pack_into_slice(&self, output: &mut [u8]) {
-        let output = array_mut_ref![output, 0, 64];
+        let output = array_mut_ref![output, 0, 80];
         let (
             trade_fee_numerator,
             trade_fee_denominator,
@@ -146,7 +243,9 @@ impl Pack for Fees {
             owner_withdraw_fee_denominator,
             host_fee_numerator,
             host_fee_denominator,
-        ) = mut_array_refs![output, 8, 8, 8, 8, 8, 8, 8, 8];
+            offpeg_fee_multiplier_numerator,
+            offpeg_fee_multiplier_denominator,
+        ) = mut_array_refs![output, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8];
         *trade_fee_numerator = self.trade_fee_numerator.to_le_bytes();
         *trade_fee_denominator = self.trade_fee_denominator.to_le_bytes();
         *owner_trade_fee_numerator = self.owner_trade_fee_numerator.to_le_bytes();
@@ -155,10 +254,12 @@ impl Pack for Fees {
         *owner_withdraw_fee_denominator = self.owner_withdraw_fee_denominator.to_le_bytes();
         *host_fee_numerator = self.host_fee_numerator.to_le_bytes();
         *host_fee_denominator = self.host_fee_denominator.to_le_bytes();
+        *offpeg_fee_multiplier_numerator = self.offpeg_fee_multiplier_numerator.to_le_bytes();
+        *offpeg_fee_multiplier_denominator = self.offpeg_fee_multiplier_denominator.to_le_bytes();
     }
 
     fn unpack_from_slice(input: &[u8]) -> Result<Fees, ProgramError> {
-        let input = array_ref![input, 0, 64];
+        let input = array_ref![input, 0, 80];
         #[allow(clippy::ptr_offset_with_cast)]
         let (
             trade_fee_numerator,
@@ -169,7 +270,9 @@ impl Pack for Fees {
             owner_withdraw_fee_denominator,
             host_fee_numerator,
             host_fee_denominator,
-        ) = array_refs![input, 8, 8, 8, 8, 8, 8, 8, 8];
+            offpeg_fee_multiplier_numerator,
+            offpeg_fee_multiplier_denominator,
+        ) = array_refs![input, 8, 8, 8, 8, 8, 8, 8, 8, 8, 8];
         Ok(Self {
             trade_fee_numerator: u64::from_le_bytes(*trade_fee_numerator),
             trade_fee_denominator: u64::from_le_bytes(*trade_fee_denominator),
@@ -179,6 +282,10 @@ impl Pack for Fees {
             owner_withdraw_fee_denominator: u64::from_le_bytes(*owner_withdraw_fee_denominator),
             host_fee_numerator: u64::from_le_bytes(*host_fee_numerator),
             host_fee_denominator: u64::from_le_bytes(*host_fee_denominator),
+            offpeg_fee_multiplier_numerator: u64::from_le_bytes(*offpeg_fee_multiplier_numerator),
+            offpeg_fee_multiplier_denominator: u64::from_le_bytes(
+                *offpeg_fee_multiplier_denominator,
+            ),
         })
     }
 }
@@ -197,6 +304,8 @@ mod tests {
         let owner_withdraw_fee_denominator = 10;
         let host_fee_numerator = 7;
         let host_fee_denominator = 100;
+        let offpeg_fee_multiplier_numerator = 2;
+        let offpeg_fee_multiplier_denominator = 1;
         let fees = Fees {
             trade_fee_numerator,
             trade_fee_denominator,
@@ -206,6 +315,8 @@ mod tests {
             owner_withdraw_fee_denominator,
             host_fee_numerator,
             host_fee_denominator,
+            offpeg_fee_multiplier_numerator,
+            offpeg_fee_multiplier_denominator,
         };
 
         let mut packed = [0u8; Fees::LEN];
@@ -222,7 +333,76 @@ mod tests {
         packed.extend_from_slice(&owner_withdraw_fee_denominator.to_le_bytes());
         packed.extend_from_slice(&host_fee_numerator.to_le_bytes());
         packed.extend_from_slice(&host_fee_denominator.to_le_bytes());
+        packed.extend_from_slice(&offpeg_fee_multiplier_numerator.to_le_bytes());
+        packed.extend_from_slice(&offpeg_fee_multiplier_denominator.to_le_bytes());
         let unpacked = Fees::unpack_from_slice(&packed).unwrap();
         assert_eq!(fees, unpacked);
     }
+
+    #[test]
+    fn offpeg_fee() {
+        let fees = Fees {
+            offpeg_fee_multiplier_numerator: 5,
+            offpeg_fee_multiplier_denominator: 1,
+            ..Fees::default()
+        };
+        // balanced pool keeps the base fee
+        assert_eq!(fees.offpeg_fee(1_000, 1_000_000, 1_000_000), Some(1_000));
+        // 3:1 pool, 4ab/(a+b)^2 = 0.75, fee * 5 / (4 * 0.75 + 1)
+        assert_eq!(fees.offpeg_fee(1_000, 3_000_000, 1_000_000), Some(1_250));
+        // fully drained side gives the full multiplier
+        assert_eq!(fees.offpeg_fee(1_000, 1_000_000, 0), Some(5_000));
+        // symmetric in the reserves
+        assert_eq!(
+            fees.offpeg_fee(1_000, 1_000_000, 9_000_000),
+            fees.offpeg_fee(1_000, 9_000_000, 1_000_000),
+        );
+        // extreme reserves do not overflow
+        assert_eq!(
+            fees.offpeg_fee(u64::MAX.into(), u64::MAX.into(), u64::MAX.into()),
+            Some(u64::MAX.into()),
+        );
+
+        let disabled = Fees::default();
+        assert_eq!(disabled.offpeg_fee(1_000, 1_000_000, 0), Some(1_000));
+    }
+
+    #[test]
+    fn validate_offpeg_fee_multiplier() {
+        let mut fees = Fees::default();
+        assert_eq!(fees.validate(), Ok(()));
+        fees.offpeg_fee_multiplier_numerator = 1;
+        fees.offpeg_fee_multiplier_denominator = 1;
+        assert_eq!(fees.validate(), Ok(()));
+        assert!(!fees.has_offpeg_fee_multiplier());
+        fees.offpeg_fee_multiplier_numerator = 1;
+        fees.offpeg_fee_multiplier_denominator = 2;
+        assert_eq!(fees.validate(), Err(SwapError::InvalidFee));
+        fees.offpeg_fee_multiplier_numerator = 2;
+        fees.offpeg_fee_multiplier_denominator = 0;
+        assert_eq!(fees.validate(), Err(SwapError::InvalidFee));
+        fees.offpeg_fee_multiplier_denominator = 1;
+        assert_eq!(fees.validate(), Ok(()));
+        assert!(fees.has_offpeg_fee_multiplier());
+
+        // at most ten times the base fees
+        fees.offpeg_fee_multiplier_numerator = 10;
+        assert_eq!(fees.validate(), Ok(()));
+        fees.offpeg_fee_multiplier_numerator = 11;
+        assert_eq!(fees.validate(), Err(SwapError::InvalidFee));
+        fees.offpeg_fee_multiplier_numerator = u64::MAX;
+        assert_eq!(fees.validate(), Err(SwapError::InvalidFee));
+
+        // scaled trading fees must stay below the traded amount
+        fees.offpeg_fee_multiplier_numerator = 10;
+        fees.trade_fee_numerator = 6;
+        fees.trade_fee_denominator = 100;
+        fees.owner_trade_fee_numerator = 3;
+        fees.owner_trade_fee_denominator = 100;
+        assert_eq!(fees.validate(), Ok(()));
+        fees.owner_trade_fee_numerator = 4;
+        assert_eq!(fees.validate(), Err(SwapError::InvalidFee));
+        fees.offpeg_fee_multiplier_numerator = 9;
+        assert_eq!(fees.validate(), Ok(()));
+    }
 }
@@ -0,0 +1,454 @@
+//! Hybrid curve for pegged tokens, with an amplification decaying toward a
+//! constant product curve as the pool gets imbalanced, to protect liquidity
+//! providers when one of the tokens depegs
+
+use {
+    crate::{
+        error::SwapError,
+        swap::{
+            calculator::{
+                CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult, TradeDirection,
+                TradingTokenResult,
+            },
+            crypto::{CryptoCurve, A_MULTIPLIER, MAX_A, PRECISION},
+            stable::MIN_AMP,
+        },
+    },
+    arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs},
+    solana_program::{
+        program_error::ProgramError,
+        program_pack::{IsInitialized, Pack, Sealed},
+    },
+    spl_math::precise_number::PreciseNumber,
+};
+
+const N_COINS: u64 = 2;
+
+/// Maximum amplification coefficient, the largest one of the crypto curve
+pub const MAX_AMP: u64 = MAX_A / (N_COINS * A_MULTIPLIER);
+
+/// Basis points of the imbalance threshold
+pub const IMBALANCE_THRESHOLD_DENOMINATOR: u64 = 10_000;
+/// Minimum imbalance threshold, in basis points
+pub const MIN_IMBALANCE_THRESHOLD: u64 = 1;
+/// Maximum imbalance threshold, in basis points, the largest one whose
+/// square is within the gammas of the crypto curve
+pub const MAX_IMBALANCE_THRESHOLD: u64 = 1_414;
+
+/// Gamma of the crypto curve for a squared basis point of imbalance, scaled
+/// by `PRECISION`, i.e. `PRECISION / IMBALANCE_THRESHOLD_DENOMINATOR**2`
+const GAMMA_PER_SQUARED_BASIS_POINT: u64 = 10_000_000_000;
+
+/// HybridCurve struct implementing CurveCalculator
+///
+/// The curve is the Curve v2 invariant with both tokens valued at par, which
+/// is the stable curve with an effective amplification of
+/// `amp * gamma**2 / (gamma + 1 - K0)**2`, where
+/// `K0 = 4 * x * y / D**2` is close to `1 - imbalance**2` for an imbalance
+/// of `|x - y| / (x + y)`.  Taking gamma as the square of the imbalance
+/// threshold, the curve is the stable curve well below the threshold, its
+/// amplification is down to about a quarter at the threshold, and it tends
+/// to a constant product curve past it.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct HybridCurve {
+    /// Amplifier constant near balance, as for the stable curve
+    pub amp: u64,
+    /// Imbalance of the pool around which the amplification decays, in
+    /// basis points of `|x - y| / (x + y)`
+    pub imbalance_threshold: u64,
+}
+
+impl HybridCurve {
+    fn crypto_curve(&self) -> Option<CryptoCurve> {
+        let threshold_squared = self
+            .imbalance_threshold
+            .checked_mul(self.imbalance_threshold)?;
+        Some(CryptoCurve {
+            // the amplification of the stable curve is `A * n**(n-1)`, and
+            // the one of the crypto curve `A * n**n * A_MULTIPLIER`
+            a: self.amp.checked_mul(N_COINS)?.checked_mul(A_MULTIPLIER)?,
+            gamma: threshold_squared.checked_mul(GAMMA_PER_SQUARED_BASIS_POINT)?,
+            price_scale: PRECISION,
+        })
+    }
+}
+
+impl CurveCalculator for HybridCurve {
+    /// Curve v2 invariant on the balances at par
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        self.crypto_curve()?.swap_without_fees(
+            source_amount,
+            swap_source_amount,
+            swap_destination_amount,
+            trade_direction,
+        )
+    }
+
+    fn new_pool_supply(&self, token_a_amount: u128, token_b_amount: u128) -> Option<u128> {
+        self.crypto_curve()?
+            .new_pool_supply(token_a_amount, token_b_amount)
+    }
+
+    fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        round_direction: RoundDirection,
+    ) -> Option<TradingTokenResult> {
+        self.crypto_curve()?.pool_tokens_to_trading_tokens(
+            pool_tokens,
+            pool_token_supply,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            round_direction,
+        )
+    }
+
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        self.crypto_curve()?.withdraw_single_token_type_exact_out(
+            source_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+        )
+    }
+
+    fn withdraw_single_token_type_exact_in(
+        &self,
+        pool_token_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        self.crypto_curve()?.withdraw_single_token_type_exact_in(
+            pool_token_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+        )
+    }
+
+    fn normalized_value(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        self.crypto_curve()?
+            .normalized_value(swap_token_a_amount, swap_token_b_amount)
+    }
+
+    fn validate(&self) -> Result<(), SwapError> {
+        if self.amp < MIN_AMP || self.amp > MAX_AMP {
+            return Err(SwapError::InvalidCurve);
+        }
+        if self.imbalance_threshold < MIN_IMBALANCE_THRESHOLD
+            || self.imbalance_threshold > MAX_IMBALANCE_THRESHOLD
+        {
+            return Err(SwapError::InvalidCurve);
+        }
+        Ok(())
+    }
+
+    fn amp(&self) -> Option<u64> {
+        Some(self.amp)
+    }
+
+    fn spot_price(&self, swap_token_a_amount: u128, swap_token_b_amount: u128) -> Option<u128> {
+        self.crypto_curve()?
+            .spot_price(swap_token_a_amount, swap_token_b_amount)
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for HybridCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for HybridCurve {}
+impl Pack for HybridCurve {
+    const LEN: usize = 16;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<HybridCurve, ProgramError> {
+        let input = array_ref![input, 0, 16];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (amp, imbalance_threshold) = array_refs![input, 8, 8];
+        Ok(Self {
+            amp: u64::from_le_bytes(*amp),
+            imbalance_threshold: u64::from_le_bytes(*imbalance_threshold),
+        })
+    }
+}
+
+impl DynPack for HybridCurve {
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 16];
+        let (amp, imbalance_threshold) = mut_array_refs![output, 8, 8];
+        *amp = self.amp.to_le_bytes();
+        *imbalance_threshold = self.imbalance_threshold.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swap::{
+        calculator::{
+            test::{
+                check_pool_value_from_deposit, check_withdraw_token_conversion,
+                CONVERSION_BASIS_POINTS_GUARANTEE,
+            },
+            SPOT_PRICE_PRECISION,
+        },
+        stable::StableCurve,
+    };
+    use proptest::prelude::*;
+
+    const TEST_AMP: u64 = 100;
+    const TEST_IMBALANCE_THRESHOLD: u64 = 200;
+
+    fn test_curve() -> HybridCurve {
+        HybridCurve {
+            amp: TEST_AMP,
+            imbalance_threshold: TEST_IMBALANCE_THRESHOLD,
+        }
+    }
+
+    #[test]
+    fn validate_parameters() {
+        assert_eq!(test_curve().validate(), Ok(()));
+        for curve in [
+            HybridCurve {
+                amp: MIN_AMP,
+                imbalance_threshold: MIN_IMBALANCE_THRESHOLD,
+            },
+            HybridCurve {
+                amp: MAX_AMP,
+                imbalance_threshold: MAX_IMBALANCE_THRESHOLD,
+            },
+        ]
+        .iter()
+        {
+            assert_eq!(curve.validate(), Ok(()));
+            // the parameters are within the ones of the crypto curve
+            assert_eq!(curve.crypto_curve().unwrap().validate(), Ok(()));
+        }
+        for curve in [
+            HybridCurve {
+                amp: MIN_AMP - 1,
+                ..test_curve()
+            },
+            HybridCurve {
+                amp: MAX_AMP + 1,
+                ..test_curve()
+            },
+            HybridCurve {
+                imbalance_threshold: MIN_IMBALANCE_THRESHOLD - 1,
+                ..test_curve()
+            },
+            HybridCurve {
+                imbalance_threshold: MAX_IMBALANCE_THRESHOLD + 1,
+                ..test_curve()
+            },
+        ]
+        .iter()
+        {
+            assert_eq!(curve.validate(), Err(SwapError::InvalidCurve));
+        }
+    }
+
+    #[test]
+    fn matches_stable_curve_near_balance() {
+        let curve = test_curve();
+        let stable_curve = StableCurve { amp: TEST_AMP };
+        let swap_token_amount = 1_000_000_000_000;
+        for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA].iter() {
+            let result = curve
+                .swap_without_fees(
+                    1_000_000_000,
+                    swap_token_amount,
+                    swap_token_amount,
+                    *trade_direction,
+                )
+                .unwrap();
+            let stable_result = stable_curve
+                .swap_without_fees(
+                    1_000_000_000,
+                    swap_token_amount,
+                    swap_token_amount,
+                    *trade_direction,
+                )
+                .unwrap();
+            assert_eq!(result.source_amount_swapped, 1_000_000_000);
+            let difference = result
+                .destination_amount_swapped
+                .abs_diff(stable_result.destination_amount_swapped);
+            assert!(difference * 1_000_000 < stable_result.destination_amount_swapped);
+        }
+
+        let price = curve
+            .spot_price(swap_token_amount, swap_token_amount)
+            .unwrap();
+        assert!(price <= SPOT_PRICE_PRECISION);
+        assert!((SPOT_PRICE_PRECISION - price) * 1_000_000 < SPOT_PRICE_PRECISION);
+    }
+
+    #[test]
+    fn decays_toward_constant_product() {
+        // token A depegged, the pool holds three times more of it
+        let (swap_token_a_amount, swap_token_b_amount) = (3_000_000_000_000, 1_000_000_000_000);
+        let constant_product_price = SPOT_PRICE_PRECISION / 3;
+
+        // the stable curve still quotes close to par
+        let stable_price = StableCurve { amp: TEST_AMP }
+            .spot_price(swap_token_a_amount, swap_token_b_amount)
+            .unwrap();
+        assert!(stable_price > SPOT_PRICE_PRECISION * 9 / 10);
+
+        let price = test_curve()
+            .spot_price(swap_token_a_amount, swap_token_b_amount)
+            .unwrap();
+        assert!(price > constant_product_price);
+        assert!(price < constant_product_price + constant_product_price / 20);
+
+        // the amplification decays later with a larger threshold
+        let later_price = HybridCurve {
+            imbalance_threshold: 5 * TEST_IMBALANCE_THRESHOLD,
+            ..test_curve()
+        }
+        .spot_price(swap_token_a_amount, swap_token_b_amount)
+        .unwrap();
+        assert!(later_price > price);
+        assert!(later_price < stable_price);
+    }
+
+    #[test]
+    fn withdraw_token_conversion() {
+        let curve = test_curve();
+        for (swap_token_a_amount, swap_token_b_amount) in [
+            (1_000_000_000, 1_000_000_000),
+            (5_000_000_000_000, 4_000_000_000_000),
+            (3_000_000_000_000, 1_000_000_000_000),
+        ]
+        .iter()
+        {
+            let pool_token_supply = curve
+                .new_pool_supply(*swap_token_a_amount, *swap_token_b_amount)
+                .unwrap();
+            for pool_token_amount in [1_000, 1_000_000, pool_token_supply / 10].iter() {
+                for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA].iter() {
+                    check_withdraw_token_conversion(
+                        &curve,
+                        *pool_token_amount,
+                        pool_token_supply,
+                        *swap_token_a_amount,
+                        *swap_token_b_amount,
+                        *trade_direction,
+                        CONVERSION_BASIS_POINTS_GUARANTEE,
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn pack_curve() {
+        let curve = test_curve();
+
+        let mut packed = [0u8; HybridCurve::LEN];
+        Pack::pack_into_slice(&curve, &mut packed[..]);
+        let unpacked = HybridCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+
+        let mut packed = vec![];
+        packed.extend_from_slice(&TEST_AMP.to_le_bytes());
+        packed.extend_from_slice(&TEST_IMBALANCE_THRESHOLD.to_le_bytes());
+        let unpacked = HybridCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_swap(
+            source_token_amount in 1..1_000_000_000_000u64,
+            swap_source_amount in 1_000_000_000..1_000_000_000_000u64,
+            value_ratio_percent in 25..400u64,
+        ) {
+            // within the balances for which the invariant is solved
+            let swap_destination_amount = swap_source_amount * value_ratio_percent / 100;
+            prop_assume!(source_token_amount <= swap_source_amount);
+            let curve = test_curve();
+            let source_token_amount = source_token_amount as u128;
+            let swap_source_amount = swap_source_amount as u128;
+            let swap_destination_amount = swap_destination_amount as u128;
+            let result = curve
+                .swap_without_fees(
+                    source_token_amount,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    TradeDirection::AtoB,
+                )
+                .unwrap();
+            let value = curve
+                .normalized_value(swap_source_amount, swap_destination_amount)
+                .unwrap();
+            // the scaled unit kept by the pool and the rounding of the amount
+            // out may be worth more than one token of value in an imbalanced
+            // pool, so only check that the value does not decrease
+            let new_value = curve
+                .normalized_value(
+                    swap_source_amount + result.source_amount_swapped,
+                    swap_destination_amount - result.destination_amount_swapped,
+                )
+                .unwrap();
+            prop_assert!(new_value.greater_than_or_equal(&value));
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_deposit(
+            pool_token_amount in 1..u64::MAX,
+            pool_token_supply in 1..u64::MAX,
+            swap_token_a_amount in 1..u64::MAX,
+            swap_token_b_amount in 1..u64::MAX,
+        ) {
+            let pool_token_amount = pool_token_amount as u128;
+            let pool_token_supply = pool_token_supply as u128;
+            let swap_token_a_amount = swap_token_a_amount as u128;
+            let swap_token_b_amount = swap_token_b_amount as u128;
+            // Make sure we will get at least one trading token out for each
+            // side, otherwise the calculation fails
+            prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 1);
+            prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 1);
+            check_pool_value_from_deposit(
+                &test_curve(),
+                pool_token_amount,
+                pool_token_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+            );
+        }
+    }
+}
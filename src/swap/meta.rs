@@ -0,0 +1,459 @@
+//! Metapool curve, pairing a token with the pool token of a base swap
+
+use {
+    crate::{
+        error::SwapError,
+        swap::{
+            calculator::{
+                CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult, TradeDirection,
+                TradingTokenResult,
+            },
+            fees::Fees,
+            stable::StableCurve,
+        },
+    },
+    arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs},
+    solana_program::{
+        program_error::ProgramError,
+        program_pack::{IsInitialized, Pack, Sealed},
+    },
+    spl_math::precise_number::PreciseNumber,
+};
+
+/// Scale of the virtual price of the base swap pool token, so that a virtual
+/// price of 1.0 is `VIRTUAL_PRICE_PRECISION`
+pub const VIRTUAL_PRICE_PRECISION: u64 = 1_000_000_000;
+
+/// MetaCurve struct implementing CurveCalculator
+///
+/// Token B is the pool token of a base swap, valued at the virtual price of
+/// the base swap, the value of its liquidity per pool token.  Amounts of
+/// token B are converted to that value before using the stable curve, as
+/// done by Curve metapools.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct MetaCurve {
+    /// Amplifier constant
+    pub amp: u64,
+    /// Cached virtual price of the base swap pool token, scaled by
+    /// `VIRTUAL_PRICE_PRECISION`
+    pub base_virtual_price: u64,
+}
+
+impl MetaCurve {
+    fn stable_curve(&self) -> StableCurve {
+        StableCurve { amp: self.amp }
+    }
+
+    /// Value of the given amount of base swap pool tokens
+    fn value_of(&self, amount: u128, round_direction: RoundDirection) -> Option<u128> {
+        let numerator = amount.checked_mul(self.base_virtual_price.into())?;
+        let value = numerator.checked_div(VIRTUAL_PRICE_PRECISION.into())?;
+        match round_direction {
+            RoundDirection::Ceiling
+                if numerator.checked_rem(VIRTUAL_PRICE_PRECISION.into())? > 0 =>
+            {
+                value.checked_add(1)
+            }
+            _ => Some(value),
+        }
+    }
+
+    /// Amount of base swap pool tokens worth the given value, rounded down
+    fn amount_worth(&self, value: u128) -> Option<u128> {
+        value
+            .checked_mul(VIRTUAL_PRICE_PRECISION.into())?
+            .checked_div(self.base_virtual_price.into())
+    }
+
+    /// Value of a single token type withdrawal, rounded up in favor of the
+    /// pool
+    fn withdrawn_value(&self, amount: u128, trade_direction: TradeDirection) -> Option<u128> {
+        match trade_direction {
+            TradeDirection::AtoB => Some(amount),
+            TradeDirection::BtoA => self.value_of(amount, RoundDirection::Ceiling),
+        }
+    }
+
+    /// Amount of a single token type withdrawal worth the given value
+    fn withdrawn_amount(&self, value: u128, trade_direction: TradeDirection) -> Option<u128> {
+        match trade_direction {
+            TradeDirection::AtoB => Some(value),
+            TradeDirection::BtoA => self.amount_worth(value),
+        }
+    }
+}
+
+impl CurveCalculator for MetaCurve {
+    /// Stable curve on the value of the base swap pool tokens
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        let destination_amount_swapped = match trade_direction {
+            TradeDirection::AtoB => {
+                let result = self.stable_curve().swap_without_fees(
+                    source_amount,
+                    swap_source_amount,
+                    self.value_of(swap_destination_amount, RoundDirection::Floor)?,
+                    trade_direction,
+                )?;
+                self.amount_worth(result.destination_amount_swapped)?
+            }
+            TradeDirection::BtoA => {
+                let result = self.stable_curve().swap_without_fees(
+                    self.value_of(source_amount, RoundDirection::Floor)?,
+                    self.value_of(swap_source_amount, RoundDirection::Floor)?,
+                    swap_destination_amount,
+                    trade_direction,
+                )?;
+                result.destination_amount_swapped
+            }
+        };
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped: source_amount,
+            destination_amount_swapped,
+        })
+    }
+
+    fn new_pool_supply(&self, token_a_amount: u128, token_b_amount: u128) -> Option<u128> {
+        self.stable_curve().new_pool_supply(
+            token_a_amount,
+            self.value_of(token_b_amount, RoundDirection::Floor)?,
+        )
+    }
+
+    fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        round_direction: RoundDirection,
+    ) -> Option<TradingTokenResult> {
+        self.stable_curve().pool_tokens_to_trading_tokens(
+            pool_tokens,
+            pool_token_supply,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            round_direction,
+        )
+    }
+
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        self.stable_curve().withdraw_single_token_type_exact_out(
+            self.withdrawn_value(source_amount, trade_direction)?,
+            swap_token_a_amount,
+            self.value_of(swap_token_b_amount, RoundDirection::Floor)?,
+            pool_supply,
+            trade_direction,
+        )
+    }
+
+    fn withdraw_single_token_type_exact_out_with_fees(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Option<u128> {
+        self.stable_curve()
+            .withdraw_single_token_type_exact_out_with_fees(
+                self.withdrawn_value(source_amount, trade_direction)?,
+                swap_token_a_amount,
+                self.value_of(swap_token_b_amount, RoundDirection::Floor)?,
+                pool_supply,
+                trade_direction,
+                fees,
+            )
+    }
+
+    fn withdraw_single_token_type_exact_in(
+        &self,
+        pool_token_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        let value = self.stable_curve().withdraw_single_token_type_exact_in(
+            pool_token_amount,
+            swap_token_a_amount,
+            self.value_of(swap_token_b_amount, RoundDirection::Floor)?,
+            pool_supply,
+            trade_direction,
+        )?;
+        self.withdrawn_amount(value, trade_direction)
+    }
+
+    fn withdraw_single_token_type_exact_in_with_fees(
+        &self,
+        pool_token_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Option<u128> {
+        let value = self
+            .stable_curve()
+            .withdraw_single_token_type_exact_in_with_fees(
+                pool_token_amount,
+                swap_token_a_amount,
+                self.value_of(swap_token_b_amount, RoundDirection::Floor)?,
+                pool_supply,
+                trade_direction,
+                fees,
+            )?;
+        self.withdrawn_amount(value, trade_direction)
+    }
+
+    fn normalized_value(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        self.stable_curve().normalized_value(
+            swap_token_a_amount,
+            self.value_of(swap_token_b_amount, RoundDirection::Floor)?,
+        )
+    }
+
+    fn validate(&self) -> Result<(), SwapError> {
+        if self.base_virtual_price == 0 {
+            return Err(SwapError::InvalidCurve);
+        }
+        self.stable_curve().validate()
+    }
+
+    fn amp(&self) -> Option<u64> {
+        Some(self.amp)
+    }
+
+    /// Spot price of token A in base swap pool tokens, from the spot price in
+    /// value of the stable curve
+    fn spot_price(&self, swap_token_a_amount: u128, swap_token_b_amount: u128) -> Option<u128> {
+        let price = self.stable_curve().spot_price(
+            swap_token_a_amount,
+            self.value_of(swap_token_b_amount, RoundDirection::Floor)?,
+        )?;
+        self.amount_worth(price)
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for MetaCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for MetaCurve {}
+impl Pack for MetaCurve {
+    const LEN: usize = 16;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<MetaCurve, ProgramError> {
+        let input = array_ref![input, 0, 16];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (amp, base_virtual_price) = array_refs![input, 8, 8];
+        Ok(Self {
+            amp: u64::from_le_bytes(*amp),
+            base_virtual_price: u64::from_le_bytes(*base_virtual_price),
+        })
+    }
+}
+
+impl DynPack for MetaCurve {
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 16];
+        let (amp, base_virtual_price) = mut_array_refs![output, 8, 8];
+        *amp = self.amp.to_le_bytes();
+        *base_virtual_price = self.base_virtual_price.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swap::calculator::SPOT_PRICE_PRECISION;
+
+    #[test]
+    fn matches_stable_curve_at_par() {
+        let amp = 100;
+        let curve = MetaCurve {
+            amp,
+            base_virtual_price: VIRTUAL_PRICE_PRECISION,
+        };
+        let stable = StableCurve { amp };
+        let (token_a_amount, token_b_amount) = (1_000_000_000, 3_000_000_000);
+        for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA].iter() {
+            let (swap_source_amount, swap_destination_amount) = match trade_direction {
+                TradeDirection::AtoB => (token_a_amount, token_b_amount),
+                TradeDirection::BtoA => (token_b_amount, token_a_amount),
+            };
+            assert_eq!(
+                curve.swap_without_fees(
+                    1_000_000,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    *trade_direction
+                ),
+                stable.swap_without_fees(
+                    1_000_000,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    *trade_direction
+                )
+            );
+            assert_eq!(
+                curve.withdraw_single_token_type_exact_in(
+                    1_000_000,
+                    token_a_amount,
+                    token_b_amount,
+                    4_000_000_000,
+                    *trade_direction
+                ),
+                stable.withdraw_single_token_type_exact_in(
+                    1_000_000,
+                    token_a_amount,
+                    token_b_amount,
+                    4_000_000_000,
+                    *trade_direction
+                )
+            );
+        }
+        assert_eq!(
+            curve.new_pool_supply(token_a_amount, token_b_amount),
+            stable.new_pool_supply(token_a_amount, token_b_amount)
+        );
+    }
+
+    #[test]
+    fn values_base_pool_tokens_at_virtual_price() {
+        let curve = MetaCurve {
+            amp: 100,
+            base_virtual_price: 2 * VIRTUAL_PRICE_PRECISION,
+        };
+        // balanced in value
+        let (token_a_amount, token_b_amount) = (2_000_000_000, 1_000_000_000);
+        assert_eq!(
+            curve.spot_price(token_a_amount, token_b_amount),
+            Some(SPOT_PRICE_PRECISION / 2)
+        );
+        assert_eq!(
+            curve.new_pool_supply(token_a_amount, token_b_amount),
+            StableCurve { amp: 100 }.new_pool_supply(token_a_amount, token_a_amount)
+        );
+
+        let a_to_b = curve
+            .swap_without_fees(
+                1_000_000,
+                token_a_amount,
+                token_b_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert_eq!(a_to_b.source_amount_swapped, 1_000_000);
+        assert!(a_to_b.destination_amount_swapped < 500_000);
+        assert!(a_to_b.destination_amount_swapped > 499_000);
+
+        let b_to_a = curve
+            .swap_without_fees(
+                500_000,
+                token_b_amount,
+                token_a_amount,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        assert_eq!(b_to_a.source_amount_swapped, 500_000);
+        assert!(b_to_a.destination_amount_swapped < 1_000_000);
+        assert!(b_to_a.destination_amount_swapped > 998_000);
+
+        // withdrawing base swap pool tokens round trips through their value
+        let pool_supply = 4_000_000_000;
+        let withdrawn = curve
+            .withdraw_single_token_type_exact_in(
+                1_000_000,
+                token_a_amount,
+                token_b_amount,
+                pool_supply,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        let burned = curve
+            .withdraw_single_token_type_exact_out(
+                withdrawn,
+                token_a_amount,
+                token_b_amount,
+                pool_supply,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        assert!(withdrawn < 500_000);
+        assert!(withdrawn > 499_000);
+        assert!(burned > 999_000);
+        assert!(burned < 1_001_000);
+    }
+
+    #[test]
+    fn validate_virtual_price() {
+        assert_eq!(
+            MetaCurve {
+                amp: 100,
+                base_virtual_price: VIRTUAL_PRICE_PRECISION,
+            }
+            .validate(),
+            Ok(())
+        );
+        assert_eq!(
+            MetaCurve {
+                amp: 100,
+                base_virtual_price: 0,
+            }
+            .validate(),
+            Err(SwapError::InvalidCurve)
+        );
+        assert_eq!(
+            MetaCurve {
+                amp: 0,
+                base_virtual_price: VIRTUAL_PRICE_PRECISION,
+            }
+            .validate(),
+            Err(SwapError::InvalidCurve)
+        );
+    }
+
+    #[test]
+    fn pack_curve() {
+        let amp = 100;
+        let base_virtual_price = 1_050_000_000;
+        let curve = MetaCurve {
+            amp,
+            base_virtual_price,
+        };
+
+        let mut packed = [0u8; MetaCurve::LEN];
+        Pack::pack_into_slice(&curve, &mut packed[..]);
+        let unpacked = MetaCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+
+        let mut packed = vec![];
+        packed.extend_from_slice(&amp.to_le_bytes());
+        packed.extend_from_slice(&base_virtual_price.to_le_bytes());
+        let unpacked = MetaCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+    }
+}
@@ -2,5 +2,12 @@
 
 pub mod base;
 pub mod calculator;
+pub mod constant_price;
+pub mod crypto;
 pub mod fees;
+pub mod hybrid;
+pub mod meta;
+pub mod offset;
+pub mod pegged;
 pub mod stable;
+pub mod weighted;
@@ -0,0 +1,521 @@
+//! Offset curve, a constant product curve on a virtual token B balance, so
+//! that a pool can be created with token A alone, as for a token launch
+
+use {
+    crate::{
+        error::SwapError,
+        swap::calculator::{
+            map_zero_to_none, CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult,
+            TradeDirection, TradingTokenResult, SPOT_PRICE_PRECISION,
+        },
+    },
+    arrayref::{array_mut_ref, array_ref},
+    solana_program::{
+        program_error::ProgramError,
+        program_pack::{IsInitialized, Pack, Sealed},
+    },
+    spl_math::{checked_ceil_div::CheckedCeilDiv, precise_number::PreciseNumber},
+};
+
+/// OffsetCurve struct implementing CurveCalculator
+///
+/// Keeps `A * (B + token_b_offset)` constant, where the offset is token B
+/// that the pool does not hold.  A token can be launched by creating the pool
+/// with its supply as token A and no token B, the offset setting its initial
+/// price.  Swaps never pay out more token B than the pool holds, and deposits
+/// are disabled after initialization, since the creator holding the pool
+/// tokens could otherwise withdraw the deposits of others against the virtual
+/// balance.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct OffsetCurve {
+    /// Amount of token B added to the balance of the pool in all calculations
+    pub token_b_offset: u64,
+}
+
+impl OffsetCurve {
+    /// Balance of token B including the offset
+    fn offset_token_b_amount(&self, swap_token_b_amount: u128) -> Option<u128> {
+        swap_token_b_amount.checked_add(self.token_b_offset.into())
+    }
+}
+
+impl CurveCalculator for OffsetCurve {
+    /// Constant product swap on the offset balances, rounded in favor of the
+    /// pool, failing rather than paying out the offset
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        let (offset_source_amount, offset_destination_amount) = match trade_direction {
+            TradeDirection::AtoB => (
+                swap_source_amount,
+                self.offset_token_b_amount(swap_destination_amount)?,
+            ),
+            TradeDirection::BtoA => (
+                self.offset_token_b_amount(swap_source_amount)?,
+                swap_destination_amount,
+            ),
+        };
+        let invariant = offset_source_amount.checked_mul(offset_destination_amount)?;
+        let new_offset_source_amount = offset_source_amount.checked_add(source_amount)?;
+        let (new_offset_destination_amount, new_offset_source_amount) =
+            invariant.checked_ceil_div(new_offset_source_amount)?;
+        let source_amount_swapped = new_offset_source_amount.checked_sub(offset_source_amount)?;
+        let destination_amount_swapped = map_zero_to_none(
+            offset_destination_amount.checked_sub(new_offset_destination_amount)?,
+        )?;
+        if destination_amount_swapped > swap_destination_amount {
+            return None;
+        }
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped,
+            destination_amount_swapped,
+        })
+    }
+
+    /// Proportional to the offset balances, so a withdrawal may be owed more
+    /// token B than the pool holds, and is then capped to the balance
+    fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        round_direction: RoundDirection,
+    ) -> Option<TradingTokenResult> {
+        let swap_token_b_amount = self.offset_token_b_amount(swap_token_b_amount)?;
+        let mut token_a_amount = pool_tokens
+            .checked_mul(swap_token_a_amount)?
+            .checked_div(pool_token_supply)?;
+        let mut token_b_amount = pool_tokens
+            .checked_mul(swap_token_b_amount)?
+            .checked_div(pool_token_supply)?;
+        if round_direction == RoundDirection::Ceiling {
+            let token_a_remainder = pool_tokens
+                .checked_mul(swap_token_a_amount)?
+                .checked_rem(pool_token_supply)?;
+            if token_a_remainder > 0 && token_a_amount > 0 {
+                token_a_amount += 1;
+            }
+            let token_b_remainder = pool_tokens
+                .checked_mul(swap_token_b_amount)?
+                .checked_rem(pool_token_supply)?;
+            if token_b_remainder > 0 && token_b_amount > 0 {
+                token_b_amount += 1;
+            }
+        }
+        Some(TradingTokenResult {
+            token_a_amount,
+            token_b_amount,
+        })
+    }
+
+    /// Pool tokens burned for a single token withdrawal on the offset
+    /// balances, `supply * (1 - sqrt(1 - dx / x))`, rounded up
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        if source_amount == 0 {
+            return Some(0);
+        }
+        let withdraw_token_amount = match trade_direction {
+            TradeDirection::AtoB => swap_token_a_amount,
+            TradeDirection::BtoA => {
+                if source_amount > swap_token_b_amount {
+                    return None;
+                }
+                self.offset_token_b_amount(swap_token_b_amount)?
+            }
+        };
+
+        let one = PreciseNumber::new(1)?;
+        let ratio = PreciseNumber::new(source_amount)?
+            .checked_div(&PreciseNumber::new(withdraw_token_amount)?)?;
+        let root = one.checked_sub(&one.checked_sub(&ratio)?.sqrt()?)?;
+        PreciseNumber::new(pool_supply)?
+            .checked_mul(&root)?
+            .ceiling()?
+            .to_imprecise()
+    }
+
+    /// Tokens received for burning pool tokens on the offset balances,
+    /// `x * (1 - ((supply - p) / supply) ^ 2)`, rounded down, failing rather
+    /// than paying out the offset
+    fn withdraw_single_token_type_exact_in(
+        &self,
+        pool_token_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        if pool_token_amount == 0 {
+            return Some(0);
+        }
+        let (withdraw_token_amount, max_token_amount) = match trade_direction {
+            TradeDirection::AtoB => (swap_token_a_amount, swap_token_a_amount),
+            TradeDirection::BtoA => (
+                self.offset_token_b_amount(swap_token_b_amount)?,
+                swap_token_b_amount,
+            ),
+        };
+
+        let one = PreciseNumber::new(1)?;
+        let base = PreciseNumber::new(pool_supply.checked_sub(pool_token_amount)?)?
+            .checked_div(&PreciseNumber::new(pool_supply)?)?;
+        let token_amount = PreciseNumber::new(withdraw_token_amount)?
+            .checked_mul(&one.checked_sub(&base.checked_pow(2)?)?)?
+            .floor()?
+            .to_imprecise()?;
+        Some(token_amount).filter(|amount| *amount <= max_token_amount)
+    }
+
+    /// The geometric mean of the offset balances, `sqrt(A * (B + offset))`
+    fn normalized_value(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        PreciseNumber::new(swap_token_a_amount)?
+            .checked_mul(&PreciseNumber::new(
+                self.offset_token_b_amount(swap_token_b_amount)?,
+            )?)?
+            .sqrt()
+    }
+
+    fn validate(&self) -> Result<(), SwapError> {
+        if self.token_b_offset == 0 {
+            return Err(SwapError::InvalidCurve);
+        }
+        Ok(())
+    }
+
+    /// Only token A needs a supply, the offset standing in for token B
+    fn validate_supply(&self, token_a_amount: u64, _token_b_amount: u64) -> Result<(), SwapError> {
+        if token_a_amount == 0 {
+            return Err(SwapError::EmptySupply);
+        }
+        Ok(())
+    }
+
+    /// The creator would own the deposits of others against the virtual
+    /// token B balance, so the pool only trades and withdraws
+    fn allows_deposits(&self) -> bool {
+        false
+    }
+
+    /// Ratio of the offset balances
+    fn spot_price(&self, swap_token_a_amount: u128, swap_token_b_amount: u128) -> Option<u128> {
+        if swap_token_a_amount == 0 {
+            return None;
+        }
+        self.offset_token_b_amount(swap_token_b_amount)?
+            .checked_mul(SPOT_PRICE_PRECISION)?
+            .checked_div(swap_token_a_amount)
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for OffsetCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for OffsetCurve {}
+impl Pack for OffsetCurve {
+    const LEN: usize = 8;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<OffsetCurve, ProgramError> {
+        let token_b_offset = array_ref![input, 0, 8];
+        Ok(Self {
+            token_b_offset: u64::from_le_bytes(*token_b_offset),
+        })
+    }
+}
+
+impl DynPack for OffsetCurve {
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let token_b_offset = array_mut_ref![output, 0, 8];
+        *token_b_offset = self.token_b_offset.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swap::calculator::test::{
+        check_curve_value_from_swap, check_pool_value_from_withdraw,
+        check_withdraw_token_conversion, CONVERSION_BASIS_POINTS_GUARANTEE,
+    };
+    use proptest::prelude::*;
+
+    /// A launch of 1 billion tokens A against a virtual million tokens B
+    const TEST_CURVE: OffsetCurve = OffsetCurve {
+        token_b_offset: 1_000_000,
+    };
+    const LAUNCH_AMOUNT: u128 = 1_000_000_000;
+
+    #[test]
+    fn swap_against_offset() {
+        assert_eq!(
+            TEST_CURVE.spot_price(LAUNCH_AMOUNT, 0),
+            Some(SPOT_PRICE_PRECISION / 1_000)
+        );
+
+        // token A can be bought without any token B in the pool
+        let result = TEST_CURVE
+            .swap_without_fees(1_000, 0, LAUNCH_AMOUNT, TradeDirection::BtoA)
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 1_000);
+        assert_eq!(result.destination_amount_swapped, 999_000);
+
+        // but only the token B swapped in can be bought back
+        let swap_token_a_amount = LAUNCH_AMOUNT - 999_000;
+        let swap_token_b_amount = 1_000;
+        let result = TEST_CURVE
+            .swap_without_fees(
+                999_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 998_002);
+        assert_eq!(result.destination_amount_swapped, 999);
+        assert_eq!(
+            TEST_CURVE.swap_without_fees(
+                1_999_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                TradeDirection::AtoB,
+            ),
+            None
+        );
+        assert_eq!(
+            TEST_CURVE.swap_without_fees(1, LAUNCH_AMOUNT, 0, TradeDirection::AtoB),
+            None
+        );
+    }
+
+    #[test]
+    fn withdraw_against_offset() {
+        let pool_supply = 1_000_000_000;
+        let result = TEST_CURVE
+            .pool_tokens_to_trading_tokens(
+                pool_supply / 10,
+                pool_supply,
+                LAUNCH_AMOUNT,
+                0,
+                RoundDirection::Floor,
+            )
+            .unwrap();
+        assert_eq!(result.token_a_amount, 100_000_000);
+        assert_eq!(result.token_b_amount, 100_000);
+
+        assert_eq!(
+            TEST_CURVE.withdraw_single_token_type_exact_out(
+                100_000_000,
+                LAUNCH_AMOUNT,
+                0,
+                pool_supply,
+                TradeDirection::AtoB,
+            ),
+            Some(51_316_702)
+        );
+        assert_eq!(
+            TEST_CURVE.withdraw_single_token_type_exact_in(
+                100_000_000,
+                LAUNCH_AMOUNT,
+                0,
+                pool_supply,
+                TradeDirection::AtoB,
+            ),
+            Some(190_000_000)
+        );
+
+        // token B is withdrawn against the offset balance, up to the real one
+        let swap_token_a_amount = LAUNCH_AMOUNT - 999_000;
+        let swap_token_b_amount = 1_000;
+        assert_eq!(
+            TEST_CURVE.withdraw_single_token_type_exact_out(
+                1_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                TradeDirection::BtoA,
+            ),
+            Some(499_626)
+        );
+        assert_eq!(
+            TEST_CURVE.withdraw_single_token_type_exact_out(
+                1_001,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                TradeDirection::BtoA,
+            ),
+            None
+        );
+        assert_eq!(
+            TEST_CURVE.withdraw_single_token_type_exact_in(
+                100_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                TradeDirection::BtoA,
+            ),
+            Some(200)
+        );
+        assert_eq!(
+            TEST_CURVE.withdraw_single_token_type_exact_in(
+                1_000_000,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_supply,
+                TradeDirection::BtoA,
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn validate_offset_and_supply() {
+        assert_eq!(TEST_CURVE.validate(), Ok(()));
+        assert_eq!(
+            OffsetCurve { token_b_offset: 0 }.validate(),
+            Err(SwapError::InvalidCurve)
+        );
+
+        assert_eq!(TEST_CURVE.validate_supply(1, 0), Ok(()));
+        assert_eq!(TEST_CURVE.validate_supply(1, 1), Ok(()));
+        assert_eq!(
+            TEST_CURVE.validate_supply(0, 1),
+            Err(SwapError::EmptySupply)
+        );
+        assert!(!TEST_CURVE.allows_deposits());
+    }
+
+    #[test]
+    fn pack_curve() {
+        let mut packed = [0u8; OffsetCurve::LEN];
+        Pack::pack_into_slice(&TEST_CURVE, &mut packed[..]);
+        let unpacked = OffsetCurve::unpack(&packed).unwrap();
+        assert_eq!(TEST_CURVE, unpacked);
+
+        let packed = TEST_CURVE.token_b_offset.to_le_bytes().to_vec();
+        let unpacked = OffsetCurve::unpack(&packed).unwrap();
+        assert_eq!(TEST_CURVE, unpacked);
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_swap(
+            source_token_amount in 1..u32::MAX as u128,
+            swap_source_amount in 1..u32::MAX as u128,
+            swap_destination_amount in 1..u32::MAX as u128,
+            token_b_offset in 1..u32::MAX as u64,
+        ) {
+            let curve = OffsetCurve { token_b_offset };
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA].iter() {
+                // the swap fails if it rounds to nothing, or would pay out
+                // the offset
+                if curve
+                    .swap_without_fees(
+                        source_token_amount,
+                        swap_source_amount,
+                        swap_destination_amount,
+                        *trade_direction,
+                    )
+                    .is_none()
+                {
+                    continue;
+                }
+                check_curve_value_from_swap(
+                    &curve,
+                    source_token_amount,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    *trade_direction,
+                );
+            }
+        }
+    }
+
+    // Withdrawals are owed token B on the offset balance, so the checks keep
+    // them within the real balance.
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_withdraw(
+            pool_token_supply in 10..1_000_000_000u128,
+            pool_basis_points in 1..5_000u128,
+            swap_token_a_amount in 1..u32::MAX as u128,
+            swap_token_b_excess in 0..u32::MAX as u128,
+            token_b_offset in 1..u32::MAX as u64,
+        ) {
+            let pool_token_amount =
+                std::cmp::max(1, pool_token_supply * pool_basis_points / 10_000);
+            let swap_token_b_amount = token_b_offset as u128 + swap_token_b_excess;
+            // Make sure we will get at least one trading token out for each
+            // side, otherwise the calculation fails
+            prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 1);
+            let curve = OffsetCurve { token_b_offset };
+            check_pool_value_from_withdraw(
+                &curve,
+                pool_token_amount,
+                pool_token_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn withdraw_token_conversion(
+            pool_token_supply in 10..1_000_000_000u128,
+            pool_basis_points in 1..1_000u128,
+            swap_token_a_amount in 1_000..1_000_000_000u128,
+            swap_token_b_excess in 0..1_000_000_000u128,
+            token_b_offset in 1..1_000_000_000u64,
+        ) {
+            let pool_token_amount =
+                std::cmp::max(1, pool_token_supply * pool_basis_points / 10_000);
+            let swap_token_b_amount = token_b_offset as u128 + swap_token_b_excess;
+            // Make sure the tokens withdrawn are large enough for the swap to
+            // round within the guarantee
+            prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 10_000);
+            prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 10_000);
+            let curve = OffsetCurve { token_b_offset };
+            check_withdraw_token_conversion(
+                &curve,
+                pool_token_amount,
+                pool_token_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                TradeDirection::AtoB,
+                CONVERSION_BASIS_POINTS_GUARANTEE,
+            );
+            check_withdraw_token_conversion(
+                &curve,
+                pool_token_amount,
+                pool_token_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                TradeDirection::BtoA,
+                CONVERSION_BASIS_POINTS_GUARANTEE,
+            );
+        }
+    }
+}
@@ -0,0 +1,606 @@
+//! Stable curve for a pair pegged at a fixed target price other than 1:1
+
+use {
+    crate::{
+        error::SwapError,
+        swap::{
+            calculator::{
+                CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult, TradeDirection,
+                TradingTokenResult,
+            },
+            fees::Fees,
+            stable::StableCurve,
+        },
+    },
+    arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs},
+    solana_program::{
+        program_error::ProgramError,
+        program_pack::{IsInitialized, Pack, Sealed},
+    },
+    spl_math::precise_number::PreciseNumber,
+};
+
+/// Scale of the target price, so that a price of 1.0 is
+/// `TARGET_PRICE_PRECISION`, as the target prices of the original contracts
+pub const TARGET_PRICE_PRECISION: u128 = 1_000_000_000_000_000_000;
+/// Minimum target price
+pub const MIN_TARGET_PRICE: u128 = TARGET_PRICE_PRECISION / 1_000;
+/// Maximum target price
+pub const MAX_TARGET_PRICE: u128 = TARGET_PRICE_PRECISION * 1_000;
+
+/// PeggedCurve struct implementing CurveCalculator
+///
+/// Token B is pegged to the target price in token A, e.g. a token redeemable
+/// for 1.05 of token A.  Amounts of token B are converted to their value in
+/// token A before using the stable curve, as done with the target prices of
+/// the original contracts.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PeggedCurve {
+    /// Amplifier constant
+    pub amp: u64,
+    /// Price of token B in token A, scaled by `TARGET_PRICE_PRECISION`
+    pub target_price: u128,
+}
+
+impl PeggedCurve {
+    fn stable_curve(&self) -> StableCurve {
+        StableCurve { amp: self.amp }
+    }
+
+    /// Value of the given amount of token B
+    fn value_of(&self, amount: u128, round_direction: RoundDirection) -> Option<u128> {
+        let numerator = amount.checked_mul(self.target_price)?;
+        let value = numerator.checked_div(TARGET_PRICE_PRECISION)?;
+        match round_direction {
+            RoundDirection::Ceiling if numerator.checked_rem(TARGET_PRICE_PRECISION)? > 0 => {
+                value.checked_add(1)
+            }
+            _ => Some(value),
+        }
+    }
+
+    /// Amount of token B worth the given value, rounded down
+    fn amount_worth(&self, value: u128) -> Option<u128> {
+        value
+            .checked_mul(TARGET_PRICE_PRECISION)?
+            .checked_div(self.target_price)
+    }
+
+    /// Value of a single token type withdrawal, rounded up in favor of the
+    /// pool
+    fn withdrawn_value(&self, amount: u128, trade_direction: TradeDirection) -> Option<u128> {
+        match trade_direction {
+            TradeDirection::AtoB => Some(amount),
+            TradeDirection::BtoA => self.value_of(amount, RoundDirection::Ceiling),
+        }
+    }
+
+    /// Amount of a single token type withdrawal worth the given value
+    fn withdrawn_amount(&self, value: u128, trade_direction: TradeDirection) -> Option<u128> {
+        match trade_direction {
+            TradeDirection::AtoB => Some(value),
+            TradeDirection::BtoA => self.amount_worth(value),
+        }
+    }
+}
+
+impl CurveCalculator for PeggedCurve {
+    /// Stable curve on the value of token B
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        let destination_amount_swapped = match trade_direction {
+            TradeDirection::AtoB => {
+                let result = self.stable_curve().swap_without_fees(
+                    source_amount,
+                    swap_source_amount,
+                    self.value_of(swap_destination_amount, RoundDirection::Floor)?,
+                    trade_direction,
+                )?;
+                self.amount_worth(result.destination_amount_swapped)?
+            }
+            TradeDirection::BtoA => {
+                let result = self.stable_curve().swap_without_fees(
+                    self.value_of(source_amount, RoundDirection::Floor)?,
+                    self.value_of(swap_source_amount, RoundDirection::Floor)?,
+                    swap_destination_amount,
+                    trade_direction,
+                )?;
+                result.destination_amount_swapped
+            }
+        };
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped: source_amount,
+            destination_amount_swapped,
+        })
+    }
+
+    fn new_pool_supply(&self, token_a_amount: u128, token_b_amount: u128) -> Option<u128> {
+        self.stable_curve().new_pool_supply(
+            token_a_amount,
+            self.value_of(token_b_amount, RoundDirection::Floor)?,
+        )
+    }
+
+    fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        round_direction: RoundDirection,
+    ) -> Option<TradingTokenResult> {
+        self.stable_curve().pool_tokens_to_trading_tokens(
+            pool_tokens,
+            pool_token_supply,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            round_direction,
+        )
+    }
+
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        self.stable_curve().withdraw_single_token_type_exact_out(
+            self.withdrawn_value(source_amount, trade_direction)?,
+            swap_token_a_amount,
+            self.value_of(swap_token_b_amount, RoundDirection::Floor)?,
+            pool_supply,
+            trade_direction,
+        )
+    }
+
+    fn withdraw_single_token_type_exact_out_with_fees(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Option<u128> {
+        self.stable_curve()
+            .withdraw_single_token_type_exact_out_with_fees(
+                self.withdrawn_value(source_amount, trade_direction)?,
+                swap_token_a_amount,
+                self.value_of(swap_token_b_amount, RoundDirection::Floor)?,
+                pool_supply,
+                trade_direction,
+                fees,
+            )
+    }
+
+    fn withdraw_single_token_type_exact_in(
+        &self,
+        pool_token_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        let value = self.stable_curve().withdraw_single_token_type_exact_in(
+            pool_token_amount,
+            swap_token_a_amount,
+            self.value_of(swap_token_b_amount, RoundDirection::Floor)?,
+            pool_supply,
+            trade_direction,
+        )?;
+        self.withdrawn_amount(value, trade_direction)
+    }
+
+    fn withdraw_single_token_type_exact_in_with_fees(
+        &self,
+        pool_token_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Option<u128> {
+        let value = self
+            .stable_curve()
+            .withdraw_single_token_type_exact_in_with_fees(
+                pool_token_amount,
+                swap_token_a_amount,
+                self.value_of(swap_token_b_amount, RoundDirection::Floor)?,
+                pool_supply,
+                trade_direction,
+                fees,
+            )?;
+        self.withdrawn_amount(value, trade_direction)
+    }
+
+    fn normalized_value(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        self.stable_curve().normalized_value(
+            swap_token_a_amount,
+            self.value_of(swap_token_b_amount, RoundDirection::Floor)?,
+        )
+    }
+
+    fn validate(&self) -> Result<(), SwapError> {
+        if self.target_price < MIN_TARGET_PRICE || self.target_price > MAX_TARGET_PRICE {
+            return Err(SwapError::InvalidCurve);
+        }
+        self.stable_curve().validate()
+    }
+
+    fn amp(&self) -> Option<u64> {
+        Some(self.amp)
+    }
+
+    /// Spot price of token A in token B, from the spot price in value of the
+    /// stable curve
+    fn spot_price(&self, swap_token_a_amount: u128, swap_token_b_amount: u128) -> Option<u128> {
+        let price = self.stable_curve().spot_price(
+            swap_token_a_amount,
+            self.value_of(swap_token_b_amount, RoundDirection::Floor)?,
+        )?;
+        self.amount_worth(price)
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for PeggedCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for PeggedCurve {}
+impl Pack for PeggedCurve {
+    const LEN: usize = 24;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<PeggedCurve, ProgramError> {
+        let input = array_ref![input, 0, 24];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (amp, target_price) = array_refs![input, 8, 16];
+        Ok(Self {
+            amp: u64::from_le_bytes(*amp),
+            target_price: u128::from_le_bytes(*target_price),
+        })
+    }
+}
+
+impl DynPack for PeggedCurve {
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 24];
+        let (amp, target_price) = mut_array_refs![output, 8, 16];
+        *amp = self.amp.to_le_bytes();
+        *target_price = self.target_price.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swap::calculator::{
+        test::{
+            check_curve_value_from_swap, check_pool_value_from_deposit,
+            check_pool_value_from_withdraw, check_withdraw_token_conversion,
+            total_and_intermediate, CONVERSION_BASIS_POINTS_GUARANTEE,
+        },
+        SPOT_PRICE_PRECISION,
+    };
+    use proptest::prelude::*;
+    use sim::StableSwapModel;
+
+    const N_COINS: u8 = 2;
+
+    #[test]
+    fn matches_stable_curve_at_par() {
+        let amp = 100;
+        let curve = PeggedCurve {
+            amp,
+            target_price: TARGET_PRICE_PRECISION,
+        };
+        let stable = StableCurve { amp };
+        let (token_a_amount, token_b_amount) = (1_000_000_000, 3_000_000_000);
+        for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA].iter() {
+            let (swap_source_amount, swap_destination_amount) = match trade_direction {
+                TradeDirection::AtoB => (token_a_amount, token_b_amount),
+                TradeDirection::BtoA => (token_b_amount, token_a_amount),
+            };
+            assert_eq!(
+                curve.swap_without_fees(
+                    1_000_000,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    *trade_direction
+                ),
+                stable.swap_without_fees(
+                    1_000_000,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    *trade_direction
+                )
+            );
+        }
+        assert_eq!(
+            curve.new_pool_supply(token_a_amount, token_b_amount),
+            stable.new_pool_supply(token_a_amount, token_b_amount)
+        );
+    }
+
+    #[test]
+    fn values_token_b_at_target_price() {
+        let curve = PeggedCurve {
+            amp: 100,
+            target_price: 1_050_000_000_000_000_000,
+        };
+        // balanced in value
+        let (token_a_amount, token_b_amount) = (1_050_000_000_000, 1_000_000_000_000);
+        assert_eq!(
+            curve.spot_price(token_a_amount, token_b_amount),
+            Some(SPOT_PRICE_PRECISION * 20 / 21)
+        );
+        assert_eq!(
+            curve.new_pool_supply(token_a_amount, token_b_amount),
+            StableCurve { amp: 100 }.new_pool_supply(token_a_amount, token_a_amount)
+        );
+
+        // withdrawing token B round trips through its value
+        let pool_supply = 2_100_000_000_000;
+        let withdrawn = curve
+            .withdraw_single_token_type_exact_in(
+                1_000_000,
+                token_a_amount,
+                token_b_amount,
+                pool_supply,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        let burned = curve
+            .withdraw_single_token_type_exact_out(
+                withdrawn,
+                token_a_amount,
+                token_b_amount,
+                pool_supply,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        assert!(withdrawn <= 952_380);
+        assert!(withdrawn > 950_000);
+        assert!(burned > 999_000);
+        assert!(burned < 1_001_000);
+    }
+
+    #[test]
+    fn validate_target_price() {
+        for target_price in [MIN_TARGET_PRICE, TARGET_PRICE_PRECISION, MAX_TARGET_PRICE].iter() {
+            assert_eq!(
+                PeggedCurve {
+                    amp: 100,
+                    target_price: *target_price,
+                }
+                .validate(),
+                Ok(())
+            );
+        }
+        for target_price in [0, MIN_TARGET_PRICE - 1, MAX_TARGET_PRICE + 1].iter() {
+            assert_eq!(
+                PeggedCurve {
+                    amp: 100,
+                    target_price: *target_price,
+                }
+                .validate(),
+                Err(SwapError::InvalidCurve)
+            );
+        }
+        assert_eq!(
+            PeggedCurve {
+                amp: 0,
+                target_price: TARGET_PRICE_PRECISION,
+            }
+            .validate(),
+            Err(SwapError::InvalidCurve)
+        );
+    }
+
+    #[test]
+    fn pack_curve() {
+        let amp = 100;
+        let target_price = 1_050_000_000_000_000_000;
+        let curve = PeggedCurve { amp, target_price };
+
+        let mut packed = [0u8; PeggedCurve::LEN];
+        Pack::pack_into_slice(&curve, &mut packed[..]);
+        let unpacked = PeggedCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+
+        let mut packed = vec![];
+        packed.extend_from_slice(&amp.to_le_bytes());
+        packed.extend_from_slice(&target_price.to_le_bytes());
+        let unpacked = PeggedCurve::unpack(&packed).unwrap();
+        assert_eq!(curve, unpacked);
+    }
+
+    proptest! {
+        #[test]
+        fn swap_matches_model(
+            swap_token_a_amount in 100..1_000_000_000_000_000_000u128,
+            swap_token_b_amount in 100..1_000_000_000_000_000_000u128,
+            source_amount in 100..100_000_000_000u128,
+            amp in 1..150u64,
+            target_price in TARGET_PRICE_PRECISION / 2..TARGET_PRICE_PRECISION * 2,
+        ) {
+            let curve = PeggedCurve { amp, target_price };
+            let mut model = StableSwapModel::new(
+                amp.into(),
+                vec![swap_token_a_amount, swap_token_b_amount],
+                N_COINS,
+            );
+            model.target_prices = vec![TARGET_PRICE_PRECISION, target_price];
+
+            // the model trades values, token A being worth its amount
+            if source_amount < swap_token_a_amount {
+                let result = curve
+                    .swap_without_fees(
+                        source_amount,
+                        swap_token_a_amount,
+                        swap_token_b_amount,
+                        TradeDirection::AtoB,
+                    )
+                    .unwrap();
+                let sim_value = model.sim_exchange(0, 1, source_amount);
+                let sim_result = sim_value * TARGET_PRICE_PRECISION / target_price;
+                let diff = (sim_result as i128 - result.destination_amount_swapped as i128).abs();
+                // tolerate a difference of 2 in value as for the stable curve,
+                // plus one for the conversion back to token B
+                let tolerance = std::cmp::max(2, sim_value / 1_000_000_000)
+                    * TARGET_PRICE_PRECISION
+                    / target_price
+                    + 1;
+                prop_assert!(
+                    diff <= tolerance as i128,
+                    "result={}, sim_result={}, diff={}",
+                    result.destination_amount_swapped,
+                    sim_result,
+                    diff
+                );
+            }
+
+            if source_amount < swap_token_b_amount {
+                let result = curve
+                    .swap_without_fees(
+                        source_amount,
+                        swap_token_b_amount,
+                        swap_token_a_amount,
+                        TradeDirection::BtoA,
+                    )
+                    .unwrap();
+                let source_value = source_amount * target_price / TARGET_PRICE_PRECISION;
+                let sim_result = model.sim_exchange(1, 0, source_value);
+                let diff = (sim_result as i128 - result.destination_amount_swapped as i128).abs();
+                // tolerate a difference of 2 as for the stable curve, plus
+                // one for the valuation of the token B balance rounded down
+                let tolerance = std::cmp::max(2, sim_result / 1_000_000_000) + 1;
+                prop_assert!(
+                    diff <= tolerance as i128,
+                    "result={}, sim_result={}, diff={}",
+                    result.destination_amount_swapped,
+                    sim_result,
+                    diff
+                );
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_swap(
+            source_token_amount in 1..u32::MAX,
+            swap_source_amount in 1..u32::MAX,
+            swap_destination_amount in 1..u32::MAX,
+            amp in 1..100u64,
+            target_price in MIN_TARGET_PRICE..MAX_TARGET_PRICE,
+        ) {
+            let curve = PeggedCurve { amp, target_price };
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA].iter() {
+                check_curve_value_from_swap(
+                    &curve,
+                    source_token_amount as u128,
+                    swap_source_amount as u128,
+                    swap_destination_amount as u128,
+                    *trade_direction,
+                );
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_deposit(
+            pool_token_amount in 1..u32::MAX,
+            pool_token_supply in 1..u32::MAX,
+            swap_token_a_amount in 1..u32::MAX,
+            swap_token_b_amount in 1..u32::MAX,
+            amp in 1..100u64,
+            target_price in MIN_TARGET_PRICE..MAX_TARGET_PRICE,
+        ) {
+            let pool_token_amount = pool_token_amount as u128;
+            let pool_token_supply = pool_token_supply as u128;
+            let swap_token_a_amount = swap_token_a_amount as u128;
+            let swap_token_b_amount = swap_token_b_amount as u128;
+            // Make sure we will get at least one trading token out for each
+            // side, otherwise the calculation fails
+            prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 1);
+            prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 1);
+            let curve = PeggedCurve { amp, target_price };
+            check_pool_value_from_deposit(
+                &curve,
+                pool_token_amount,
+                pool_token_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_withdraw(
+            (pool_token_supply, pool_token_amount) in total_and_intermediate(),
+            swap_token_a_amount in 1..u32::MAX,
+            swap_token_b_amount in 1..u32::MAX,
+            amp in 1..100u64,
+            target_price in MIN_TARGET_PRICE..MAX_TARGET_PRICE,
+        ) {
+            let pool_token_amount = pool_token_amount as u128;
+            let pool_token_supply = pool_token_supply as u128;
+            let swap_token_a_amount = swap_token_a_amount as u128;
+            let swap_token_b_amount = swap_token_b_amount as u128;
+            // Make sure we will get at least one trading token out for each
+            // side, otherwise the calculation fails
+            prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 1);
+            prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 1);
+            let curve = PeggedCurve { amp, target_price };
+            check_pool_value_from_withdraw(
+                &curve,
+                pool_token_amount,
+                pool_token_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn withdraw_token_conversion(
+            (pool_token_supply, pool_token_amount) in total_and_intermediate(),
+            swap_token_a_amount in 1..u32::MAX,
+            swap_token_b_amount in 1..u32::MAX,
+            amp in 1..100u64,
+            target_price in MIN_TARGET_PRICE..MAX_TARGET_PRICE,
+        ) {
+            let curve = PeggedCurve { amp, target_price };
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA].iter() {
+                check_withdraw_token_conversion(
+                    &curve,
+                    pool_token_amount as u128,
+                    pool_token_supply as u128,
+                    swap_token_a_amount as u128,
+                    swap_token_b_amount as u128,
+                    *trade_direction,
+                    CONVERSION_BASIS_POINTS_GUARANTEE,
+                );
+            }
+        }
+    }
+}
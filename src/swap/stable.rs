@@ -1,8 +1,11 @@
 use {
     crate::{
-        swap::calculator::{
-            CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult, TradeDirection,
-            TradingTokenResult,
+        swap::{
+            calculator::{
+                CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult, TradeDirection,
+                TradingTokenResult, SPOT_PRICE_PRECISION,
+            },
+            fees::{calculate_fee, Fees},
         },
         error::SwapError,
     },
@@ -19,6 +22,12 @@ const N_COINS: u8 = 2;
 const N_COINS_SQUARED: u8 = 4;
 const ITERATIONS: u8 = 32;
 
+/// Minimum amplification coefficient, below which the curve is no longer a
+/// stable curve
+pub const MIN_AMP: u64 = 1;
+/// Maximum amplification coefficient, as in the original contracts
+pub const MAX_AMP: u64 = 1_000_000;
+
 /// Calculates A for deriving D
 ///
 /// Per discussion with the designer and writer of stable curves, this A is not
@@ -157,6 +166,35 @@ fn compute_new_destination_amount(
     u128::try_from(y).ok()
 }
 
+/// Fee charged on one token when withdrawing an imbalanced amount, computed
+/// on the deviation of its new balance from the ideal balance, which keeps
+/// the same share of the reduced invariant:
+///
+/// fee * n / (4 * (n - 1)) * |old_amount * d1 / d0 - new_amount|
+fn imbalance_fee(
+    old_amount: u128,
+    new_amount: u128,
+    d0: u128,
+    d1: u128,
+    fees: &Fees,
+) -> Option<u128> {
+    let ideal_amount = U256::from(old_amount)
+        .checked_mul(d1.into())?
+        .checked_div(d0.into())?;
+    let ideal_amount = u128::try_from(ideal_amount).ok()?;
+    let difference = if ideal_amount > new_amount {
+        ideal_amount.checked_sub(new_amount)?
+    } else {
+        new_amount.checked_sub(ideal_amount)?
+    };
+    calculate_fee(
+        difference,
+        u128::from(fees.trade_fee_numerator).checked_mul(N_COINS.into())?,
+        u128::from(fees.trade_fee_denominator)
+            .checked_mul(N_COINS.checked_sub(1)?.checked_mul(4)?.into())?,
+    )
+}
+
 impl CurveCalculator for StableCurve {
     /// Stable curve
     fn swap_without_fees(
@@ -189,6 +227,13 @@ impl CurveCalculator for StableCurve {
         })
     }
 
+    /// The initial supply is the invariant D of the initial deposit, as in
+    /// Curve, so that the virtual price of a pool token starts at 1.0
+    fn new_pool_supply(&self, token_a_amount: u128, token_b_amount: u128) -> Option<u128> {
+        let leverage = compute_a(self.amp)?;
+        compute_d(leverage, token_a_amount, token_b_amount)
+    }
+
     /// Re-implementation of `remove_liquidity`:
     ///
     /// <https://github.com/curvefi/curve-contract/blob/80bbe179083c9a7062e4c482b0be3bfb7501f2bd/contracts/pool-templates/base/SwapTemplateBase.vy#L513>
@@ -264,6 +309,120 @@ impl CurveCalculator for StableCurve {
         final_amount.ceiling()?.to_imprecise()
     }
 
+    /// Charge the trading fee on each token's deviation from an ideal
+    /// proportional withdrawal, as done by Curve's `calc_withdraw_one_coin`
+    /// and `remove_liquidity_imbalance`, rather than on half the withdrawn
+    /// amount.  Balanced withdrawals are free, and withdrawals that push the
+    /// pool further out of balance pay more than ones that restore it.
+    /// Withdrawals leaving less of the withdrawn token than its fee fail.
+    fn withdraw_single_token_type_exact_out_with_fees(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Option<u128> {
+        if source_amount == 0 {
+            return Some(0);
+        }
+        let leverage = compute_a(self.amp)?;
+        let (withdraw_token_amount, other_token_amount) = match trade_direction {
+            TradeDirection::AtoB => (swap_token_a_amount, swap_token_b_amount),
+            TradeDirection::BtoA => (swap_token_b_amount, swap_token_a_amount),
+        };
+        let d0 = compute_d(leverage, withdraw_token_amount, other_token_amount)?;
+        let new_withdraw_token_amount = withdraw_token_amount.checked_sub(source_amount)?;
+        let d1 = compute_d(leverage, new_withdraw_token_amount, other_token_amount)?;
+
+        let withdraw_token_fee = imbalance_fee(
+            withdraw_token_amount,
+            new_withdraw_token_amount,
+            d0,
+            d1,
+            fees,
+        )?;
+        let other_token_fee = imbalance_fee(other_token_amount, other_token_amount, d0, d1, fees)?;
+        let d2 = compute_d(
+            leverage,
+            new_withdraw_token_amount.checked_sub(withdraw_token_fee)?,
+            other_token_amount.checked_sub(other_token_fee)?,
+        )?;
+
+        let diff = PreciseNumber::new(d0)?.checked_sub(&PreciseNumber::new(d2)?)?;
+        let final_amount = (diff.checked_mul(&PreciseNumber::new(pool_supply)?))?
+            .checked_div(&PreciseNumber::new(d0)?)?;
+        final_amount.ceiling()?.to_imprecise()
+    }
+
+    fn withdraw_single_token_type_exact_in(
+        &self,
+        pool_token_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        self.withdraw_single_token_type_exact_in_with_fees(
+            pool_token_amount,
+            swap_token_a_amount,
+            swap_token_b_amount,
+            pool_supply,
+            trade_direction,
+            &Fees::default(),
+        )
+    }
+
+    /// Solve for the balance of the withdrawn token at the reduced invariant,
+    /// charging the trading fee on each token's deviation from an ideal
+    /// proportional withdrawal, as done by Curve's `calc_withdraw_one_coin`.
+    fn withdraw_single_token_type_exact_in_with_fees(
+        &self,
+        pool_token_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+        fees: &Fees,
+    ) -> Option<u128> {
+        if pool_token_amount == 0 {
+            return Some(0);
+        }
+        let leverage = compute_a(self.amp)?;
+        let (withdraw_token_amount, other_token_amount) = match trade_direction {
+            TradeDirection::AtoB => (swap_token_a_amount, swap_token_b_amount),
+            TradeDirection::BtoA => (swap_token_b_amount, swap_token_a_amount),
+        };
+        let d0 = compute_d(leverage, withdraw_token_amount, other_token_amount)?;
+        // round the invariant reduction down, in favor of the pool
+        let d_reduction = U256::from(pool_token_amount)
+            .checked_mul(d0.into())?
+            .checked_div(pool_supply.into())?;
+        let d1 = d0.checked_sub(u128::try_from(d_reduction).ok()?)?;
+        let new_withdraw_token_amount =
+            compute_new_destination_amount(leverage, other_token_amount, d1)?;
+
+        let withdraw_token_fee = imbalance_fee(
+            withdraw_token_amount,
+            new_withdraw_token_amount,
+            d0,
+            d1,
+            fees,
+        )?;
+        let other_token_fee = imbalance_fee(other_token_amount, other_token_amount, d0, d1, fees)?;
+        let new_withdraw_token_amount = compute_new_destination_amount(
+            leverage,
+            other_token_amount.checked_sub(other_token_fee)?,
+            d1,
+        )?;
+        Some(
+            withdraw_token_amount
+                .checked_sub(withdraw_token_fee)?
+                .saturating_sub(new_withdraw_token_amount),
+        )
+    }
+
     fn normalized_value(
         &self,
         swap_token_a_amount: u128,
@@ -304,8 +463,39 @@ impl CurveCalculator for StableCurve {
     }
 
     fn validate(&self) -> Result<(), SwapError> {
-        // TODO are all amps valid?
-        Ok(())
+        if self.amp < MIN_AMP || self.amp > MAX_AMP {
+            Err(SwapError::InvalidCurve)
+        } else {
+            Ok(())
+        }
+    }
+
+    fn amp(&self) -> Option<u64> {
+        Some(self.amp)
+    }
+
+    /// The spot price is the ratio of the partial derivatives of the
+    /// invariant, both multiplied by `n**n * x * y`:
+    /// (A * n**n * n**n * x * y + D**3 / x) / (A * n**n * n**n * x * y + D**3 / y)
+    fn spot_price(&self, swap_token_a_amount: u128, swap_token_b_amount: u128) -> Option<u128> {
+        if swap_token_a_amount == 0 || swap_token_b_amount == 0 {
+            return None;
+        }
+        let leverage = compute_a(self.amp)?;
+        let d_val = compute_d(leverage, swap_token_a_amount, swap_token_b_amount)?;
+        let d_cubed = checked_u8_power(&U256::from(d_val), N_COINS.checked_add(1)?)?;
+        let leverage_term = U256::from(leverage)
+            .checked_mul(N_COINS_SQUARED.into())?
+            .checked_mul(swap_token_a_amount.into())?
+            .checked_mul(swap_token_b_amount.into())?;
+        let numerator =
+            leverage_term.checked_add(d_cubed.checked_div(swap_token_a_amount.into())?)?;
+        let denominator =
+            leverage_term.checked_add(d_cubed.checked_div(swap_token_b_amount.into())?)?;
+        let price = numerator
+            .checked_mul(SPOT_PRICE_PRECISION.into())?
+            .checked_div(denominator)?;
+        u128::try_from(price).ok()
     }
 }
 
@@ -342,21 +532,88 @@ mod tests {
     use super::*;
     use crate::swap::calculator::{
         test::{
-            check_curve_value_from_swap,
-            check_pool_value_from_deposit, check_pool_value_from_withdraw,
-            check_withdraw_token_conversion, total_and_intermediate,
+            check_curve_value_from_swap, check_pool_value_from_deposit,
+            check_pool_value_from_withdraw, check_withdraw_token_conversion,
+            check_withdraw_token_conversion_with_fees, total_and_intermediate,
             CONVERSION_BASIS_POINTS_GUARANTEE,
         },
-        RoundDirection, INITIAL_SWAP_POOL_AMOUNT,
+        RoundDirection,
+    };
+    use crate::swap::{
+        base::{CurveType, SwapCurve},
+        fees::Fees,
     };
     use proptest::prelude::*;
     use sim::StableSwapModel;
+    use std::sync::Arc;
 
     #[test]
     fn initial_pool_amount() {
         let amp = 1;
         let calculator = StableCurve { amp };
-        assert_eq!(calculator.new_pool_supply(), INITIAL_SWAP_POOL_AMOUNT);
+        // balanced pool: D is the sum of the balances
+        assert_eq!(calculator.new_pool_supply(1_000, 1_000), Some(2_000));
+        assert_eq!(calculator.new_pool_supply(0, 0), Some(0));
+        // imbalanced pool: D sits between the sum and the constant product value
+        let supply = calculator.new_pool_supply(1_000, 9_000).unwrap();
+        assert!(supply < 10_000);
+        assert!(supply > 6_000);
+        let leverage = compute_a(amp).unwrap();
+        assert_eq!(supply, compute_d(leverage, 1_000, 9_000).unwrap());
+    }
+
+    #[test]
+    fn validate_amp() {
+        assert_eq!(StableCurve { amp: MIN_AMP }.validate(), Ok(()));
+        assert_eq!(StableCurve { amp: MAX_AMP }.validate(), Ok(()));
+        assert_eq!(
+            StableCurve { amp: MIN_AMP - 1 }.validate(),
+            Err(SwapError::InvalidCurve)
+        );
+        assert_eq!(
+            StableCurve { amp: MAX_AMP + 1 }.validate(),
+            Err(SwapError::InvalidCurve)
+        );
+    }
+
+    #[test]
+    fn spot_price() {
+        let calculator = StableCurve { amp: 100 };
+        assert_eq!(
+            calculator.spot_price(1_000_000, 1_000_000),
+            Some(SPOT_PRICE_PRECISION)
+        );
+        assert_eq!(calculator.spot_price(0, 1_000_000), None);
+        assert_eq!(calculator.spot_price(1_000_000, 0), None);
+
+        // token A is cheaper when the pool holds more of it
+        let price = calculator.spot_price(3_000_000, 1_000_000).unwrap();
+        assert!(price < SPOT_PRICE_PRECISION);
+        let inverse = calculator.spot_price(1_000_000, 3_000_000).unwrap();
+        assert!(inverse > SPOT_PRICE_PRECISION);
+
+        // close to the price of a small trade
+        let swap_source_amount: u128 = 3_000_000_000_000;
+        let swap_destination_amount: u128 = 1_000_000_000_000;
+        let source_amount: u128 = 1_000_000;
+        let result = calculator
+            .swap_without_fees(
+                source_amount,
+                swap_source_amount,
+                swap_destination_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        let trade_price = result.destination_amount_swapped * SPOT_PRICE_PRECISION / source_amount;
+        let price = calculator
+            .spot_price(swap_source_amount, swap_destination_amount)
+            .unwrap();
+        assert!(price.abs_diff(trade_price) * 10_000 < price);
+
+        // stays within range at the largest balances
+        assert!(calculator
+            .spot_price(u64::MAX as u128, u64::MAX as u128 / 2)
+            .is_some());
     }
 
     fn check_pool_token_rate(
@@ -399,6 +656,78 @@ mod tests {
         assert_eq!(result.destination_amount_swapped, 0);
     }
 
+    #[test]
+    fn swap_offpeg_fee() {
+        let swap_curve = SwapCurve {
+            curve_type: CurveType::Stable,
+            calculator: Arc::new(StableCurve { amp: 100 }),
+        };
+        let base_fees = Fees {
+            trade_fee_numerator: 4,
+            trade_fee_denominator: 10_000,
+            owner_trade_fee_numerator: 1,
+            owner_trade_fee_denominator: 10_000,
+            ..Fees::default()
+        };
+        let dynamic_fees = Fees {
+            offpeg_fee_multiplier_numerator: 5,
+            offpeg_fee_multiplier_denominator: 1,
+            ..base_fees.clone()
+        };
+        let source_amount = 1_000_000;
+        let (small, large) = (1_000_000_000, 9_000_000_000);
+
+        // balanced pool, small trade: fees barely change
+        let base = swap_curve
+            .swap(
+                source_amount,
+                small,
+                small,
+                TradeDirection::AtoB,
+                &base_fees,
+            )
+            .unwrap();
+        let dynamic = swap_curve
+            .swap(
+                source_amount,
+                small,
+                small,
+                TradeDirection::AtoB,
+                &dynamic_fees,
+            )
+            .unwrap();
+        assert_eq!(base.trade_fee, 400);
+        assert_eq!(dynamic.trade_fee, 400);
+        assert_eq!(dynamic.owner_fee, 100);
+
+        // draining the scarce side is expensive
+        let draining = swap_curve
+            .swap(
+                source_amount,
+                large,
+                small,
+                TradeDirection::AtoB,
+                &dynamic_fees,
+            )
+            .unwrap();
+        // rebalancing the pool is cheap
+        let rebalancing = swap_curve
+            .swap(
+                source_amount,
+                small,
+                large,
+                TradeDirection::AtoB,
+                &dynamic_fees,
+            )
+            .unwrap();
+        assert!(draining.trade_fee > base.trade_fee);
+        assert!(draining.owner_fee > base.owner_fee);
+        assert!(rebalancing.trade_fee < draining.trade_fee);
+        assert!(rebalancing.trade_fee >= base.trade_fee);
+        assert!(draining.trade_fee <= base.trade_fee * 5);
+        assert!(draining.destination_amount_swapped < rebalancing.destination_amount_swapped);
+    }
+
     proptest! {
         #[test]
         fn swap_no_fee(
@@ -571,6 +900,201 @@ mod tests {
         }
     }
 
+    proptest! {
+        #[test]
+        fn withdraw_token_conversion_with_fees(
+            (pool_token_supply, pool_token_amount) in total_and_intermediate(),
+            swap_token_a_amount in 1..u64::MAX,
+            swap_token_b_amount in 1..u64::MAX,
+            amp in 1..100u64,
+            trade_fee_numerator in 0..100u64,
+        ) {
+            // Make sure both sides of the withdrawal are large enough for the
+            // minimum fee of one token to be negligible
+            prop_assume!(
+                pool_token_amount as u128 * swap_token_a_amount as u128
+                    / pool_token_supply as u128 >= 10_000
+            );
+            prop_assume!(
+                pool_token_amount as u128 * swap_token_b_amount as u128
+                    / pool_token_supply as u128 >= 10_000
+            );
+            let swap_curve = SwapCurve {
+                curve_type: CurveType::Stable,
+                calculator: Arc::new(StableCurve { amp }),
+            };
+            let fees = Fees {
+                trade_fee_numerator,
+                trade_fee_denominator: 10_000,
+                ..Fees::default()
+            };
+            check_withdraw_token_conversion_with_fees(
+                &swap_curve,
+                pool_token_amount as u128,
+                pool_token_supply as u128,
+                swap_token_a_amount as u128,
+                swap_token_b_amount as u128,
+                TradeDirection::AtoB,
+                &fees,
+                CONVERSION_BASIS_POINTS_GUARANTEE
+            );
+            check_withdraw_token_conversion_with_fees(
+                &swap_curve,
+                pool_token_amount as u128,
+                pool_token_supply as u128,
+                swap_token_a_amount as u128,
+                swap_token_b_amount as u128,
+                TradeDirection::BtoA,
+                &fees,
+                CONVERSION_BASIS_POINTS_GUARANTEE
+            );
+        }
+    }
+
+    #[test]
+    fn withdraw_single_token_fee_follows_imbalance() {
+        let curve = StableCurve { amp: 100 };
+        let fees = Fees {
+            trade_fee_numerator: 4,
+            trade_fee_denominator: 10_000,
+            ..Fees::default()
+        };
+        let pool_supply = 2_000_000_000;
+        let withdraw_amount = 1_000_000;
+        let pool_tokens = |swap_token_a_amount, swap_token_b_amount, fees: &Fees| {
+            curve
+                .withdraw_single_token_type_exact_out_with_fees(
+                    withdraw_amount,
+                    swap_token_a_amount,
+                    swap_token_b_amount,
+                    pool_supply,
+                    TradeDirection::AtoB,
+                    fees,
+                )
+                .unwrap()
+        };
+        let no_fees = Fees::default();
+
+        // balanced pool: about the fee on half the withdrawn amount
+        let fee = pool_tokens(1_000_000_000, 1_000_000_000, &fees)
+            - pool_tokens(1_000_000_000, 1_000_000_000, &no_fees);
+        assert!((195..=205).contains(&fee));
+
+        // withdrawing the scarce token costs more than withdrawing the
+        // abundant one
+        let scarce_fee = pool_tokens(200_000_000, 1_800_000_000, &fees)
+            - pool_tokens(200_000_000, 1_800_000_000, &no_fees);
+        let abundant_fee = pool_tokens(1_800_000_000, 200_000_000, &fees)
+            - pool_tokens(1_800_000_000, 200_000_000, &no_fees);
+        assert!(scarce_fee > abundant_fee);
+    }
+
+    proptest! {
+        #[test]
+        fn withdraw_token_exact_in_matches_exact_out(
+            (pool_token_supply, pool_token_amount) in total_and_intermediate(),
+            swap_token_a_amount in 1..u64::MAX,
+            swap_token_b_amount in 1..u64::MAX,
+            amp in 1..100u64,
+            trade_fee_numerator in 0..100u64,
+        ) {
+            let pool_token_amount = pool_token_amount as u128;
+            let pool_token_supply = pool_token_supply as u128;
+            let swap_token_a_amount = swap_token_a_amount as u128;
+            let swap_token_b_amount = swap_token_b_amount as u128;
+            // Make sure both sides of the withdrawal are large enough for
+            // truncation in the invariant to be negligible
+            prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 10_000);
+            prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 10_000);
+            let curve = StableCurve { amp };
+            let fees = Fees {
+                trade_fee_numerator,
+                trade_fee_denominator: 10_000,
+                ..Fees::default()
+            };
+            let token_amount = curve.withdraw_single_token_type_exact_in(
+                pool_token_amount,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_token_supply,
+                TradeDirection::AtoB,
+            ).unwrap();
+            let token_amount_with_fees = curve.withdraw_single_token_type_exact_in_with_fees(
+                pool_token_amount,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                pool_token_supply,
+                TradeDirection::AtoB,
+                &fees,
+            ).unwrap();
+            prop_assert!(token_amount_with_fees <= token_amount);
+            prop_assert!(token_amount <= swap_token_a_amount);
+
+            // withdrawing the same amount out must not cost fewer pool tokens
+            // than were burned, beyond truncation
+            if token_amount > 0 {
+                let burned = curve.withdraw_single_token_type_exact_out(
+                    token_amount,
+                    swap_token_a_amount,
+                    swap_token_b_amount,
+                    pool_token_supply,
+                    TradeDirection::AtoB,
+                ).unwrap();
+                let epsilon = std::cmp::max(
+                    1,
+                    pool_token_amount * CONVERSION_BASIS_POINTS_GUARANTEE / 10000,
+                );
+                prop_assert!(burned <= pool_token_amount + epsilon);
+            }
+        }
+    }
+
+    #[test]
+    fn withdraw_single_token_exact_in() {
+        let curve = StableCurve { amp: 100 };
+        let fees = Fees {
+            trade_fee_numerator: 4,
+            trade_fee_denominator: 10_000,
+            ..Fees::default()
+        };
+        let pool_supply = 2_000_000_000;
+        let swap_token_amount = 1_000_000_000;
+        // burning 0.1% of a balanced pool gives about 0.2% of one side
+        let token_amount = curve
+            .withdraw_single_token_type_exact_in(
+                2_000_000,
+                swap_token_amount,
+                swap_token_amount,
+                pool_supply,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        assert!((1_999_000..2_000_000).contains(&token_amount));
+        // the fee is about the trading fee on half the amount
+        let token_amount_with_fees = curve
+            .withdraw_single_token_type_exact_in_with_fees(
+                2_000_000,
+                swap_token_amount,
+                swap_token_amount,
+                pool_supply,
+                TradeDirection::BtoA,
+                &fees,
+            )
+            .unwrap();
+        let fee = token_amount - token_amount_with_fees;
+        assert!((395..=405).contains(&fee));
+        assert_eq!(
+            curve.withdraw_single_token_type_exact_in(
+                0,
+                swap_token_amount,
+                swap_token_amount,
+                pool_supply,
+                TradeDirection::BtoA,
+            ),
+            Some(0)
+        );
+    }
+
     // this test comes from a failed proptest
     #[test]
     fn withdraw_token_conversion_huge_withdrawal() {
@@ -0,0 +1,750 @@
+//! Weighted product curve, as used by Balancer pools
+
+use {
+    crate::{
+        error::SwapError,
+        swap::calculator::{
+            CurveCalculator, DynPack, RoundDirection, SwapWithoutFeesResult, TradeDirection,
+            TradingTokenResult, SPOT_PRICE_PRECISION,
+        },
+    },
+    arrayref::{array_mut_ref, array_ref, array_refs, mut_array_refs},
+    solana_program::{
+        program_error::ProgramError,
+        program_pack::{IsInitialized, Pack, Sealed},
+    },
+    spl_math::{
+        precise_number::{PreciseNumber, ONE},
+        uint::U256,
+    },
+    std::convert::TryFrom,
+};
+
+const ITERATIONS: u8 = 128;
+
+/// Minimum weight of a token
+pub const MIN_WEIGHT: u64 = 1;
+/// Maximum sum of the weights of both tokens, once reduced to their lowest
+/// terms, as in the original contracts.  This bounds the exponents of the
+/// fractional powers, and so their cost and precision loss.
+pub const MAX_TOTAL_WEIGHT: u64 = 50;
+
+/// Trades are capped to `1 / MAX_IN_RATIO_DENOMINATOR` of the source balance,
+/// as the `MAX_IN_RATIO` of the original contracts
+pub const MAX_IN_RATIO_DENOMINATOR: u128 = 2;
+
+/// Bound on the error of the fractional powers, as a fraction
+/// `1 / POW_ERROR_DENOMINATOR`, which is always rounded in favor of the pool
+const POW_ERROR_DENOMINATOR: u128 = 10_000_000_000;
+
+fn pow_error() -> Option<PreciseNumber> {
+    PreciseNumber::new(1)?.checked_div(&PreciseNumber::new(POW_ERROR_DENOMINATOR)?)
+}
+
+/// Greatest common divisor, to reduce the weights to their lowest terms
+fn gcd(mut a: u128, mut b: u128) -> u128 {
+    while b != 0 {
+        let remainder = a % b;
+        a = b;
+        b = remainder;
+    }
+    a
+}
+
+/// Approximates the nth root of a precise number with Newton's method,
+/// starting from a power of two above the root so that the guess decreases
+/// until the root is reached
+fn checked_root(base: &PreciseNumber, root: u128) -> Option<PreciseNumber> {
+    if root == 1 || base.value.is_zero() {
+        return Some(base.clone());
+    }
+    // base < 2 ** exponent, so the root is below 2 ** ceil(exponent / root)
+    let exponent =
+        i64::try_from(base.value.bits()).ok()? - i64::from(128 - ONE.leading_zeros()) + 1;
+    let signed_root = i64::try_from(root).ok()?;
+    let guess_exponent = exponent
+        .checked_add(signed_root)?
+        .checked_sub(1)?
+        .div_euclid(signed_root);
+    let mut guess = PreciseNumber {
+        value: if guess_exponent >= 0 {
+            U256::from(ONE) << usize::try_from(guess_exponent).ok()?
+        } else {
+            U256::from(ONE) >> usize::try_from(-guess_exponent).ok()?
+        },
+    };
+    let root_minus_one = root.checked_sub(1)?;
+    let precise_root = PreciseNumber::new(root)?;
+    let precise_root_minus_one = PreciseNumber::new(root_minus_one)?;
+    for _ in 0..ITERATIONS {
+        // x_k+1 = ((n - 1) * x_k + A / (x_k ^ (n - 1))) / n
+        let next_guess = precise_root_minus_one
+            .checked_mul(&guess)?
+            .checked_add(&base.checked_div(&guess.checked_pow(root_minus_one)?)?)?
+            .checked_div(&precise_root)?;
+        if next_guess.greater_than_or_equal(&guess) {
+            break;
+        }
+        guess = next_guess;
+    }
+    Some(guess)
+}
+
+/// Raises a precise number to the power `numerator / denominator`, taking the
+/// root first so that bases below one do not vanish
+fn checked_pow_fraction(
+    base: &PreciseNumber,
+    numerator: u128,
+    denominator: u128,
+) -> Option<PreciseNumber> {
+    checked_root(base, denominator)?.checked_pow(numerator)
+}
+
+/// Factor of a token in the normalized value,
+/// `(amount / weight) ^ (weight / total_weight)`.  Powers above one half are
+/// taken as the base divided by the complementary power, which keeps the
+/// relative error low for large weights.
+fn weighted_factor(amount: u128, weight: u128, total_weight: u128) -> Option<PreciseNumber> {
+    let base = PreciseNumber::new(amount)?.checked_div(&PreciseNumber::new(weight)?)?;
+    if weight.checked_mul(2)? <= total_weight {
+        checked_pow_fraction(&base, weight, total_weight)
+    } else {
+        base.checked_div(&checked_pow_fraction(
+            &base,
+            total_weight.checked_sub(weight)?,
+            total_weight,
+        )?)
+    }
+}
+
+/// WeightedCurve struct implementing CurveCalculator
+///
+/// Keeps `A ^ w_A * B ^ w_B` constant, as Balancer pools do, so that token A
+/// makes up `w_A / (w_A + w_B)` of the value of the pool at any price.  An
+/// 80/20 pool can be given as weights of 80 and 20, or 4 and 1.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct WeightedCurve {
+    /// Weight of token A
+    pub token_a_weight: u64,
+    /// Weight of token B
+    pub token_b_weight: u64,
+}
+
+impl WeightedCurve {
+    /// Weights of token A and B reduced to their lowest terms, so that the
+    /// fractional powers take the smallest roots
+    fn reduced_weights(&self) -> Option<(u128, u128)> {
+        let token_a_weight = u128::from(self.token_a_weight);
+        let token_b_weight = u128::from(self.token_b_weight);
+        let divisor = gcd(token_a_weight, token_b_weight);
+        Some((
+            token_a_weight.checked_div(divisor)?,
+            token_b_weight.checked_div(divisor)?,
+        ))
+    }
+
+    /// Weight of the given token, and the total weight, reduced
+    fn weight(&self, trade_direction: TradeDirection) -> Option<(u128, u128)> {
+        let (token_a_weight, token_b_weight) = self.reduced_weights()?;
+        let weight = match trade_direction {
+            TradeDirection::AtoB => token_a_weight,
+            TradeDirection::BtoA => token_b_weight,
+        };
+        Some((weight, token_a_weight.checked_add(token_b_weight)?))
+    }
+}
+
+impl CurveCalculator for WeightedCurve {
+    /// Weighted product swap, as `calcOutGivenIn` of the original contracts:
+    /// `dy = y * (1 - (x / (x + dx)) ^ (w_x / w_y))`
+    fn swap_without_fees(
+        &self,
+        source_amount: u128,
+        swap_source_amount: u128,
+        swap_destination_amount: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<SwapWithoutFeesResult> {
+        if source_amount == 0 {
+            return Some(SwapWithoutFeesResult {
+                source_amount_swapped: 0,
+                destination_amount_swapped: 0,
+            });
+        }
+        if source_amount.checked_mul(MAX_IN_RATIO_DENOMINATOR)? > swap_source_amount {
+            return None;
+        }
+        let (source_weight, total_weight) = self.weight(trade_direction)?;
+        let destination_weight = total_weight.checked_sub(source_weight)?;
+
+        let one = PreciseNumber::new(1)?;
+        let base = PreciseNumber::new(swap_source_amount)?.checked_div(&PreciseNumber::new(
+            swap_source_amount.checked_add(source_amount)?,
+        )?)?;
+        let power = checked_pow_fraction(&base, source_weight, destination_weight)?
+            .checked_add(&pow_error()?)?;
+        let destination_amount_swapped = if power.greater_than_or_equal(&one) {
+            0
+        } else {
+            PreciseNumber::new(swap_destination_amount)?
+                .checked_mul(&one.checked_sub(&power)?)?
+                .floor()?
+                .to_imprecise()?
+        };
+        Some(SwapWithoutFeesResult {
+            source_amount_swapped: source_amount,
+            destination_amount_swapped,
+        })
+    }
+
+    /// Proportional to the balances, as `joinPool` and `exitPool` of the
+    /// original contracts
+    fn pool_tokens_to_trading_tokens(
+        &self,
+        pool_tokens: u128,
+        pool_token_supply: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        round_direction: RoundDirection,
+    ) -> Option<TradingTokenResult> {
+        let mut token_a_amount = pool_tokens
+            .checked_mul(swap_token_a_amount)?
+            .checked_div(pool_token_supply)?;
+        let mut token_b_amount = pool_tokens
+            .checked_mul(swap_token_b_amount)?
+            .checked_div(pool_token_supply)?;
+        let (token_a_amount, token_b_amount) = match round_direction {
+            RoundDirection::Floor => (token_a_amount, token_b_amount),
+            RoundDirection::Ceiling => {
+                let token_a_remainder = pool_tokens
+                    .checked_mul(swap_token_a_amount)?
+                    .checked_rem(pool_token_supply)?;
+
+                if token_a_remainder > 0 && token_a_amount > 0 {
+                    token_a_amount += 1;
+                }
+                let token_b_remainder = pool_tokens
+                    .checked_mul(swap_token_b_amount)?
+                    .checked_rem(pool_token_supply)?;
+                if token_b_remainder > 0 && token_b_amount > 0 {
+                    token_b_amount += 1;
+                }
+                (token_a_amount, token_b_amount)
+            }
+        };
+        Some(TradingTokenResult {
+            token_a_amount,
+            token_b_amount,
+        })
+    }
+
+    /// Pool tokens burned for a single token withdrawal, as
+    /// `calcPoolInGivenSingleOut` of the original contracts without the fee:
+    /// `supply * (1 - ((x - dx) / x) ^ (w_x / w))`, rounded up
+    fn withdraw_single_token_type_exact_out(
+        &self,
+        source_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        if source_amount == 0 {
+            return Some(0);
+        }
+        let (weight, total_weight) = self.weight(trade_direction)?;
+        let withdraw_token_amount = match trade_direction {
+            TradeDirection::AtoB => swap_token_a_amount,
+            TradeDirection::BtoA => swap_token_b_amount,
+        };
+
+        let one = PreciseNumber::new(1)?;
+        let base = PreciseNumber::new(withdraw_token_amount.checked_sub(source_amount)?)?
+            .checked_div(&PreciseNumber::new(withdraw_token_amount)?)?;
+        let power = checked_pow_fraction(&base, weight, total_weight)?;
+        let power = match power.checked_sub(&pow_error()?) {
+            Some(power) => power,
+            None => PreciseNumber::new(0)?,
+        };
+        PreciseNumber::new(pool_supply)?
+            .checked_mul(&one.checked_sub(&power)?)?
+            .ceiling()?
+            .to_imprecise()
+    }
+
+    /// Tokens received for burning pool tokens, as `calcSingleOutGivenPoolIn`
+    /// of the original contracts without the fee:
+    /// `x * (1 - ((supply - p) / supply) ^ (w / w_x))`, rounded down
+    fn withdraw_single_token_type_exact_in(
+        &self,
+        pool_token_amount: u128,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+        pool_supply: u128,
+        trade_direction: TradeDirection,
+    ) -> Option<u128> {
+        if pool_token_amount == 0 {
+            return Some(0);
+        }
+        let (weight, total_weight) = self.weight(trade_direction)?;
+        let withdraw_token_amount = match trade_direction {
+            TradeDirection::AtoB => swap_token_a_amount,
+            TradeDirection::BtoA => swap_token_b_amount,
+        };
+
+        let one = PreciseNumber::new(1)?;
+        let base = PreciseNumber::new(pool_supply.checked_sub(pool_token_amount)?)?
+            .checked_div(&PreciseNumber::new(pool_supply)?)?;
+        let power = checked_pow_fraction(&base, total_weight, weight)?.checked_add(&pow_error()?)?;
+        if power.greater_than_or_equal(&one) {
+            return Some(0);
+        }
+        PreciseNumber::new(withdraw_token_amount)?
+            .checked_mul(&one.checked_sub(&power)?)?
+            .floor()?
+            .to_imprecise()
+    }
+
+    /// The weighted geometric mean of the balances divided by their weights,
+    /// `(A / w_A) ^ (w_A / w) * (B / w_B) ^ (w_B / w)`, which is `sqrt(A * B)`
+    /// for an even pool
+    fn normalized_value(
+        &self,
+        swap_token_a_amount: u128,
+        swap_token_b_amount: u128,
+    ) -> Option<PreciseNumber> {
+        let (token_a_weight, token_b_weight) = self.reduced_weights()?;
+        let total_weight = token_a_weight.checked_add(token_b_weight)?;
+        weighted_factor(swap_token_a_amount, token_a_weight, total_weight)?.checked_mul(
+            &weighted_factor(swap_token_b_amount, token_b_weight, total_weight)?,
+        )
+    }
+
+    fn validate(&self) -> Result<(), SwapError> {
+        if self.token_a_weight < MIN_WEIGHT || self.token_b_weight < MIN_WEIGHT {
+            return Err(SwapError::InvalidCurve);
+        }
+        let (token_a_weight, token_b_weight) =
+            self.reduced_weights().ok_or(SwapError::InvalidCurve)?;
+        if token_a_weight + token_b_weight > u128::from(MAX_TOTAL_WEIGHT) {
+            return Err(SwapError::InvalidCurve);
+        }
+        Ok(())
+    }
+
+    /// Ratio of the balances divided by their weights, as `calcSpotPrice` of
+    /// the original contracts
+    fn spot_price(&self, swap_token_a_amount: u128, swap_token_b_amount: u128) -> Option<u128> {
+        if swap_token_a_amount == 0 || swap_token_b_amount == 0 {
+            return None;
+        }
+        let (token_a_weight, token_b_weight) = self.reduced_weights()?;
+        swap_token_b_amount
+            .checked_mul(token_a_weight)?
+            .checked_mul(SPOT_PRICE_PRECISION)?
+            .checked_div(swap_token_a_amount.checked_mul(token_b_weight)?)
+    }
+}
+
+/// IsInitialized is required to use `Pack::pack` and `Pack::unpack`
+impl IsInitialized for WeightedCurve {
+    fn is_initialized(&self) -> bool {
+        true
+    }
+}
+impl Sealed for WeightedCurve {}
+impl Pack for WeightedCurve {
+    const LEN: usize = 16;
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        (self as &dyn DynPack).pack_into_slice(output);
+    }
+
+    fn unpack_from_slice(input: &[u8]) -> Result<WeightedCurve, ProgramError> {
+        let input = array_ref![input, 0, 16];
+        #[allow(clippy::ptr_offset_with_cast)]
+        let (token_a_weight, token_b_weight) = array_refs![input, 8, 8];
+        Ok(Self {
+            token_a_weight: u64::from_le_bytes(*token_a_weight),
+            token_b_weight: u64::from_le_bytes(*token_b_weight),
+        })
+    }
+}
+
+impl DynPack for WeightedCurve {
+    fn pack_into_slice(&self, output: &mut [u8]) {
+        let output = array_mut_ref![output, 0, 16];
+        let (token_a_weight, token_b_weight) = mut_array_refs![output, 8, 8];
+        *token_a_weight = self.token_a_weight.to_le_bytes();
+        *token_b_weight = self.token_b_weight.to_le_bytes();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::swap::calculator::test::{
+        check_curve_value_from_swap, check_pool_value_from_deposit, check_pool_value_from_withdraw,
+        check_withdraw_token_conversion, CONVERSION_BASIS_POINTS_GUARANTEE,
+    };
+    use proptest::prelude::*;
+
+    /// 80/20 pool
+    const TEST_CURVE: WeightedCurve = WeightedCurve {
+        token_a_weight: 80,
+        token_b_weight: 20,
+    };
+
+    #[test]
+    fn validate_weights() {
+        assert_eq!(TEST_CURVE.validate(), Ok(()));
+        for (token_a_weight, token_b_weight) in [(49, 1), (1, 49), (98, 2), (30, 20)].iter() {
+            let curve = WeightedCurve {
+                token_a_weight: *token_a_weight,
+                token_b_weight: *token_b_weight,
+            };
+            assert_eq!(curve.validate(), Ok(()));
+        }
+        for (token_a_weight, token_b_weight) in [(0, 1), (1, 0), (0, 0), (50, 1), (99, 1)].iter() {
+            let curve = WeightedCurve {
+                token_a_weight: *token_a_weight,
+                token_b_weight: *token_b_weight,
+            };
+            assert_eq!(curve.validate(), Err(SwapError::InvalidCurve));
+        }
+    }
+
+    #[test]
+    fn root_and_fractional_power() {
+        let base = PreciseNumber::new(1_000_000_000).unwrap();
+        let root = checked_root(&base, 3).unwrap();
+        assert_eq!(root.to_imprecise(), Some(1_000));
+        let square = checked_pow_fraction(&base, 2, 3).unwrap();
+        assert_eq!(square.to_imprecise(), Some(1_000_000));
+
+        // 0.25 ^ (1 / 2)
+        let base = PreciseNumber::new(1)
+            .unwrap()
+            .checked_div(&PreciseNumber::new(4).unwrap())
+            .unwrap();
+        let root = checked_root(&base, 2).unwrap();
+        let half = PreciseNumber::new(1)
+            .unwrap()
+            .checked_div(&PreciseNumber::new(2).unwrap())
+            .unwrap();
+        assert!(root.almost_eq(&half, U256::from(10)));
+    }
+
+    #[test]
+    fn swap_at_spot_price() {
+        // token A is worth as much as token B in this pool
+        let token_a_amount = 4_000_000_000;
+        let token_b_amount = 1_000_000_000;
+        assert_eq!(
+            TEST_CURVE.spot_price(token_a_amount, token_b_amount),
+            Some(SPOT_PRICE_PRECISION)
+        );
+
+        let result = TEST_CURVE
+            .swap_without_fees(
+                1_000_000,
+                token_a_amount,
+                token_b_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 1_000_000);
+        assert_eq!(result.destination_amount_swapped, 999_375);
+        let result = TEST_CURVE
+            .swap_without_fees(
+                1_000_000,
+                token_b_amount,
+                token_a_amount,
+                TradeDirection::BtoA,
+            )
+            .unwrap();
+        assert_eq!(result.source_amount_swapped, 1_000_000);
+        assert_eq!(result.destination_amount_swapped, 999_375);
+
+        let result = TEST_CURVE
+            .swap_without_fees(0, token_a_amount, token_b_amount, TradeDirection::AtoB)
+            .unwrap();
+        assert_eq!(result.destination_amount_swapped, 0);
+    }
+
+    #[test]
+    fn swap_max_in_ratio() {
+        let token_a_amount = 4_000_000_000;
+        let token_b_amount = 1_000_000_000;
+        let result = TEST_CURVE
+            .swap_without_fees(
+                token_a_amount / MAX_IN_RATIO_DENOMINATOR,
+                token_a_amount,
+                token_b_amount,
+                TradeDirection::AtoB,
+            )
+            .unwrap();
+        assert_eq!(result.destination_amount_swapped, 802_469_135);
+        assert_eq!(
+            TEST_CURVE.swap_without_fees(
+                token_a_amount / MAX_IN_RATIO_DENOMINATOR + 1,
+                token_a_amount,
+                token_b_amount,
+                TradeDirection::AtoB,
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn normalized_value_of_weighted_balances() {
+        let value = TEST_CURVE
+            .normalized_value(4_000_000_000, 1_000_000_000)
+            .unwrap();
+        assert_eq!(value.to_imprecise(), Some(1_000_000_000));
+
+        // reduces to sqrt(A * B) for an even pool
+        let even_curve = WeightedCurve {
+            token_a_weight: 50,
+            token_b_weight: 50,
+        };
+        let value = even_curve.normalized_value(4, 9).unwrap();
+        assert_eq!(value, PreciseNumber::new(6).unwrap());
+    }
+
+    #[test]
+    fn withdraw_single_token() {
+        let token_a_amount = 4_000_000_000;
+        let token_b_amount = 1_000_000_000;
+        let pool_supply = 1_000_000_000;
+        assert_eq!(
+            TEST_CURVE.withdraw_single_token_type_exact_out(
+                1_000_000,
+                token_a_amount,
+                token_b_amount,
+                pool_supply,
+                TradeDirection::AtoB,
+            ),
+            Some(200_006)
+        );
+        assert_eq!(
+            TEST_CURVE.withdraw_single_token_type_exact_out(
+                1_000_000,
+                token_a_amount,
+                token_b_amount,
+                pool_supply,
+                TradeDirection::BtoA,
+            ),
+            Some(200_081)
+        );
+        // all of one side is worth all of the pool tokens
+        assert_eq!(
+            TEST_CURVE.withdraw_single_token_type_exact_out(
+                token_a_amount,
+                token_a_amount,
+                token_b_amount,
+                pool_supply,
+                TradeDirection::AtoB,
+            ),
+            Some(pool_supply)
+        );
+
+        assert_eq!(
+            TEST_CURVE.withdraw_single_token_type_exact_in(
+                1_000_000,
+                token_a_amount,
+                token_b_amount,
+                pool_supply,
+                TradeDirection::AtoB,
+            ),
+            Some(4_999_374)
+        );
+        assert_eq!(
+            TEST_CURVE.withdraw_single_token_type_exact_in(
+                1_000_000,
+                token_a_amount,
+                token_b_amount,
+                pool_supply,
+                TradeDirection::BtoA,
+            ),
+            Some(4_990_009)
+        );
+    }
+
+    #[test]
+    fn pack_curve() {
+        let mut packed = [0u8; WeightedCurve::LEN];
+        Pack::pack_into_slice(&TEST_CURVE, &mut packed[..]);
+        let unpacked = WeightedCurve::unpack(&packed).unwrap();
+        assert_eq!(TEST_CURVE, unpacked);
+
+        let mut packed = vec![];
+        packed.extend_from_slice(&TEST_CURVE.token_a_weight.to_le_bytes());
+        packed.extend_from_slice(&TEST_CURVE.token_b_weight.to_le_bytes());
+        let unpacked = WeightedCurve::unpack(&packed).unwrap();
+        assert_eq!(TEST_CURVE, unpacked);
+    }
+
+    prop_compose! {
+        fn weights()(token_a_weight in MIN_WEIGHT..MAX_TOTAL_WEIGHT)
+                    (token_b_weight in MIN_WEIGHT..=MAX_TOTAL_WEIGHT - token_a_weight,
+                     token_a_weight in Just(token_a_weight))
+                    -> (u64, u64) {
+            (token_a_weight, token_b_weight)
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_deposit(
+            (token_a_weight, token_b_weight) in weights(),
+            pool_token_amount in 1..u64::MAX,
+            pool_token_supply in 1..u64::MAX,
+            swap_token_a_amount in 1..u64::MAX,
+            swap_token_b_amount in 1..u64::MAX,
+        ) {
+            let pool_token_amount = pool_token_amount as u128;
+            let pool_token_supply = pool_token_supply as u128;
+            let swap_token_a_amount = swap_token_a_amount as u128;
+            let swap_token_b_amount = swap_token_b_amount as u128;
+            // Make sure we will get at least one trading token out for each
+            // side, otherwise the calculation fails
+            prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 1);
+            prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 1);
+            let curve = WeightedCurve {
+                token_a_weight,
+                token_b_weight,
+            };
+            check_pool_value_from_deposit(
+                &curve,
+                pool_token_amount,
+                pool_token_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+            );
+        }
+    }
+
+    // The fractional powers are precise to about 12 digits, so the value
+    // checks are limited to balances of 10 digits, balanced in value within a
+    // factor of two, where a token unit is worth less than a normalized token.
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_swap(
+            (token_a_weight, token_b_weight) in weights(),
+            swap_source_amount in 1_000..10_000_000_000u128,
+            value_ratio_percent in 50..200u128,
+            trade_basis_points in 1..10_000u128,
+        ) {
+            let curve = WeightedCurve {
+                token_a_weight,
+                token_b_weight,
+            };
+            for trade_direction in [TradeDirection::AtoB, TradeDirection::BtoA].iter() {
+                let (source_weight, destination_weight) = match trade_direction {
+                    TradeDirection::AtoB => (token_a_weight as u128, token_b_weight as u128),
+                    TradeDirection::BtoA => (token_b_weight as u128, token_a_weight as u128),
+                };
+                let swap_destination_amount = swap_source_amount * destination_weight
+                    * value_ratio_percent
+                    / (source_weight * 100);
+                if !(1..=10_000_000_000).contains(&swap_destination_amount) {
+                    continue;
+                }
+                // Keep at least half of the destination balance, so that a
+                // destination token stays worth less than a normalized token
+                let max_source_amount = std::cmp::min(
+                    swap_source_amount / MAX_IN_RATIO_DENOMINATOR,
+                    swap_source_amount * destination_weight / (2 * source_weight),
+                );
+                let source_token_amount =
+                    std::cmp::max(1, max_source_amount * trade_basis_points / 10_000);
+                check_curve_value_from_swap(
+                    &curve,
+                    source_token_amount,
+                    swap_source_amount,
+                    swap_destination_amount,
+                    *trade_direction,
+                );
+            }
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn curve_value_does_not_decrease_from_withdraw(
+            (token_a_weight, token_b_weight) in weights(),
+            pool_token_supply in 10..1_000_000_000u128,
+            pool_basis_points in 1..1_000u128,
+            swap_token_a_amount in 1_000..1_000_000_000u128,
+            value_ratio_percent in 50..200u128,
+        ) {
+            let pool_token_amount =
+                std::cmp::max(1, pool_token_supply * pool_basis_points / 10_000);
+            let swap_token_b_amount = swap_token_a_amount
+                * token_b_weight as u128
+                * value_ratio_percent
+                / (token_a_weight as u128 * 100);
+            prop_assume!(swap_token_b_amount >= 1_000);
+            prop_assume!(swap_token_b_amount < 1_000_000_000);
+            // Make sure we will get at least one trading token out for each
+            // side, otherwise the calculation fails
+            prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 1);
+            prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 1);
+            let curve = WeightedCurve {
+                token_a_weight,
+                token_b_weight,
+            };
+            check_pool_value_from_withdraw(
+                &curve,
+                pool_token_amount,
+                pool_token_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+            );
+        }
+    }
+
+    proptest! {
+        #[test]
+        fn withdraw_token_conversion(
+            (token_a_weight, token_b_weight) in weights(),
+            pool_token_supply in 10..1_000_000_000u128,
+            pool_basis_points in 1..1_000u128,
+            swap_token_a_amount in 1_000..1_000_000_000u128,
+            value_ratio_percent in 50..200u128,
+        ) {
+            let pool_token_amount =
+                std::cmp::max(1, pool_token_supply * pool_basis_points / 10_000);
+            let swap_token_b_amount = swap_token_a_amount
+                * token_b_weight as u128
+                * value_ratio_percent
+                / (token_a_weight as u128 * 100);
+            prop_assume!(swap_token_b_amount >= 1_000);
+            prop_assume!(swap_token_b_amount < 1_000_000_000);
+            prop_assume!(pool_token_amount * swap_token_a_amount / pool_token_supply >= 1);
+            prop_assume!(pool_token_amount * swap_token_b_amount / pool_token_supply >= 1);
+            let curve = WeightedCurve {
+                token_a_weight,
+                token_b_weight,
+            };
+            check_withdraw_token_conversion(
+                &curve,
+                pool_token_amount,
+                pool_token_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                TradeDirection::AtoB,
+                CONVERSION_BASIS_POINTS_GUARANTEE,
+            );
+            check_withdraw_token_conversion(
+                &curve,
+                pool_token_amount,
+                pool_token_supply,
+                swap_token_a_amount,
+                swap_token_b_amount,
+                TradeDirection::BtoA,
+                CONVERSION_BASIS_POINTS_GUARANTEE,
+            );
+        }
+    }
+}